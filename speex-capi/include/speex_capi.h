@@ -0,0 +1,329 @@
+/*
+ * Copyright (c) 2023.
+ * This Source Code Form is subject to the terms of the Mozilla Public License,
+ * v. 2.0. If a copy of the MPL was not distributed with this file, You can
+ * obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+#ifndef SPEEX_CAPI_H
+#define SPEEX_CAPI_H
+
+/* Generated with cbindgen from speex-capi, do not edit by hand */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/*
+ Major version of the API, changed on incompatible changes
+ */
+#define SPEEX_CAPI_VERSION_MAJOR 1
+
+/*
+ Minor version of the API, changed when functions are added
+ */
+#define SPEEX_CAPI_VERSION_MINOR 0
+
+/*
+ Modes, as taken by the create functions
+ */
+typedef enum SpeexCapiMode {
+  SPEEX_CAPI_MODE_NARROWBAND = 0,
+  SPEEX_CAPI_MODE_WIDEBAND = 1,
+  SPEEX_CAPI_MODE_ULTRA_WIDEBAND = 2,
+} SpeexCapiMode;
+
+/*
+ How the encoder picks the bitrate of each frame
+ */
+typedef enum SpeexCapiRateControl {
+  /*
+   Constant bitrate from `quality`
+   */
+  SPEEX_CAPI_RATE_CONTROL_QUALITY = 0,
+  /*
+   Constant bitrate, the highest quality within `bitrate`
+   */
+  SPEEX_CAPI_RATE_CONTROL_BITRATE = 1,
+  /*
+   Variable bitrate from `vbr_quality`, capped at `bitrate` unless it is 0
+   */
+  SPEEX_CAPI_RATE_CONTROL_VBR = 2,
+  /*
+   Average bitrate of `bitrate`
+   */
+  SPEEX_CAPI_RATE_CONTROL_ABR = 3,
+} SpeexCapiRateControl;
+
+/*
+ Result of every fallible function
+ */
+typedef enum SpeexCapiStatus {
+  SPEEX_CAPI_STATUS_OK = 0,
+  /*
+   A required pointer was null
+   */
+  SPEEX_CAPI_STATUS_NULL_POINTER = -1,
+  /*
+   The mode isn't one of `SpeexCapiMode`
+   */
+  SPEEX_CAPI_STATUS_INVALID_MODE = -2,
+  /*
+   The config has a setting out of range, or settings that conflict
+   */
+  SPEEX_CAPI_STATUS_INVALID_CONFIG = -3,
+  /*
+   The output buffer can't hold the result. Nothing was written
+   */
+  SPEEX_CAPI_STATUS_BUFFER_TOO_SMALL = -4,
+  /*
+   The number of samples isn't the frame size of the encoder
+   */
+  SPEEX_CAPI_STATUS_WRONG_FRAME_SIZE = -5,
+  /*
+   The packet couldn't be decoded
+   */
+  SPEEX_CAPI_STATUS_CORRUPT_STREAM = -6,
+  /*
+   Reading or writing a file failed
+   */
+  SPEEX_CAPI_STATUS_IO = -7,
+  /*
+   A file isn't a supported WAV or Ogg Speex file
+   */
+  SPEEX_CAPI_STATUS_INVALID_FILE = -8,
+  /*
+   The library panicked. This is a bug
+   */
+  SPEEX_CAPI_STATUS_PANIC = -9,
+} SpeexCapiStatus;
+
+/*
+ A decoder and the bits packets are read into
+ */
+typedef struct SpeexCapiDecoder SpeexCapiDecoder;
+
+/*
+ An encoder and the bits it packs frames into
+ */
+typedef struct SpeexCapiEncoder SpeexCapiEncoder;
+
+/*
+ Complete set of encoder settings
+
+ Fill with `speex_capi_config_default` before changing fields, so fields
+ added in later minor versions get their defaults.
+ */
+typedef struct SpeexCapiConfig {
+  /*
+   One of `SpeexCapiRateControl`
+   */
+  int32_t rate_control;
+  /*
+   Quality between 0 and 10, for `SPEEX_CAPI_RATE_CONTROL_QUALITY`
+   */
+  int32_t quality;
+  /*
+   Quality between 0 and 10, for `SPEEX_CAPI_RATE_CONTROL_VBR`
+   */
+  float vbr_quality;
+  /*
+   Bits per second, see `SpeexCapiRateControl`
+   */
+  int32_t bitrate;
+  /*
+   Whether to use Voice Activity Detection
+   */
+  bool vad;
+  /*
+   Whether to use Discontinuous Transmission
+   */
+  bool dtx;
+  /*
+   Analysis complexity between 1 and 10
+   */
+  int32_t complexity;
+  /*
+   Whether the encoder's highpass filter is used
+   */
+  bool highpass;
+  /*
+   Sampling rate used for bitrate computation, or 0 for the mode's own
+   */
+  int32_t sampling_rate;
+  /*
+   Narrowband submode overriding the rate control, or 0 for none
+   */
+  int32_t low_submode;
+  /*
+   Wideband submode overriding the rate control, or 0 for none. Only
+   valid in wideband mode
+   */
+  int32_t high_submode;
+} SpeexCapiConfig;
+
+/*
+ Audio decoded from an Ogg Speex file, owned by the library until freed
+ with `speex_capi_audio_free`
+ */
+typedef struct SpeexCapiAudio {
+  /*
+   Interleaved 16 bit samples
+   */
+  int16_t *samples;
+  size_t samples_len;
+  uint32_t sample_rate;
+  uint16_t channels;
+} SpeexCapiAudio;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/*
+ Fills `config` with the library defaults for `mode`
+ */
+SpeexCapiStatus speex_capi_config_default(int32_t mode, struct SpeexCapiConfig *config);
+
+/*
+ Creates a decoder for `mode`. Destroy it with
+ `speex_capi_decoder_destroy`.
+ */
+SpeexCapiStatus speex_capi_decoder_create(int32_t mode, struct SpeexCapiDecoder **decoder);
+
+/*
+ Destroys a decoder. Does nothing if `decoder` is null.
+ */
+void speex_capi_decoder_destroy(struct SpeexCapiDecoder *decoder);
+
+/*
+ Gets the number of samples in a frame, or 0 if `decoder` is null
+ */
+size_t speex_capi_decoder_frame_size(const struct SpeexCapiDecoder *decoder);
+
+/*
+ Sets whether the decoder's perceptual enhancement is used
+ */
+SpeexCapiStatus speex_capi_decoder_set_enhancement(struct SpeexCapiDecoder *decoder,
+                                                   bool enhancement);
+
+/*
+ Decodes every frame of a packet into samples in the range of an
+ `int16_t`
+
+ If `packet` is null, one frame is produced by the decoder's packet loss
+ concealment instead. On success the number of samples decoded is stored
+ in `samples_len`.
+ */
+SpeexCapiStatus speex_capi_decode(struct SpeexCapiDecoder *decoder,
+                                  const uint8_t *packet,
+                                  size_t packet_len,
+                                  float *samples,
+                                  size_t samples_capacity,
+                                  size_t *samples_len);
+
+/*
+ Same as `speex_capi_decode`, for 16 bit samples
+ */
+SpeexCapiStatus speex_capi_decode_int(struct SpeexCapiDecoder *decoder,
+                                      const uint8_t *packet,
+                                      size_t packet_len,
+                                      int16_t *samples,
+                                      size_t samples_capacity,
+                                      size_t *samples_len);
+
+/*
+ Creates an encoder for `mode`, configured with `config`, or with the
+ library defaults if `config` is null. Destroy it with
+ `speex_capi_encoder_destroy`.
+ */
+SpeexCapiStatus speex_capi_encoder_create(int32_t mode,
+                                          const struct SpeexCapiConfig *config,
+                                          struct SpeexCapiEncoder **encoder);
+
+/*
+ Destroys an encoder. Does nothing if `encoder` is null.
+ */
+void speex_capi_encoder_destroy(struct SpeexCapiEncoder *encoder);
+
+/*
+ Gets the number of samples in a frame, or 0 if `encoder` is null
+ */
+size_t speex_capi_encoder_frame_size(const struct SpeexCapiEncoder *encoder);
+
+/*
+ Replaces every setting of the encoder with those in `config`
+ */
+SpeexCapiStatus speex_capi_encoder_configure(struct SpeexCapiEncoder *encoder,
+                                             const struct SpeexCapiConfig *config);
+
+/*
+ Encodes one frame of samples in the range of an `int16_t` into a packet
+
+ `samples_len` has to be the frame size. On success the size of the
+ packet is stored in `packet_len`.
+ */
+SpeexCapiStatus speex_capi_encode(struct SpeexCapiEncoder *encoder,
+                                  const float *samples,
+                                  size_t samples_len,
+                                  uint8_t *packet,
+                                  size_t packet_capacity,
+                                  size_t *packet_len);
+
+/*
+ Same as `speex_capi_encode`, for 16 bit samples
+ */
+SpeexCapiStatus speex_capi_encode_int(struct SpeexCapiEncoder *encoder,
+                                      const int16_t *samples,
+                                      size_t samples_len,
+                                      uint8_t *packet,
+                                      size_t packet_capacity,
+                                      size_t *packet_len);
+
+/*
+ Gets the version the library was built with, as
+ `(major << 16) | minor`. Callers should check the major version matches
+ the one in the header they were compiled with.
+ */
+uint32_t speex_capi_version(void);
+
+/*
+ Gets a static description of a status code
+ */
+const char *speex_capi_status_message(int32_t status);
+
+/*
+ Encodes a WAV file into an Ogg Speex file
+
+ The mode is picked from the sampling rate of the WAV file. `quality` is
+ between 0 and 10, or -1 for the library default.
+ */
+SpeexCapiStatus speex_capi_wav_to_ogg(const char *wav_path, const char *ogg_path, int32_t quality);
+
+/*
+ Decodes an Ogg Speex file into a 16 bit WAV file
+ */
+SpeexCapiStatus speex_capi_ogg_to_wav(const char *ogg_path, const char *wav_path);
+
+/*
+ Decodes an Ogg Speex file held in memory
+
+ On success `audio` owns the samples, and has to be freed with
+ `speex_capi_audio_free`.
+ */
+SpeexCapiStatus speex_capi_ogg_decode(const uint8_t *data,
+                                      size_t data_len,
+                                      struct SpeexCapiAudio *audio);
+
+/*
+ Frees the samples of audio from `speex_capi_ogg_decode`, leaving it
+ empty. Does nothing if `audio` is null or already freed.
+ */
+void speex_capi_audio_free(struct SpeexCapiAudio *audio);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* SPEEX_CAPI_H */
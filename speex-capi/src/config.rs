@@ -0,0 +1,210 @@
+////////////////////////////////////////////////////////////////////////////////
+// Copyright (c) 2023.                                                         /
+// This Source Code Form is subject to the terms of the Mozilla Public License,/
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can    /
+// obtain one at http://mozilla.org/MPL/2.0/.                                  /
+////////////////////////////////////////////////////////////////////////////////
+
+//! Encoder settings as a plain C struct, converted to an `EncoderConfig`
+
+use speex_safe::{EncoderConfig, ModeId, NbSubmodeId, RateControl, WbSubmodeId};
+
+use crate::{guard, mode_from_id, SpeexCapiStatus};
+
+/// How the encoder picks the bitrate of each frame
+#[repr(C)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum SpeexCapiRateControl {
+    /// Constant bitrate from `quality`
+    Quality = 0,
+    /// Constant bitrate, the highest quality within `bitrate`
+    Bitrate = 1,
+    /// Variable bitrate from `vbr_quality`, capped at `bitrate` unless it is 0
+    Vbr = 2,
+    /// Average bitrate of `bitrate`
+    Abr = 3,
+}
+
+/// Complete set of encoder settings
+///
+/// Fill with `speex_capi_config_default` before changing fields, so fields
+/// added in later minor versions get their defaults.
+#[repr(C)]
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct SpeexCapiConfig {
+    /// One of `SpeexCapiRateControl`
+    pub rate_control: i32,
+    /// Quality between 0 and 10, for `SPEEX_CAPI_RATE_CONTROL_QUALITY`
+    pub quality: i32,
+    /// Quality between 0 and 10, for `SPEEX_CAPI_RATE_CONTROL_VBR`
+    pub vbr_quality: f32,
+    /// Bits per second, see `SpeexCapiRateControl`
+    pub bitrate: i32,
+    /// Whether to use Voice Activity Detection
+    pub vad: bool,
+    /// Whether to use Discontinuous Transmission
+    pub dtx: bool,
+    /// Analysis complexity between 1 and 10
+    pub complexity: i32,
+    /// Whether the encoder's highpass filter is used
+    pub highpass: bool,
+    /// Sampling rate used for bitrate computation, or 0 for the mode's own
+    pub sampling_rate: i32,
+    /// Narrowband submode overriding the rate control, or 0 for none
+    pub low_submode: i32,
+    /// Wideband submode overriding the rate control, or 0 for none. Only
+    /// valid in wideband mode
+    pub high_submode: i32,
+}
+
+impl From<&EncoderConfig> for SpeexCapiConfig {
+    fn from(config: &EncoderConfig) -> Self {
+        let (rate_control, quality, vbr_quality, bitrate) = match config.rate_control {
+            RateControl::Quality(quality) => (SpeexCapiRateControl::Quality, quality, 0.0, 0),
+            RateControl::Bitrate(bitrate) => (SpeexCapiRateControl::Bitrate, 0, 0.0, bitrate),
+            RateControl::Vbr {
+                quality,
+                max_bitrate,
+            } => {
+                (
+                    SpeexCapiRateControl::Vbr,
+                    0,
+                    quality,
+                    max_bitrate.unwrap_or(0),
+                )
+            }
+            RateControl::Abr(bitrate) => (SpeexCapiRateControl::Abr, 0, 0.0, bitrate),
+        };
+        Self {
+            rate_control: rate_control as i32,
+            quality,
+            vbr_quality,
+            bitrate,
+            vad: config.vad,
+            dtx: config.dtx,
+            complexity: config.complexity,
+            highpass: config.highpass,
+            sampling_rate: config.sampling_rate.unwrap_or(0),
+            low_submode: config.low_submode.map_or(0, |submode| submode as i32),
+            high_submode: config.high_submode.map_or(0, |submode| submode as i32),
+        }
+    }
+}
+
+impl SpeexCapiConfig {
+    /// Converts to an `EncoderConfig` for `mode`, checking every setting
+    pub fn to_encoder_config(&self, mode: ModeId) -> Result<EncoderConfig, SpeexCapiStatus> {
+        let rate_control = match self.rate_control {
+            0 => RateControl::Quality(self.quality),
+            1 => RateControl::Bitrate(self.bitrate),
+            2 => {
+                RateControl::Vbr {
+                    quality: self.vbr_quality,
+                    max_bitrate: (self.bitrate != 0).then_some(self.bitrate),
+                }
+            }
+            3 => RateControl::Abr(self.bitrate),
+            _ => return Err(SpeexCapiStatus::InvalidConfig),
+        };
+        let low_submode = match self.low_submode {
+            0 => None,
+            1..=8 => Some(NbSubmodeId::from(self.low_submode)),
+            _ => return Err(SpeexCapiStatus::InvalidConfig),
+        };
+        let high_submode = match self.high_submode {
+            0 => None,
+            1..=4 => Some(WbSubmodeId::from(self.high_submode)),
+            _ => return Err(SpeexCapiStatus::InvalidConfig),
+        };
+        let config = EncoderConfig {
+            mode,
+            rate_control,
+            vad: self.vad,
+            dtx: self.dtx,
+            complexity: self.complexity,
+            highpass: self.highpass,
+            sampling_rate: (self.sampling_rate != 0).then_some(self.sampling_rate),
+            low_submode,
+            high_submode,
+        };
+        config
+            .validate()
+            .map_err(|_| SpeexCapiStatus::InvalidConfig)?;
+        Ok(config)
+    }
+}
+
+/// Fills `config` with the library defaults for `mode`
+#[no_mangle]
+pub unsafe extern "C" fn speex_capi_config_default(
+    mode: i32,
+    config: *mut SpeexCapiConfig,
+) -> SpeexCapiStatus {
+    guard(|| {
+        let config = config.as_mut().ok_or(SpeexCapiStatus::NullPointer)?;
+        *config = SpeexCapiConfig::from(&EncoderConfig::new(mode_from_id(mode)?));
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn round_trips_encoder_configs() {
+        let mut config = EncoderConfig::new(ModeId::WideBand);
+        config.rate_control = RateControl::Vbr {
+            quality: 6.5,
+            max_bitrate: Some(20000),
+        };
+        config.complexity = 4;
+        assert_eq!(
+            SpeexCapiConfig::from(&config).to_encoder_config(ModeId::WideBand),
+            Ok(config)
+        );
+
+        config.rate_control = RateControl::Quality(3);
+        config.low_submode = Some(NbSubmodeId::Medium);
+        config.high_submode = Some(WbSubmodeId::QuantizedLow);
+        assert_eq!(
+            SpeexCapiConfig::from(&config).to_encoder_config(ModeId::WideBand),
+            Ok(config)
+        );
+    }
+
+    #[test]
+    fn rejects_out_of_range_settings() {
+        let default = SpeexCapiConfig::from(&EncoderConfig::new(ModeId::NarrowBand));
+        let invalid = [
+            SpeexCapiConfig {
+                rate_control: 4,
+                ..default
+            },
+            SpeexCapiConfig {
+                quality: 11,
+                ..default
+            },
+            SpeexCapiConfig {
+                complexity: 0,
+                ..default
+            },
+            SpeexCapiConfig {
+                low_submode: 9,
+                ..default
+            },
+            // Narrowband has no high submode
+            SpeexCapiConfig {
+                high_submode: 1,
+                ..default
+            },
+        ];
+        for config in invalid {
+            assert_eq!(
+                config.to_encoder_config(ModeId::NarrowBand),
+                Err(SpeexCapiStatus::InvalidConfig),
+                "{config:?}"
+            );
+        }
+    }
+}
@@ -0,0 +1,136 @@
+////////////////////////////////////////////////////////////////////////////////
+// Copyright (c) 2023.                                                         /
+// This Source Code Form is subject to the terms of the Mozilla Public License,/
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can    /
+// obtain one at http://mozilla.org/MPL/2.0/.                                  /
+////////////////////////////////////////////////////////////////////////////////
+
+use speex_safe::{DecoderSample, DynamicDecoder, SpeexBits};
+
+use crate::{guard, input, mode_from_id, output, SpeexCapiStatus};
+
+/// A decoder and the bits packets are read into
+pub struct SpeexCapiDecoder {
+    decoder: DynamicDecoder,
+    bits: SpeexBits<'static>,
+    frame_size: usize,
+}
+
+impl SpeexCapiDecoder {
+    /// Decodes every frame of `packet`, or conceals one lost frame if there is
+    /// no packet
+    fn decode<T: DecoderSample>(
+        &mut self,
+        packet: Option<&[u8]>,
+        samples: &mut [T],
+        samples_len: &mut usize,
+    ) -> Result<(), SpeexCapiStatus> {
+        let decoded = self
+            .decoder
+            .decode_packet(&mut self.bits, packet)
+            .map_err(|_| SpeexCapiStatus::CorruptStream)?;
+        if decoded.len() > samples.len() {
+            return Err(SpeexCapiStatus::BufferTooSmall);
+        }
+        samples[..decoded.len()].copy_from_slice(&decoded);
+        *samples_len = decoded.len();
+        Ok(())
+    }
+}
+
+/// Creates a decoder for `mode`. Destroy it with
+/// `speex_capi_decoder_destroy`.
+#[no_mangle]
+pub unsafe extern "C" fn speex_capi_decoder_create(
+    mode: i32,
+    decoder: *mut *mut SpeexCapiDecoder,
+) -> SpeexCapiStatus {
+    guard(|| {
+        let decoder = decoder.as_mut().ok_or(SpeexCapiStatus::NullPointer)?;
+        *decoder = std::ptr::null_mut();
+        let mut inner = DynamicDecoder::new(mode_from_id(mode)?);
+        let frame_size = inner.get_frame_size() as usize;
+        *decoder = Box::into_raw(Box::new(SpeexCapiDecoder {
+            decoder: inner,
+            bits: SpeexBits::new(),
+            frame_size,
+        }));
+        Ok(())
+    })
+}
+
+/// Destroys a decoder. Does nothing if `decoder` is null.
+#[no_mangle]
+pub unsafe extern "C" fn speex_capi_decoder_destroy(decoder: *mut SpeexCapiDecoder) {
+    if !decoder.is_null() {
+        drop(Box::from_raw(decoder));
+    }
+}
+
+/// Gets the number of samples in a frame, or 0 if `decoder` is null
+#[no_mangle]
+pub unsafe extern "C" fn speex_capi_decoder_frame_size(decoder: *const SpeexCapiDecoder) -> usize {
+    decoder.as_ref().map_or(0, |decoder| decoder.frame_size)
+}
+
+/// Sets whether the decoder's perceptual enhancement is used
+#[no_mangle]
+pub unsafe extern "C" fn speex_capi_decoder_set_enhancement(
+    decoder: *mut SpeexCapiDecoder,
+    enhancement: bool,
+) -> SpeexCapiStatus {
+    guard(|| {
+        let decoder = decoder.as_mut().ok_or(SpeexCapiStatus::NullPointer)?;
+        decoder.decoder.set_enhancement(enhancement);
+        Ok(())
+    })
+}
+
+/// Decodes every frame of a packet into samples in the range of an
+/// `int16_t`
+///
+/// If `packet` is null, one frame is produced by the decoder's packet loss
+/// concealment instead. On success the number of samples decoded is stored
+/// in `samples_len`.
+#[no_mangle]
+pub unsafe extern "C" fn speex_capi_decode(
+    decoder: *mut SpeexCapiDecoder,
+    packet: *const u8,
+    packet_len: usize,
+    samples: *mut f32,
+    samples_capacity: usize,
+    samples_len: *mut usize,
+) -> SpeexCapiStatus {
+    guard(|| {
+        let decoder = decoder.as_mut().ok_or(SpeexCapiStatus::NullPointer)?;
+        let packet = if packet.is_null() {
+            None
+        } else {
+            Some(input(packet, packet_len)?)
+        };
+        let (samples, samples_len) = output(samples, samples_capacity, samples_len)?;
+        decoder.decode(packet, samples, samples_len)
+    })
+}
+
+/// Same as `speex_capi_decode`, for 16 bit samples
+#[no_mangle]
+pub unsafe extern "C" fn speex_capi_decode_int(
+    decoder: *mut SpeexCapiDecoder,
+    packet: *const u8,
+    packet_len: usize,
+    samples: *mut i16,
+    samples_capacity: usize,
+    samples_len: *mut usize,
+) -> SpeexCapiStatus {
+    guard(|| {
+        let decoder = decoder.as_mut().ok_or(SpeexCapiStatus::NullPointer)?;
+        let packet = if packet.is_null() {
+            None
+        } else {
+            Some(input(packet, packet_len)?)
+        };
+        let (samples, samples_len) = output(samples, samples_capacity, samples_len)?;
+        decoder.decode(packet, samples, samples_len)
+    })
+}
@@ -0,0 +1,154 @@
+////////////////////////////////////////////////////////////////////////////////
+// Copyright (c) 2023.                                                         /
+// This Source Code Form is subject to the terms of the Mozilla Public License,/
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can    /
+// obtain one at http://mozilla.org/MPL/2.0/.                                  /
+////////////////////////////////////////////////////////////////////////////////
+
+use speex_safe::{DynamicEncoder, EncoderConfig, EncoderError, ModeId, SpeexBits};
+
+use crate::config::SpeexCapiConfig;
+use crate::{guard, input, mode_from_id, output, SpeexCapiStatus};
+
+/// An encoder and the bits it packs frames into
+pub struct SpeexCapiEncoder {
+    encoder: DynamicEncoder,
+    bits: SpeexBits<'static>,
+    mode: ModeId,
+    frame_size: usize,
+}
+
+impl From<EncoderError> for SpeexCapiStatus {
+    fn from(err: EncoderError) -> Self {
+        match err {
+            EncoderError::TooSmallBuffer => SpeexCapiStatus::WrongFrameSize,
+        }
+    }
+}
+
+impl SpeexCapiEncoder {
+    fn check_frame(&self, len: usize) -> Result<(), SpeexCapiStatus> {
+        if len == self.frame_size {
+            Ok(())
+        } else {
+            Err(SpeexCapiStatus::WrongFrameSize)
+        }
+    }
+
+    /// Writes the packed frame to `packet`, leaving it untouched if the frame
+    /// doesn't fit
+    fn write_packet(&mut self, packet: &mut [u8], len: &mut usize) -> Result<(), SpeexCapiStatus> {
+        let bytes = self.bits.num_bytes() as usize;
+        if bytes > packet.len() {
+            return Err(SpeexCapiStatus::BufferTooSmall);
+        }
+        *len = self.bits.write(&mut packet[..bytes]) as usize;
+        Ok(())
+    }
+}
+
+/// Creates an encoder for `mode`, configured with `config`, or with the
+/// library defaults if `config` is null. Destroy it with
+/// `speex_capi_encoder_destroy`.
+#[no_mangle]
+pub unsafe extern "C" fn speex_capi_encoder_create(
+    mode: i32,
+    config: *const SpeexCapiConfig,
+    encoder: *mut *mut SpeexCapiEncoder,
+) -> SpeexCapiStatus {
+    guard(|| {
+        let encoder = encoder.as_mut().ok_or(SpeexCapiStatus::NullPointer)?;
+        *encoder = std::ptr::null_mut();
+        let mode = mode_from_id(mode)?;
+        let config = match config.as_ref() {
+            Some(config) => config.to_encoder_config(mode)?,
+            None => EncoderConfig::new(mode),
+        };
+        let mut inner = config.build().map_err(|_| SpeexCapiStatus::InvalidConfig)?;
+        let frame_size = inner.get_frame_size() as usize;
+        *encoder = Box::into_raw(Box::new(SpeexCapiEncoder {
+            encoder: inner,
+            bits: SpeexBits::new(),
+            mode,
+            frame_size,
+        }));
+        Ok(())
+    })
+}
+
+/// Destroys an encoder. Does nothing if `encoder` is null.
+#[no_mangle]
+pub unsafe extern "C" fn speex_capi_encoder_destroy(encoder: *mut SpeexCapiEncoder) {
+    if !encoder.is_null() {
+        drop(Box::from_raw(encoder));
+    }
+}
+
+/// Gets the number of samples in a frame, or 0 if `encoder` is null
+#[no_mangle]
+pub unsafe extern "C" fn speex_capi_encoder_frame_size(encoder: *const SpeexCapiEncoder) -> usize {
+    encoder.as_ref().map_or(0, |encoder| encoder.frame_size)
+}
+
+/// Replaces every setting of the encoder with those in `config`
+#[no_mangle]
+pub unsafe extern "C" fn speex_capi_encoder_configure(
+    encoder: *mut SpeexCapiEncoder,
+    config: *const SpeexCapiConfig,
+) -> SpeexCapiStatus {
+    guard(|| {
+        let encoder = encoder.as_mut().ok_or(SpeexCapiStatus::NullPointer)?;
+        let config = config.as_ref().ok_or(SpeexCapiStatus::NullPointer)?;
+        config
+            .to_encoder_config(encoder.mode)?
+            .apply_to(&mut encoder.encoder)
+            .map_err(|_| SpeexCapiStatus::InvalidConfig)
+    })
+}
+
+/// Encodes one frame of samples in the range of an `int16_t` into a packet
+///
+/// `samples_len` has to be the frame size. On success the size of the
+/// packet is stored in `packet_len`.
+#[no_mangle]
+pub unsafe extern "C" fn speex_capi_encode(
+    encoder: *mut SpeexCapiEncoder,
+    samples: *const f32,
+    samples_len: usize,
+    packet: *mut u8,
+    packet_capacity: usize,
+    packet_len: *mut usize,
+) -> SpeexCapiStatus {
+    guard(|| {
+        let encoder = encoder.as_mut().ok_or(SpeexCapiStatus::NullPointer)?;
+        let samples = input(samples, samples_len)?;
+        let (packet, packet_len) = output(packet, packet_capacity, packet_len)?;
+        encoder.check_frame(samples.len())?;
+        let mut frame = samples.to_vec();
+        encoder.bits.reset();
+        encoder.encoder.encode(&mut frame, &mut encoder.bits)?;
+        encoder.write_packet(packet, packet_len)
+    })
+}
+
+/// Same as `speex_capi_encode`, for 16 bit samples
+#[no_mangle]
+pub unsafe extern "C" fn speex_capi_encode_int(
+    encoder: *mut SpeexCapiEncoder,
+    samples: *const i16,
+    samples_len: usize,
+    packet: *mut u8,
+    packet_capacity: usize,
+    packet_len: *mut usize,
+) -> SpeexCapiStatus {
+    guard(|| {
+        let encoder = encoder.as_mut().ok_or(SpeexCapiStatus::NullPointer)?;
+        let samples = input(samples, samples_len)?;
+        let (packet, packet_len) = output(packet, packet_capacity, packet_len)?;
+        encoder.check_frame(samples.len())?;
+        let mut frame = samples.to_vec();
+        encoder.bits.reset();
+        encoder.encoder.encode_int(&mut frame, &mut encoder.bits)?;
+        encoder.write_packet(packet, packet_len)
+    })
+}
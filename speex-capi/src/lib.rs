@@ -0,0 +1,176 @@
+////////////////////////////////////////////////////////////////////////////////
+// Copyright (c) 2023.                                                         /
+// This Source Code Form is subject to the terms of the Mozilla Public License,/
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can    /
+// obtain one at http://mozilla.org/MPL/2.0/.                                  /
+////////////////////////////////////////////////////////////////////////////////
+
+//! A small C API over `speex-safe`, for linking as a static or shared library.
+//!
+//! Every function checks its pointers and lengths and reports failures
+//! through a [`SpeexCapiStatus`], so C callers get the same checks as Rust
+//! ones. Panics are caught at the boundary and reported as
+//! [`SpeexCapiStatus::Panic`]. The matching header is `include/speex_capi.h`.
+
+#![allow(clippy::missing_safety_doc)]
+
+pub mod config;
+pub mod decoder;
+pub mod encoder;
+pub mod ogg;
+
+use std::ffi::c_char;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::slice;
+
+use speex_safe::ModeId;
+
+/// Major version of the API, changed on incompatible changes
+pub const SPEEX_CAPI_VERSION_MAJOR: u32 = 1;
+/// Minor version of the API, changed when functions are added
+pub const SPEEX_CAPI_VERSION_MINOR: u32 = 0;
+
+/// Result of every fallible function
+#[repr(C)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum SpeexCapiStatus {
+    Ok = 0,
+    /// A required pointer was null
+    NullPointer = -1,
+    /// The mode isn't one of `SpeexCapiMode`
+    InvalidMode = -2,
+    /// The config has a setting out of range, or settings that conflict
+    InvalidConfig = -3,
+    /// The output buffer can't hold the result. Nothing was written
+    BufferTooSmall = -4,
+    /// The number of samples isn't the frame size of the encoder
+    WrongFrameSize = -5,
+    /// The packet couldn't be decoded
+    CorruptStream = -6,
+    /// Reading or writing a file failed
+    Io = -7,
+    /// A file isn't a supported WAV or Ogg Speex file
+    InvalidFile = -8,
+    /// The library panicked. This is a bug
+    Panic = -9,
+}
+
+/// Modes, as taken by the create functions
+#[repr(C)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum SpeexCapiMode {
+    Narrowband = 0,
+    Wideband = 1,
+    UltraWideband = 2,
+}
+
+/// Gets the version the library was built with, as
+/// `(major << 16) | minor`. Callers should check the major version matches
+/// the one in the header they were compiled with.
+#[no_mangle]
+pub extern "C" fn speex_capi_version() -> u32 {
+    (SPEEX_CAPI_VERSION_MAJOR << 16) | SPEEX_CAPI_VERSION_MINOR
+}
+
+impl SpeexCapiStatus {
+    const ALL: [SpeexCapiStatus; 10] = [
+        SpeexCapiStatus::Ok,
+        SpeexCapiStatus::NullPointer,
+        SpeexCapiStatus::InvalidMode,
+        SpeexCapiStatus::InvalidConfig,
+        SpeexCapiStatus::BufferTooSmall,
+        SpeexCapiStatus::WrongFrameSize,
+        SpeexCapiStatus::CorruptStream,
+        SpeexCapiStatus::Io,
+        SpeexCapiStatus::InvalidFile,
+        SpeexCapiStatus::Panic,
+    ];
+}
+
+/// Gets a static description of a status code
+#[no_mangle]
+pub extern "C" fn speex_capi_status_message(status: i32) -> *const c_char {
+    let status = SpeexCapiStatus::ALL
+        .into_iter()
+        .find(|&known| known as i32 == status);
+    let message: &'static [u8] = match status {
+        None => b"unknown status\0",
+        Some(SpeexCapiStatus::Ok) => b"success\0",
+        Some(SpeexCapiStatus::NullPointer) => b"a required pointer was null\0",
+        Some(SpeexCapiStatus::InvalidMode) => b"invalid mode\0",
+        Some(SpeexCapiStatus::InvalidConfig) => b"invalid encoder config\0",
+        Some(SpeexCapiStatus::BufferTooSmall) => b"output buffer too small\0",
+        Some(SpeexCapiStatus::WrongFrameSize) => b"input is not one frame long\0",
+        Some(SpeexCapiStatus::CorruptStream) => b"corrupt packet\0",
+        Some(SpeexCapiStatus::Io) => b"I/O error\0",
+        Some(SpeexCapiStatus::InvalidFile) => b"invalid or unsupported file\0",
+        Some(SpeexCapiStatus::Panic) => b"internal error\0",
+    };
+    message.as_ptr() as *const c_char
+}
+
+/// Runs the body of an exported function, turning panics into a status
+fn guard(body: impl FnOnce() -> Result<(), SpeexCapiStatus>) -> SpeexCapiStatus {
+    match catch_unwind(AssertUnwindSafe(body)) {
+        Ok(Ok(())) => SpeexCapiStatus::Ok,
+        Ok(Err(status)) => status,
+        Err(_) => SpeexCapiStatus::Panic,
+    }
+}
+
+fn mode_from_id(mode: i32) -> Result<ModeId, SpeexCapiStatus> {
+    match mode {
+        0 => Ok(ModeId::NarrowBand),
+        1 => Ok(ModeId::WideBand),
+        2 => Ok(ModeId::UltraWideBand),
+        _ => Err(SpeexCapiStatus::InvalidMode),
+    }
+}
+
+/// Borrows a C array, which may be null when empty
+unsafe fn input<'a, T>(ptr: *const T, len: usize) -> Result<&'a [T], SpeexCapiStatus> {
+    match (ptr.is_null(), len) {
+        (true, 0) => Ok(&[]),
+        (true, _) => Err(SpeexCapiStatus::NullPointer),
+        (false, _) => Ok(slice::from_raw_parts(ptr, len)),
+    }
+}
+
+/// Borrows a C output array along with where its used length is stored
+unsafe fn output<'a, T>(
+    ptr: *mut T,
+    capacity: usize,
+    len: *mut usize,
+) -> Result<(&'a mut [T], &'a mut usize), SpeexCapiStatus> {
+    let len = len.as_mut().ok_or(SpeexCapiStatus::NullPointer)?;
+    *len = 0;
+    let buffer = match (ptr.is_null(), capacity) {
+        (true, 0) => &mut [],
+        (true, _) => return Err(SpeexCapiStatus::NullPointer),
+        (false, _) => slice::from_raw_parts_mut(ptr, capacity),
+    };
+    Ok((buffer, len))
+}
+
+#[cfg(test)]
+mod test {
+    use std::ffi::CStr;
+
+    use super::*;
+
+    #[test]
+    fn panics_become_a_status() {
+        assert_eq!(guard(|| Ok(())), SpeexCapiStatus::Ok);
+        assert_eq!(guard(|| Err(SpeexCapiStatus::Io)), SpeexCapiStatus::Io);
+        assert_eq!(guard(|| panic!("bug")), SpeexCapiStatus::Panic);
+    }
+
+    #[test]
+    fn status_messages_are_terminated() {
+        let message =
+            unsafe { CStr::from_ptr(speex_capi_status_message(SpeexCapiStatus::Io as i32)) };
+        assert_eq!(message.to_str(), Ok("I/O error"));
+        let message = unsafe { CStr::from_ptr(speex_capi_status_message(42)) };
+        assert_eq!(message.to_str(), Ok("unknown status"));
+    }
+}
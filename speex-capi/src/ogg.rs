@@ -0,0 +1,150 @@
+////////////////////////////////////////////////////////////////////////////////
+// Copyright (c) 2023.                                                         /
+// This Source Code Form is subject to the terms of the Mozilla Public License,/
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can    /
+// obtain one at http://mozilla.org/MPL/2.0/.                                  /
+////////////////////////////////////////////////////////////////////////////////
+
+//! Whole-file conversion between WAV and Ogg Speex
+
+use std::ffi::{c_char, CStr};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind};
+use std::ptr::{null_mut, slice_from_raw_parts_mut};
+
+use speex_safe::wav::{decode_ogg, ogg_to_wav, wav_to_ogg, DecodeOptions, EncodeOptions, WavError};
+use speex_safe::DecoderError;
+
+use crate::{guard, input, SpeexCapiStatus};
+
+/// Audio decoded from an Ogg Speex file, owned by the library until freed
+/// with `speex_capi_audio_free`
+#[repr(C)]
+#[derive(Debug)]
+pub struct SpeexCapiAudio {
+    /// Interleaved 16 bit samples
+    pub samples: *mut i16,
+    pub samples_len: usize,
+    pub sample_rate: u32,
+    pub channels: u16,
+}
+
+impl From<WavError> for SpeexCapiStatus {
+    fn from(err: WavError) -> Self {
+        match err {
+            // The Ogg reader reports malformed and truncated pages this way
+            WavError::Io(err)
+                if matches!(
+                    err.kind(),
+                    ErrorKind::InvalidData | ErrorKind::UnexpectedEof
+                ) =>
+            {
+                SpeexCapiStatus::InvalidFile
+            }
+            WavError::Io(_) => SpeexCapiStatus::Io,
+            WavError::Decoder(DecoderError::CorruptStream | DecoderError::Unknown(_)) => {
+                SpeexCapiStatus::CorruptStream
+            }
+            _ => SpeexCapiStatus::InvalidFile,
+        }
+    }
+}
+
+unsafe fn path<'a>(path: *const c_char) -> Result<&'a str, SpeexCapiStatus> {
+    if path.is_null() {
+        return Err(SpeexCapiStatus::NullPointer);
+    }
+    CStr::from_ptr(path)
+        .to_str()
+        .map_err(|_| SpeexCapiStatus::Io)
+}
+
+/// Encodes a WAV file into an Ogg Speex file
+///
+/// The mode is picked from the sampling rate of the WAV file. `quality` is
+/// between 0 and 10, or -1 for the library default.
+#[no_mangle]
+pub unsafe extern "C" fn speex_capi_wav_to_ogg(
+    wav_path: *const c_char,
+    ogg_path: *const c_char,
+    quality: i32,
+) -> SpeexCapiStatus {
+    guard(|| {
+        let wav_path = path(wav_path)?;
+        let ogg_path = path(ogg_path)?;
+        let options = EncodeOptions {
+            quality: match quality {
+                -1 => None,
+                0..=10 => Some(quality),
+                _ => return Err(SpeexCapiStatus::InvalidConfig),
+            },
+            ..Default::default()
+        };
+        let reader = BufReader::new(File::open(wav_path).map_err(|_| SpeexCapiStatus::Io)?);
+        let writer = BufWriter::new(File::create(ogg_path).map_err(|_| SpeexCapiStatus::Io)?);
+        Ok(wav_to_ogg(reader, writer, &options)?)
+    })
+}
+
+/// Decodes an Ogg Speex file into a 16 bit WAV file
+#[no_mangle]
+pub unsafe extern "C" fn speex_capi_ogg_to_wav(
+    ogg_path: *const c_char,
+    wav_path: *const c_char,
+) -> SpeexCapiStatus {
+    guard(|| {
+        let ogg_path = path(ogg_path)?;
+        let wav_path = path(wav_path)?;
+        let reader = BufReader::new(File::open(ogg_path).map_err(|_| SpeexCapiStatus::Io)?);
+        let writer = BufWriter::new(File::create(wav_path).map_err(|_| SpeexCapiStatus::Io)?);
+        Ok(ogg_to_wav(reader, writer, &DecodeOptions::default())?)
+    })
+}
+
+/// Decodes an Ogg Speex file held in memory
+///
+/// On success `audio` owns the samples, and has to be freed with
+/// `speex_capi_audio_free`.
+#[no_mangle]
+pub unsafe extern "C" fn speex_capi_ogg_decode(
+    data: *const u8,
+    data_len: usize,
+    audio: *mut SpeexCapiAudio,
+) -> SpeexCapiStatus {
+    guard(|| {
+        let audio = audio.as_mut().ok_or(SpeexCapiStatus::NullPointer)?;
+        *audio = SpeexCapiAudio {
+            samples: null_mut(),
+            samples_len: 0,
+            sample_rate: 0,
+            channels: 0,
+        };
+        let data = input(data, data_len)?;
+        let decoded = decode_ogg(data, &DecodeOptions::default())?;
+        let samples = Box::into_raw(decoded.samples.into_boxed_slice());
+        *audio = SpeexCapiAudio {
+            samples: samples as *mut i16,
+            samples_len: samples.len(),
+            sample_rate: decoded.sample_rate,
+            channels: decoded.channels,
+        };
+        Ok(())
+    })
+}
+
+/// Frees the samples of audio from `speex_capi_ogg_decode`, leaving it
+/// empty. Does nothing if `audio` is null or already freed.
+#[no_mangle]
+pub unsafe extern "C" fn speex_capi_audio_free(audio: *mut SpeexCapiAudio) {
+    let Some(audio) = audio.as_mut() else {
+        return;
+    };
+    if !audio.samples.is_null() {
+        drop(Box::from_raw(slice_from_raw_parts_mut(
+            audio.samples,
+            audio.samples_len,
+        )));
+    }
+    audio.samples = null_mut();
+    audio.samples_len = 0;
+}
@@ -0,0 +1,291 @@
+/*
+ * Copyright (c) 2023.
+ * This Source Code Form is subject to the terms of the Mozilla Public License,
+ * v. 2.0. If a copy of the MPL was not distributed with this file, You can
+ * obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+/* Exercises the C API the way a C program would, run by tests/c_api.rs */
+
+#include <math.h>
+#include <stdio.h>
+#include <string.h>
+
+#include "speex_capi.h"
+
+static int failures = 0;
+
+#define CHECK(cond)                                                        \
+  do {                                                                     \
+    if (!(cond)) {                                                         \
+      fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__, __LINE__,     \
+              #cond);                                                      \
+      failures++;                                                          \
+    }                                                                      \
+  } while (0)
+
+#define CHECK_STATUS(expr, expected)                                       \
+  do {                                                                     \
+    SpeexCapiStatus status_ = (expr);                                      \
+    if (status_ != (expected)) {                                           \
+      fprintf(stderr, "%s:%d: %s returned %d (%s)\n", __FILE__, __LINE__,  \
+              #expr, (int)status_, speex_capi_status_message(status_));    \
+      failures++;                                                          \
+    }                                                                      \
+  } while (0)
+
+#define FRAMES 50
+
+static void sine(int16_t *samples, size_t len, size_t offset, int rate) {
+  for (size_t i = 0; i < len; i++) {
+    double t = (double)(offset + i) / rate;
+    samples[i] = (int16_t)(8000.0 * sin(2.0 * 3.14159265358979 * 440.0 * t));
+  }
+}
+
+static void test_version(void) {
+  uint32_t version = speex_capi_version();
+  CHECK(version >> 16 == SPEEX_CAPI_VERSION_MAJOR);
+  CHECK((version & 0xffff) >= SPEEX_CAPI_VERSION_MINOR);
+  CHECK(strcmp(speex_capi_status_message(SPEEX_CAPI_STATUS_OK), "success") == 0);
+  CHECK(strcmp(speex_capi_status_message(1234), "unknown status") == 0);
+}
+
+static void test_config(void) {
+  SpeexCapiConfig config;
+  CHECK_STATUS(speex_capi_config_default(SPEEX_CAPI_MODE_WIDEBAND, &config),
+               SPEEX_CAPI_STATUS_OK);
+  CHECK(config.rate_control == SPEEX_CAPI_RATE_CONTROL_QUALITY);
+  CHECK(config.complexity >= 1 && config.complexity <= 10);
+  CHECK_STATUS(speex_capi_config_default(7, &config),
+               SPEEX_CAPI_STATUS_INVALID_MODE);
+  CHECK_STATUS(speex_capi_config_default(SPEEX_CAPI_MODE_NARROWBAND, NULL),
+               SPEEX_CAPI_STATUS_NULL_POINTER);
+
+  SpeexCapiEncoder *encoder = NULL;
+  speex_capi_config_default(SPEEX_CAPI_MODE_NARROWBAND, &config);
+  config.quality = 11;
+  CHECK_STATUS(speex_capi_encoder_create(SPEEX_CAPI_MODE_NARROWBAND, &config,
+                                         &encoder),
+               SPEEX_CAPI_STATUS_INVALID_CONFIG);
+  CHECK(encoder == NULL);
+
+  speex_capi_config_default(SPEEX_CAPI_MODE_NARROWBAND, &config);
+  config.rate_control = SPEEX_CAPI_RATE_CONTROL_VBR;
+  config.vbr_quality = 4.0f;
+  CHECK_STATUS(speex_capi_encoder_create(SPEEX_CAPI_MODE_NARROWBAND, &config,
+                                         &encoder),
+               SPEEX_CAPI_STATUS_OK);
+  config.high_submode = 1;
+  CHECK_STATUS(speex_capi_encoder_configure(encoder, &config),
+               SPEEX_CAPI_STATUS_INVALID_CONFIG);
+  speex_capi_encoder_destroy(encoder);
+}
+
+static void test_round_trip(int32_t mode, size_t expected_frame_size) {
+  SpeexCapiEncoder *encoder = NULL;
+  SpeexCapiDecoder *decoder = NULL;
+  CHECK_STATUS(speex_capi_encoder_create(mode, NULL, &encoder),
+               SPEEX_CAPI_STATUS_OK);
+  CHECK_STATUS(speex_capi_decoder_create(mode, &decoder), SPEEX_CAPI_STATUS_OK);
+  CHECK_STATUS(speex_capi_decoder_set_enhancement(decoder, true),
+               SPEEX_CAPI_STATUS_OK);
+
+  size_t frame_size = speex_capi_encoder_frame_size(encoder);
+  CHECK(frame_size == expected_frame_size);
+  CHECK(speex_capi_decoder_frame_size(decoder) == frame_size);
+
+  int16_t input[640];
+  int16_t output[640];
+  float output_float[640];
+  uint8_t packet[256];
+  double energy = 0.0;
+  for (size_t frame = 0; frame < FRAMES; frame++) {
+    size_t packet_len = 0;
+    size_t samples_len = 0;
+    sine(input, frame_size, frame * frame_size, (int)frame_size * 50);
+    CHECK_STATUS(speex_capi_encode_int(encoder, input, frame_size, packet,
+                                       sizeof(packet), &packet_len),
+                 SPEEX_CAPI_STATUS_OK);
+    CHECK(packet_len > 0);
+    if (frame % 2 == 0) {
+      CHECK_STATUS(speex_capi_decode_int(decoder, packet, packet_len, output,
+                                         640, &samples_len),
+                   SPEEX_CAPI_STATUS_OK);
+    } else {
+      CHECK_STATUS(speex_capi_decode(decoder, packet, packet_len, output_float,
+                                     640, &samples_len),
+                   SPEEX_CAPI_STATUS_OK);
+      for (size_t i = 0; i < samples_len; i++) {
+        output[i] = (int16_t)output_float[i];
+      }
+    }
+    CHECK(samples_len == frame_size);
+    if (frame >= FRAMES / 2) {
+      for (size_t i = 0; i < samples_len; i++) {
+        energy += (double)output[i] * output[i];
+      }
+    }
+  }
+  /* The tone survives coding, rather than decoding to silence */
+  CHECK(energy / (frame_size * FRAMES / 2) > 1000.0 * 1000.0);
+
+  /* A lost packet is concealed with a frame */
+  size_t samples_len = 0;
+  CHECK_STATUS(speex_capi_decode_int(decoder, NULL, 0, output, 640,
+                                     &samples_len),
+               SPEEX_CAPI_STATUS_OK);
+  CHECK(samples_len == frame_size);
+
+  speex_capi_encoder_destroy(encoder);
+  speex_capi_decoder_destroy(decoder);
+}
+
+static void test_errors(void) {
+  SpeexCapiEncoder *encoder = NULL;
+  SpeexCapiDecoder *decoder = NULL;
+  CHECK_STATUS(speex_capi_encoder_create(3, NULL, &encoder),
+               SPEEX_CAPI_STATUS_INVALID_MODE);
+  CHECK_STATUS(speex_capi_decoder_create(-1, &decoder),
+               SPEEX_CAPI_STATUS_INVALID_MODE);
+  CHECK_STATUS(speex_capi_encoder_create(SPEEX_CAPI_MODE_NARROWBAND, NULL, NULL),
+               SPEEX_CAPI_STATUS_NULL_POINTER);
+  CHECK(speex_capi_encoder_frame_size(NULL) == 0);
+  speex_capi_encoder_destroy(NULL);
+  speex_capi_decoder_destroy(NULL);
+
+  speex_capi_encoder_create(SPEEX_CAPI_MODE_NARROWBAND, NULL, &encoder);
+  speex_capi_decoder_create(SPEEX_CAPI_MODE_NARROWBAND, &decoder);
+
+  int16_t input[160] = {0};
+  int16_t output[160];
+  uint8_t packet[256];
+  size_t packet_len = 99;
+  size_t samples_len = 99;
+  CHECK_STATUS(speex_capi_encode_int(encoder, input, 159, packet,
+                                     sizeof(packet), &packet_len),
+               SPEEX_CAPI_STATUS_WRONG_FRAME_SIZE);
+  CHECK(packet_len == 0);
+  CHECK_STATUS(speex_capi_encode_int(encoder, input, 160, packet, 2,
+                                     &packet_len),
+               SPEEX_CAPI_STATUS_BUFFER_TOO_SMALL);
+  CHECK_STATUS(speex_capi_encode_int(encoder, NULL, 160, packet,
+                                     sizeof(packet), &packet_len),
+               SPEEX_CAPI_STATUS_NULL_POINTER);
+  CHECK_STATUS(speex_capi_encode_int(encoder, input, 160, packet,
+                                     sizeof(packet), NULL),
+               SPEEX_CAPI_STATUS_NULL_POINTER);
+
+  CHECK_STATUS(speex_capi_encode_int(encoder, input, 160, packet,
+                                     sizeof(packet), &packet_len),
+               SPEEX_CAPI_STATUS_OK);
+  CHECK_STATUS(speex_capi_decode_int(decoder, packet, packet_len, output, 80,
+                                     &samples_len),
+               SPEEX_CAPI_STATUS_BUFFER_TOO_SMALL);
+  CHECK(samples_len == 0);
+  CHECK_STATUS(speex_capi_decode_int(NULL, packet, packet_len, output, 160,
+                                     &samples_len),
+               SPEEX_CAPI_STATUS_NULL_POINTER);
+
+  speex_capi_encoder_destroy(encoder);
+  speex_capi_decoder_destroy(decoder);
+}
+
+static void put_u16(FILE *file, uint16_t value) {
+  fputc(value & 0xff, file);
+  fputc(value >> 8, file);
+}
+
+static void put_u32(FILE *file, uint32_t value) {
+  put_u16(file, value & 0xffff);
+  put_u16(file, value >> 16);
+}
+
+static int write_wav(const char *path, int rate, size_t len) {
+  FILE *file = fopen(path, "wb");
+  if (file == NULL) {
+    return 0;
+  }
+  fwrite("RIFF", 1, 4, file);
+  put_u32(file, (uint32_t)(36 + len * 2));
+  fwrite("WAVEfmt ", 1, 8, file);
+  put_u32(file, 16);
+  put_u16(file, 1);
+  put_u16(file, 1);
+  put_u32(file, (uint32_t)rate);
+  put_u32(file, (uint32_t)rate * 2);
+  put_u16(file, 2);
+  put_u16(file, 16);
+  fwrite("data", 1, 4, file);
+  put_u32(file, (uint32_t)(len * 2));
+  for (size_t i = 0; i < len; i++) {
+    int16_t sample;
+    sine(&sample, 1, i, rate);
+    put_u16(file, (uint16_t)sample);
+  }
+  return fclose(file) == 0;
+}
+
+static void test_ogg(const char *dir) {
+  char wav_path[4096];
+  char ogg_path[4096];
+  char out_path[4096];
+  snprintf(wav_path, sizeof(wav_path), "%s/in.wav", dir);
+  snprintf(ogg_path, sizeof(ogg_path), "%s/out.spx", dir);
+  snprintf(out_path, sizeof(out_path), "%s/out.wav", dir);
+
+  CHECK(write_wav(wav_path, 16000, 16000));
+  CHECK_STATUS(speex_capi_wav_to_ogg(wav_path, ogg_path, 8),
+               SPEEX_CAPI_STATUS_OK);
+  CHECK_STATUS(speex_capi_wav_to_ogg(wav_path, ogg_path, 11),
+               SPEEX_CAPI_STATUS_INVALID_CONFIG);
+  CHECK_STATUS(speex_capi_ogg_to_wav(ogg_path, out_path), SPEEX_CAPI_STATUS_OK);
+  CHECK_STATUS(speex_capi_ogg_to_wav("/nonexistent/file.spx", out_path),
+               SPEEX_CAPI_STATUS_IO);
+  CHECK_STATUS(speex_capi_ogg_to_wav(wav_path, out_path),
+               SPEEX_CAPI_STATUS_INVALID_FILE);
+
+  static uint8_t data[1 << 20];
+  FILE *file = fopen(ogg_path, "rb");
+  CHECK(file != NULL);
+  if (file == NULL) {
+    return;
+  }
+  size_t data_len = fread(data, 1, sizeof(data), file);
+  fclose(file);
+
+  SpeexCapiAudio audio;
+  CHECK_STATUS(speex_capi_ogg_decode(data, data_len, &audio),
+               SPEEX_CAPI_STATUS_OK);
+  CHECK(audio.sample_rate == 16000);
+  CHECK(audio.channels == 1);
+  CHECK(audio.samples != NULL);
+  CHECK(audio.samples_len >= 16000);
+  speex_capi_audio_free(&audio);
+  CHECK(audio.samples == NULL);
+  CHECK(audio.samples_len == 0);
+  speex_capi_audio_free(&audio);
+
+  CHECK_STATUS(speex_capi_ogg_decode(data, 10, &audio),
+               SPEEX_CAPI_STATUS_INVALID_FILE);
+  CHECK(audio.samples == NULL);
+}
+
+int main(int argc, char **argv) {
+  if (argc != 2) {
+    fprintf(stderr, "usage: %s <scratch dir>\n", argv[0]);
+    return 2;
+  }
+  test_version();
+  test_config();
+  test_round_trip(SPEEX_CAPI_MODE_NARROWBAND, 160);
+  test_round_trip(SPEEX_CAPI_MODE_WIDEBAND, 320);
+  test_round_trip(SPEEX_CAPI_MODE_ULTRA_WIDEBAND, 640);
+  test_errors();
+  test_ogg(argv[1]);
+  if (failures != 0) {
+    fprintf(stderr, "%d checks failed\n", failures);
+    return 1;
+  }
+  return 0;
+}
@@ -0,0 +1,59 @@
+////////////////////////////////////////////////////////////////////////////////
+// Copyright (c) 2023.                                                         /
+// This Source Code Form is subject to the terms of the Mozilla Public License,/
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can    /
+// obtain one at http://mozilla.org/MPL/2.0/.                                  /
+////////////////////////////////////////////////////////////////////////////////
+
+//! Compiles tests/c/test_api.c against the header and the static library,
+//! and runs it.
+
+#![cfg(unix)]
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// The `deps` directory this test runs from, where cargo also puts the
+/// static library
+fn deps_dir() -> PathBuf {
+    let exe = std::env::current_exe().unwrap();
+    exe.parent().unwrap().to_path_buf()
+}
+
+#[test]
+fn c_test_program() {
+    let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let library = deps_dir().join("libspeex_capi.a");
+    assert!(library.exists(), "{} was not built", library.display());
+
+    let scratch = Path::new(env!("CARGO_TARGET_TMPDIR")).join("speex-capi");
+    fs::create_dir_all(&scratch).unwrap();
+    let program = scratch.join("test_api");
+
+    let compiler = std::env::var("CC").unwrap_or_else(|_| "cc".to_string());
+    let output = Command::new(&compiler)
+        .arg("-std=c99")
+        .arg("-Wall")
+        .arg("-Werror")
+        .arg("-I")
+        .arg(manifest_dir.join("include"))
+        .arg(manifest_dir.join("tests/c/test_api.c"))
+        .arg(&library)
+        .args(["-lpthread", "-ldl", "-lm", "-o"])
+        .arg(&program)
+        .output()
+        .unwrap_or_else(|err| panic!("couldn't run {compiler}: {err}"));
+    assert!(
+        output.status.success(),
+        "compiling test_api.c failed:\n{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let output = Command::new(&program).arg(&scratch).output().unwrap();
+    assert!(
+        output.status.success(),
+        "test_api failed:\n{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
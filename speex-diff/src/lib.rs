@@ -0,0 +1,266 @@
+////////////////////////////////////////////////////////////////////////////////
+// Copyright (c) 2023.                                                         /
+// This Source Code Form is subject to the terms of the Mozilla Public License,/
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can    /
+// obtain one at http://mozilla.org/MPL/2.0/.                                  /
+////////////////////////////////////////////////////////////////////////////////
+
+//! Differential testing of the pure-Rust codec in `speex-rs` against
+//! libspeex, through `speex-safe`.
+//!
+//! Each [`Case`] encodes a signal with libspeex and, where `speex-rs` has an
+//! encoder for the mode, with `speex-rs` too, comparing the packets frame by
+//! frame. The libspeex packets are then decoded by both implementations and
+//! the audio compared. The first frame that differs is reported along with
+//! the coded parameter it differs in.
+
+pub mod signals;
+#[path = "../../speex-safe/tests/support/mod.rs"]
+mod support;
+
+use std::fmt::{Display, Formatter};
+
+use speex_rs::{nb_frame_layout, Bits, Mode, NbDecoder, NbEncoder, Parameter, SbDecoder};
+use speex_safe::{DynamicDecoder, DynamicEncoder, SpeexBits};
+
+/// Largest packet either encoder can produce for one frame
+const MAX_PACKET_SIZE: usize = 200;
+/// Lowest SNR, in dB, at which two decoded frames are considered identical
+const MIN_FRAME_SNR: f64 = 60.0;
+
+/// Settings shared by both implementations for one run
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Case {
+    pub mode: Mode,
+    pub quality: i32,
+    pub complexity: i32,
+}
+
+impl Display for Case {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:?} quality {} complexity {}",
+            self.mode, self.quality, self.complexity
+        )
+    }
+}
+
+/// Where the two implementations first disagree
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Divergence {
+    /// The encoders packed different bits
+    Bitstream {
+        frame: usize,
+        parameter: Parameter,
+        subframe: Option<usize>,
+    },
+    /// The decoders produced different audio from the same packet
+    Pcm { frame: usize, snr: f64 },
+}
+
+impl Display for Divergence {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Divergence::Bitstream {
+                frame,
+                parameter,
+                subframe: Some(subframe),
+            } => {
+                write!(
+                    f,
+                    "encoded frame {frame} differs in the {parameter} of subframe {subframe}"
+                )
+            }
+            Divergence::Bitstream {
+                frame, parameter, ..
+            } => write!(f, "encoded frame {frame} differs in the {parameter}"),
+            Divergence::Pcm { frame, snr } => {
+                write!(f, "decoded frame {frame} differs, SNR {snr:.1} dB")
+            }
+        }
+    }
+}
+
+/// Encodes `signal` with libspeex, one packet per frame
+pub fn encode_libspeex(case: Case, signal: &[f32]) -> Vec<Vec<u8>> {
+    let mut encoder = DynamicEncoder::new(case.mode);
+    encoder.set_quality(case.quality);
+    encoder.set_complexity(case.complexity);
+    support::encode_packets(&mut encoder, signal)
+}
+
+/// Encodes `signal` with `speex-rs`, one packet per frame, or returns `None`
+/// if there is no pure-Rust encoder for the mode yet
+pub fn encode_rust(case: Case, signal: &[f32]) -> Option<Vec<Vec<u8>>> {
+    if case.mode != Mode::NarrowBand {
+        return None;
+    }
+    let mut encoder = NbEncoder::new();
+    encoder.set_quality(case.quality);
+    encoder.set_complexity(case.complexity);
+    let mut bits = Bits::new();
+    let packets = signal
+        .chunks_exact(case.mode.get_frame_size() as usize)
+        .map(|frame| {
+            bits.reset();
+            encoder
+                .encode(frame, &mut bits)
+                .expect("frames are always full");
+            let mut packet = vec![0u8; MAX_PACKET_SIZE];
+            let len = bits.write(&mut packet);
+            packet.truncate(len);
+            packet
+        })
+        .collect();
+    Some(packets)
+}
+
+/// Decodes `packets` with libspeex
+pub fn decode_libspeex(mode: Mode, packets: &[Vec<u8>]) -> Vec<f32> {
+    let mut decoder = DynamicDecoder::new(mode);
+    let frame_size = mode.get_frame_size() as usize;
+    let mut out = vec![0f32; frame_size];
+    let mut decoded = Vec::with_capacity(packets.len() * frame_size);
+    for packet in packets {
+        let mut bits = SpeexBits::new();
+        bits.read_from(&mut packet.clone());
+        // A rejected packet leaves its frame silent, which the comparison
+        // then reports
+        if decoder.decode(&mut bits, &mut out).is_err() {
+            out.fill(0.0);
+        }
+        decoded.extend_from_slice(&out);
+    }
+    decoded
+}
+
+/// Decodes `packets` with `speex-rs`
+pub fn decode_rust(mode: Mode, packets: &[Vec<u8>]) -> Vec<f32> {
+    let mut nb = NbDecoder::new();
+    let mut sb = match mode {
+        Mode::NarrowBand => None,
+        Mode::WideBand => Some(SbDecoder::wideband()),
+        Mode::UltraWideBand => Some(SbDecoder::ultra_wideband()),
+    };
+    let frame_size = mode.get_frame_size() as usize;
+    let mut out = vec![0f32; frame_size];
+    let mut decoded = Vec::with_capacity(packets.len() * frame_size);
+    for packet in packets {
+        let mut bits = Bits::from_bytes(packet);
+        let result = match &mut sb {
+            Some(sb) => sb.decode(&mut bits, &mut out),
+            None => nb.decode(&mut bits, &mut out),
+        };
+        if result.is_err() {
+            out.fill(0.0);
+        }
+        decoded.extend_from_slice(&out);
+    }
+    decoded
+}
+
+/// Finds the first packet of `actual` that differs from `expected`
+pub fn compare_packets(actual: &[Vec<u8>], expected: &[Vec<u8>]) -> Option<Divergence> {
+    let frame = actual
+        .iter()
+        .zip(expected)
+        .position(|(actual, expected)| actual != expected)?;
+    let (parameter, subframe) = differing_field(&actual[frame], &expected[frame]);
+    Some(Divergence::Bitstream {
+        frame,
+        parameter,
+        subframe,
+    })
+}
+
+/// Walks two narrowband packets field by field to find the first coded
+/// parameter they disagree on.
+///
+/// Anything the layout does not describe, such as in-band messages or
+/// trailing bits, is put down to the header.
+fn differing_field(actual: &[u8], expected: &[u8]) -> (Parameter, Option<usize>) {
+    let mut actual = Bits::from_bytes(actual);
+    let mut expected = Bits::from_bytes(expected);
+    let header = expected.peek_unsigned(5);
+    if actual.peek_unsigned(5) != header {
+        return (Parameter::Header, None);
+    }
+    for field in nb_frame_layout(header as usize).unwrap_or_default() {
+        if actual.unpack_unsigned(field.bits) != expected.unpack_unsigned(field.bits) {
+            return (field.parameter, field.subframe);
+        }
+    }
+    (Parameter::Header, None)
+}
+
+/// Finds the first frame of `actual` too far from `expected`
+pub fn compare_pcm(frame_size: usize, actual: &[f32], expected: &[f32]) -> Option<Divergence> {
+    actual
+        .chunks(frame_size)
+        .zip(expected.chunks(frame_size))
+        .map(|(actual, expected)| support::snr(expected, actual))
+        .enumerate()
+        .find(|&(_, snr)| snr <= MIN_FRAME_SNR)
+        .map(|(frame, snr)| Divergence::Pcm { frame, snr })
+}
+
+/// Runs `signal` through both implementations with the settings of `case`,
+/// comparing the bitstreams first and then the decoded audio
+pub fn run(case: Case, signal: &[f32]) -> Result<(), Divergence> {
+    let expected = encode_libspeex(case, signal);
+    if let Some(actual) = encode_rust(case, signal) {
+        if let Some(divergence) = compare_packets(&actual, &expected) {
+            return Err(divergence);
+        }
+    }
+
+    let reference = decode_libspeex(case.mode, &expected);
+    let decoded = decode_rust(case.mode, &expected);
+    match compare_pcm(case.mode.get_frame_size() as usize, &decoded, &reference) {
+        Some(divergence) => Err(divergence),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use speex_rs::Field;
+
+    use super::*;
+
+    /// Packs a submode 5 frame, changing one field from the default
+    fn packet(changed: usize) -> Vec<u8> {
+        let mut bits = Bits::new();
+        let layout: Vec<Field> = nb_frame_layout(5).unwrap();
+        for (i, field) in layout.iter().enumerate() {
+            let value = match i {
+                0 => 5,
+                _ if i == changed => 1,
+                _ => 0,
+            };
+            bits.pack(value, field.bits);
+        }
+        let mut packet = vec![0u8; MAX_PACKET_SIZE];
+        let len = bits.write(&mut packet);
+        packet.truncate(len);
+        packet
+    }
+
+    #[test]
+    fn reports_the_differing_parameter() {
+        let expected = vec![packet(0); 3];
+        let mut actual = expected.clone();
+        // Header, LSP, gain, then the first subframe's pitch
+        actual[2] = packet(3);
+        assert_eq!(
+            compare_packets(&actual, &expected),
+            Some(Divergence::Bitstream {
+                frame: 2,
+                parameter: Parameter::Pitch,
+                subframe: Some(0),
+            })
+        );
+        assert_eq!(compare_packets(&expected, &expected), None);
+    }
+}
@@ -0,0 +1,119 @@
+////////////////////////////////////////////////////////////////////////////////
+// Copyright (c) 2023.                                                         /
+// This Source Code Form is subject to the terms of the Mozilla Public License,/
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can    /
+// obtain one at http://mozilla.org/MPL/2.0/.                                  /
+////////////////////////////////////////////////////////////////////////////////
+
+//! Synthetic speech-like test signals.
+//!
+//! Every generator is deterministic so that a divergence can be reproduced
+//! from the name of the signal alone.
+
+use std::f32::consts::PI;
+
+/// A small linear congruential generator giving white noise in [-1, 1)
+struct Noise(u32);
+
+impl Noise {
+    fn next(&mut self) -> f32 {
+        self.0 = self.0.wrapping_mul(1103515245).wrapping_add(12345);
+        (self.0 >> 16) as f32 / 32768.0 - 1.0
+    }
+}
+
+/// A sine sweeping from 100 Hz up to most of the band and back down
+pub fn chirp(sampling_rate: usize, len: usize) -> Vec<f32> {
+    let top = 0.45 * sampling_rate as f32;
+    let mut phase = 0f32;
+    (0..len)
+        .map(|n| {
+            let progress = n as f32 / len as f32;
+            let sweep = 1.0 - (2.0 * progress - 1.0).abs();
+            phase += 2.0 * PI * (100.0 + (top - 100.0) * sweep) / sampling_rate as f32;
+            phase %= 2.0 * PI;
+            8000.0 * phase.sin()
+        })
+        .collect()
+}
+
+/// Glottal-like pulses with a gliding pitch, shaped by two formant
+/// resonators
+pub fn pulse_train(sampling_rate: usize, len: usize) -> Vec<f32> {
+    let rate = sampling_rate as f32;
+    let mut noise = Noise(2718);
+    let mut phase = 0f32;
+    let mut formants = [
+        Resonator::new(700.0, 130.0, rate),
+        Resonator::new(1220.0, 70.0, rate),
+    ];
+    (0..len)
+        .map(|n| {
+            let t = n as f32 / rate;
+            let pitch = 110.0 + 60.0 * (2.0 * PI * 1.5 * t).sin();
+            phase += pitch / rate;
+            let pulse = if phase >= 1.0 {
+                phase -= 1.0;
+                6000.0
+            } else {
+                0.0
+            };
+            let excitation = pulse + 20.0 * noise.next();
+            formants
+                .iter_mut()
+                .map(|formant| formant.filter(excitation))
+                .sum()
+        })
+        .collect()
+}
+
+/// Bursts of white noise separated by near silence
+pub fn noise_bursts(sampling_rate: usize, len: usize) -> Vec<f32> {
+    let burst = sampling_rate / 10;
+    let mut noise = Noise(31415);
+    (0..len)
+        .map(|n| {
+            let level = if (n / burst).is_multiple_of(2) {
+                4000.0
+            } else {
+                3.0
+            };
+            level * noise.next()
+        })
+        .collect()
+}
+
+/// Every generator in turn, `frames` frames of `frame_size` samples each
+pub fn speech_like(sampling_rate: usize, frame_size: usize, frames: usize) -> Vec<f32> {
+    let len = frame_size * frames / 3;
+    let mut signal = chirp(sampling_rate, len);
+    signal.extend(pulse_train(sampling_rate, len));
+    signal.extend(noise_bursts(sampling_rate, frame_size * frames - 2 * len));
+    signal
+}
+
+/// Two-pole resonator modelling a formant
+struct Resonator {
+    a1: f32,
+    a2: f32,
+    gain: f32,
+    mem: [f32; 2],
+}
+
+impl Resonator {
+    fn new(frequency: f32, bandwidth: f32, sampling_rate: f32) -> Self {
+        let r = (-PI * bandwidth / sampling_rate).exp();
+        Self {
+            a1: 2.0 * r * (2.0 * PI * frequency / sampling_rate).cos(),
+            a2: -r * r,
+            gain: 1.0 - r,
+            mem: [0.0; 2],
+        }
+    }
+
+    fn filter(&mut self, x: f32) -> f32 {
+        let y = self.gain * x + self.a1 * self.mem[0] + self.a2 * self.mem[1];
+        self.mem = [y, self.mem[0]];
+        y
+    }
+}
@@ -0,0 +1,57 @@
+////////////////////////////////////////////////////////////////////////////////
+// Copyright (c) 2023.                                                         /
+// This Source Code Form is subject to the terms of the Mozilla Public License,/
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can    /
+// obtain one at http://mozilla.org/MPL/2.0/.                                  /
+////////////////////////////////////////////////////////////////////////////////
+
+//! Compares speex-rs with libspeex at every quality and complexity.
+
+use speex_diff::signals::speech_like;
+use speex_diff::{run, Case};
+use speex_rs::Mode;
+
+/// Frames of each signal, long enough for every generator to get a turn
+const FRAMES: usize = 60;
+
+/// Runs every quality and complexity of `mode`, listing the first
+/// divergence of each failing case
+fn assert_matches_libspeex(mode: Mode) {
+    let signal = speech_like(
+        mode.get_sampling_rate() as usize,
+        mode.get_frame_size() as usize,
+        FRAMES,
+    );
+    let failures: Vec<String> = (0..=10)
+        .flat_map(|quality| {
+            (0..=10).map(move |complexity| {
+                Case {
+                    mode,
+                    quality,
+                    complexity,
+                }
+            })
+        })
+        .filter_map(|case| {
+            run(case, &signal)
+                .err()
+                .map(|divergence| format!("{case}: {divergence}"))
+        })
+        .collect();
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+#[test]
+fn narrowband_matches_libspeex() {
+    assert_matches_libspeex(Mode::NarrowBand);
+}
+
+#[test]
+fn wideband_matches_libspeex() {
+    assert_matches_libspeex(Mode::WideBand);
+}
+
+#[test]
+fn ultra_wideband_matches_libspeex() {
+    assert_matches_libspeex(Mode::UltraWideBand);
+}
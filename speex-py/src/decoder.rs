@@ -0,0 +1,100 @@
+////////////////////////////////////////////////////////////////////////////////
+// Copyright (c) 2023.                                                         /
+// This Source Code Form is subject to the terms of the Mozilla Public License,/
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can    /
+// obtain one at http://mozilla.org/MPL/2.0/.                                  /
+////////////////////////////////////////////////////////////////////////////////
+
+use std::sync::Mutex;
+
+use numpy::PyArray1;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use speex_safe::{DecoderError, DecoderSample, DynamicDecoder, ModeId, SpeexBits};
+
+use crate::{lock, Mode};
+
+struct DecoderState {
+    decoder: DynamicDecoder,
+    bits: SpeexBits<'static>,
+}
+
+impl DecoderState {
+    fn decode<T: DecoderSample>(&mut self, packet: Option<&[u8]>) -> Result<Vec<T>, DecoderError> {
+        self.decoder.decode_packet(&mut self.bits, packet)
+    }
+}
+
+/// Decodes packets into frames of audio
+#[pyclass(module = "speex_py", frozen)]
+pub struct Decoder {
+    state: Mutex<DecoderState>,
+    mode: Mode,
+    frame_size: usize,
+}
+
+#[pymethods]
+impl Decoder {
+    #[new]
+    #[pyo3(signature = (mode, enhancement = true))]
+    fn new(mode: Mode, enhancement: bool) -> Self {
+        let mut decoder = DynamicDecoder::new(ModeId::from(mode));
+        decoder.set_enhancement(enhancement);
+        let frame_size = decoder.get_frame_size() as usize;
+        Self {
+            state: Mutex::new(DecoderState {
+                decoder,
+                bits: SpeexBits::new(),
+            }),
+            mode,
+            frame_size,
+        }
+    }
+
+    #[getter]
+    fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// Number of samples in a frame
+    #[getter]
+    fn frame_size(&self) -> usize {
+        self.frame_size
+    }
+
+    /// Sets whether the perceptual enhancement is used
+    fn set_enhancement(&self, enhancement: bool) {
+        lock(&self.state).decoder.set_enhancement(enhancement);
+    }
+
+    fn get_enhancement(&self) -> bool {
+        lock(&self.state).decoder.get_enhancement()
+    }
+
+    /// Decodes every frame of a packet into an array of `float32`. If the
+    /// packet is `None`, one frame is made up by packet loss concealment.
+    #[pyo3(signature = (packet))]
+    fn decode<'py>(
+        &self,
+        py: Python<'py>,
+        packet: Option<&[u8]>,
+    ) -> PyResult<Bound<'py, PyArray1<f32>>> {
+        let samples = py
+            .detach(|| lock(&self.state).decode::<f32>(packet))
+            .map_err(|err| PyValueError::new_err(err.to_string()))?;
+        Ok(PyArray1::from_vec(py, samples))
+    }
+
+    /// Same as `decode`, returning an array of `int16`
+    #[pyo3(signature = (packet))]
+    fn decode_int<'py>(
+        &self,
+        py: Python<'py>,
+        packet: Option<&[u8]>,
+    ) -> PyResult<Bound<'py, PyArray1<i16>>> {
+        let samples = py
+            .detach(|| lock(&self.state).decode::<i16>(packet))
+            .map_err(|err| PyValueError::new_err(err.to_string()))?;
+        Ok(PyArray1::from_vec(py, samples))
+    }
+}
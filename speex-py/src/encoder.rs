@@ -0,0 +1,195 @@
+////////////////////////////////////////////////////////////////////////////////
+// Copyright (c) 2023.                                                         /
+// This Source Code Form is subject to the terms of the Mozilla Public License,/
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can    /
+// obtain one at http://mozilla.org/MPL/2.0/.                                  /
+////////////////////////////////////////////////////////////////////////////////
+
+use std::sync::Mutex;
+
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+use speex_safe::{DynamicEncoder, ModeId, SpeexBits};
+
+use crate::{lock, Mode, Samples};
+
+struct EncoderState {
+    encoder: DynamicEncoder,
+    bits: SpeexBits<'static>,
+}
+
+impl EncoderState {
+    /// Encodes one frame, which has to be exactly a frame long
+    fn encode_frame(&mut self, frame: &mut Samples) -> Vec<u8> {
+        self.bits.reset();
+        match frame {
+            Samples::Int(frame) => self.encoder.encode_int(frame, &mut self.bits),
+            Samples::Float(frame) => self.encoder.encode(frame, &mut self.bits),
+        }
+        .expect("frames are a whole frame long");
+        let mut packet = vec![0; self.bits.num_bytes() as usize];
+        let written = self.bits.write(&mut packet) as usize;
+        packet.truncate(written);
+        packet
+    }
+}
+
+/// Encodes frames of audio into packets, one frame per packet
+#[pyclass(module = "speex_py", frozen)]
+pub struct Encoder {
+    state: Mutex<EncoderState>,
+    mode: Mode,
+    frame_size: usize,
+}
+
+#[pymethods]
+impl Encoder {
+    #[new]
+    fn new(mode: Mode) -> Self {
+        let mut encoder = DynamicEncoder::new(ModeId::from(mode));
+        let frame_size = encoder.get_frame_size() as usize;
+        Self {
+            state: Mutex::new(EncoderState {
+                encoder,
+                bits: SpeexBits::new(),
+            }),
+            mode,
+            frame_size,
+        }
+    }
+
+    #[getter]
+    fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// Number of samples in a frame
+    #[getter]
+    fn frame_size(&self) -> usize {
+        self.frame_size
+    }
+
+    /// Sets the quality between 0 and 10, for constant bitrate encoding
+    fn set_quality(&self, quality: i32) {
+        lock(&self.state).encoder.set_quality(quality);
+    }
+
+    fn set_complexity(&self, complexity: i32) {
+        lock(&self.state).encoder.set_complexity(complexity);
+    }
+
+    fn get_complexity(&self) -> i32 {
+        lock(&self.state).encoder.get_complexity()
+    }
+
+    fn set_vbr(&self, vbr: bool) {
+        lock(&self.state).encoder.set_vbr(vbr);
+    }
+
+    fn get_vbr(&self) -> bool {
+        lock(&self.state).encoder.get_vbr()
+    }
+
+    /// Sets the quality between 0 and 10, for variable bitrate encoding
+    fn set_vbr_quality(&self, quality: f32) {
+        lock(&self.state).encoder.set_vbr_quality(quality);
+    }
+
+    fn get_vbr_quality(&self) -> f32 {
+        lock(&self.state).encoder.get_vbr_quality()
+    }
+
+    /// Sets the average bitrate to aim for, in bits per second
+    fn set_abr(&self, abr: i32) {
+        lock(&self.state).encoder.set_abr(abr);
+    }
+
+    fn get_abr(&self) -> i32 {
+        lock(&self.state).encoder.get_abr()
+    }
+
+    /// Sets the constant bitrate in bits per second, rounded down to one the
+    /// mode supports
+    fn set_bitrate(&self, bitrate: i32) {
+        lock(&self.state).encoder.set_bitrate(bitrate);
+    }
+
+    fn get_bitrate(&self) -> i32 {
+        lock(&self.state).encoder.get_bitrate()
+    }
+
+    fn set_vad(&self, vad: bool) {
+        lock(&self.state).encoder.set_vad(vad);
+    }
+
+    fn get_vad(&self) -> bool {
+        lock(&self.state).encoder.get_vad()
+    }
+
+    fn set_dtx(&self, dtx: bool) {
+        lock(&self.state).encoder.set_dtx(dtx);
+    }
+
+    fn get_dtx(&self) -> bool {
+        lock(&self.state).encoder.get_dtx()
+    }
+
+    /// Encodes one frame of samples into a packet
+    fn encode<'py>(
+        &self,
+        py: Python<'py>,
+        samples: &Bound<'py, PyAny>,
+    ) -> PyResult<Bound<'py, PyBytes>> {
+        let mut samples = Samples::extract(samples)?;
+        if samples.len() != self.frame_size {
+            return Err(PyValueError::new_err(format!(
+                "Expected {} samples, got {}",
+                self.frame_size,
+                samples.len()
+            )));
+        }
+        let packet = py.detach(|| lock(&self.state).encode_frame(&mut samples));
+        Ok(PyBytes::new(py, &packet))
+    }
+
+    /// Encodes a whole signal into one packet per frame. The last frame is
+    /// padded with silence.
+    fn encode_frames<'py>(
+        &self,
+        py: Python<'py>,
+        samples: &Bound<'py, PyAny>,
+    ) -> PyResult<Vec<Bound<'py, PyBytes>>> {
+        let samples = Samples::extract(samples)?;
+        let frame_size = self.frame_size;
+        let packets = py.detach(|| {
+            let mut state = lock(&self.state);
+            match samples {
+                Samples::Int(samples) => {
+                    samples
+                        .chunks(frame_size)
+                        .map(|chunk| {
+                            let mut frame = chunk.to_vec();
+                            frame.resize(frame_size, 0);
+                            state.encode_frame(&mut Samples::Int(frame))
+                        })
+                        .collect::<Vec<_>>()
+                }
+                Samples::Float(samples) => {
+                    samples
+                        .chunks(frame_size)
+                        .map(|chunk| {
+                            let mut frame = chunk.to_vec();
+                            frame.resize(frame_size, 0.0);
+                            state.encode_frame(&mut Samples::Float(frame))
+                        })
+                        .collect()
+                }
+            }
+        });
+        Ok(packets
+            .iter()
+            .map(|packet| PyBytes::new(py, packet))
+            .collect())
+    }
+}
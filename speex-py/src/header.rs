@@ -0,0 +1,136 @@
+////////////////////////////////////////////////////////////////////////////////
+// Copyright (c) 2023.                                                         /
+// This Source Code Form is subject to the terms of the Mozilla Public License,/
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can    /
+// obtain one at http://mozilla.org/MPL/2.0/.                                  /
+////////////////////////////////////////////////////////////////////////////////
+
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+use speex_safe::{ModeId, SpeexHeader};
+
+use crate::Mode;
+
+/// The header packet that starts a speex stream
+#[pyclass(module = "speex_py")]
+#[derive(Clone)]
+pub struct Header {
+    pub(crate) header: SpeexHeader,
+}
+
+#[pymethods]
+impl Header {
+    #[new]
+    fn new(rate: i32, channels: i32, mode: Mode) -> Self {
+        Self {
+            header: SpeexHeader::new(rate, channels, ModeId::from(mode).get_mode()),
+        }
+    }
+
+    /// Parses the header from the first packet of a stream
+    #[staticmethod]
+    fn from_packet(packet: &[u8]) -> PyResult<Self> {
+        let header = SpeexHeader::from_packet(packet)
+            .ok_or_else(|| PyValueError::new_err("Packet is not a Speex header"))?;
+        Ok(Self { header })
+    }
+
+    /// Serializes the header into a packet
+    fn make_packet<'py>(&mut self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, &self.header.make_packet())
+    }
+
+    /// Version string of the encoder that wrote the stream
+    #[getter]
+    fn version(&self) -> String {
+        self.header.get_version()
+    }
+
+    #[getter]
+    fn version_id(&self) -> i32 {
+        self.header.get_version_id()
+    }
+
+    #[getter]
+    fn rate(&self) -> i32 {
+        self.header.get_rate()
+    }
+
+    #[getter]
+    fn mode(&self) -> Mode {
+        Mode::from(self.header.get_mode())
+    }
+
+    #[getter]
+    fn mode_bitstream_version(&self) -> i32 {
+        self.header.get_mode_bitstream_version()
+    }
+
+    #[getter]
+    fn channels(&self) -> i32 {
+        self.header.get_nb_channels()
+    }
+
+    /// Bitrate of the stream, or -1 if unknown
+    #[getter]
+    fn get_bitrate(&self) -> i32 {
+        self.header.get_bitrate()
+    }
+
+    #[setter]
+    fn set_bitrate(&mut self, bitrate: i32) {
+        self.header.set_bitrate(bitrate);
+    }
+
+    #[getter]
+    fn frame_size(&self) -> i32 {
+        self.header.get_frame_size()
+    }
+
+    #[getter]
+    fn get_vbr(&self) -> bool {
+        self.header.get_vbr()
+    }
+
+    #[setter]
+    fn set_vbr(&mut self, vbr: bool) {
+        self.header.set_vbr(vbr);
+    }
+
+    #[getter]
+    fn get_frames_per_packet(&self) -> i32 {
+        self.header.get_frames_per_packet()
+    }
+
+    #[setter]
+    fn set_frames_per_packet(&mut self, frames_per_packet: i32) {
+        self.header.set_frames_per_packet(frames_per_packet);
+    }
+
+    /// Number of additional headers following the comment packet
+    #[getter]
+    fn get_extra_headers(&self) -> i32 {
+        self.header.get_extra_headers()
+    }
+
+    #[setter]
+    fn set_extra_headers(&mut self, extra_headers: i32) {
+        self.header.set_extra_headers(extra_headers);
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "Header(rate={}, channels={}, mode={}, frame_size={}, vbr={})",
+            self.header.get_rate(),
+            self.header.get_nb_channels(),
+            self.header.get_mode() as i32,
+            self.header.get_frame_size(),
+            if self.header.get_vbr() {
+                "True"
+            } else {
+                "False"
+            },
+        )
+    }
+}
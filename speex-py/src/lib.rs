@@ -0,0 +1,119 @@
+////////////////////////////////////////////////////////////////////////////////
+// Copyright (c) 2023.                                                         /
+// This Source Code Form is subject to the terms of the Mozilla Public License,/
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can    /
+// obtain one at http://mozilla.org/MPL/2.0/.                                  /
+////////////////////////////////////////////////////////////////////////////////
+
+//! Python bindings for `speex-safe`, built into the `speex_py` module.
+//!
+//! Samples are one dimensional numpy arrays of `int16` or `float32`, in the
+//! range of an `int16` either way, and packets are `bytes`. The GIL is
+//! released while audio is encoded or decoded, so separate coders can run on
+//! separate Python threads.
+
+mod decoder;
+mod encoder;
+mod header;
+mod ogg;
+
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use numpy::PyReadonlyArray1;
+use pyo3::exceptions::PyTypeError;
+use pyo3::prelude::*;
+use speex_safe::ModeId;
+
+/// The modes of speex, by the sampling rate they code
+#[pyclass(module = "speex_py", eq, eq_int, frozen, hash)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Mode {
+    /// 8kHz
+    #[pyo3(name = "NARROWBAND")]
+    NarrowBand = 0,
+    /// 16kHz
+    #[pyo3(name = "WIDEBAND")]
+    WideBand = 1,
+    /// 32kHz
+    #[pyo3(name = "ULTRA_WIDEBAND")]
+    UltraWideBand = 2,
+}
+
+#[pymethods]
+impl Mode {
+    /// Number of samples in a frame
+    #[getter]
+    fn frame_size(&self) -> usize {
+        ModeId::from(*self).get_frame_size() as usize
+    }
+}
+
+impl From<Mode> for ModeId {
+    fn from(mode: Mode) -> Self {
+        match mode {
+            Mode::NarrowBand => ModeId::NarrowBand,
+            Mode::WideBand => ModeId::WideBand,
+            Mode::UltraWideBand => ModeId::UltraWideBand,
+        }
+    }
+}
+
+impl From<ModeId> for Mode {
+    fn from(mode: ModeId) -> Self {
+        match mode {
+            ModeId::NarrowBand => Mode::NarrowBand,
+            ModeId::WideBand => Mode::WideBand,
+            ModeId::UltraWideBand => Mode::UltraWideBand,
+        }
+    }
+}
+
+/// Samples copied out of a numpy array, so the GIL can be released while
+/// they are coded
+enum Samples {
+    Int(Vec<i16>),
+    Float(Vec<f32>),
+}
+
+impl Samples {
+    fn extract(samples: &Bound<'_, PyAny>) -> PyResult<Self> {
+        if let Ok(array) = samples.extract::<PyReadonlyArray1<'_, i16>>() {
+            return Ok(Samples::Int(array.as_array().to_vec()));
+        }
+        if let Ok(array) = samples.extract::<PyReadonlyArray1<'_, f32>>() {
+            return Ok(Samples::Float(array.as_array().to_vec()));
+        }
+        Err(PyTypeError::new_err(
+            "samples must be a one dimensional numpy array of int16 or float32",
+        ))
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Samples::Int(samples) => samples.len(),
+            Samples::Float(samples) => samples.len(),
+        }
+    }
+}
+
+/// Locks the state of a coder. A panic while it was locked has already been
+/// raised in Python, so the state is used as it was left.
+fn lock<T>(state: &Mutex<T>) -> MutexGuard<'_, T> {
+    state.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[pymodule]
+fn speex_py(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<Mode>()?;
+    m.add_class::<encoder::Encoder>()?;
+    m.add_class::<decoder::Decoder>()?;
+    m.add_class::<header::Header>()?;
+    m.add_class::<ogg::OggPacket>()?;
+    m.add_class::<ogg::DecodedAudio>()?;
+    m.add_function(wrap_pyfunction!(ogg::read_packets, m)?)?;
+    m.add_function(wrap_pyfunction!(ogg::read_headers, m)?)?;
+    m.add_function(wrap_pyfunction!(ogg::decode_ogg, m)?)?;
+    m.add_function(wrap_pyfunction!(ogg::encode_ogg, m)?)?;
+    m.add("__version__", env!("CARGO_PKG_VERSION"))?;
+    Ok(())
+}
@@ -0,0 +1,257 @@
+////////////////////////////////////////////////////////////////////////////////
+// Copyright (c) 2023.                                                         /
+// This Source Code Form is subject to the terms of the Mozilla Public License,/
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can    /
+// obtain one at http://mozilla.org/MPL/2.0/.                                  /
+////////////////////////////////////////////////////////////////////////////////
+
+//! Reading Ogg Speex streams, from `bytes` or from a file, and writing them
+
+use std::io::{self, ErrorKind};
+use std::path::PathBuf;
+
+use numpy::{PyArray1, PyArray2, PyArrayMethods, PyReadonlyArray2};
+use pyo3::exceptions::{PyTypeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+use speex_safe::ogg::OggReader;
+use speex_safe::wav::{self, DecodeOptions, EncodeOptions, WavError};
+use speex_safe::{HeadersError, ModeId, SpeexComments, SpeexHeaders};
+
+use crate::header::Header;
+use crate::{Mode, Samples};
+
+/// A whole stream, given as `bytes` or as the path of a file
+enum Source {
+    Bytes(Vec<u8>),
+    Path(PathBuf),
+}
+
+impl Source {
+    fn extract(source: &Bound<'_, PyAny>) -> PyResult<Self> {
+        if let Ok(bytes) = source.cast::<PyBytes>() {
+            return Ok(Source::Bytes(bytes.as_bytes().to_vec()));
+        }
+        Ok(Source::Path(source.extract()?))
+    }
+
+    /// Reads the stream, meant to be called with the GIL released
+    fn read(self) -> io::Result<Vec<u8>> {
+        match self {
+            Source::Bytes(bytes) => Ok(bytes),
+            Source::Path(path) => std::fs::read(path),
+        }
+    }
+}
+
+/// Malformed and truncated Ogg pages are reported as I/O errors, which are
+/// raised as `ValueError` rather than `OSError`
+fn io_error(err: io::Error) -> PyErr {
+    match err.kind() {
+        ErrorKind::InvalidData | ErrorKind::UnexpectedEof => PyValueError::new_err(err.to_string()),
+        _ => err.into(),
+    }
+}
+
+fn wav_error(err: WavError) -> PyErr {
+    match err {
+        WavError::Io(err) => io_error(err),
+        err => PyValueError::new_err(err.to_string()),
+    }
+}
+
+fn headers_error(err: HeadersError) -> PyErr {
+    match err {
+        HeadersError::Io(err) => io_error(err),
+        err => PyValueError::new_err(err.to_string()),
+    }
+}
+
+/// Takes interleaved samples and their number of channels from an array
+/// shaped `(frames,)` or `(frames, channels)`
+fn extract_interleaved(samples: &Bound<'_, PyAny>) -> PyResult<(Vec<f32>, u16)> {
+    if let Ok(array) = samples.extract::<PyReadonlyArray2<'_, i16>>() {
+        let channels = array.as_array().ncols();
+        let samples = array
+            .as_array()
+            .iter()
+            .map(|&sample| sample as f32)
+            .collect();
+        return Ok((samples, channels_u16(channels)?));
+    }
+    if let Ok(array) = samples.extract::<PyReadonlyArray2<'_, f32>>() {
+        let channels = array.as_array().ncols();
+        let samples = array.as_array().iter().copied().collect();
+        return Ok((samples, channels_u16(channels)?));
+    }
+    match Samples::extract(samples) {
+        Ok(Samples::Int(samples)) => Ok((samples.into_iter().map(f32::from).collect(), 1)),
+        Ok(Samples::Float(samples)) => Ok((samples, 1)),
+        Err(_) => {
+            Err(PyTypeError::new_err(
+                "samples must be a numpy array of int16 or float32, shaped (frames,) or (frames, \
+                 channels)",
+            ))
+        }
+    }
+}
+
+fn channels_u16(channels: usize) -> PyResult<u16> {
+    u16::try_from(channels).map_err(|_| PyValueError::new_err("Too many channels"))
+}
+
+fn comment_pairs(comments: &SpeexComments) -> Vec<(String, String)> {
+    comments
+        .iter()
+        .map(|(key, value)| (key.into_owned(), value.into_owned()))
+        .collect()
+}
+
+/// A packet of an Ogg stream
+#[pyclass(module = "speex_py", frozen, get_all)]
+pub struct OggPacket {
+    data: Py<PyBytes>,
+    /// Position of the last sample finished by this packet, or -1
+    granule_position: i64,
+    serial: u32,
+    /// Whether this is the first packet of the stream
+    bos: bool,
+    /// Whether this is the last packet of the stream
+    eos: bool,
+}
+
+/// Audio decoded from an Ogg Speex stream
+#[pyclass(module = "speex_py", frozen, get_all)]
+pub struct DecodedAudio {
+    /// `int16` samples, shaped `(frames, channels)`
+    samples: Py<PyArray2<i16>>,
+    sample_rate: u32,
+    channels: u16,
+    /// `(key, value)` pairs of the comment packet, `None` if it is malformed
+    comments: Option<Vec<(String, String)>>,
+}
+
+/// Reads every packet of an Ogg stream
+#[pyfunction]
+pub fn read_packets(py: Python<'_>, source: &Bound<'_, PyAny>) -> PyResult<Vec<OggPacket>> {
+    let source = Source::extract(source)?;
+    let packets = py
+        .detach(|| {
+            let mut reader = OggReader::new(io::Cursor::new(source.read()?));
+            let mut packets = Vec::new();
+            while let Some(packet) = reader.read_packet()? {
+                packets.push(packet);
+            }
+            Ok(packets)
+        })
+        .map_err(io_error)?;
+    Ok(packets
+        .into_iter()
+        .map(|packet| {
+            OggPacket {
+                data: PyBytes::new(py, &packet.data).unbind(),
+                granule_position: packet.granule_position,
+                serial: packet.serial,
+                bos: packet.bos,
+                eos: packet.eos,
+            }
+        })
+        .collect())
+}
+
+/// Reads the header and the `(key, value)` comments of an Ogg Speex stream
+#[pyfunction]
+pub fn read_headers(
+    py: Python<'_>,
+    source: &Bound<'_, PyAny>,
+) -> PyResult<(Header, Vec<(String, String)>)> {
+    let source = Source::extract(source)?;
+    let headers = py.detach(|| {
+        let data = source.read().map_err(HeadersError::Io)?;
+        SpeexHeaders::read_ogg(&mut OggReader::new(data.as_slice()))
+    });
+    let headers = headers.map_err(headers_error)?;
+    Ok((
+        Header {
+            header: headers.header,
+        },
+        comment_pairs(&headers.comments),
+    ))
+}
+
+/// Decodes a whole Ogg Speex stream
+///
+/// `mode` decodes with a different mode than the stream was encoded with, and
+/// `channels` forces the number of channels of the output.
+#[pyfunction]
+#[pyo3(signature = (source, enhancement = true, mode = None, channels = None))]
+pub fn decode_ogg(
+    py: Python<'_>,
+    source: &Bound<'_, PyAny>,
+    enhancement: bool,
+    mode: Option<Mode>,
+    channels: Option<u16>,
+) -> PyResult<DecodedAudio> {
+    let source = Source::extract(source)?;
+    let options = DecodeOptions {
+        enhancement,
+        mode: mode.map(ModeId::from),
+        channels,
+        ..Default::default()
+    };
+    let decoded = py.detach(|| {
+        let data = source.read()?;
+        wav::decode_ogg(data.as_slice(), &options)
+    });
+    let decoded = decoded.map_err(wav_error)?;
+    let frames = decoded.samples.len() / decoded.channels.max(1) as usize;
+    let samples = PyArray1::from_vec(py, decoded.samples)
+        .reshape([frames, decoded.channels as usize])?
+        .unbind();
+    Ok(DecodedAudio {
+        samples,
+        sample_rate: decoded.sample_rate,
+        channels: decoded.channels,
+        comments: decoded.comments.as_ref().map(comment_pairs),
+    })
+}
+
+/// Encodes audio into an Ogg Speex stream
+///
+/// `samples` is shaped `(frames,)` for mono or `(frames, channels)`. The mode
+/// is picked from `sample_rate` unless one is given.
+#[pyfunction]
+#[pyo3(signature = (
+    samples,
+    sample_rate,
+    quality = None,
+    vbr = false,
+    frames_per_packet = 1,
+    mode = None,
+))]
+pub fn encode_ogg<'py>(
+    py: Python<'py>,
+    samples: &Bound<'py, PyAny>,
+    sample_rate: u32,
+    quality: Option<i32>,
+    vbr: bool,
+    frames_per_packet: i32,
+    mode: Option<Mode>,
+) -> PyResult<Bound<'py, PyBytes>> {
+    let (samples, channels) = extract_interleaved(samples)?;
+    let options = EncodeOptions {
+        mode: mode.map(ModeId::from),
+        quality,
+        vbr,
+        frames_per_packet,
+        ..Default::default()
+    };
+    let stream = py
+        .detach(|| {
+            let mut stream = Vec::new();
+            wav::encode_ogg(&samples, sample_rate, channels, &options, &mut stream)?;
+            Ok(stream)
+        })
+        .map_err(wav_error)?;
+    Ok(PyBytes::new(py, &stream))
+}
@@ -0,0 +1,44 @@
+# Copyright (c) 2023.
+# This Source Code Form is subject to the terms of the Mozilla Public License,
+# v. 2.0. If a copy of the MPL was not distributed with this file, You can
+# obtain one at http://mozilla.org/MPL/2.0/.
+
+import numpy as np
+import pytest
+
+import speex_py
+
+SAMPLE_RATES = {
+    speex_py.Mode.NARROWBAND: 8000,
+    speex_py.Mode.WIDEBAND: 16000,
+    speex_py.Mode.ULTRA_WIDEBAND: 32000,
+}
+
+
+def make_tone(sample_rate, seconds=1.0, frequency=440.0):
+    """A 440 Hz tone with a few quieter harmonics, as int16"""
+    t = np.arange(int(sample_rate * seconds)) / sample_rate
+    signal = sum(
+        8000.0 / harmonic * np.sin(2 * np.pi * frequency * harmonic * t)
+        for harmonic in (1, 2, 3)
+    )
+    return signal.astype(np.int16)
+
+
+def rms(samples):
+    return float(np.sqrt(np.mean(np.asarray(samples, dtype=np.float64) ** 2)))
+
+
+def peak_frequency(samples, sample_rate):
+    spectrum = np.abs(np.fft.rfft(np.asarray(samples, dtype=np.float64)))
+    return np.argmax(spectrum) * sample_rate / len(samples)
+
+
+@pytest.fixture(params=list(SAMPLE_RATES), ids=lambda mode: str(mode))
+def mode(request):
+    return request.param
+
+
+@pytest.fixture
+def tone():
+    return make_tone
@@ -0,0 +1,157 @@
+# Copyright (c) 2023.
+# This Source Code Form is subject to the terms of the Mozilla Public License,
+# v. 2.0. If a copy of the MPL was not distributed with this file, You can
+# obtain one at http://mozilla.org/MPL/2.0/.
+
+from concurrent.futures import ThreadPoolExecutor
+
+import numpy as np
+import pytest
+
+import speex_py
+from conftest import SAMPLE_RATES, peak_frequency, rms
+
+
+def test_frame_sizes():
+    assert speex_py.Mode.NARROWBAND.frame_size == 160
+    assert speex_py.Mode.WIDEBAND.frame_size == 320
+    assert speex_py.Mode.ULTRA_WIDEBAND.frame_size == 640
+    assert speex_py.Encoder(speex_py.Mode.WIDEBAND).frame_size == 320
+    assert speex_py.Decoder(speex_py.Mode.WIDEBAND).frame_size == 320
+
+
+def test_round_trip(mode, tone):
+    sample_rate = SAMPLE_RATES[mode]
+    samples = tone(sample_rate)
+    encoder = speex_py.Encoder(mode)
+    encoder.set_quality(8)
+    decoder = speex_py.Decoder(mode)
+
+    packets = encoder.encode_frames(samples)
+    assert len(packets) == -(-len(samples) // encoder.frame_size)
+    assert all(isinstance(packet, bytes) and packet for packet in packets)
+
+    decoded = np.concatenate([decoder.decode_int(packet) for packet in packets])
+    assert decoded.dtype == np.int16
+    assert len(decoded) == len(packets) * decoder.frame_size
+
+    # Skip the first frames while the codec settles
+    settled = decoded[sample_rate // 4 : len(samples)]
+    assert peak_frequency(settled, sample_rate) == pytest.approx(440, abs=10)
+    assert rms(settled) == pytest.approx(rms(samples), rel=0.3)
+
+
+def test_float_and_int_samples_encode_the_same(tone):
+    samples = tone(8000)[:160]
+    int_packet = speex_py.Encoder(speex_py.Mode.NARROWBAND).encode(samples)
+    float_packet = speex_py.Encoder(speex_py.Mode.NARROWBAND).encode(
+        samples.astype(np.float32)
+    )
+    assert int_packet == float_packet
+
+
+def test_decode_returns_float32(tone):
+    encoder = speex_py.Encoder(speex_py.Mode.NARROWBAND)
+    decoder = speex_py.Decoder(speex_py.Mode.NARROWBAND)
+    decoded = decoder.decode(encoder.encode(tone(8000)[:160]))
+    assert decoded.dtype == np.float32
+    assert decoded.shape == (160,)
+
+
+def test_strided_input(tone):
+    samples = tone(8000)
+    strided = samples[::2][:160]
+    encoder = speex_py.Encoder(speex_py.Mode.NARROWBAND)
+    expected = speex_py.Encoder(speex_py.Mode.NARROWBAND).encode(strided.copy())
+    assert encoder.encode(strided) == expected
+
+
+def test_lost_packets_are_concealed(tone):
+    samples = tone(8000)
+    encoder = speex_py.Encoder(speex_py.Mode.NARROWBAND)
+    decoder = speex_py.Decoder(speex_py.Mode.NARROWBAND)
+    packets = encoder.encode_frames(samples)
+    for packet in packets[:20]:
+        decoder.decode_int(packet)
+    concealed = decoder.decode_int(None)
+    assert concealed.shape == (160,)
+    assert rms(concealed) > 100
+
+
+def test_settings():
+    encoder = speex_py.Encoder(speex_py.Mode.WIDEBAND)
+    encoder.set_complexity(7)
+    assert encoder.get_complexity() == 7
+    encoder.set_vbr(True)
+    assert encoder.get_vbr()
+    encoder.set_vbr_quality(6.5)
+    assert encoder.get_vbr_quality() == pytest.approx(6.5)
+    encoder.set_vad(True)
+    assert encoder.get_vad()
+    encoder.set_dtx(True)
+    assert encoder.get_dtx()
+
+    decoder = speex_py.Decoder(speex_py.Mode.WIDEBAND, enhancement=False)
+    assert not decoder.get_enhancement()
+    decoder.set_enhancement(True)
+    assert decoder.get_enhancement()
+
+
+def test_bitrate_follows_quality(tone):
+    samples = tone(16000)
+    sizes = []
+    for quality in (2, 8):
+        encoder = speex_py.Encoder(speex_py.Mode.WIDEBAND)
+        encoder.set_quality(quality)
+        sizes.append(sum(len(packet) for packet in encoder.encode_frames(samples)))
+    assert sizes[0] < sizes[1]
+
+
+def test_wrong_frame_size():
+    encoder = speex_py.Encoder(speex_py.Mode.NARROWBAND)
+    with pytest.raises(ValueError, match="Expected 160 samples, got 100"):
+        encoder.encode(np.zeros(100, dtype=np.int16))
+
+
+@pytest.mark.parametrize(
+    "samples",
+    [
+        np.zeros(160, dtype=np.float64),
+        np.zeros(160, dtype=np.int32),
+        np.zeros((160, 1), dtype=np.int16),
+        [0] * 160,
+    ],
+)
+def test_unsupported_samples(samples):
+    encoder = speex_py.Encoder(speex_py.Mode.NARROWBAND)
+    with pytest.raises(TypeError):
+        encoder.encode(samples)
+
+
+def test_corrupt_packet():
+    decoder = speex_py.Decoder(speex_py.Mode.NARROWBAND)
+    with pytest.raises(ValueError):
+        # Requests narrowband submode 10, which doesn't exist
+        decoder.decode_int(b"\x50" * 38)
+
+
+def test_threads_match_sequential(tone):
+    samples = tone(16000, seconds=2.0)
+
+    def encode(_):
+        encoder = speex_py.Encoder(speex_py.Mode.WIDEBAND)
+        return encoder.encode_frames(samples)
+
+    expected = encode(None)
+    with ThreadPoolExecutor(max_workers=4) as pool:
+        results = list(pool.map(encode, range(8)))
+    assert all(result == expected for result in results)
+
+
+def test_shared_encoder_across_threads(tone):
+    """Calls on one coder are serialized rather than raising"""
+    frame = tone(8000)[:160]
+    encoder = speex_py.Encoder(speex_py.Mode.NARROWBAND)
+    with ThreadPoolExecutor(max_workers=4) as pool:
+        packets = list(pool.map(lambda _: encoder.encode(frame), range(64)))
+    assert all(packets)
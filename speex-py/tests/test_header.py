@@ -0,0 +1,39 @@
+# Copyright (c) 2023.
+# This Source Code Form is subject to the terms of the Mozilla Public License,
+# v. 2.0. If a copy of the MPL was not distributed with this file, You can
+# obtain one at http://mozilla.org/MPL/2.0/.
+
+import pytest
+
+import speex_py
+
+
+def test_new_header():
+    header = speex_py.Header(16000, 2, speex_py.Mode.WIDEBAND)
+    assert header.rate == 16000
+    assert header.channels == 2
+    assert header.mode == speex_py.Mode.WIDEBAND
+    assert header.frame_size == 320
+    assert header.version.startswith("speex-")
+
+
+def test_packet_round_trip():
+    header = speex_py.Header(32000, 1, speex_py.Mode.ULTRA_WIDEBAND)
+    header.vbr = True
+    header.bitrate = 28000
+    header.frames_per_packet = 3
+    header.extra_headers = 1
+
+    parsed = speex_py.Header.from_packet(header.make_packet())
+    assert parsed.rate == 32000
+    assert parsed.channels == 1
+    assert parsed.mode == speex_py.Mode.ULTRA_WIDEBAND
+    assert parsed.vbr
+    assert parsed.bitrate == 28000
+    assert parsed.frames_per_packet == 3
+    assert parsed.extra_headers == 1
+
+
+def test_invalid_packet():
+    with pytest.raises(ValueError):
+        speex_py.Header.from_packet(b"OggS not a speex header")
@@ -0,0 +1,97 @@
+# Copyright (c) 2023.
+# This Source Code Form is subject to the terms of the Mozilla Public License,
+# v. 2.0. If a copy of the MPL was not distributed with this file, You can
+# obtain one at http://mozilla.org/MPL/2.0/.
+
+import numpy as np
+import pytest
+
+import speex_py
+from conftest import SAMPLE_RATES, peak_frequency, rms
+
+
+def test_round_trip(mode, tone):
+    sample_rate = SAMPLE_RATES[mode]
+    samples = tone(sample_rate)
+    stream = speex_py.encode_ogg(samples, sample_rate, quality=8)
+    assert stream.startswith(b"OggS")
+
+    decoded = speex_py.decode_ogg(stream)
+    assert decoded.sample_rate == sample_rate
+    assert decoded.channels == 1
+    assert decoded.samples.dtype == np.int16
+    assert decoded.samples.shape == (len(samples), 1)
+    settled = decoded.samples[sample_rate // 4 :, 0]
+    assert peak_frequency(settled, sample_rate) == pytest.approx(440, abs=10)
+    assert rms(settled) == pytest.approx(rms(samples), rel=0.3)
+
+
+def test_stereo(tone):
+    left = tone(16000)
+    stereo = np.stack([left, left // 2], axis=1)
+    decoded = speex_py.decode_ogg(speex_py.encode_ogg(stereo, 16000))
+    assert decoded.channels == 2
+    assert decoded.samples.shape == stereo.shape
+    assert rms(decoded.samples[4000:, 0]) > rms(decoded.samples[4000:, 1])
+
+    mono = speex_py.decode_ogg(speex_py.encode_ogg(stereo, 16000), channels=1)
+    assert mono.samples.shape == (len(left), 1)
+
+
+def test_float_samples(tone):
+    samples = tone(8000).astype(np.float32)
+    decoded = speex_py.decode_ogg(speex_py.encode_ogg(samples, 8000))
+    assert decoded.samples.shape == (len(samples), 1)
+
+
+def test_decode_with_lower_mode(tone):
+    stream = speex_py.encode_ogg(tone(16000), 16000)
+    decoded = speex_py.decode_ogg(stream, mode=speex_py.Mode.NARROWBAND)
+    assert decoded.sample_rate == 8000
+    assert decoded.samples.shape == (8000, 1)
+
+
+def test_read_from_path(tmp_path, tone):
+    path = tmp_path / "tone.spx"
+    path.write_bytes(speex_py.encode_ogg(tone(16000), 16000, vbr=True))
+
+    header, comments = speex_py.read_headers(path)
+    assert header.rate == 16000
+    assert header.mode == speex_py.Mode.WIDEBAND
+    assert header.vbr
+    assert isinstance(comments, list)
+
+    decoded = speex_py.decode_ogg(str(path))
+    assert decoded.samples.shape == (16000, 1)
+    assert decoded.comments == comments
+
+
+def test_read_packets(tone):
+    samples = tone(8000)
+    stream = speex_py.encode_ogg(samples, 8000, frames_per_packet=2)
+    packets = speex_py.read_packets(stream)
+    assert len(packets) > 2
+    assert packets[0].bos and not packets[0].eos
+    assert packets[-1].eos
+    assert packets[-1].granule_position == len(samples)
+    assert len({packet.serial for packet in packets}) == 1
+
+    header = speex_py.Header.from_packet(packets[0].data)
+    assert header.frames_per_packet == 2
+    decoder = speex_py.Decoder(header.mode)
+    assert len(decoder.decode_int(packets[2].data)) == 320
+
+
+def test_errors(tmp_path):
+    with pytest.raises(ValueError):
+        speex_py.decode_ogg(b"this is not an ogg stream")
+    with pytest.raises(ValueError):
+        speex_py.read_headers(b"")
+    with pytest.raises(OSError):
+        speex_py.decode_ogg(tmp_path / "missing.spx")
+    with pytest.raises(TypeError):
+        speex_py.decode_ogg(42)
+    with pytest.raises(ValueError):
+        speex_py.encode_ogg(np.zeros(100, dtype=np.int16), 96000)
+    with pytest.raises(TypeError):
+        speex_py.encode_ogg(np.zeros(100, dtype=np.float64), 8000)
@@ -0,0 +1,155 @@
+////////////////////////////////////////////////////////////////////////////////
+// Copyright (c) 2023.                                                         /
+// This Source Code Form is subject to the terms of the Mozilla Public License,/
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can    /
+// obtain one at http://mozilla.org/MPL/2.0/.                                  /
+////////////////////////////////////////////////////////////////////////////////
+
+//! Writes the seed corpora of the fuzz targets to `fuzz/seeds`
+//!
+//! ```sh
+//! cargo run -p speex-safe --example fuzz_seeds
+//! ```
+
+use std::path::Path;
+use std::{fs, io};
+
+use speex_safe::{
+    CoderMode,
+    ControlFunctions,
+    ModeId,
+    NbMode,
+    SpeexBits,
+    SpeexComments,
+    SpeexEncoder,
+    SpeexHeader,
+    UwbMode,
+    WbMode,
+};
+
+#[path = "../tests/support/mod.rs"]
+mod support;
+
+/// Frames coded into each packet
+const FRAMES_PER_PACKET: usize = 3;
+
+/// Half a second of speech, then half a second of silence, so VBR and DTX get
+/// to code every kind of frame
+fn speech_like(rate: usize, channels: usize) -> Vec<f32> {
+    support::speech_like(rate, rate / 2)
+        .into_iter()
+        .chain(std::iter::repeat_n(0.0, rate / 2))
+        .flat_map(|y| {
+            // The right channel is quieter, for the stereo information to
+            // carry something
+            (0..channels).map(move |channel| y / (1 + channel) as f32)
+        })
+        .collect()
+}
+
+/// Codes `signal` into packets of `FRAMES_PER_PACKET` frames each
+fn encode<T: CoderMode>(
+    encoder: &mut SpeexEncoder<T>,
+    signal: &[f32],
+    channels: usize,
+) -> Vec<Vec<u8>> {
+    let frame_size = encoder.get_frame_size() as usize * channels;
+    let mut bits = SpeexBits::new();
+    signal
+        .chunks_exact(frame_size * FRAMES_PER_PACKET)
+        .map(|packet| {
+            bits.reset();
+            for frame in packet.chunks_exact(frame_size) {
+                if channels == 2 {
+                    encoder
+                        .encode_stereo(&mut frame.to_vec(), &mut bits)
+                        .unwrap();
+                } else {
+                    encoder.encode(&mut frame.to_vec(), &mut bits).unwrap();
+                }
+            }
+            let mut packet = vec![0; 2000];
+            let len = bits.write(&mut packet) as usize;
+            packet.truncate(len);
+            packet
+        })
+        .collect()
+}
+
+/// Packets from a constant bitrate at a few qualities, and from VBR with DTX
+fn packets<T: CoderMode>(new: fn() -> SpeexEncoder<T>, channels: usize) -> Vec<Vec<u8>> {
+    let mut packets = Vec::new();
+    for quality in [0, 4, 8, 10] {
+        let mut encoder = new();
+        encoder.set_quality(quality);
+        let rate = encoder.get_sampling_rate() as usize;
+        // A packet from the voiced half is enough for each quality
+        packets.push(encode(&mut encoder, &speech_like(rate, channels), channels)[2].clone());
+    }
+    let mut encoder = new();
+    encoder.set_vbr(true);
+    encoder.set_dtx(true);
+    let rate = encoder.get_sampling_rate() as usize;
+    let vbr = encode(&mut encoder, &speech_like(rate, channels), channels);
+    packets.push(vbr[2].clone());
+    packets.push(vbr.last().unwrap().clone());
+    packets
+}
+
+fn write_seeds(dir: &Path, target: &str, seeds: &[Vec<u8>]) -> io::Result<()> {
+    let dir = dir.join(target);
+    fs::create_dir_all(&dir)?;
+    for (index, seed) in seeds.iter().enumerate() {
+        fs::write(dir.join(format!("seed-{index:02}")), seed)?;
+    }
+    Ok(())
+}
+
+fn main() -> io::Result<()> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("fuzz/seeds");
+
+    write_seeds(&dir, "decode_nb", &packets(SpeexEncoder::<NbMode>::new, 1))?;
+    write_seeds(&dir, "decode_wb", &packets(SpeexEncoder::<WbMode>::new, 1))?;
+    write_seeds(
+        &dir,
+        "decode_uwb",
+        &packets(SpeexEncoder::<UwbMode>::new, 1),
+    )?;
+
+    // The first byte picks the mode
+    let inband: Vec<Vec<u8>> = [
+        packets(SpeexEncoder::<NbMode>::new, 2),
+        packets(SpeexEncoder::<WbMode>::new, 2),
+        packets(SpeexEncoder::<UwbMode>::new, 2),
+    ]
+    .into_iter()
+    .enumerate()
+    .flat_map(|(mode, packets)| {
+        packets
+            .into_iter()
+            .map(move |packet| [&[mode as u8][..], &packet].concat())
+    })
+    .collect();
+    write_seeds(&dir, "inband", &inband)?;
+
+    let mut comments = SpeexComments::new("speex-safe");
+    comments.add("TITLE", "fuzz").unwrap();
+    let comments = comments.make_packet();
+    let mut header = Vec::new();
+    for (mode, channels) in [
+        (ModeId::NarrowBand, 1),
+        (ModeId::WideBand, 2),
+        (ModeId::UltraWideBand, 1),
+    ] {
+        let packet = SpeexHeader::new(mode.get_sampling_rate() as i32, channels, mode.get_mode())
+            .make_packet();
+        // The first byte is where the packet is split into the header and
+        // comments of a stream
+        header.push([&[packet.len() as u8][..], &packet, &comments].concat());
+        header.push(packet);
+    }
+    header.push(comments);
+    write_seeds(&dir, "header", &header)?;
+
+    Ok(())
+}
@@ -0,0 +1,120 @@
+////////////////////////////////////////////////////////////////////////////////
+// Copyright (c) 2023.                                                         /
+// This Source Code Form is subject to the terms of the Mozilla Public License,/
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can    /
+// obtain one at http://mozilla.org/MPL/2.0/.                                  /
+////////////////////////////////////////////////////////////////////////////////
+
+//! What the fuzz targets run on each input
+//!
+//! These are also run on the seed corpora and on every crash found so far by
+//! `tests/fuzz_regressions.rs`, which includes this file as a module.
+
+use speex_safe::{
+    CoderMode,
+    ControlFunctions,
+    NbMode,
+    SpeexBits,
+    SpeexComments,
+    SpeexDecoder,
+    SpeexHeader,
+    SpeexHeaders,
+    UwbMode,
+    WbMode,
+};
+
+/// Decodes every frame of `packet` as floats and again as integers, calling
+/// `inspect` after each frame, then conceals a lost frame
+fn decode_frames<T: CoderMode>(
+    decoder: &mut SpeexDecoder<T>,
+    packet: &[u8],
+    inspect: fn(&mut SpeexDecoder<T>),
+) {
+    let frame_size = decoder.get_frame_size() as usize;
+    let mut bits = SpeexBits::new();
+
+    bits.read_from(&mut packet.to_vec());
+    let mut out = vec![0.0; frame_size];
+    while decoder.decode(&mut bits, &mut out).is_ok() {
+        inspect(decoder);
+    }
+
+    bits.read_from(&mut packet.to_vec());
+    let mut out_int = vec![0; frame_size];
+    while decoder.decode_int(&mut bits, &mut out_int).is_ok() {
+        inspect(decoder);
+    }
+
+    decoder.decode_lost(&mut out).unwrap();
+    decoder.decode_lost_int(&mut out_int).unwrap();
+}
+
+/// Decodes a narrowband packet
+pub fn decode_nb(packet: &[u8]) {
+    decode_frames(&mut SpeexDecoder::<NbMode>::new(), packet, |decoder| {
+        decoder.get_submode();
+    });
+}
+
+/// Decodes a wideband packet
+pub fn decode_wb(packet: &[u8]) {
+    decode_frames(&mut SpeexDecoder::<WbMode>::new(), packet, |decoder| {
+        decoder.get_low_submode();
+        let _ = decoder.get_high_submode();
+    });
+}
+
+/// Decodes an ultra-wideband packet
+pub fn decode_uwb(packet: &[u8]) {
+    decode_frames(&mut SpeexDecoder::<UwbMode>::new(), packet, |decoder| {
+        decoder.get_low_submode();
+    });
+}
+
+/// Parses a header or comments packet, and reads everything back out of it
+pub fn header(packet: &[u8]) {
+    if let Some(header) = SpeexHeader::from_packet(packet) {
+        header.get_version();
+        header.get_mode();
+        header.get_rate();
+        header.get_nb_channels();
+        header.get_frame_size();
+        header.get_frames_per_packet();
+    }
+    if let Ok(comments) = SpeexComments::from_packet(packet) {
+        comments.get_vendor();
+        comments.iter().count();
+        comments.get_utf8_errors();
+    }
+    // Split anywhere, as the header and comments of a stream
+    if let Some((&split, rest)) = packet.split_first() {
+        let (header, comments) = rest.split_at((split as usize).min(rest.len()));
+        let _ = SpeexHeaders::from_packets(header, comments);
+    }
+}
+
+/// Decodes a stereo packet with in-band messages, in the mode picked by the
+/// first byte
+pub fn inband(data: &[u8]) {
+    let Some((&mode, packet)) = data.split_first() else {
+        return;
+    };
+    match mode % 3 {
+        0 => decode_stereo(SpeexDecoder::<NbMode>::new(), packet),
+        1 => decode_stereo(SpeexDecoder::<WbMode>::new(), packet),
+        _ => decode_stereo(SpeexDecoder::<UwbMode>::new(), packet),
+    }
+}
+
+fn decode_stereo<T: CoderMode>(mut decoder: SpeexDecoder<T>, packet: &[u8]) {
+    let frame_size = decoder.get_frame_size() as usize;
+    let mut bits = SpeexBits::new();
+
+    bits.read_from(&mut packet.to_vec());
+    let mut out = vec![0.0; frame_size * 2];
+    while decoder.decode_stereo(&mut bits, &mut out).is_ok() {}
+
+    bits.read_from(&mut packet.to_vec());
+    let mut out_int = vec![0; frame_size * 2];
+    while decoder.decode_stereo_int(&mut bits, &mut out_int).is_ok() {}
+}
@@ -7,11 +7,13 @@
 use std::env;
 use std::path::PathBuf;
 
-use bindgen::{CargoCallbacks, MacroTypeVariation};
+use bindgen::{Abi, MacroTypeVariation};
 
 fn main() {
     // Tell cargo to invalidate the built crate whenever the wrapper changes
     println!("cargo:rerun-if-changed=wrapper.h");
+    println!("cargo:rerun-if-changed=shim");
+    println!("cargo:rerun-if-changed=wasm/include");
     let dst = PathBuf::from(env::var_os("OUT_DIR").unwrap());
 
     let c_files = [
@@ -52,6 +54,28 @@ fn main() {
     ];
 
     let mut ccomp = cc::Build::new();
+    let mut clang_args = Vec::new();
+
+    // Warnings and fatal errors are reported through src/os_support.rs.
+    // Fatal errors unwind back into Rust, so the C code needs unwind tables.
+    ccomp.include("shim").define("OS_SUPPORT_CUSTOM", None);
+
+    // wasm32-unknown-unknown has no libc, so the few pieces libspeex needs
+    // come from shim headers backed by src/wasm.rs. It can't unwind either,
+    // so a fatal error aborts there. wasm32-wasi has a libc in the wasi-sdk
+    // sysroot and builds like any other target.
+    let arch = env::var("CARGO_CFG_TARGET_ARCH").unwrap();
+    let os = env::var("CARGO_CFG_TARGET_OS").unwrap();
+    if arch == "wasm32" && os == "unknown" {
+        ccomp
+            .include("wasm/include")
+            .define("SPEEX_SYS_NO_LIBC", None)
+            .flag("-ffreestanding")
+            .flag("-fno-math-errno");
+        clang_args.push("-Iwasm/include".to_string());
+    } else {
+        ccomp.flag_if_supported("-fexceptions");
+    }
 
     ccomp.include("speex/include");
     println!("cargo:include=speex/include");
@@ -65,6 +89,11 @@ fn main() {
     }
 
     ccomp.define("FLOATING_POINT", None).define("EXPORT", "");
+    // Shapes the quantization noise of the narrowband encoder with the
+    // psychoacoustic model from Vorbis
+    if env::var_os("CARGO_FEATURE_VORBIS_PSY").is_some() {
+        ccomp.define("VORBIS_PSYCHO", None);
+    }
     ccomp.warnings(false);
     ccomp.out_dir(dst.join("lib"));
     ccomp.compile("speex");
@@ -72,7 +101,10 @@ fn main() {
     let bindings = bindgen::Builder::default()
         .default_macro_constant_type(MacroTypeVariation::Signed)
         .header("wrapper.h")
-        .parse_callbacks(Box::new(CargoCallbacks))
+        .clang_args(clang_args)
+        .parse_callbacks(Box::new(bindgen::CargoCallbacks::new()))
+        // Any call into libspeex can unwind out of a fatal error
+        .override_abi(Abi::CUnwind, ".*")
         .generate()
         .expect("Unable to generate bindings");
 
@@ -0,0 +1,89 @@
+////////////////////////////////////////////////////////////////////////////////
+// Copyright (c) 2023.                                                         /
+// This Source Code Form is subject to the terms of the Mozilla Public License,/
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can    /
+// obtain one at http://mozilla.org/MPL/2.0/.                                  /
+////////////////////////////////////////////////////////////////////////////////
+
+/* Replaces the allocation of os_support.h with functions implemented in Rust
+ * in src/alloc.rs, and its error reporting, which prints to stderr and exits,
+ * with the ones in src/os_support.rs */
+
+#ifndef SPEEX_SYS_OS_SUPPORT_CUSTOM_H
+#define SPEEX_SYS_OS_SUPPORT_CUSTOM_H
+
+void *speex_sys_alloc(int size);
+void *speex_sys_realloc(void *ptr, int size);
+void speex_sys_free(void *ptr);
+void speex_sys_fatal(const char *str, const char *file, int line);
+void speex_sys_warning(const char *str);
+void speex_sys_warning_int(const char *str, int val);
+void speex_sys_notify(const char *str);
+
+#define OVERRIDE_SPEEX_ALLOC
+static inline void *speex_alloc(int size)
+{
+   return speex_sys_alloc(size);
+}
+
+#define OVERRIDE_SPEEX_ALLOC_SCRATCH
+static inline void *speex_alloc_scratch(int size)
+{
+   return speex_sys_alloc(size);
+}
+
+#define OVERRIDE_SPEEX_REALLOC
+static inline void *speex_realloc(void *ptr, int size)
+{
+   return speex_sys_realloc(ptr, size);
+}
+
+#define OVERRIDE_SPEEX_FREE
+static inline void speex_free(void *ptr)
+{
+   speex_sys_free(ptr);
+}
+
+#define OVERRIDE_SPEEX_FREE_SCRATCH
+static inline void speex_free_scratch(void *ptr)
+{
+   speex_sys_free(ptr);
+}
+
+#define OVERRIDE_SPEEX_FATAL
+static inline void _speex_fatal(const char *str, const char *file, int line)
+{
+   speex_sys_fatal(str, file, line);
+}
+
+#define OVERRIDE_SPEEX_WARNING
+static inline void speex_warning(const char *str)
+{
+   speex_sys_warning(str);
+}
+
+#define OVERRIDE_SPEEX_WARNING_INT
+static inline void speex_warning_int(const char *str, int val)
+{
+   speex_sys_warning_int(str, val);
+}
+
+#define OVERRIDE_SPEEX_NOTIFY
+static inline void speex_notify(const char *str)
+{
+   speex_sys_notify(str);
+}
+
+#ifdef SPEEX_SYS_NO_LIBC
+
+/* There is nowhere to print to */
+#define OVERRIDE_SPEEX_PUTC
+static inline void _speex_putc(int ch, void *file)
+{
+   (void)ch;
+   (void)file;
+}
+
+#endif
+
+#endif
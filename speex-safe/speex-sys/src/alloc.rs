@@ -0,0 +1,180 @@
+////////////////////////////////////////////////////////////////////////////////
+// Copyright (c) 2023.                                                         /
+// This Source Code Form is subject to the terms of the Mozilla Public License,/
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can    /
+// obtain one at http://mozilla.org/MPL/2.0/.                                  /
+////////////////////////////////////////////////////////////////////////////////
+
+//! Allocation for libspeex, declared in `shim/os_support_custom.h`
+//!
+//! Memory comes from Rust's global allocator, or from the [`Arena`] in scope
+//! on the allocating thread.
+
+use std::alloc::{alloc_zeroed, dealloc, handle_alloc_error, realloc, Layout};
+use std::cell::Cell;
+use std::ffi::{c_int, c_void};
+use std::panic;
+use std::ptr::{self, NonNull};
+
+use crate::FatalError;
+
+/// Space in front of each allocation holding its size and where it came from,
+/// as `free` isn't told either. Large enough to keep the allocation itself 16
+/// byte aligned.
+const HEADER: usize = 16;
+
+/// Marks an allocation taken from an arena, which is never freed on its own
+const IN_ARENA: usize = 1;
+
+thread_local! {
+    static CURRENT_ARENA: Cell<*const Arena> = const { Cell::new(ptr::null()) };
+}
+
+fn layout(size: usize) -> Layout {
+    Layout::from_size_align(HEADER + size, HEADER).expect("allocation too large")
+}
+
+/// Fills in the header at `base`, returning the allocation after it
+unsafe fn finish(base: *mut u8, size: usize, source: usize) -> *mut c_void {
+    let header = base as *mut usize;
+    header.write(size);
+    header.add(1).write(source);
+    base.add(HEADER) as *mut c_void
+}
+
+/// Size and source of the allocation at `ptr`
+unsafe fn header(ptr: *mut c_void) -> (*mut u8, usize, usize) {
+    let base = (ptr as *mut u8).sub(HEADER);
+    let header = base as *const usize;
+    (base, header.read(), header.add(1).read())
+}
+
+/// Zeroed allocation, standing in for `calloc`
+///
+/// Running out of room in an arena is a fatal error, unwinding like the ones
+/// libspeex raises itself.
+#[no_mangle]
+pub unsafe extern "C-unwind" fn speex_sys_alloc(size: c_int) -> *mut c_void {
+    let size = size.max(0) as usize;
+    if let Some(arena) = CURRENT_ARENA.get().as_ref() {
+        return match arena.take(HEADER + size) {
+            Some(base) => finish(base, size, IN_ARENA),
+            None => {
+                panic::resume_unwind(Box::new(FatalError {
+                    message: format!("Arena of {} bytes exhausted", arena.capacity()),
+                    file: file!().to_string(),
+                    line: line!(),
+                }))
+            }
+        };
+    }
+    let base = alloc_zeroed(layout(size));
+    if base.is_null() {
+        handle_alloc_error(layout(size));
+    }
+    finish(base, size, 0)
+}
+
+#[no_mangle]
+pub unsafe extern "C-unwind" fn speex_sys_realloc(ptr: *mut c_void, size: c_int) -> *mut c_void {
+    if ptr.is_null() {
+        return speex_sys_alloc(size);
+    }
+    let (base, old_size, source) = header(ptr);
+    if source == IN_ARENA || !CURRENT_ARENA.get().is_null() {
+        // Moves into or out of an arena
+        let new = speex_sys_alloc(size);
+        let size = size.max(0) as usize;
+        ptr::copy_nonoverlapping(ptr as *const u8, new as *mut u8, old_size.min(size));
+        speex_sys_free(ptr);
+        return new;
+    }
+    let size = size.max(0) as usize;
+    let base = realloc(base, layout(old_size), HEADER + size);
+    if base.is_null() {
+        handle_alloc_error(layout(size));
+    }
+    finish(base, size, 0)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn speex_sys_free(ptr: *mut c_void) {
+    if ptr.is_null() {
+        return;
+    }
+    let (base, size, source) = header(ptr);
+    if source != IN_ARENA {
+        dealloc(base, layout(size));
+    }
+}
+
+/// A fixed block of memory for libspeex to allocate from, so that creating an
+/// encoder or decoder doesn't touch the global allocator
+///
+/// Memory taken from the arena is only given back when the arena is dropped,
+/// which must not happen before the state allocated in it is destroyed.
+pub struct Arena {
+    start: NonNull<u8>,
+    capacity: usize,
+    used: Cell<usize>,
+}
+
+// SAFETY: the arena owns its memory, and is only used through `&self` on the
+// thread running `scope`. It isn't Sync, `used` is a plain Cell.
+unsafe impl Send for Arena {}
+
+impl Arena {
+    /// Allocates an arena of `capacity` bytes from the global allocator
+    pub fn new(capacity: usize) -> Self {
+        let layout = Layout::from_size_align(capacity.max(1), HEADER).expect("arena too large");
+        let start = unsafe { alloc_zeroed(layout) };
+        let start = NonNull::new(start).unwrap_or_else(|| handle_alloc_error(layout));
+        Self {
+            start,
+            capacity,
+            used: Cell::new(0),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Bytes handed out so far, including the bookkeeping of each allocation
+    pub fn used(&self) -> usize {
+        self.used.get()
+    }
+
+    /// Runs `f` with the allocations libspeex makes on this thread taken from
+    /// the arena
+    pub fn scope<R>(&self, f: impl FnOnce() -> R) -> R {
+        struct Restore(*const Arena);
+
+        impl Drop for Restore {
+            fn drop(&mut self) {
+                CURRENT_ARENA.set(self.0);
+            }
+        }
+
+        let _restore = Restore(CURRENT_ARENA.replace(self));
+        f()
+    }
+
+    /// Takes `size` bytes, still zeroed as nothing is handed out twice
+    fn take(&self, size: usize) -> Option<*mut u8> {
+        let start = self.used.get();
+        let end = start.checked_add(size.checked_next_multiple_of(HEADER)?)?;
+        if end > self.capacity {
+            return None;
+        }
+        self.used.set(end);
+        Some(unsafe { self.start.as_ptr().add(start) })
+    }
+}
+
+impl Drop for Arena {
+    fn drop(&mut self) {
+        let layout = Layout::from_size_align(self.capacity.max(1), HEADER).unwrap();
+        unsafe { dealloc(self.start.as_ptr(), layout) }
+    }
+}
@@ -10,10 +10,19 @@
 
 include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
 
+mod alloc;
+mod os_support;
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+mod wasm;
+
+pub use alloc::Arena;
+
+pub use os_support::{catch_fatal, FatalError};
+
 #[cfg(test)]
 mod test {
-    use std::ffi::{c_char, c_void, CStr};
-    use std::ptr::null;
+    use std::ffi::{c_char, c_int, c_void, CStr};
+    use std::ptr::{null, null_mut};
 
     use super::*;
 
@@ -29,4 +38,95 @@ mod test {
         let version_str = format!("{c_str:?}");
         assert_eq!(version_str, "\"speex-1.2.1\"".to_string())
     }
+
+    // Parts of kiss_fft.h, which isn't a public header
+    #[repr(C)]
+    #[derive(Copy, Clone, Default)]
+    struct kiss_fft_cpx {
+        r: f32,
+        i: f32,
+    }
+
+    extern "C-unwind" {
+        fn kiss_fft_alloc(
+            nfft: c_int,
+            inverse_fft: c_int,
+            mem: *mut c_void,
+            lenmem: *mut usize,
+        ) -> *mut c_void;
+        fn kiss_fft(cfg: *mut c_void, fin: *const kiss_fft_cpx, fout: *mut kiss_fft_cpx);
+    }
+
+    #[test]
+    fn fatal_error_is_caught() {
+        let mut len = 0;
+        unsafe { kiss_fft_alloc(64, 0, null_mut(), &mut len) };
+        let mut mem = vec![0u64; len.div_ceil(8)];
+        let cfg = unsafe { kiss_fft_alloc(64, 0, mem.as_mut_ptr() as *mut c_void, &mut len) };
+        assert!(!cfg.is_null());
+
+        let mut buf = [kiss_fft_cpx::default(); 64];
+        let ptr = buf.as_mut_ptr();
+        let err = catch_fatal(|| unsafe { kiss_fft(cfg, ptr, ptr) }).unwrap_err();
+        assert_eq!(err.message, "In-place FFT not supported");
+        assert!(err.file.ends_with("kiss_fft.c"));
+
+        let mut out = [kiss_fft_cpx::default(); 64];
+        catch_fatal(|| unsafe { kiss_fft(cfg, buf.as_ptr(), out.as_mut_ptr()) }).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "not a fatal error")]
+    fn other_panics_unwind() {
+        let _ = catch_fatal(|| panic!("not a fatal error"));
+    }
+
+    #[cfg(feature = "log")]
+    #[test]
+    fn notifications_are_logged() {
+        use std::sync::Mutex;
+
+        struct Capture(Mutex<Vec<(log::Level, String)>>);
+
+        impl log::Log for Capture {
+            fn enabled(&self, metadata: &log::Metadata) -> bool {
+                metadata.target() == "speex"
+            }
+
+            fn log(&self, record: &log::Record) {
+                if self.enabled(record.metadata()) {
+                    let entry = (record.level(), record.args().to_string());
+                    self.0.lock().unwrap().push(entry);
+                }
+            }
+
+            fn flush(&self) {}
+        }
+
+        static CAPTURE: Capture = Capture(Mutex::new(Vec::new()));
+        log::set_logger(&CAPTURE).unwrap();
+        log::set_max_level(log::LevelFilter::Info);
+
+        let packet = [0x50u8; 38];
+        let mut out = [0f32; 160];
+        unsafe {
+            let state = speex_decoder_init(&speex_nb_mode);
+            let mut bits = std::mem::zeroed::<SpeexBits>();
+            speex_bits_init(&mut bits);
+            speex_bits_read_from(
+                &mut bits,
+                packet.as_ptr() as *const c_char,
+                packet.len() as i32,
+            );
+            speex_decode(state, &mut bits, out.as_mut_ptr());
+            speex_bits_destroy(&mut bits);
+            speex_decoder_destroy(state);
+        }
+
+        let logged = CAPTURE.0.lock().unwrap();
+        assert!(logged.contains(&(
+            log::Level::Info,
+            "Invalid mode encountered. The stream is corrupted.".to_string()
+        )));
+    }
 }
@@ -0,0 +1,122 @@
+////////////////////////////////////////////////////////////////////////////////
+// Copyright (c) 2023.                                                         /
+// This Source Code Form is subject to the terms of the Mozilla Public License,/
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can    /
+// obtain one at http://mozilla.org/MPL/2.0/.                                  /
+////////////////////////////////////////////////////////////////////////////////
+
+//! Warnings and fatal errors of libspeex, declared in
+//! `shim/os_support_custom.h`
+//!
+//! Warnings and notifications go to the `log` and `tracing` facades when those
+//! features are enabled, and are dropped otherwise rather than written to
+//! stderr like libspeex itself does. Fatal errors unwind out of libspeex, to be
+//! caught by [`catch_fatal`].
+
+use std::error::Error;
+use std::ffi::{c_char, c_int, CStr};
+use std::fmt::{self, Display, Formatter};
+use std::panic::{self, AssertUnwindSafe};
+
+/// Target of the records logged for libspeex
+#[cfg(any(feature = "log", feature = "tracing"))]
+const TARGET: &str = "speex";
+
+#[derive(Copy, Clone)]
+enum Level {
+    Error,
+    Warn,
+    Info,
+}
+
+fn report(level: Level, message: &str) {
+    #[cfg(feature = "log")]
+    {
+        let level = match level {
+            Level::Error => log::Level::Error,
+            Level::Warn => log::Level::Warn,
+            Level::Info => log::Level::Info,
+        };
+        log::log!(target: TARGET, level, "{message}");
+    }
+    #[cfg(feature = "tracing")]
+    match level {
+        Level::Error => tracing::error!(target: TARGET, "{message}"),
+        Level::Warn => tracing::warn!(target: TARGET, "{message}"),
+        Level::Info => tracing::info!(target: TARGET, "{message}"),
+    }
+    // Without either facade there's nowhere a library should be writing to
+    #[cfg(not(any(feature = "log", feature = "tracing")))]
+    let _ = (level, message);
+}
+
+/// Some messages end with a newline of their own
+unsafe fn message(str: *const c_char) -> String {
+    CStr::from_ptr(str).to_string_lossy().trim_end().to_string()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn speex_sys_warning(str: *const c_char) {
+    report(Level::Warn, &message(str));
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn speex_sys_warning_int(str: *const c_char, val: c_int) {
+    report(Level::Warn, &format!("{} {val}", message(str)));
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn speex_sys_notify(str: *const c_char) {
+    report(Level::Info, &message(str));
+}
+
+/// Unwinds out of libspeex instead of exiting the process
+#[no_mangle]
+pub unsafe extern "C-unwind" fn speex_sys_fatal(
+    str: *const c_char,
+    file: *const c_char,
+    line: c_int,
+) {
+    let error = FatalError {
+        message: message(str),
+        file: message(file),
+        line: line as u32,
+    };
+    report(Level::Error, &error.to_string());
+    panic::resume_unwind(Box::new(error));
+}
+
+/// An internal error libspeex can't carry on from, such as a failed assertion
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FatalError {
+    pub message: String,
+    /// Source file of libspeex the error was raised in
+    pub file: String,
+    pub line: u32,
+}
+
+impl Display for FatalError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Fatal (internal) error in {}, line {}: {}",
+            self.file, self.line, self.message
+        )
+    }
+}
+
+impl Error for FatalError {}
+
+/// Runs `f`, returning a fatal error libspeex raised while it ran as an `Err`
+///
+/// Fatal errors unwind out of libspeex without cleaning up its state, which
+/// should be dropped rather than used again. Other panics carry on unwinding.
+/// Where panics abort, such as on wasm32-unknown-unknown, so do fatal errors.
+pub fn catch_fatal<R>(f: impl FnOnce() -> R) -> Result<R, FatalError> {
+    panic::catch_unwind(AssertUnwindSafe(f)).map_err(|payload| {
+        match payload.downcast::<FatalError>() {
+            Ok(error) => *error,
+            Err(payload) => panic::resume_unwind(payload),
+        }
+    })
+}
@@ -0,0 +1,32 @@
+////////////////////////////////////////////////////////////////////////////////
+// Copyright (c) 2023.                                                         /
+// This Source Code Form is subject to the terms of the Mozilla Public License,/
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can    /
+// obtain one at http://mozilla.org/MPL/2.0/.                                  /
+////////////////////////////////////////////////////////////////////////////////
+
+//! The libc math functions libspeex needs on wasm32-unknown-unknown, declared
+//! in the headers under `wasm/include`
+
+macro_rules! math_functions {
+    ($($name:ident => $f:ident($($arg:ident),+);)*) => {
+        $(
+            #[no_mangle]
+            pub extern "C" fn $name($($arg: f64),+) -> f64 {
+                math_functions!(@call $f, $($arg),+)
+            }
+        )*
+    };
+    (@call $f:ident, $x:ident) => { $x.$f() };
+    (@call $f:ident, $x:ident, $y:ident) => { $x.$f($y) };
+}
+
+math_functions! {
+    speex_sys_acos => acos(x);
+    speex_sys_atan => atan(x);
+    speex_sys_cos => cos(x);
+    speex_sys_exp => exp(x);
+    speex_sys_log => ln(x);
+    speex_sys_pow => powf(x, y);
+    speex_sys_sin => sin(x);
+}
@@ -0,0 +1,35 @@
+////////////////////////////////////////////////////////////////////////////////
+// Copyright (c) 2023.                                                         /
+// This Source Code Form is subject to the terms of the Mozilla Public License,/
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can    /
+// obtain one at http://mozilla.org/MPL/2.0/.                                  /
+////////////////////////////////////////////////////////////////////////////////
+
+/* The math functions used by libspeex, for targets without a libc. They are
+ * implemented in Rust, in src/wasm.rs */
+
+#ifndef SPEEX_SYS_MATH_H
+#define SPEEX_SYS_MATH_H
+
+double speex_sys_acos(double x);
+double speex_sys_atan(double x);
+double speex_sys_cos(double x);
+double speex_sys_exp(double x);
+double speex_sys_log(double x);
+double speex_sys_pow(double x, double y);
+double speex_sys_sin(double x);
+
+#define acos(x) speex_sys_acos(x)
+#define atan(x) speex_sys_atan(x)
+#define cos(x) speex_sys_cos(x)
+#define exp(x) speex_sys_exp(x)
+#define log(x) speex_sys_log(x)
+#define pow(x, y) speex_sys_pow(x, y)
+#define sin(x) speex_sys_sin(x)
+
+/* These compile down to single WebAssembly instructions */
+#define fabs(x) __builtin_fabs(x)
+#define floor(x) __builtin_floor(x)
+#define sqrt(x) __builtin_sqrt(x)
+
+#endif
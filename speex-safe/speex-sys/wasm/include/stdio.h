@@ -0,0 +1,22 @@
+////////////////////////////////////////////////////////////////////////////////
+// Copyright (c) 2023.                                                         /
+// This Source Code Form is subject to the terms of the Mozilla Public License,/
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can    /
+// obtain one at http://mozilla.org/MPL/2.0/.                                  /
+////////////////////////////////////////////////////////////////////////////////
+
+/* Only referenced by debugging helpers that are never called, so nothing is
+ * linked in */
+
+#ifndef SPEEX_SYS_STDIO_H
+#define SPEEX_SYS_STDIO_H
+
+typedef struct FILE FILE;
+
+int printf(const char *format, ...);
+int fprintf(FILE *file, const char *format, ...);
+int sprintf(char *str, const char *format, ...);
+FILE *fopen(const char *path, const char *mode);
+int fclose(FILE *file);
+
+#endif
@@ -0,0 +1,16 @@
+////////////////////////////////////////////////////////////////////////////////
+// Copyright (c) 2023.                                                         /
+// This Source Code Form is subject to the terms of the Mozilla Public License,/
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can    /
+// obtain one at http://mozilla.org/MPL/2.0/.                                  /
+////////////////////////////////////////////////////////////////////////////////
+
+/* Allocation goes through os_support_custom.h instead, so only the types
+ * are needed */
+
+#ifndef SPEEX_SYS_STDLIB_H
+#define SPEEX_SYS_STDLIB_H
+
+#include <stddef.h>
+
+#endif
@@ -11,3 +11,12 @@
 #include "speex/include/speex/speex_header.h"
 #include "speex/include/speex/speex_stereo.h"
 #include "speex/include/speex/speex_types.h"
+
+/* Requests for internal state, from libspeex/modes.h. That header isn't
+ * public and drags the internals in with it, so they are repeated here */
+#define SPEEX_GET_PI_GAIN 100
+#define SPEEX_GET_EXC 101
+#define SPEEX_GET_INNOV 102
+#define SPEEX_GET_DTX_STATUS 103
+#define SPEEX_SET_INNOVATION_SAVE 104
+#define SPEEX_SET_WIDEBAND 105
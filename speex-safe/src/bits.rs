@@ -19,6 +19,11 @@ pub struct SpeexBits<'a> {
     backing: SysBits,
 }
 
+// SAFETY: the raw pointer in `backing` is to a buffer that's either allocated
+// for these bits alone or the one borrowed mutably for 'a in `buffer_ref`, so
+// nothing on another thread can reach it.
+unsafe impl Send for SpeexBits<'_> {}
+
 impl<'a> SpeexBits<'a> {
     pub(crate) fn backing_mut_ptr(&mut self) -> *mut SysBits {
         &mut self.backing as *mut SysBits
@@ -0,0 +1,377 @@
+////////////////////////////////////////////////////////////////////////////////
+// Copyright (c) 2023.                                                         /
+// This Source Code Form is subject to the terms of the Mozilla Public License,/
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can    /
+// obtain one at http://mozilla.org/MPL/2.0/.                                  /
+////////////////////////////////////////////////////////////////////////////////
+
+use std::borrow::Cow;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+/// A field of a comment packet
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum CommentField {
+    /// The vendor string, or its length
+    Vendor,
+    /// The number of comments
+    CommentCount,
+    /// The comment at the given index, or its length
+    Comment(usize),
+}
+
+impl Display for CommentField {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CommentField::Vendor => write!(f, "vendor string"),
+            CommentField::CommentCount => write!(f, "comment count"),
+            CommentField::Comment(index) => write!(f, "comment {index}"),
+        }
+    }
+}
+
+/// Error type for reading and editing comments
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum CommentsError {
+    /// The packet ends within the given field
+    UnexpectedEnd(CommentField),
+    /// The packet claims more comments than it has room for
+    TooManyComments(u32),
+    /// A key is empty, or contains `=` or characters outside of printable
+    /// ASCII
+    InvalidKey(String),
+    /// A field is too long for its length to fit in 32 bits
+    TooLong,
+}
+
+impl Display for CommentsError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CommentsError::UnexpectedEnd(field) => {
+                write!(f, "Comment packet ends within the {field}")
+            }
+            CommentsError::TooManyComments(count) => {
+                write!(f, "Comment packet is too short to hold {count} comments")
+            }
+            CommentsError::InvalidKey(key) => write!(f, "Invalid comment key {key:?}"),
+            CommentsError::TooLong => write!(f, "Comment is too long"),
+        }
+    }
+}
+
+impl Error for CommentsError {}
+
+/// Vorbis-style comments, stored in the second packet of an Ogg Speex stream
+///
+/// The packet holds a vendor string followed by a list of `KEY=value`
+/// comments. Fields are kept as the bytes they were read as, so a packet
+/// with invalid UTF-8, comments without a `=` or data after the last comment
+/// is written back out unchanged. Invalid UTF-8 is replaced when reading
+/// values as strings, and can be found with `get_utf8_errors`.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct SpeexComments {
+    vendor: Vec<u8>,
+    comments: Vec<Vec<u8>>,
+    // Anything after the last comment, such as the framing bit Vorbis adds
+    trailing: Vec<u8>,
+}
+
+fn take<'a>(
+    rest: &mut &'a [u8],
+    len: usize,
+    field: CommentField,
+) -> Result<&'a [u8], CommentsError> {
+    if rest.len() < len {
+        return Err(CommentsError::UnexpectedEnd(field));
+    }
+    let (taken, remaining) = rest.split_at(len);
+    *rest = remaining;
+    Ok(taken)
+}
+
+fn read_u32(rest: &mut &[u8], field: CommentField) -> Result<u32, CommentsError> {
+    let bytes = take(rest, 4, field)?;
+    Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+fn check_length(bytes: &[u8]) -> Result<(), CommentsError> {
+    u32::try_from(bytes.len())
+        .map(|_| ())
+        .map_err(|_| CommentsError::TooLong)
+}
+
+fn check_key(key: &str) -> Result<(), CommentsError> {
+    let valid = !key.is_empty() && key.bytes().all(|b| (0x20..=0x7D).contains(&b) && b != b'=');
+    if valid {
+        Ok(())
+    } else {
+        Err(CommentsError::InvalidKey(key.to_string()))
+    }
+}
+
+/// Splits a comment into its key and value, if it has a `=`
+fn split_comment(comment: &[u8]) -> Option<(&[u8], &[u8])> {
+    let split = comment.iter().position(|&b| b == b'=')?;
+    Some((&comment[..split], &comment[split + 1..]))
+}
+
+impl SpeexComments {
+    /// Creates an empty set of comments with the given vendor string
+    pub fn new(vendor: &str) -> Self {
+        Self {
+            vendor: vendor.as_bytes().to_vec(),
+            comments: Vec::new(),
+            trailing: Vec::new(),
+        }
+    }
+
+    /// Parses the comments from the second packet of a speex stream
+    pub fn from_packet(packet: &[u8]) -> Result<Self, CommentsError> {
+        let mut rest = packet;
+        let vendor_len = read_u32(&mut rest, CommentField::Vendor)?;
+        let vendor = take(&mut rest, vendor_len as usize, CommentField::Vendor)?.to_vec();
+
+        let count = read_u32(&mut rest, CommentField::CommentCount)?;
+        // Every comment takes at least its length, so don't trust a count
+        // that couldn't fit before allocating for it
+        if count as usize > rest.len() / 4 {
+            return Err(CommentsError::TooManyComments(count));
+        }
+        let mut comments = Vec::with_capacity(count as usize);
+        for index in 0..count as usize {
+            let field = CommentField::Comment(index);
+            let len = read_u32(&mut rest, field)?;
+            comments.push(take(&mut rest, len as usize, field)?.to_vec());
+        }
+
+        Ok(Self {
+            vendor,
+            comments,
+            trailing: rest.to_vec(),
+        })
+    }
+
+    /// Serializes the comments into the second packet of a speex stream
+    pub fn make_packet(&self) -> Vec<u8> {
+        let len = 8
+            + self.vendor.len()
+            + self.comments.iter().map(|c| 4 + c.len()).sum::<usize>()
+            + self.trailing.len();
+        let mut packet = Vec::with_capacity(len);
+        packet.extend_from_slice(&(self.vendor.len() as u32).to_le_bytes());
+        packet.extend_from_slice(&self.vendor);
+        packet.extend_from_slice(&(self.comments.len() as u32).to_le_bytes());
+        for comment in &self.comments {
+            packet.extend_from_slice(&(comment.len() as u32).to_le_bytes());
+            packet.extend_from_slice(comment);
+        }
+        packet.extend_from_slice(&self.trailing);
+        packet
+    }
+
+    /// Gets the vendor string, replacing any invalid UTF-8
+    pub fn get_vendor(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.vendor)
+    }
+
+    /// Sets the vendor string
+    pub fn set_vendor(&mut self, vendor: &str) -> Result<(), CommentsError> {
+        check_length(vendor.as_bytes())?;
+        self.vendor = vendor.as_bytes().to_vec();
+        Ok(())
+    }
+
+    /// Gets the number of comments, including malformed ones
+    pub fn len(&self) -> usize {
+        self.comments.len()
+    }
+
+    /// Whether there are no comments
+    pub fn is_empty(&self) -> bool {
+        self.comments.is_empty()
+    }
+
+    /// Iterates over the keys and values of all comments that have a `=`,
+    /// replacing any invalid UTF-8
+    pub fn iter(&self) -> impl Iterator<Item = (Cow<'_, str>, Cow<'_, str>)> {
+        self.comments.iter().filter_map(|comment| {
+            let (key, value) = split_comment(comment)?;
+            Some((String::from_utf8_lossy(key), String::from_utf8_lossy(value)))
+        })
+    }
+
+    /// Gets the value of the first comment with the given key
+    ///
+    /// Keys are compared ignoring ASCII case, as in the Vorbis comment spec.
+    pub fn get(&self, key: &str) -> Option<Cow<'_, str>> {
+        self.get_all(key).into_iter().next()
+    }
+
+    /// Gets the values of all comments with the given key
+    pub fn get_all(&self, key: &str) -> Vec<Cow<'_, str>> {
+        self.comments
+            .iter()
+            .filter_map(|comment| split_comment(comment))
+            .filter(|(k, _)| k.eq_ignore_ascii_case(key.as_bytes()))
+            .map(|(_, value)| String::from_utf8_lossy(value))
+            .collect()
+    }
+
+    /// Appends a `KEY=value` comment, keeping any existing ones with the same
+    /// key
+    pub fn add(&mut self, key: &str, value: &str) -> Result<(), CommentsError> {
+        check_key(key)?;
+        let comment = format!("{key}={value}").into_bytes();
+        check_length(&comment)?;
+        self.comments.push(comment);
+        Ok(())
+    }
+
+    /// Replaces all comments with the given key with a single one
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), CommentsError> {
+        check_key(key)?;
+        self.remove(key);
+        self.add(key, value)
+    }
+
+    /// Removes all comments with the given key, returning how many were
+    /// removed
+    pub fn remove(&mut self, key: &str) -> usize {
+        let before = self.comments.len();
+        self.comments.retain(|comment| {
+            match split_comment(comment) {
+                Some((k, _)) => !k.eq_ignore_ascii_case(key.as_bytes()),
+                None => true,
+            }
+        });
+        before - self.comments.len()
+    }
+
+    /// Gets the comments exactly as they are stored in the packet
+    pub fn get_raw_comments(&self) -> &[Vec<u8>] {
+        &self.comments
+    }
+
+    /// Appends a comment as is, without checking its contents
+    pub fn add_raw(&mut self, comment: Vec<u8>) -> Result<(), CommentsError> {
+        check_length(&comment)?;
+        self.comments.push(comment);
+        Ok(())
+    }
+
+    /// Gets the fields that aren't valid UTF-8
+    pub fn get_utf8_errors(&self) -> Vec<CommentField> {
+        let vendor = std::str::from_utf8(&self.vendor)
+            .is_err()
+            .then_some(CommentField::Vendor);
+        let comments = self
+            .comments
+            .iter()
+            .enumerate()
+            .filter(|(_, comment)| std::str::from_utf8(comment).is_err())
+            .map(|(index, _)| CommentField::Comment(index));
+        vendor.into_iter().chain(comments).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn packet(vendor: &[u8], comments: &[&[u8]], trailing: &[u8]) -> Vec<u8> {
+        let mut packet = Vec::new();
+        packet.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+        packet.extend_from_slice(vendor);
+        packet.extend_from_slice(&(comments.len() as u32).to_le_bytes());
+        for comment in comments {
+            packet.extend_from_slice(&(comment.len() as u32).to_le_bytes());
+            packet.extend_from_slice(comment);
+        }
+        packet.extend_from_slice(trailing);
+        packet
+    }
+
+    #[test]
+    fn reads_comments() {
+        let data = packet(b"vendor", &[b"TITLE=a", b"artist=b", b"ARTIST=c"], &[]);
+        let comments = SpeexComments::from_packet(&data).unwrap();
+        assert_eq!(comments.get_vendor(), "vendor");
+        assert_eq!(comments.len(), 3);
+        assert_eq!(comments.get("title").unwrap(), "a");
+        assert_eq!(comments.get_all("Artist"), vec!["b", "c"]);
+        assert_eq!(comments.get("album"), None);
+        assert!(comments.get_utf8_errors().is_empty());
+    }
+
+    #[test]
+    fn round_trips_unknown_fields() {
+        let data = packet(
+            b"ven\xffdor",
+            &[b"no equals sign", b"KEY=\xfe\xff", b""],
+            &[1, 2, 3],
+        );
+        let comments = SpeexComments::from_packet(&data).unwrap();
+        assert_eq!(comments.make_packet(), data);
+        assert_eq!(comments.get_vendor(), "ven\u{fffd}dor");
+        assert_eq!(comments.get("key").unwrap(), "\u{fffd}\u{fffd}");
+        assert_eq!(
+            comments.get_utf8_errors(),
+            vec![CommentField::Vendor, CommentField::Comment(1)]
+        );
+        assert_eq!(comments.iter().count(), 1);
+    }
+
+    #[test]
+    fn rejects_bad_lengths() {
+        let data = packet(b"vendor", &[b"A=b"], &[]);
+        for len in 0..data.len() {
+            assert!(
+                SpeexComments::from_packet(&data[..len]).is_err(),
+                "accepted {len} bytes"
+            );
+        }
+        assert_eq!(
+            SpeexComments::from_packet(&data[..3]),
+            Err(CommentsError::UnexpectedEnd(CommentField::Vendor))
+        );
+        assert_eq!(
+            SpeexComments::from_packet(&data[..data.len() - 1]),
+            Err(CommentsError::UnexpectedEnd(CommentField::Comment(0)))
+        );
+
+        let mut data = packet(b"", &[], &[]);
+        data[4..8].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(
+            SpeexComments::from_packet(&data),
+            Err(CommentsError::TooManyComments(u32::MAX))
+        );
+    }
+
+    #[test]
+    fn edits_comments() {
+        let mut comments = SpeexComments::new("vendor");
+        comments.add("TITLE", "first").unwrap();
+        comments.add("title", "second").unwrap();
+        comments.add("ARTIST", "someone").unwrap();
+        comments.set("Title", "only").unwrap();
+        assert_eq!(comments.get_all("TITLE"), vec!["only"]);
+        assert_eq!(comments.remove("artist"), 1);
+        assert_eq!(comments.len(), 1);
+
+        assert!(matches!(
+            comments.add("A=B", "c"),
+            Err(CommentsError::InvalidKey(_))
+        ));
+        assert!(matches!(
+            comments.add("", "c"),
+            Err(CommentsError::InvalidKey(_))
+        ));
+        assert!(matches!(
+            comments.add("\u{e9}", "c"),
+            Err(CommentsError::InvalidKey(_))
+        ));
+
+        let parsed = SpeexComments::from_packet(&comments.make_packet()).unwrap();
+        assert_eq!(parsed, comments);
+    }
+}
@@ -0,0 +1,465 @@
+////////////////////////////////////////////////////////////////////////////////
+// Copyright (c) 2023.                                                         /
+// This Source Code Form is subject to the terms of the Mozilla Public License,/
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can    /
+// obtain one at http://mozilla.org/MPL/2.0/.                                  /
+////////////////////////////////////////////////////////////////////////////////
+
+use std::error::Error;
+use std::fmt::Display;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{DynamicEncoder, ModeId, NbSubmodeId, WbSubmodeId};
+
+/// How the encoder picks the bitrate of each frame
+#[derive(Copy, Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum RateControl {
+    /// Constant bitrate picked from a quality between 0 and 10
+    Quality(i32),
+    /// Constant bitrate, using the highest quality that does not exceed the
+    /// given bits per second
+    Bitrate(i32),
+    /// Variable BitRate with a quality between 0 and 10, optionally capped at
+    /// a maximum number of bits per second
+    Vbr {
+        quality: f32,
+        max_bitrate: Option<i32>,
+    },
+    /// Average BitRate, adjusting the VBR quality to hit the given bits per
+    /// second on average
+    Abr(i32),
+}
+
+impl RateControl {
+    /// Whether the bitrate changes from frame to frame
+    pub fn is_variable(&self) -> bool {
+        matches!(self, RateControl::Vbr { .. } | RateControl::Abr(_))
+    }
+}
+
+impl Default for RateControl {
+    /// The library default, quality 8
+    fn default() -> Self {
+        RateControl::Quality(8)
+    }
+}
+
+/// Error type for an `EncoderConfig` that can't be applied to an encoder
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ConfigError {
+    /// The quality was outside of 0 to 10
+    InvalidQuality(f32),
+    /// A bitrate was zero or negative
+    InvalidBitrate(i32),
+    /// The complexity was outside of 1 to 10
+    InvalidComplexity(i32),
+    /// The sampling rate was zero or negative
+    InvalidSamplingRate(i32),
+    /// A high submode was given for a mode without a configurable wideband
+    /// layer
+    NoHighSubmode(ModeId),
+    /// Submodes were given along with VBR or ABR, which pick the submode of
+    /// each frame themselves
+    SubmodeWithVariableBitrate,
+    /// DTX was enabled without VAD, VBR or ABR, so no frame is ever detected
+    /// as silent
+    DtxWithoutVad,
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::InvalidQuality(quality) => {
+                write!(f, "Quality {quality} is not between 0 and 10")
+            }
+            ConfigError::InvalidBitrate(bitrate) => {
+                write!(f, "Bitrate {bitrate} is not positive")
+            }
+            ConfigError::InvalidComplexity(complexity) => {
+                write!(f, "Complexity {complexity} is not between 1 and 10")
+            }
+            ConfigError::InvalidSamplingRate(rate) => {
+                write!(f, "Sampling rate {rate} is not positive")
+            }
+            ConfigError::NoHighSubmode(mode) => {
+                write!(f, "{mode:?} mode has no configurable high submode")
+            }
+            ConfigError::SubmodeWithVariableBitrate => {
+                write!(f, "Submodes can't be set when using VBR or ABR")
+            }
+            ConfigError::DtxWithoutVad => {
+                write!(f, "DTX requires VAD, VBR or ABR to be enabled")
+            }
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+/// Complete set of encoder settings, applied in one go with `build`
+///
+/// Several of the encoder's controls overwrite each other: setting the
+/// quality, bitrate or ABR target picks new submodes, and setting the VBR
+/// quality also sets the quality. `build` applies the settings in an order
+/// where none of them are lost.
+///
+/// With the `serde` feature, this can be read from and written to
+/// configuration files. Fields left out take their default values.
+#[derive(Copy, Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct EncoderConfig {
+    /// Mode to encode with
+    pub mode: ModeId,
+    /// How the bitrate is picked
+    pub rate_control: RateControl,
+    /// Whether to use Voice Activity Detection. VBR and ABR always detect
+    /// voice activity
+    pub vad: bool,
+    /// Whether to use Discontinuous Transmission
+    pub dtx: bool,
+    /// Analysis complexity between 1 and 10
+    pub complexity: i32,
+    /// Whether the encoder's highpass filter is used
+    pub highpass: bool,
+    /// Sampling rate used for bitrate computation, the mode's own rate when
+    /// `None`
+    pub sampling_rate: Option<i32>,
+    /// Narrowband submode, overriding the one picked by the rate control
+    pub low_submode: Option<NbSubmodeId>,
+    /// Wideband submode, overriding the one picked by the rate control. Only
+    /// valid in wideband mode
+    pub high_submode: Option<WbSubmodeId>,
+}
+
+impl Default for EncoderConfig {
+    fn default() -> Self {
+        Self::new(ModeId::NarrowBand)
+    }
+}
+
+impl EncoderConfig {
+    /// Creates a config with the library defaults for the given mode
+    pub fn new(mode: ModeId) -> Self {
+        Self {
+            mode,
+            rate_control: RateControl::default(),
+            vad: false,
+            dtx: false,
+            complexity: 2,
+            highpass: true,
+            sampling_rate: None,
+            low_submode: None,
+            high_submode: None,
+        }
+    }
+
+    /// Checks that the settings are in range and compatible with each other
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let check_bitrate = |bitrate: i32| {
+            if bitrate > 0 {
+                Ok(())
+            } else {
+                Err(ConfigError::InvalidBitrate(bitrate))
+            }
+        };
+        match self.rate_control {
+            RateControl::Quality(quality) => {
+                if !(0..=10).contains(&quality) {
+                    return Err(ConfigError::InvalidQuality(quality as f32));
+                }
+            }
+            RateControl::Vbr {
+                quality,
+                max_bitrate,
+            } => {
+                if !(0.0..=10.0).contains(&quality) {
+                    return Err(ConfigError::InvalidQuality(quality));
+                }
+                if let Some(max_bitrate) = max_bitrate {
+                    check_bitrate(max_bitrate)?;
+                }
+            }
+            RateControl::Bitrate(bitrate) | RateControl::Abr(bitrate) => {
+                check_bitrate(bitrate)?;
+            }
+        }
+        if !(1..=10).contains(&self.complexity) {
+            return Err(ConfigError::InvalidComplexity(self.complexity));
+        }
+        if let Some(rate) = self.sampling_rate {
+            if rate <= 0 {
+                return Err(ConfigError::InvalidSamplingRate(rate));
+            }
+        }
+        if self.high_submode.is_some() && self.mode != ModeId::WideBand {
+            return Err(ConfigError::NoHighSubmode(self.mode));
+        }
+        let has_submodes = self.low_submode.is_some() || self.high_submode.is_some();
+        if has_submodes && self.rate_control.is_variable() {
+            return Err(ConfigError::SubmodeWithVariableBitrate);
+        }
+        if self.dtx && !self.vad && !self.rate_control.is_variable() {
+            return Err(ConfigError::DtxWithoutVad);
+        }
+        Ok(())
+    }
+
+    /// Creates an encoder with these settings
+    pub fn build(&self) -> Result<DynamicEncoder, ConfigError> {
+        self.validate()?;
+        let mut encoder = DynamicEncoder::new(self.mode);
+        self.apply(&mut encoder);
+        Ok(encoder)
+    }
+
+    /// Applies the settings to an existing encoder of the same mode
+    pub fn apply_to(&self, encoder: &mut DynamicEncoder) -> Result<(), ConfigError> {
+        self.validate()?;
+        assert_eq!(
+            self.mode,
+            encoder_mode(encoder),
+            "Encoder mode doesn't match the config"
+        );
+        self.apply(encoder);
+        Ok(())
+    }
+
+    fn apply(&self, encoder: &mut DynamicEncoder) {
+        encoder.set_complexity(self.complexity);
+        // The sampling rate has to come before anything that computes bitrates
+        if let Some(rate) = self.sampling_rate {
+            encoder.set_sampling_rate(rate);
+        }
+        // Turning ABR off also turns VBR off, in case this encoder had either
+        if !matches!(self.rate_control, RateControl::Abr(_)) {
+            encoder.set_abr(0);
+        }
+        match self.rate_control {
+            RateControl::Quality(quality) => encoder.set_quality(quality),
+            RateControl::Bitrate(bitrate) => encoder.set_bitrate(bitrate),
+            RateControl::Vbr {
+                quality,
+                max_bitrate,
+            } => {
+                encoder.set_vbr(true);
+                encoder.set_vbr_quality(quality);
+                encoder.set_vbr_max_bitrate(max_bitrate.unwrap_or(0));
+            }
+            RateControl::Abr(bitrate) => encoder.set_abr(bitrate),
+        }
+        // The rate control picks submodes, so explicit ones go after it
+        match encoder {
+            DynamicEncoder::Nb(inner) => {
+                if let Some(submode) = self.low_submode {
+                    inner.set_submode(submode);
+                }
+            }
+            DynamicEncoder::Wb(inner) => {
+                if let Some(submode) = self.low_submode {
+                    inner.set_low_submode(submode);
+                }
+                if let Some(submode) = self.high_submode {
+                    inner.set_high_submode(submode);
+                }
+            }
+            DynamicEncoder::Uwb(inner) => {
+                if let Some(submode) = self.low_submode {
+                    inner.set_low_submode(submode);
+                }
+            }
+        }
+        encoder.set_vad(self.vad);
+        encoder.set_dtx(self.dtx);
+        encoder.set_highpass(self.highpass);
+    }
+
+    /// Reads the current settings back from an encoder
+    ///
+    /// The encoder can't report the quality it was set to, so constant
+    /// bitrate encoders are described by their bitrate and narrowband
+    /// submode. The wideband submode can't be read back either, and is
+    /// always `None`.
+    pub fn from_encoder(encoder: &mut DynamicEncoder) -> Self {
+        let abr = encoder.get_abr();
+        let rate_control = if abr > 0 {
+            RateControl::Abr(abr)
+        } else if encoder.get_vbr() {
+            let max_bitrate = encoder.get_vbr_max_bitrate();
+            RateControl::Vbr {
+                quality: encoder.get_vbr_quality(),
+                max_bitrate: (max_bitrate > 0).then_some(max_bitrate),
+            }
+        } else {
+            RateControl::Bitrate(encoder.get_bitrate())
+        };
+        let vad = encoder.get_vad();
+        // With VAD the submode changes from frame to frame, and can even be
+        // the empty submode 0
+        let low_submode = if rate_control.is_variable() || vad {
+            None
+        } else {
+            Some(match encoder {
+                DynamicEncoder::Nb(inner) => inner.get_submode(),
+                DynamicEncoder::Wb(inner) => inner.get_low_submode(),
+                DynamicEncoder::Uwb(inner) => inner.get_low_submode(),
+            })
+        };
+
+        Self {
+            mode: encoder_mode(encoder),
+            rate_control,
+            vad,
+            dtx: encoder.get_dtx(),
+            complexity: encoder.get_complexity(),
+            highpass: encoder.get_highpass(),
+            sampling_rate: Some(encoder.get_sampling_rate()),
+            low_submode,
+            high_submode: None,
+        }
+    }
+}
+
+fn encoder_mode(encoder: &DynamicEncoder) -> ModeId {
+    match encoder {
+        DynamicEncoder::Nb(_) => ModeId::NarrowBand,
+        DynamicEncoder::Wb(_) => ModeId::WideBand,
+        DynamicEncoder::Uwb(_) => ModeId::UltraWideBand,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn validates_settings() {
+        let config = |modify: fn(&mut EncoderConfig)| {
+            let mut config = EncoderConfig::new(ModeId::WideBand);
+            modify(&mut config);
+            config.validate()
+        };
+
+        assert_eq!(config(|_| {}), Ok(()));
+        assert_eq!(
+            config(|c| c.rate_control = RateControl::Quality(11)),
+            Err(ConfigError::InvalidQuality(11.0))
+        );
+        assert_eq!(
+            config(|c| {
+                c.rate_control = RateControl::Vbr {
+                    quality: 5.0,
+                    max_bitrate: Some(0),
+                }
+            }),
+            Err(ConfigError::InvalidBitrate(0))
+        );
+        assert_eq!(
+            config(|c| c.complexity = 0),
+            Err(ConfigError::InvalidComplexity(0))
+        );
+        assert_eq!(
+            config(|c| {
+                c.mode = ModeId::UltraWideBand;
+                c.high_submode = Some(WbSubmodeId::NoQuantize);
+            }),
+            Err(ConfigError::NoHighSubmode(ModeId::UltraWideBand))
+        );
+        assert_eq!(
+            config(|c| {
+                c.rate_control = RateControl::Abr(20000);
+                c.low_submode = Some(NbSubmodeId::Low);
+            }),
+            Err(ConfigError::SubmodeWithVariableBitrate)
+        );
+        assert_eq!(config(|c| c.dtx = true), Err(ConfigError::DtxWithoutVad));
+        assert_eq!(
+            config(|c| {
+                c.dtx = true;
+                c.rate_control = RateControl::Abr(20000);
+            }),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn submodes_override_quality() {
+        let config = EncoderConfig {
+            rate_control: RateControl::Quality(10),
+            low_submode: Some(NbSubmodeId::Low),
+            sampling_rate: Some(16000),
+            ..EncoderConfig::new(ModeId::NarrowBand)
+        };
+        let mut encoder = config.build().unwrap();
+        assert_eq!(encoder.get_sampling_rate(), 16000);
+        let mut encoder = encoder.into_nb().unwrap();
+        assert_eq!(encoder.get_submode(), NbSubmodeId::Low);
+    }
+
+    #[test]
+    fn reads_back_settings() {
+        let config = EncoderConfig {
+            rate_control: RateControl::Vbr {
+                quality: 6.5,
+                max_bitrate: Some(30000),
+            },
+            dtx: true,
+            complexity: 7,
+            highpass: false,
+            ..EncoderConfig::new(ModeId::WideBand)
+        };
+        let mut encoder = config.build().unwrap();
+        let read_back = EncoderConfig::from_encoder(&mut encoder);
+        assert_eq!(
+            read_back,
+            EncoderConfig {
+                sampling_rate: Some(16000),
+                ..config
+            }
+        );
+
+        let config = EncoderConfig {
+            rate_control: RateControl::Bitrate(15000),
+            vad: true,
+            ..EncoderConfig::new(ModeId::NarrowBand)
+        };
+        let mut encoder = config.build().unwrap();
+        let read_back = EncoderConfig::from_encoder(&mut encoder);
+        assert_eq!(read_back.rate_control, RateControl::Bitrate(15000));
+        assert!(read_back.vad);
+
+        // Rebuilding from a read back config gives the same encoder settings
+        let mut rebuilt = read_back.build().unwrap();
+        assert_eq!(EncoderConfig::from_encoder(&mut rebuilt), read_back);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn parses_toml() {
+        let config: EncoderConfig = toml::from_str(
+            r#"
+            mode = "WideBand"
+            complexity = 5
+            vad = true
+            rate_control = { abr = 24000 }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config,
+            EncoderConfig {
+                complexity: 5,
+                vad: true,
+                rate_control: RateControl::Abr(24000),
+                ..EncoderConfig::new(ModeId::WideBand)
+            }
+        );
+
+        let text = toml::to_string(&config).unwrap();
+        assert_eq!(toml::from_str::<EncoderConfig>(&text).unwrap(), config);
+    }
+}
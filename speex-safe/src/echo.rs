@@ -0,0 +1,537 @@
+////////////////////////////////////////////////////////////////////////////////
+// Copyright (c) 2023.                                                         /
+// This Source Code Form is subject to the terms of the Mozilla Public License,/
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can    /
+// obtain one at http://mozilla.org/MPL/2.0/.                                  /
+////////////////////////////////////////////////////////////////////////////////
+
+//! Acoustic echo cancellation, for taking the far end back out of what the
+//! microphone picks up
+//!
+//! This follows the multidelay block frequency domain (MDF) filter of
+//! speexdsp. The tail is split into blocks of a frame, each filtered and
+//! adapted in the frequency domain, with a learning rate that follows an
+//! estimate of how much echo is left in the output. That keeps the filter
+//! from diverging while both ends talk at once.
+//!
+//! Frames of the near and far end have to line up: the far end frame passed
+//! along with a near end frame is the one that was played back while it was
+//! being recorded.
+
+use crate::fft::{Complex, Fft};
+use crate::mode::CoderMode;
+use crate::{ControlFunctions, EncoderError, SpeexBits, SpeexEncoder};
+
+/// Amplitude of an `i16` sample, which frames are scaled to
+const FULL_SCALE: f32 = 32767.0;
+
+/// Pre-emphasis of both ends, so the filter adapts about as fast at high
+/// frequencies as at low ones
+const PREEMPHASIS: f32 = 0.9;
+
+/// The least echo thought to leak through, about 23 dB below the estimate
+const MIN_LEAK: f32 = 0.005;
+
+/// Takes the echo of the far end out of the near end
+pub struct EchoCanceller {
+    frame_size: usize,
+    sampling_rate: u32,
+    blocks: usize,
+
+    fft: Fft,
+    /// Spectra of two frames of the far end at a time, newest first, one for
+    /// each block of the tail
+    far: Vec<Vec<Complex>>,
+    weights: Vec<Vec<Complex>>,
+    /// Share of the step each block takes, more for those holding more of
+    /// the response
+    prop: Vec<f32>,
+    far_previous: Vec<f32>,
+    estimate: Vec<Complex>,
+    error: Vec<Complex>,
+    gradient: Vec<Complex>,
+
+    /// Smoothed power spectrum of the far end
+    far_power: Vec<f32>,
+    /// Step of each bin, normalised by the power of the far end
+    step: Vec<f32>,
+    error_smoothed: Vec<f32>,
+    estimate_smoothed: Vec<f32>,
+    pey: f32,
+    pyy: f32,
+    adapted: bool,
+    sum_adapt: f32,
+
+    near_memory: f32,
+    far_memory: f32,
+    out_memory: f32,
+}
+
+impl EchoCanceller {
+    /// Creates an echo canceller for frames of `frame_size` samples at
+    /// `sampling_rate`, with a tail of at least `tail_length` samples
+    pub fn new(frame_size: usize, sampling_rate: u32, tail_length: usize) -> Self {
+        assert!(frame_size > 0, "Frame size must be positive");
+        let blocks = tail_length.div_ceil(frame_size).max(1);
+        let window_len = 2 * frame_size;
+        let bins = frame_size + 1;
+        let mut canceller = Self {
+            frame_size,
+            sampling_rate,
+            blocks,
+            fft: Fft::new(window_len),
+            far: vec![vec![Complex::default(); window_len]; blocks],
+            weights: vec![vec![Complex::default(); window_len]; blocks],
+            prop: vec![0.0; blocks],
+            far_previous: vec![0.0; frame_size],
+            estimate: vec![Complex::default(); window_len],
+            error: vec![Complex::default(); window_len],
+            gradient: vec![Complex::default(); window_len],
+            far_power: vec![0.0; bins],
+            step: vec![0.0; bins],
+            error_smoothed: vec![0.0; bins],
+            estimate_smoothed: vec![0.0; bins],
+            pey: 0.0,
+            pyy: 0.0,
+            adapted: false,
+            sum_adapt: 0.0,
+            near_memory: 0.0,
+            far_memory: 0.0,
+            out_memory: 0.0,
+        };
+        canceller.reset();
+        canceller
+    }
+
+    /// Creates an echo canceller for the frames `encoder` takes
+    pub fn for_encoder<T: CoderMode>(encoder: &mut SpeexEncoder<T>, tail_length: usize) -> Self {
+        let frame_size = encoder.get_frame_size() as usize;
+        let sampling_rate = encoder.get_sampling_rate() as u32;
+        Self::new(frame_size, sampling_rate, tail_length)
+    }
+
+    pub fn get_frame_size(&self) -> usize {
+        self.frame_size
+    }
+
+    pub fn get_sampling_rate(&self) -> u32 {
+        self.sampling_rate
+    }
+
+    /// Sets the length of the tail, in samples, rounded up to whole frames.
+    /// This resets the canceller.
+    pub fn set_tail_length(&mut self, tail_length: usize) {
+        *self = Self::new(self.frame_size, self.sampling_rate, tail_length);
+    }
+
+    /// Gets the length of the tail, in samples
+    pub fn get_tail_length(&self) -> usize {
+        self.blocks * self.frame_size
+    }
+
+    /// Forgets the echo path, for when it has changed completely
+    pub fn reset(&mut self) {
+        for spectrum in self.far.iter_mut().chain(&mut self.weights) {
+            spectrum.fill(Complex::default());
+        }
+        self.far_previous.fill(0.0);
+        self.far_power.fill(0.0);
+        self.step.fill(1.0);
+        self.error_smoothed.fill(0.0);
+        self.estimate_smoothed.fill(0.0);
+        self.pey = 1.0;
+        self.pyy = 1.0;
+        self.adapted = false;
+        self.sum_adapt = 0.0;
+        self.near_memory = 0.0;
+        self.far_memory = 0.0;
+        self.out_memory = 0.0;
+    }
+
+    /// Takes the echo of `far` out of `near` in place
+    pub fn cancel(&mut self, near: &mut [f32], far: &[f32]) {
+        let n = self.frame_size;
+        assert_eq!(near.len(), n, "Near end frame is the wrong size");
+        assert_eq!(far.len(), n, "Far end frame is the wrong size");
+
+        for sample in near.iter_mut() {
+            let x = *sample;
+            *sample = x - PREEMPHASIS * self.near_memory;
+            self.near_memory = x;
+        }
+
+        // The far end spectrum of this frame and the last, overlapping
+        self.far.rotate_right(1);
+        let mut far_energy = 0.0;
+        let spectrum = &mut self.far[0];
+        for i in 0..n {
+            let x = far[i] - PREEMPHASIS * self.far_memory;
+            self.far_memory = far[i];
+            far_energy += x * x;
+            spectrum[i] = Complex::new(self.far_previous[i], 0.0);
+            spectrum[n + i] = Complex::new(x, 0.0);
+            self.far_previous[i] = x;
+        }
+        self.fft.forward(spectrum);
+
+        // The estimate of the echo is the far end through every block
+        self.estimate.fill(Complex::default());
+        for (far, weights) in self.far.iter().zip(&self.weights) {
+            for ((out, &x), &w) in self.estimate.iter_mut().zip(far).zip(weights) {
+                *out += x * w;
+            }
+        }
+        self.fft.inverse(&mut self.estimate);
+
+        let (mut error_energy, mut echo_energy, mut correlation) = (0.0, 0.0, 0.0);
+        for (i, sample) in near.iter_mut().enumerate() {
+            let echo = self.estimate[n + i].re;
+            let error = *sample - echo;
+            error_energy += error * error;
+            echo_energy += echo * echo;
+            correlation += error * echo;
+            *sample = error;
+            self.estimate[i] = Complex::default();
+            self.estimate[n + i] = Complex::new(echo, 0.0);
+            self.error[i] = Complex::default();
+            self.error[n + i] = Complex::new(error, 0.0);
+        }
+
+        for sample in near.iter_mut() {
+            self.out_memory = *sample + PREEMPHASIS * self.out_memory;
+            *sample = self.out_memory.clamp(-FULL_SCALE - 1.0, FULL_SCALE);
+        }
+
+        if !(error_energy.is_finite() && echo_energy.is_finite()) || echo_energy > n as f32 * 1e9 {
+            // Diverged, which should never happen, but is better recovered
+            // from than kept
+            self.reset();
+            return;
+        }
+
+        self.fft.forward(&mut self.estimate);
+        self.fft.forward(&mut self.error);
+        self.update_step(far_energy, error_energy, echo_energy, correlation);
+        self.adapt();
+    }
+
+    /// Same as `cancel`, for frames of `i16`s
+    pub fn cancel_int(&mut self, near: &mut [i16], far: &[i16]) {
+        let mut float_near = near.iter().map(|&x| x as f32).collect::<Vec<_>>();
+        let float_far = far.iter().map(|&x| x as f32).collect::<Vec<_>>();
+        self.cancel(&mut float_near, &float_far);
+        for (out, x) in near.iter_mut().zip(float_near) {
+            *out = x.round() as i16;
+        }
+    }
+
+    /// Cancels the echo of `far` out of `near` and encodes it
+    pub fn encode<T: CoderMode>(
+        &mut self,
+        encoder: &mut SpeexEncoder<T>,
+        near: &mut [f32],
+        far: &[f32],
+        bits: &mut SpeexBits,
+    ) -> Result<(), EncoderError> {
+        self.cancel(near, far);
+        encoder.encode(near, bits)
+    }
+
+    /// Sets the step of each bin from how much echo is thought to be left in
+    /// the error, so that adaptation slows down while the near end talks
+    fn update_step(
+        &mut self,
+        far_energy: f32,
+        error_energy: f32,
+        echo_energy: f32,
+        correlation: f32,
+    ) {
+        let n = self.frame_size;
+        let frame_seconds = n as f32 / self.sampling_rate as f32;
+        let smoothing = 0.35 / self.blocks as f32;
+
+        let (mut pey, mut pyy) = (0.0, 0.0);
+        for i in 0..=n {
+            // Never below the power of this frame, so the first frames of the
+            // far end can't take too long a step
+            let far = self.far[0][i].norm_sqr();
+            self.far_power[i] =
+                ((1.0 - smoothing) * self.far_power[i] + 1.0 + smoothing * far).max(far);
+            // How the error goes up and down with the estimate of the echo
+            // tells how much of the echo leaks through
+            let error = self.error[i].norm_sqr();
+            let echo = self.estimate[i].norm_sqr();
+            let error_change = error - self.error_smoothed[i];
+            let echo_change = echo - self.estimate_smoothed[i];
+            pey += error_change * echo_change;
+            pyy += echo_change * echo_change;
+            self.error_smoothed[i] += frame_seconds * (error - self.error_smoothed[i]);
+            self.estimate_smoothed[i] += frame_seconds * (echo - self.estimate_smoothed[i]);
+        }
+        let pyy = pyy.sqrt();
+        let pey = pey / (pyy + 1.0);
+
+        let error_energy = error_energy + 1.0;
+        let alpha = (2.0 * frame_seconds * echo_energy).min(0.5 * frame_seconds * error_energy)
+            / error_energy;
+        self.pey = (1.0 - alpha) * self.pey + alpha * pey;
+        self.pyy = ((1.0 - alpha) * self.pyy + alpha * pyy).max(1.0);
+        self.pey = self.pey.clamp(MIN_LEAK * self.pyy, self.pyy);
+        let leak = self.pey / self.pyy;
+
+        // Residual to error ratio, the share of the error that is echo
+        let rer = ((0.0001 * far_energy + 3.0 * leak * echo_energy) / error_energy)
+            .max(correlation * correlation / (1.0 + error_energy * echo_energy))
+            .min(0.5);
+
+        if !self.adapted && self.sum_adapt > self.blocks as f32 && leak > 0.03 {
+            self.adapted = true;
+        }
+        if self.adapted {
+            for i in 0..=n {
+                let error = self.error[i].norm_sqr() + 1.0;
+                let residual = (leak * self.estimate[i].norm_sqr()).min(0.5 * error);
+                let residual = 0.7 * residual + 0.3 * rer * error;
+                self.step[i] = residual / (error * (self.far_power[i] + 10.0));
+            }
+        } else {
+            // Until the leak can be trusted, adapting as fast as the far end
+            // stands above the error
+            let rate = if far_energy > n as f32 * 1000.0 {
+                (0.25 * far_energy).min(0.25 * error_energy) / error_energy
+            } else {
+                0.0
+            };
+            for i in 0..=n {
+                self.step[i] = rate / (self.far_power[i] + 10.0);
+            }
+            self.sum_adapt += rate;
+        }
+    }
+
+    /// Moves every block along its gradient, constrained to a frame of taps
+    fn adapt(&mut self) {
+        let n = self.frame_size;
+
+        let energies = self
+            .weights
+            .iter()
+            .map(|weights| weights.iter().map(|w| w.norm_sqr()).sum::<f32>().sqrt())
+            .collect::<Vec<_>>();
+        let loudest = energies.iter().copied().fold(1.0, f32::max);
+        let sum = energies.iter().sum::<f32>() + 0.1 * loudest * self.blocks as f32;
+        for (share, energy) in self.prop.iter_mut().zip(energies) {
+            *share = 0.99 * (energy + 0.1 * loudest) / sum;
+        }
+
+        for ((weights, far), &share) in self.weights.iter_mut().zip(&self.far).zip(&self.prop) {
+            for (k, (out, (&x, &e))) in self
+                .gradient
+                .iter_mut()
+                .zip(far.iter().zip(&self.error))
+                .enumerate()
+            {
+                let step = self.step[k.min(2 * n - k)];
+                *out = (x.conj() * e).scale(step * share);
+            }
+            self.fft.inverse(&mut self.gradient);
+            self.gradient[n..].fill(Complex::default());
+            self.fft.forward(&mut self.gradient);
+            for (w, &g) in weights.iter_mut().zip(&self.gradient) {
+                *w += g;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::preprocess::Preprocessor;
+    use crate::{support, NbMode};
+
+    const RATE: u32 = 8000;
+    const FRAME: usize = 160;
+
+    /// Noise through a low pass, closer to the spectrum of speech
+    fn coloured_noise(len: usize, amplitude: f32, seed: u32) -> Vec<f32> {
+        let mut last = 0.0;
+        support::white_noise(seed, amplitude, len)
+            .into_iter()
+            .map(|x| {
+                last = 0.8 * last + x * 0.6;
+                last
+            })
+            .collect()
+    }
+
+    /// A room that answers after `delay` samples and dies away by 60 dB over
+    /// `length`, attenuating by about 6 dB overall
+    fn room(delay: usize, length: usize) -> Vec<f32> {
+        let mut response = vec![0.0; delay];
+        let taps = support::white_noise(7, 1.0, length);
+        response.extend(
+            taps.iter()
+                .enumerate()
+                .map(|(i, x)| x * 10f32.powf(-3.0 * i as f32 / length as f32)),
+        );
+        let energy = response.iter().map(|x| x * x).sum::<f32>();
+        response
+            .iter_mut()
+            .for_each(|x| *x *= (0.25 / energy).sqrt());
+        response
+    }
+
+    fn convolve(signal: &[f32], response: &[f32]) -> Vec<f32> {
+        (0..signal.len())
+            .map(|n| {
+                response
+                    .iter()
+                    .take(n + 1)
+                    .enumerate()
+                    .map(|(k, h)| h * signal[n - k])
+                    .sum()
+            })
+            .collect()
+    }
+
+    fn energy(signal: &[f32]) -> f32 {
+        signal.iter().map(|x| x * x).sum()
+    }
+
+    fn cancel_all(canceller: &mut EchoCanceller, near: &[f32], far: &[f32]) -> Vec<f32> {
+        let mut output = near.to_vec();
+        for (near, far) in output.chunks_exact_mut(FRAME).zip(far.chunks_exact(FRAME)) {
+            canceller.cancel(near, far);
+        }
+        output
+    }
+
+    /// Echo return loss enhancement over the last second
+    fn erle(near: &[f32], output: &[f32]) -> f32 {
+        let last = near.len() - RATE as usize..near.len();
+        10.0 * (energy(&near[last.clone()]) / energy(&output[last])).log10()
+    }
+
+    #[test]
+    fn tail_length() {
+        let mut canceller = EchoCanceller::new(FRAME, RATE, 1000);
+        assert_eq!(canceller.get_tail_length(), 1120);
+        canceller.set_tail_length(160);
+        assert_eq!(canceller.get_tail_length(), 160);
+        assert_eq!(
+            (canceller.get_frame_size(), canceller.get_sampling_rate()),
+            (FRAME, RATE)
+        );
+    }
+
+    #[test]
+    fn passes_through_without_far_end() {
+        let near = coloured_noise(RATE as usize, 3000.0, 1);
+        let far = vec![0.0; near.len()];
+        let mut canceller = EchoCanceller::new(FRAME, RATE, 800);
+        let output = cancel_all(&mut canceller, &near, &far);
+        for (a, b) in output.iter().zip(&near) {
+            assert!((a - b).abs() < 0.5, "{a} {b}");
+        }
+    }
+
+    #[test]
+    fn cancels_room_echo() {
+        let far = support::white_noise(1, 3000.0, RATE as usize * 6);
+        let near = convolve(&far, &room(40, 600));
+        let mut canceller = EchoCanceller::new(FRAME, RATE, 800);
+        let output = cancel_all(&mut canceller, &near, &far);
+        let enhancement = erle(&near, &output);
+        assert!(enhancement > 25.0, "{enhancement} dB");
+
+        // Speech-like far end, which the filter adapts to more slowly
+        let far = coloured_noise(RATE as usize * 8, 3000.0, 2);
+        let near = convolve(&far, &room(40, 600));
+        canceller.reset();
+        let output = cancel_all(&mut canceller, &near, &far);
+        let enhancement = erle(&near, &output);
+        assert!(enhancement > 15.0, "{enhancement} dB");
+    }
+
+    #[test]
+    fn short_tail_leaves_echo() {
+        let far = support::white_noise(1, 3000.0, RATE as usize * 6);
+        let near = convolve(&far, &room(40, 1200));
+        let mut long = EchoCanceller::new(FRAME, RATE, 1280);
+        let mut short = EchoCanceller::new(FRAME, RATE, 320);
+        let long = erle(&near, &cancel_all(&mut long, &near, &far));
+        let short = erle(&near, &cancel_all(&mut short, &near, &far));
+        assert!(long > short + 5.0, "{long} dB, {short} dB");
+    }
+
+    #[test]
+    fn keeps_near_end_through_double_talk() {
+        let len = RATE as usize * 10;
+        let far = support::white_noise(1, 3000.0, len);
+        let echo = convolve(&far, &room(40, 600));
+        // The near end talks over the far end from the fourth to the sixth
+        // second
+        let mut talk = coloured_noise(len, 3000.0, 3);
+        let talking = 4 * RATE as usize..6 * RATE as usize;
+        for (i, x) in talk.iter_mut().enumerate() {
+            if !talking.contains(&i) {
+                *x = 0.0;
+            }
+        }
+        let near = echo
+            .iter()
+            .zip(&talk)
+            .map(|(e, t)| e + t)
+            .collect::<Vec<_>>();
+        let mut canceller = EchoCanceller::new(FRAME, RATE, 800);
+        let output = cancel_all(&mut canceller, &near, &far);
+
+        // The near end comes through while the echo stays out
+        let residual = output[talking.clone()]
+            .iter()
+            .zip(&talk[talking.clone()])
+            .map(|(o, t)| o - t)
+            .collect::<Vec<_>>();
+        let distortion = 10.0 * (energy(&talk[talking.clone()]) / energy(&residual)).log10();
+        assert!(distortion > 15.0, "{distortion} dB");
+
+        // And the filter is still there once it stops
+        let enhancement = erle(&near, &output);
+        assert!(enhancement > 25.0, "{enhancement} dB");
+    }
+
+    #[test]
+    fn reset_forgets_echo_path() {
+        let far = support::white_noise(1, 3000.0, RATE as usize * 4);
+        let near = convolve(&far, &room(40, 600));
+        let mut canceller = EchoCanceller::new(FRAME, RATE, 800);
+        cancel_all(&mut canceller, &near, &far);
+        canceller.reset();
+        let mut frame = near[..FRAME].to_vec();
+        canceller.cancel(&mut frame, &far[..FRAME]);
+        for (a, b) in frame.iter().zip(&near) {
+            assert!((a - b).abs() < 0.5, "{a} {b}");
+        }
+    }
+
+    #[test]
+    fn chains_into_encoder() {
+        let mut encoder = SpeexEncoder::<NbMode>::new();
+        let mut canceller = EchoCanceller::for_encoder(&mut encoder, 800);
+        let mut preprocessor = Preprocessor::for_encoder(&mut encoder);
+        assert_eq!(canceller.get_frame_size(), FRAME);
+
+        let far = support::white_noise(1, 3000.0, RATE as usize);
+        let near = convolve(&far, &room(40, 600));
+        let mut bits = SpeexBits::new();
+        for (near, far) in near.chunks_exact(FRAME).zip(far.chunks_exact(FRAME)) {
+            let mut frame = near.to_vec();
+            canceller.cancel(&mut frame, far);
+            preprocessor.run(&mut frame);
+            bits.reset();
+            encoder.encode(&mut frame, &mut bits).unwrap();
+            assert!(bits.num_bytes() > 0);
+        }
+    }
+}
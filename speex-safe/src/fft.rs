@@ -0,0 +1,215 @@
+////////////////////////////////////////////////////////////////////////////////
+// Copyright (c) 2023.                                                         /
+// This Source Code Form is subject to the terms of the Mozilla Public License,/
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can    /
+// obtain one at http://mozilla.org/MPL/2.0/.                                  /
+////////////////////////////////////////////////////////////////////////////////
+
+//! Mixed radix FFT for the sizes the speex frames come in, laid out like the
+//! kiss_fft libspeex uses
+
+use std::f64::consts::PI;
+use std::ops::{Add, AddAssign, Mul, Sub};
+
+#[derive(Copy, Clone, Default, PartialEq, Debug)]
+pub(crate) struct Complex {
+    pub re: f32,
+    pub im: f32,
+}
+
+impl Complex {
+    pub fn new(re: f32, im: f32) -> Self {
+        Self { re, im }
+    }
+
+    pub fn conj(self) -> Self {
+        Self::new(self.re, -self.im)
+    }
+
+    pub fn norm_sqr(self) -> f32 {
+        self.re * self.re + self.im * self.im
+    }
+
+    pub fn scale(self, factor: f32) -> Self {
+        Self::new(self.re * factor, self.im * factor)
+    }
+}
+
+impl Add for Complex {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        Self::new(self.re + rhs.re, self.im + rhs.im)
+    }
+}
+
+impl AddAssign for Complex {
+    fn add_assign(&mut self, rhs: Self) {
+        *self = *self + rhs;
+    }
+}
+
+impl Sub for Complex {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self {
+        Self::new(self.re - rhs.re, self.im - rhs.im)
+    }
+}
+
+impl Mul for Complex {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self {
+        Self::new(
+            self.re * rhs.re - self.im * rhs.im,
+            self.re * rhs.im + self.im * rhs.re,
+        )
+    }
+}
+
+pub(crate) struct Fft {
+    len: usize,
+    /// `exp(-2πik/len)`
+    twiddles: Vec<Complex>,
+    factors: Vec<usize>,
+    buffer: Vec<Complex>,
+}
+
+impl Fft {
+    pub fn new(len: usize) -> Self {
+        assert!(len > 0, "FFT of nothing");
+        let twiddles = (0..len)
+            .map(|k| {
+                let phase = -2.0 * PI * k as f64 / len as f64;
+                Complex::new(phase.cos() as f32, phase.sin() as f32)
+            })
+            .collect();
+
+        // Radix 4 first, as kiss_fft does, then whatever primes are left
+        let mut factors = Vec::new();
+        let mut rest = len;
+        let mut factor = 4;
+        while rest > 1 {
+            while !rest.is_multiple_of(factor) {
+                factor = match factor {
+                    4 => 2,
+                    2 => 3,
+                    _ => factor + 2,
+                };
+                if factor * factor > rest {
+                    factor = rest;
+                }
+            }
+            factors.push(factor);
+            rest /= factor;
+        }
+
+        Self {
+            len,
+            twiddles,
+            factors,
+            buffer: vec![Complex::default(); len],
+        }
+    }
+
+    /// Transforms `data` in place
+    pub fn forward(&mut self, data: &mut [Complex]) {
+        assert_eq!(data.len(), self.len);
+        if self.factors.is_empty() {
+            return;
+        }
+        self.buffer.copy_from_slice(data);
+        self.work(data, 0, 1, 0);
+    }
+
+    /// Transforms `data` back in place, scaled so that it undoes `forward`
+    pub fn inverse(&mut self, data: &mut [Complex]) {
+        let scale = 1.0 / self.len as f32;
+        data.iter_mut().for_each(|x| *x = x.conj());
+        self.forward(data);
+        data.iter_mut().for_each(|x| *x = x.conj().scale(scale));
+    }
+
+    /// Decimation in time of the part of `buffer` starting at `offset`, every
+    /// `stride`, into `out`
+    fn work(&self, out: &mut [Complex], offset: usize, stride: usize, depth: usize) {
+        let radix = self.factors[depth];
+        let m = out.len() / radix;
+        if m == 1 {
+            for (k, out) in out.iter_mut().enumerate() {
+                *out = self.buffer[offset + k * stride];
+            }
+        } else {
+            for (k, chunk) in out.chunks_exact_mut(m).enumerate() {
+                self.work(chunk, offset + k * stride, stride * radix, depth + 1);
+            }
+        }
+
+        let mut scratch = [Complex::default(); 16];
+        let mut heap_scratch = Vec::new();
+        let scratch = if radix <= scratch.len() {
+            &mut scratch[..radix]
+        } else {
+            heap_scratch.resize(radix, Complex::default());
+            &mut heap_scratch[..]
+        };
+        for u in 0..m {
+            for (q, value) in scratch.iter_mut().enumerate() {
+                *value = out[u + q * m];
+            }
+            for q1 in 0..radix {
+                let k = u + q1 * m;
+                let mut sum = scratch[0];
+                for (q, &value) in scratch.iter().enumerate().skip(1) {
+                    sum += value * self.twiddles[(q * k * stride) % self.len];
+                }
+                out[k] = sum;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn naive_dft(input: &[Complex]) -> Vec<Complex> {
+        let n = input.len();
+        (0..n)
+            .map(|k| {
+                input
+                    .iter()
+                    .enumerate()
+                    .fold(Complex::default(), |sum, (i, &x)| {
+                        let phase = -2.0 * PI * (k * i % n) as f64 / n as f64;
+                        sum + x * Complex::new(phase.cos() as f32, phase.sin() as f32)
+                    })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn matches_naive_dft() {
+        for len in [1, 2, 7, 12, 320, 640, 1280, 1000] {
+            let input = (0..len)
+                .map(|i| Complex::new((i as f32 * 0.37).sin(), (i as f32 * 1.3).cos()))
+                .collect::<Vec<_>>();
+            let expected = naive_dft(&input);
+            let mut fft = Fft::new(len);
+            let mut output = input.clone();
+            fft.forward(&mut output);
+            for (a, b) in output.iter().zip(&expected) {
+                assert!(
+                    (*a - *b).norm_sqr() < 1e-6 * len as f32,
+                    "{len}: {a:?} {b:?}"
+                );
+            }
+
+            fft.inverse(&mut output);
+            for (a, b) in output.iter().zip(&input) {
+                assert!((*a - *b).norm_sqr() < 1e-9, "{len}: {a:?} {b:?}");
+            }
+        }
+    }
+}
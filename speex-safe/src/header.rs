@@ -5,10 +5,18 @@
 // obtain one at http://mozilla.org/MPL/2.0/.                                  /
 ////////////////////////////////////////////////////////////////////////////////
 
+use std::error::Error;
+use std::ffi::{c_char, c_void};
+use std::fmt::{Display, Formatter};
+use std::io::{Read, Write};
 use std::mem::MaybeUninit;
+use std::{io, slice};
 
 use speex_sys::{SpeexHeader as SysHeader, SpeexMode};
 
+use crate::ogg::{OggReader, OggWriter};
+use crate::{CommentsError, ModeId, SpeexComments};
+
 /// Standard speex stream header
 ///
 /// ## Why doesn't this implement `Drop`?
@@ -16,9 +24,8 @@ use speex_sys::{SpeexHeader as SysHeader, SpeexMode};
 /// You may notice in `speex_sys` there is a `free` function for headers.
 /// The data within `SpeexHeader` is actually entirely stack allocated. There is
 /// nothing to be freed. The `free` is for the arrays/pointers allocated by
-/// `packet_to_header` and `header_to_packet`. For `packet_to_header` instead of
-/// using a manual call to free, it is wrapped in a `Vec` which can manage the
-/// memory just fine.
+/// `packet_to_header` and `header_to_packet`, which are copied out and freed
+/// straight away by `from_packet` and `make_packet`.
 #[derive(Debug, Clone, Copy)]
 pub struct SpeexHeader {
     backing: SysHeader,
@@ -39,27 +46,270 @@ impl SpeexHeader {
         Self { backing }
     }
 
-    // TODO: NONE of this is safe. It's all just a guess.
-
-    pub unsafe fn from_packet(packet: &mut [u8]) -> Self {
+    /// Parses a header from the first packet of a speex stream
+    ///
+    /// Returns `None` if the packet is too short, doesn't start with the speex
+    /// magic string or specifies an invalid mode.
+    pub fn from_packet(packet: &[u8]) -> Option<Self> {
+        // speex_packet_to_header takes a mutable pointer despite only reading
+        // from it, so work on a copy rather than casting away the const
+        let mut packet = packet.to_vec();
         let backing = unsafe {
-            let ptr = packet.as_mut_ptr() as *mut i8;
+            let ptr = packet.as_mut_ptr() as *mut c_char;
             let length = packet.len() as i32;
             let header_ptr = speex_sys::speex_packet_to_header(ptr, length);
+            if header_ptr.is_null() {
+                return None;
+            }
             let derefed = *header_ptr;
-            speex_sys::speex_header_free(header_ptr as *mut std::ffi::c_void);
+            speex_sys::speex_header_free(header_ptr as *mut c_void);
             derefed
         };
-        Self { backing }
+        Some(Self { backing })
     }
 
-    pub unsafe fn make_packet(&mut self) -> Vec<u8> {
+    /// Serializes the header into the packet that starts a speex stream
+    pub fn make_packet(&mut self) -> Vec<u8> {
         let ptr = &mut self.backing as *mut SysHeader;
         let mut size: i32 = 0;
         let size_ptr = &mut size as *mut i32;
         unsafe {
-            let buff_ptr = speex_sys::speex_header_to_packet(ptr, size_ptr) as *mut u8;
-            Vec::from_raw_parts(buff_ptr, size as usize, size as usize)
+            // The buffer is allocated by libspeex, so it has to be copied out
+            // and handed back rather than adopted by a Vec
+            let buff_ptr = speex_sys::speex_header_to_packet(ptr, size_ptr);
+            let packet = slice::from_raw_parts(buff_ptr as *const u8, size as usize).to_vec();
+            speex_sys::speex_header_free(buff_ptr as *mut c_void);
+            packet
+        }
+    }
+
+    /// Gets the version string of the speex library that wrote the header
+    pub fn get_version(&self) -> String {
+        let bytes: Vec<u8> = self
+            .backing
+            .speex_version
+            .iter()
+            .take_while(|&&c| c != 0)
+            .map(|&c| c as u8)
+            .collect();
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+
+    /// Gets the version of the header format
+    pub fn get_version_id(&self) -> i32 {
+        self.backing.speex_version_id
+    }
+
+    /// Gets the sampling rate of the stream
+    pub fn get_rate(&self) -> i32 {
+        self.backing.rate
+    }
+
+    /// Gets the mode the stream was encoded with
+    pub fn get_mode(&self) -> ModeId {
+        self.backing.mode.into()
+    }
+
+    /// Gets the bitstream version of the mode the stream was encoded with
+    pub fn get_mode_bitstream_version(&self) -> i32 {
+        self.backing.mode_bitstream_version
+    }
+
+    /// Gets the number of channels in the stream, either 1 or 2
+    pub fn get_nb_channels(&self) -> i32 {
+        self.backing.nb_channels
+    }
+
+    /// Gets the bitrate of the stream, or -1 if it is unknown
+    pub fn get_bitrate(&self) -> i32 {
+        self.backing.bitrate
+    }
+
+    /// Sets the bitrate of the stream, -1 marks it as unknown
+    pub fn set_bitrate(&mut self, bitrate: i32) {
+        self.backing.bitrate = bitrate;
+    }
+
+    /// Gets the size (in samples) of a frame
+    pub fn get_frame_size(&self) -> i32 {
+        self.backing.frame_size
+    }
+
+    /// Gets whether the stream uses Variable BitRate
+    pub fn get_vbr(&self) -> bool {
+        self.backing.vbr != 0
+    }
+
+    /// Sets whether the stream uses Variable BitRate
+    pub fn set_vbr(&mut self, vbr: bool) {
+        self.backing.vbr = vbr as i32;
+    }
+
+    /// Gets the number of frames stored in each packet
+    pub fn get_frames_per_packet(&self) -> i32 {
+        self.backing.frames_per_packet
+    }
+
+    /// Sets the number of frames stored in each packet
+    pub fn set_frames_per_packet(&mut self, frames_per_packet: i32) {
+        self.backing.frames_per_packet = frames_per_packet;
+    }
+
+    /// Gets the number of additional headers following the comment packet
+    pub fn get_extra_headers(&self) -> i32 {
+        self.backing.extra_headers
+    }
+
+    /// Sets the number of additional headers following the comment packet
+    pub fn set_extra_headers(&mut self, extra_headers: i32) {
+        self.backing.extra_headers = extra_headers;
+    }
+}
+
+/// Error type for reading the header packets of a stream
+#[derive(Debug)]
+pub enum HeadersError {
+    Io(io::Error),
+    /// The stream ends before all header packets were read
+    MissingPacket,
+    /// The first packet is not a valid `SpeexHeader`
+    InvalidHeader,
+    /// The second packet is not a valid comment packet
+    InvalidComments(CommentsError),
+}
+
+impl Display for HeadersError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HeadersError::Io(err) => write!(f, "{err}"),
+            HeadersError::MissingPacket => write!(f, "Stream ends within the headers"),
+            HeadersError::InvalidHeader => write!(f, "First packet is not a Speex header"),
+            HeadersError::InvalidComments(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl Error for HeadersError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            HeadersError::Io(err) => Some(err),
+            HeadersError::InvalidComments(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for HeadersError {
+    fn from(err: io::Error) -> Self {
+        HeadersError::Io(err)
+    }
+}
+
+impl From<CommentsError> for HeadersError {
+    fn from(err: CommentsError) -> Self {
+        HeadersError::InvalidComments(err)
+    }
+}
+
+/// The two packets every Ogg Speex stream starts with
+#[derive(Debug, Clone)]
+pub struct SpeexHeaders {
+    pub header: SpeexHeader,
+    pub comments: SpeexComments,
+}
+
+impl SpeexHeaders {
+    /// Parses the header and comment packets
+    pub fn from_packets(header: &[u8], comments: &[u8]) -> Result<Self, HeadersError> {
+        Ok(Self {
+            header: SpeexHeader::from_packet(header).ok_or(HeadersError::InvalidHeader)?,
+            comments: SpeexComments::from_packet(comments)?,
+        })
+    }
+
+    /// Serializes the header and comment packets
+    pub fn make_packets(&mut self) -> (Vec<u8>, Vec<u8>) {
+        (self.header.make_packet(), self.comments.make_packet())
+    }
+
+    /// Reads the header packets from the start of an Ogg stream, skipping
+    /// any extra headers so the next packet read is the first audio packet
+    pub fn read_ogg<R: Read>(reader: &mut OggReader<R>) -> Result<Self, HeadersError> {
+        let mut next_packet = || -> Result<Vec<u8>, HeadersError> {
+            let packet = reader.read_packet()?.ok_or(HeadersError::MissingPacket)?;
+            Ok(packet.data)
+        };
+        let header = next_packet()?;
+        let comments = next_packet()?;
+        let headers = Self::from_packets(&header, &comments)?;
+        for _ in 0..headers.header.get_extra_headers().max(0) {
+            next_packet()?;
         }
+        Ok(headers)
+    }
+
+    /// Writes the header packets to the start of an Ogg stream, each on a
+    /// page of its own
+    pub fn write_ogg<W: Write>(&mut self, writer: &mut OggWriter<W>) -> io::Result<()> {
+        let (header, comments) = self.make_packets();
+        writer.write_packet(&header, 0, false)?;
+        writer.flush()?;
+        writer.write_packet(&comments, 0, false)?;
+        writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn round_trips_headers() {
+        let mut header = SpeexHeader::new(16000, 2, ModeId::WideBand.get_mode());
+        header.set_vbr(true);
+        header.set_frames_per_packet(3);
+        let mut comments = SpeexComments::new("vendor");
+        comments.add("TITLE", "test").unwrap();
+        let mut headers = SpeexHeaders { header, comments };
+
+        let mut writer = OggWriter::new(Vec::new(), 7);
+        headers.write_ogg(&mut writer).unwrap();
+        writer.write_packet(b"audio", 320, true).unwrap();
+        let stream = writer.into_inner().unwrap();
+
+        let mut reader = OggReader::new(stream.as_slice());
+        let mut read = SpeexHeaders::read_ogg(&mut reader).unwrap();
+        assert_eq!(read.header.get_rate(), 16000);
+        assert_eq!(read.header.get_nb_channels(), 2);
+        assert_eq!(read.header.get_mode(), ModeId::WideBand);
+        assert!(read.header.get_vbr());
+        assert_eq!(read.header.get_frames_per_packet(), 3);
+        assert_eq!(read.comments, headers.comments);
+        assert_eq!(read.make_packets(), headers.make_packets());
+        assert_eq!(reader.read_packet().unwrap().unwrap().data, b"audio");
+    }
+
+    #[test]
+    fn reports_bad_headers() {
+        let comments = SpeexComments::new("vendor").make_packet();
+        assert!(matches!(
+            SpeexHeaders::from_packets(b"not a header", &comments),
+            Err(HeadersError::InvalidHeader)
+        ));
+
+        let mut header = SpeexHeader::new(8000, 1, ModeId::NarrowBand.get_mode());
+        assert!(matches!(
+            SpeexHeaders::from_packets(&header.make_packet(), &comments[..6]),
+            Err(HeadersError::InvalidComments(_))
+        ));
+
+        let mut writer = OggWriter::new(Vec::new(), 7);
+        writer.write_packet(&header.make_packet(), 0, true).unwrap();
+        let stream = writer.into_inner().unwrap();
+        let mut reader = OggReader::new(stream.as_slice());
+        assert!(matches!(
+            SpeexHeaders::read_ogg(&mut reader),
+            Err(HeadersError::MissingPacket)
+        ));
     }
 }
@@ -0,0 +1,273 @@
+////////////////////////////////////////////////////////////////////////////////
+// Copyright (c) 2023.                                                         /
+// This Source Code Form is subject to the terms of the Mozilla Public License,/
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can    /
+// obtain one at http://mozilla.org/MPL/2.0/.                                  /
+////////////////////////////////////////////////////////////////////////////////
+
+//! Internal values of the last frame an encoder or decoder coded, through the
+//! requests libspeex keeps for itself in `modes.h`
+//!
+//! The values are in the same scale as the samples, and split by subframe.
+//! Every mode has the same number of subframes, [`SUBFRAMES`]. Wideband and
+//! ultra-wideband coders report the values of their highest band.
+
+use std::ffi::c_void;
+use std::ptr::null_mut;
+
+use crate::mode::CoderMode;
+use crate::{
+    dynamic_mapping,
+    ControlFunctions,
+    DynamicDecoder,
+    DynamicEncoder,
+    SpeexDecoder,
+    SpeexEncoder,
+};
+
+/// Number of subframes in a frame, in every mode
+pub const SUBFRAMES: usize = 4;
+
+/// Values of the last frame coded
+#[derive(Clone, Debug, PartialEq)]
+pub struct FrameInfo {
+    /// Gain of the LPC analysis filter at half the sampling rate, for each
+    /// subframe. This is what libspeex calls the "pi gain", not a pitch gain.
+    pub pi_gains: [f32; SUBFRAMES],
+    /// RMS of the excitation of each subframe
+    pub excitation_rms: [f32; SUBFRAMES],
+    /// The innovation, if saving it is enabled
+    ///
+    /// An encoder saves the RMS of the innovation of each subframe, so this is
+    /// [`SUBFRAMES`] long. A decoder saves the innovation itself, a frame
+    /// long. The highest band of a wideband or ultra-wideband decoder is
+    /// folded up from its lower band, so only its even samples are set.
+    pub innovation: Option<Vec<f32>>,
+}
+
+fn get_subframes<C: ControlFunctions>(coder: &mut C, request: i32) -> [f32; SUBFRAMES] {
+    let mut values = [0.0; SUBFRAMES];
+    unsafe {
+        coder
+            .ctl(request, values.as_mut_ptr() as *mut c_void)
+            .unwrap();
+    }
+    values
+}
+
+/// Points libspeex at `buffer`, or at nothing, returning the buffer to be kept
+/// alive for as long as libspeex may write to it
+fn set_innovation_save<C: ControlFunctions>(
+    coder: &mut C,
+    buffer: Option<Box<[f32]>>,
+) -> Option<Box<[f32]>> {
+    let mut buffer = buffer;
+    let ptr = buffer
+        .as_mut()
+        .map_or(null_mut(), |buffer| buffer.as_mut_ptr() as *mut c_void);
+    unsafe {
+        coder
+            .ctl(speex_sys::SPEEX_SET_INNOVATION_SAVE, ptr)
+            .unwrap();
+    }
+    buffer
+}
+
+impl<T: CoderMode> SpeexEncoder<T> {
+    /// Sets whether the RMS of the innovation of each subframe is saved, to be
+    /// returned by `get_frame_info`
+    pub fn set_innovation_save(&mut self, save: bool) {
+        let buffer = save.then(|| vec![0.0; SUBFRAMES].into_boxed_slice());
+        self.innovation = set_innovation_save(self, buffer);
+    }
+
+    pub fn get_innovation_save(&self) -> bool {
+        self.innovation.is_some()
+    }
+
+    /// Gets the values of the last frame encoded
+    pub fn get_frame_info(&mut self) -> FrameInfo {
+        FrameInfo {
+            pi_gains: get_subframes(self, speex_sys::SPEEX_GET_PI_GAIN),
+            excitation_rms: get_subframes(self, speex_sys::SPEEX_GET_EXC),
+            innovation: self.innovation.as_deref().map(<[f32]>::to_vec),
+        }
+    }
+}
+
+impl<T: CoderMode> SpeexDecoder<T> {
+    /// Sets whether the innovation of each frame is saved, to be returned by
+    /// `get_frame_info`
+    pub fn set_innovation_save(&mut self, save: bool) {
+        let frame_size = self.get_frame_size() as usize;
+        let buffer = save.then(|| vec![0.0; frame_size].into_boxed_slice());
+        self.innovation = set_innovation_save(self, buffer);
+    }
+
+    pub fn get_innovation_save(&self) -> bool {
+        self.innovation.is_some()
+    }
+
+    /// Gets the values of the last frame decoded
+    pub fn get_frame_info(&mut self) -> FrameInfo {
+        FrameInfo {
+            pi_gains: get_subframes(self, speex_sys::SPEEX_GET_PI_GAIN),
+            excitation_rms: get_subframes(self, speex_sys::SPEEX_GET_EXC),
+            innovation: self.innovation.as_deref().map(<[f32]>::to_vec),
+        }
+    }
+
+    /// Gets whether the stream is in discontinuous transmission, where a
+    /// missing packet is silence rather than a lost one
+    pub fn get_dtx_status(&mut self) -> bool {
+        let mut status = 0i32;
+        unsafe {
+            self.ctl(
+                speex_sys::SPEEX_GET_DTX_STATUS,
+                &mut status as *mut i32 as *mut c_void,
+            )
+            .unwrap();
+        }
+        status != 0
+    }
+}
+
+impl DynamicEncoder {
+    /// Sets whether the RMS of the innovation of each subframe is saved, to be
+    /// returned by `get_frame_info`
+    pub fn set_innovation_save(&mut self, save: bool) {
+        dynamic_mapping!(self, DynamicEncoder, inner => inner.set_innovation_save(save))
+    }
+
+    pub fn get_innovation_save(&self) -> bool {
+        dynamic_mapping!(self, DynamicEncoder, inner => inner.get_innovation_save())
+    }
+
+    /// Gets the values of the last frame encoded
+    pub fn get_frame_info(&mut self) -> FrameInfo {
+        dynamic_mapping!(self, DynamicEncoder, inner => inner.get_frame_info())
+    }
+}
+
+impl DynamicDecoder {
+    /// Sets whether the innovation of each frame is saved, to be returned by
+    /// `get_frame_info`
+    pub fn set_innovation_save(&mut self, save: bool) {
+        dynamic_mapping!(self, DynamicDecoder, inner => inner.set_innovation_save(save))
+    }
+
+    pub fn get_innovation_save(&self) -> bool {
+        dynamic_mapping!(self, DynamicDecoder, inner => inner.get_innovation_save())
+    }
+
+    /// Gets the values of the last frame decoded
+    pub fn get_frame_info(&mut self) -> FrameInfo {
+        dynamic_mapping!(self, DynamicDecoder, inner => inner.get_frame_info())
+    }
+
+    /// Gets whether the stream is in discontinuous transmission, where a
+    /// missing packet is silence rather than a lost one
+    pub fn get_dtx_status(&mut self) -> bool {
+        dynamic_mapping!(self, DynamicDecoder, inner => inner.get_dtx_status())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{ModeId, SpeexBits};
+
+    const MODES: [ModeId; 3] = [ModeId::NarrowBand, ModeId::WideBand, ModeId::UltraWideBand];
+
+    /// Tones in every band of every mode. The highest band of ultra-wideband
+    /// is folded up from the band below it, which needs something in it too.
+    fn tones(len: usize, offset: usize) -> Vec<f32> {
+        (offset..offset + len)
+            .map(|n| n as f32)
+            .map(|n| {
+                5000.0 * (n * 0.05).sin() + 2000.0 * (n * 1.2).sin() + 2000.0 * (n * 2.4).sin()
+            })
+            .collect()
+    }
+
+    /// Codes `frames` of `signal` and returns the info of the last frame on
+    /// both ends
+    fn code(
+        encoder: &mut DynamicEncoder,
+        decoder: &mut DynamicDecoder,
+        frames: usize,
+        signal: fn(usize, usize) -> Vec<f32>,
+    ) -> (FrameInfo, FrameInfo) {
+        let frame_size = encoder.get_frame_size() as usize;
+        let mut out = vec![0.0; frame_size];
+        for n in 0..frames {
+            let mut bits = SpeexBits::new();
+            encoder
+                .encode(&mut signal(frame_size, n * frame_size), &mut bits)
+                .unwrap();
+            let mut packet = vec![0; bits.num_bytes() as usize];
+            bits.write(&mut packet);
+            bits.read_from(&mut packet);
+            decoder.decode(&mut bits, &mut out).unwrap();
+        }
+        (encoder.get_frame_info(), decoder.get_frame_info())
+    }
+
+    #[test]
+    fn frame_info_of_tones() {
+        for mode in MODES {
+            let mut encoder = DynamicEncoder::new(mode);
+            let mut decoder = DynamicDecoder::new(mode);
+            let (encoded, decoded) = code(&mut encoder, &mut decoder, 10, tones);
+
+            for info in [&encoded, &decoded] {
+                assert!(
+                    info.pi_gains.iter().all(|&gain| gain > 0.0),
+                    "{mode:?} {info:?}"
+                );
+                assert!(
+                    info.excitation_rms.iter().all(|&rms| rms > 0.0),
+                    "{mode:?} {info:?}"
+                );
+                assert_eq!(info.innovation, None);
+            }
+        }
+    }
+
+    #[test]
+    fn innovation_sizes() {
+        for mode in MODES {
+            let mut encoder = DynamicEncoder::new(mode);
+            let mut decoder = DynamicDecoder::new(mode);
+            encoder.set_innovation_save(true);
+            decoder.set_innovation_save(true);
+            assert!(encoder.get_innovation_save() && decoder.get_innovation_save());
+            let (encoded, decoded) = code(&mut encoder, &mut decoder, 10, tones);
+
+            let innovation = encoded.innovation.unwrap();
+            assert_eq!(innovation.len(), SUBFRAMES);
+            assert!(innovation.iter().any(|&rms| rms > 0.0), "{mode:?}");
+            let innovation = decoded.innovation.unwrap();
+            assert_eq!(innovation.len(), mode.get_frame_size() as usize);
+            assert!(innovation.iter().any(|&sample| sample != 0.0), "{mode:?}");
+
+            encoder.set_innovation_save(false);
+            decoder.set_innovation_save(false);
+            let (encoded, decoded) = code(&mut encoder, &mut decoder, 1, tones);
+            assert_eq!((encoded.innovation, decoded.innovation), (None, None));
+        }
+    }
+
+    #[test]
+    fn dtx_status() {
+        let mut encoder = DynamicEncoder::new(ModeId::NarrowBand);
+        let mut decoder = DynamicDecoder::new(ModeId::NarrowBand);
+        encoder.set_vbr(true);
+        encoder.set_dtx(true);
+        code(&mut encoder, &mut decoder, 10, tones);
+        assert!(!decoder.get_dtx_status());
+
+        code(&mut encoder, &mut decoder, 50, |len, _| vec![0.0; len]);
+        assert!(decoder.get_dtx_status());
+    }
+}
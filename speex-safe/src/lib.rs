@@ -6,20 +6,47 @@
 ////////////////////////////////////////////////////////////////////////////////
 
 pub(crate) mod bits;
+pub(crate) mod comments;
+pub(crate) mod config;
+pub mod echo;
+pub(crate) mod fft;
 pub(crate) mod header;
+#[cfg(feature = "introspection")]
+pub mod introspection;
+pub mod metrics;
 pub(crate) mod mode;
+pub mod ogg;
+pub mod preprocess;
+pub mod resampler;
 pub(crate) mod stereo_state;
+pub mod stream;
+// The shared test helpers name this crate the way the integration tests do
+#[cfg(test)]
+extern crate self as speex_safe;
+#[cfg(test)]
+#[path = "../tests/support/mod.rs"]
+mod support;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "wav")]
+pub mod wav;
 
 use std::ffi::{c_char, c_void, CStr};
 use std::ptr::null;
 
 pub use bits::SpeexBits;
-pub use header::SpeexHeader;
+pub use comments::{CommentField, CommentsError, SpeexComments};
+pub use config::{ConfigError, EncoderConfig, RateControl};
+pub use header::{HeadersError, SpeexHeader, SpeexHeaders};
 pub use mode::{
+    CoderMode,
     ControlError,
     ControlFunctions,
+    DecoderError,
+    DecoderSample,
     DynamicDecoder,
     DynamicEncoder,
+    EncoderError,
     ModeId,
     NbMode,
     NbSubmodeId,
@@ -30,6 +57,7 @@ pub use mode::{
     WbMode,
     WbSubmodeId,
 };
+pub use speex_sys::{catch_fatal, Arena, FatalError};
 use speex_sys::{
     speex_lib_ctl,
     SPEEX_LIB_GET_EXTRA_VERSION,
@@ -0,0 +1,369 @@
+////////////////////////////////////////////////////////////////////////////////
+// Copyright (c) 2023.                                                         /
+// This Source Code Form is subject to the terms of the Mozilla Public License,/
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can    /
+// obtain one at http://mozilla.org/MPL/2.0/.                                  /
+////////////////////////////////////////////////////////////////////////////////
+
+//! Objective measures of how close decoded speech is to what was encoded, for
+//! tuning the encoder without external tools
+//!
+//! The decoded signal lags the original by the lookahead of the encoder and
+//! the decoder, [`get_coding_delay`]. [`Metrics::measure`] starts from that
+//! delay and lines the two up exactly before comparing them.
+//!
+//! The perceptual estimate follows the outline of PESQ: the loudness of each
+//! critical band is compared frame by frame, with quiet differences masked
+//! and added noise weighted more than lost sound, and the disturbance mapped
+//! to a mean opinion score. It isn't calibrated against PESQ, so its scores
+//! are for comparing settings with each other only.
+
+use crate::fft::{Complex, Fft};
+use crate::mode::CoderMode;
+use crate::{ControlFunctions, SpeexDecoder, SpeexEncoder};
+
+/// Range the SNR of each frame is held within, so neither silence nor a
+/// perfect frame outweighs the rest
+const SEGMENT_SNR_RANGE: (f32, f32) = (-10.0, 35.0);
+
+/// Frames of the reference this far below its average power are silence
+const SILENCE: f32 = 1e-3;
+
+/// Threshold of hearing in each bin, relative to the average power of a bin
+const HEARING_THRESHOLD: f32 = 1e-4;
+
+/// Scale of the loudness of a band, in sones of sorts
+const LOUDNESS_SCALE: f32 = 6.0;
+
+/// Frames in each interval whose disturbance is summed as one
+const INTERVAL: usize = 20;
+
+/// Scores of decoded speech against the original
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Metrics {
+    /// Average SNR of the frames of the original that aren't silent, in dB
+    pub segmental_snr: f32,
+    /// RMS difference of the log power spectra, averaged over frames of the
+    /// original that aren't silent, in dB
+    pub log_spectral_distance: f32,
+    /// Estimate of the mean opinion score, from 1 to 4.5. Signals shorter
+    /// than a block of the spectrum, about 32ms, score 4.5.
+    pub perceptual: f32,
+    /// How far the decoded signal was found to lag the original, in samples
+    pub delay: usize,
+}
+
+impl Metrics {
+    /// Compares `decoded` against `reference`, both at `sampling_rate`. The
+    /// decoded signal is expected to lag by about `delay` samples, and is
+    /// searched for within a frame of that.
+    pub fn measure(reference: &[f32], decoded: &[f32], sampling_rate: u32, delay: usize) -> Self {
+        let frame = sampling_rate as usize / 50;
+        let delay = find_delay(reference, decoded, delay, frame);
+        let decoded = &decoded[delay.min(decoded.len())..];
+        let len = reference.len().min(decoded.len());
+        let (reference, decoded) = (&reference[..len], &decoded[..len]);
+
+        let size = (sampling_rate as usize * 32 / 1000).next_power_of_two();
+        let reference_spectra = power_spectra(reference, size);
+        let decoded_spectra = power_spectra(decoded, size);
+        Self {
+            segmental_snr: segmental_snr(reference, decoded, frame),
+            log_spectral_distance: log_spectral_distance(&reference_spectra, &decoded_spectra),
+            perceptual: perceptual(&reference_spectra, &decoded_spectra, sampling_rate, size),
+            delay,
+        }
+    }
+}
+
+/// Gets how many samples decoding lags behind what was encoded
+pub fn get_coding_delay<T: CoderMode>(
+    encoder: &mut SpeexEncoder<T>,
+    decoder: &mut SpeexDecoder<T>,
+) -> usize {
+    (encoder.get_lookahead() + decoder.get_lookahead()) as usize
+}
+
+/// Finds the lag of `decoded` behind `reference` within `search` samples of
+/// `expected`, as the peak of their cross-correlation
+pub fn find_delay(reference: &[f32], decoded: &[f32], expected: usize, search: usize) -> usize {
+    let lowest = expected.saturating_sub(search);
+    let highest = expected + search;
+    let len = reference.len().min(decoded.len());
+    if len == 0 {
+        return expected;
+    }
+
+    // Long enough that no lag searched wraps around
+    let size = (len + highest + 1).next_power_of_two();
+    let mut fft = Fft::new(size);
+    let mut spread = |signal: &[f32]| {
+        let mut spectrum = vec![Complex::default(); size];
+        for (out, &x) in spectrum.iter_mut().zip(signal) {
+            *out = Complex::new(x, 0.0);
+        }
+        fft.forward(&mut spectrum);
+        spectrum
+    };
+    let reference = spread(&reference[..len]);
+    let mut correlation = spread(&decoded[..decoded.len().min(len + highest)]);
+    for (out, &r) in correlation.iter_mut().zip(&reference) {
+        *out = r.conj() * *out;
+    }
+    fft.inverse(&mut correlation);
+
+    (lowest..=highest)
+        .max_by(|&a, &b| correlation[a].re.total_cmp(&correlation[b].re))
+        .unwrap_or(expected)
+}
+
+fn energy(signal: &[f32]) -> f32 {
+    signal.iter().map(|x| x * x).sum()
+}
+
+fn segmental_snr(reference: &[f32], decoded: &[f32], frame: usize) -> f32 {
+    let threshold = SILENCE * energy(reference) / reference.len().max(1) as f32 * frame as f32;
+    let (low, high) = SEGMENT_SNR_RANGE;
+    let snrs = reference
+        .chunks_exact(frame)
+        .zip(decoded.chunks_exact(frame))
+        .filter(|(reference, _)| energy(reference) > threshold)
+        .map(|(reference, decoded)| {
+            let noise = reference
+                .iter()
+                .zip(decoded)
+                .map(|(x, y)| (x - y).powi(2))
+                .sum::<f32>();
+            (10.0 * (energy(reference) / noise).log10()).clamp(low, high)
+        })
+        .collect::<Vec<_>>();
+    snrs.iter().sum::<f32>() / snrs.len().max(1) as f32
+}
+
+/// Power spectra of Hann windowed blocks of `size`, overlapping by half, from
+/// DC to Nyquist
+fn power_spectra(signal: &[f32], size: usize) -> Vec<Vec<f32>> {
+    let window = (0..size)
+        .map(|i| 0.5 - 0.5 * (2.0 * std::f32::consts::PI * i as f32 / size as f32).cos())
+        .collect::<Vec<_>>();
+    let mut fft = Fft::new(size);
+    let mut spectrum = vec![Complex::default(); size];
+    signal
+        .windows(size)
+        .step_by(size / 2)
+        .map(|block| {
+            for ((out, &x), &w) in spectrum.iter_mut().zip(block).zip(&window) {
+                *out = Complex::new(x * w, 0.0);
+            }
+            fft.forward(&mut spectrum);
+            spectrum[..=size / 2].iter().map(|x| x.norm_sqr()).collect()
+        })
+        .collect()
+}
+
+/// Average power of a bin across all of `spectra`
+fn bin_power(spectra: &[Vec<f32>]) -> f32 {
+    let bins = spectra.iter().map(|spectrum| spectrum.len()).sum::<usize>();
+    spectra.iter().flatten().sum::<f32>() / bins.max(1) as f32
+}
+
+/// Whether each of `spectra` stands above silence
+fn active(spectra: &[Vec<f32>]) -> Vec<bool> {
+    let threshold = SILENCE * bin_power(spectra);
+    spectra
+        .iter()
+        .map(|spectrum| spectrum.iter().sum::<f32>() / spectrum.len() as f32 > threshold)
+        .collect()
+}
+
+fn log_spectral_distance(reference: &[Vec<f32>], decoded: &[Vec<f32>]) -> f32 {
+    // Well below anything audible, so empty bins don't count for much
+    let floor = (1e-5 * bin_power(reference)).max(f32::MIN_POSITIVE);
+    let distances = reference
+        .iter()
+        .zip(decoded)
+        .zip(active(reference))
+        .filter(|(_, active)| *active)
+        .map(|((reference, decoded), _)| {
+            let squared = reference[1..]
+                .iter()
+                .zip(&decoded[1..])
+                .map(|(r, d)| (10.0 * ((r + floor) / (d + floor)).log10()).powi(2))
+                .sum::<f32>();
+            (squared / (reference.len() - 1) as f32).sqrt()
+        })
+        .collect::<Vec<_>>();
+    distances.iter().sum::<f32>() / distances.len().max(1) as f32
+}
+
+/// Critical band rate of `frequency`, in Bark
+fn bark(frequency: f32) -> f32 {
+    13.0 * (0.00076 * frequency).atan() + 3.5 * (frequency / 7500.0).powi(2).atan()
+}
+
+/// Loudness of a band with `power`, where `threshold` is just audible
+fn loudness(power: f32, threshold: f32) -> f32 {
+    LOUDNESS_SCALE * ((0.5 + 0.5 * power / threshold).powf(0.23) - 1.0).max(0.0)
+}
+
+/// Norm of `values` to the power `p`
+fn norm(values: &[f32], p: i32) -> f32 {
+    (values.iter().map(|x| x.powi(p)).sum::<f32>() / values.len().max(1) as f32)
+        .powf(1.0 / p as f32)
+}
+
+fn perceptual(
+    reference: &[Vec<f32>],
+    decoded: &[Vec<f32>],
+    sampling_rate: u32,
+    size: usize,
+) -> f32 {
+    if reference.is_empty() {
+        return 4.5;
+    }
+
+    // Bands a Bark wide, above 100Hz or so
+    let bands = (1..=size / 2)
+        .map(|k| bark(k as f32 * sampling_rate as f32 / size as f32) as usize)
+        .collect::<Vec<_>>();
+    let band_count = bands.last().map_or(0, |&band| band + 1);
+    let mut widths = vec![0.0f32; band_count];
+    for &band in &bands {
+        widths[band] += 1.0;
+    }
+    let thresholds = widths
+        .iter()
+        .map(|width| HEARING_THRESHOLD * width)
+        .collect::<Vec<_>>();
+    let total_width = widths[1..].iter().sum::<f32>();
+
+    // Both brought to the same level, as a listener would set the volume.
+    // Silence stays silent.
+    let scale = |spectra: &[Vec<f32>]| {
+        let power = bin_power(spectra);
+        if power > 0.0 {
+            1.0 / power
+        } else {
+            0.0
+        }
+    };
+    let reference_scale = scale(reference);
+    let decoded_scale = scale(decoded);
+    let band_powers = |spectrum: &[f32], scale: f32| {
+        let mut powers = vec![0.0f32; band_count];
+        for (&band, power) in bands.iter().zip(&spectrum[1..]) {
+            powers[band] += power * scale;
+        }
+        powers
+    };
+
+    let (symmetric, asymmetric): (Vec<f32>, Vec<f32>) = reference
+        .iter()
+        .zip(decoded)
+        .map(|(reference, decoded)| {
+            let reference = band_powers(reference, reference_scale);
+            let decoded = band_powers(decoded, decoded_scale);
+            let (mut symmetric, mut asymmetric) = (0.0, 0.0);
+            for band in 1..band_count {
+                let threshold = thresholds[band];
+                let heard = loudness(reference[band], threshold);
+                let played = loudness(decoded[band], threshold);
+                // Differences under a quarter of the loudness are masked
+                let disturbance = ((played - heard).abs() - 0.25 * heard.min(played)).max(0.0);
+                // Sound that isn't in the reference is far more annoying than
+                // sound that's missing
+                let ratio = ((decoded[band] + 50.0 * threshold)
+                    / (reference[band] + 50.0 * threshold))
+                    .powf(1.2);
+                let ratio = if ratio < 3.0 { 0.0 } else { ratio.min(12.0) };
+                symmetric += widths[band] * disturbance * disturbance;
+                asymmetric += widths[band] * disturbance * ratio;
+            }
+            ((symmetric / total_width).sqrt(), asymmetric / total_width)
+        })
+        .unzip();
+
+    // Loud moments over a third of a second dominate, then the intervals are
+    // averaged
+    let aggregate = |frames: &[f32]| {
+        let intervals = frames
+            .windows(INTERVAL.min(frames.len()))
+            .step_by(INTERVAL / 2)
+            .map(|interval| norm(interval, 6))
+            .collect::<Vec<_>>();
+        norm(&intervals, 2)
+    };
+    let symmetric = aggregate(&symmetric);
+    let asymmetric = aggregate(&asymmetric);
+    (4.5 - 0.1 * symmetric - 0.0309 * asymmetric).clamp(1.0, 4.5)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::support;
+
+    const RATE: u32 = 8000;
+
+    fn tone_and_noise(len: usize) -> Vec<f32> {
+        let noise = support::white_noise(1, 1000.0 / 12f32.sqrt(), len);
+        let tone = support::tone(RATE, 440.0, 3000.0, len);
+        tone.iter().zip(noise).map(|(x, n)| x + n).collect()
+    }
+
+    /// Adds uniform noise spanning `amplitude` from peak to peak
+    fn with_noise(signal: &[f32], amplitude: f32) -> Vec<f32> {
+        let noise = support::white_noise(99, amplitude / 12f32.sqrt(), signal.len());
+        signal.iter().zip(noise).map(|(x, n)| x + n).collect()
+    }
+
+    #[test]
+    fn identical_signals_score_best() {
+        let signal = tone_and_noise(RATE as usize * 2);
+        let metrics = Metrics::measure(&signal, &signal, RATE, 0);
+        assert_eq!(metrics.delay, 0);
+        assert_eq!(metrics.segmental_snr, SEGMENT_SNR_RANGE.1);
+        assert!(metrics.log_spectral_distance < 1e-3);
+        assert_eq!(metrics.perceptual, 4.5);
+    }
+
+    #[test]
+    fn measures_short_and_silent_signals() {
+        let signal = tone_and_noise(100);
+        let metrics = Metrics::measure(&signal, &signal, RATE, 0);
+        assert_eq!(metrics.perceptual, 4.5);
+        assert!(metrics.segmental_snr.is_finite());
+        assert!(metrics.log_spectral_distance.is_finite());
+
+        let silence = vec![0.0; RATE as usize];
+        let metrics = Metrics::measure(&silence, &silence, RATE, 0);
+        assert!(metrics.perceptual.is_finite());
+        assert!(metrics.log_spectral_distance.is_finite());
+    }
+
+    #[test]
+    fn finds_delay() {
+        let signal = tone_and_noise(RATE as usize * 2);
+        let mut delayed = vec![0.0; 123];
+        delayed.extend_from_slice(&signal);
+        assert_eq!(find_delay(&signal, &delayed, 100, 160), 123);
+        let metrics = Metrics::measure(&signal, &delayed, RATE, 100);
+        assert_eq!(metrics.delay, 123);
+        assert!(metrics.log_spectral_distance < 1e-3);
+    }
+
+    #[test]
+    fn worse_with_more_noise() {
+        let signal = tone_and_noise(RATE as usize * 2);
+        let scores = [30.0, 300.0, 3000.0]
+            .map(|amplitude| Metrics::measure(&signal, &with_noise(&signal, amplitude), RATE, 0));
+        for pair in scores.windows(2) {
+            assert!(pair[0].segmental_snr > pair[1].segmental_snr, "{scores:?}");
+            assert!(
+                pair[0].log_spectral_distance < pair[1].log_spectral_distance,
+                "{scores:?}"
+            );
+            assert!(pair[0].perceptual > pair[1].perceptual, "{scores:?}");
+        }
+    }
+}
@@ -5,11 +5,13 @@
 // obtain one at http://mozilla.org/MPL/2.0/.                                  /
 ////////////////////////////////////////////////////////////////////////////////
 
+use std::error::Error;
 use std::ffi::c_void;
 use std::fmt::{Display, Formatter};
 use std::marker::{PhantomData, PhantomPinned};
+use std::ptr::null_mut;
 
-use speex_sys::SpeexMode;
+use speex_sys::{catch_fatal, Arena, FatalError, SpeexCallback, SpeexMode};
 
 use crate::mode::{CoderMode, ControlFunctions, ModeId};
 use crate::{
@@ -20,6 +22,7 @@ use crate::{
     NbMode,
     NbSubmodeId,
     SpeexBits,
+    SpeexStereoState,
     UwbMode,
     WbMode,
     WbSubmodeId,
@@ -63,14 +66,44 @@ impl SpeexDecoderHandle {
 }
 
 /// A struct representing a speex decoder.
+///
+/// # Fatal errors
+///
+/// Internal errors libspeex can't carry on from, such as a failed assertion,
+/// unwind out of the methods calling into it as a panic carrying a
+/// [`FatalError`]. Only `new_in` catches them, as running out of room in an
+/// arena is to be expected. Elsewhere [`catch_fatal`] turns them back into an
+/// error, after which the decoder shouldn't be used again.
 pub struct SpeexDecoder<T: CoderMode> {
     encoder_handle: *mut SpeexDecoderHandle,
     pub mode: &'static SpeexMode,
+    // Where libspeex saves the innovation of each frame, once enabled
+    #[cfg(feature = "introspection")]
+    pub(crate) innovation: Option<Box<[f32]>>,
+    // Registered as the in-band stereo handler the first time a stereo frame
+    // is decoded, or up front by `new_in`
+    stereo: Option<SpeexStereoState>,
+    // Holds the decoder and stereo states when created with `new_in`. Fields
+    // are dropped in the order they're declared, so this has to come last.
+    arena: Option<Arena>,
     _phantom: PhantomData<T>,
 }
 
 impl<T: CoderMode> mode::private::Sealed for SpeexDecoder<T> {}
 
+// SAFETY: the state behind `encoder_handle` is only reachable through this
+// struct, and libspeex keeps no global mutable state of its own, so it can be
+// used from whichever thread owns the decoder. `mode` points into the mode
+// definitions libspeex keeps as constants, which are never written to. The
+// stereo handler registered with the state points at the `SysStereoState` that
+// `stereo` owns on the heap, so it stays valid when the decoder moves and is
+// only used while decoding. State created by `new_in`, the stereo state
+// included, lives in `arena`, whose memory moves along with the decoder; the
+// arena is only put in the thread local that libspeex allocates from while
+// `new_in` runs, and freeing memory taken from it does nothing. It isn't Sync,
+// as every call touching the state takes `&mut self`.
+unsafe impl<T: CoderMode> Send for SpeexDecoder<T> {}
+
 impl<T: CoderMode> ControlFunctions for SpeexDecoder<T> {
     unsafe fn ctl(&mut self, request: i32, ptr: *mut c_void) -> Result<(), ControlError> {
         let result = speex_sys::speex_decoder_ctl(self.encoder_handle as *mut c_void, request, ptr);
@@ -83,6 +116,8 @@ pub enum DecoderError {
     TooSmallBuffer,
     EndOfStream,
     CorruptStream,
+    /// libspeex returned a code it isn't documented to return
+    Unknown(i32),
 }
 
 impl Display for DecoderError {
@@ -91,10 +126,15 @@ impl Display for DecoderError {
             DecoderError::TooSmallBuffer => write!(f, "Buffer is too small to decode into"),
             DecoderError::EndOfStream => write!(f, "End of stream reached while decoding"),
             DecoderError::CorruptStream => write!(f, "Corrupt stream was unable to be decoded"),
+            DecoderError::Unknown(code) => {
+                write!(f, "Unexpected return value from speex_decode ({code})")
+            }
         }
     }
 }
 
+impl Error for DecoderError {}
+
 impl<T: CoderMode> SpeexDecoder<T> {
     /// Set whether to use enhancement.
     pub fn set_enhancement(&mut self, state: bool) {
@@ -116,6 +156,8 @@ impl<T: CoderMode> SpeexDecoder<T> {
     }
 
     /// Decode one frame of speex data from the bitstream
+    ///
+    /// Panics with a [`FatalError`] if libspeex raises one.
     pub fn decode(&mut self, bits: &mut SpeexBits, out: &mut [f32]) -> Result<(), DecoderError> {
         let frame_size = self.get_frame_size() as usize;
         if out.len() < frame_size {
@@ -130,7 +172,7 @@ impl<T: CoderMode> SpeexDecoder<T> {
             0 => Ok(()),
             -1 => Err(DecoderError::EndOfStream),
             -2 => Err(DecoderError::CorruptStream),
-            _ => panic!("Unexpected return value from speex_decode"),
+            code => Err(DecoderError::Unknown(code)),
         }
     }
 
@@ -142,7 +184,46 @@ impl<T: CoderMode> SpeexDecoder<T> {
         Ok(out)
     }
 
+    /// Produces one frame of audio for a packet that was lost, using the
+    /// decoder's packet loss concealment
+    ///
+    /// Panics with a [`FatalError`] if libspeex raises one.
+    pub fn decode_lost(&mut self, out: &mut [f32]) -> Result<(), DecoderError> {
+        let frame_size = self.get_frame_size() as usize;
+        if out.len() < frame_size {
+            return Err(DecoderError::TooSmallBuffer);
+        }
+        let out_ptr = out.as_mut_ptr();
+        let result = unsafe {
+            speex_sys::speex_decode(self.encoder_handle as *mut c_void, null_mut(), out_ptr)
+        };
+        match result {
+            0 => Ok(()),
+            code => Err(DecoderError::Unknown(code)),
+        }
+    }
+
+    /// Produces one frame of audio for a packet that was lost, as i16
+    ///
+    /// Panics with a [`FatalError`] if libspeex raises one.
+    pub fn decode_lost_int(&mut self, out: &mut [i16]) -> Result<(), DecoderError> {
+        let frame_size = self.get_frame_size() as usize;
+        if out.len() < frame_size {
+            return Err(DecoderError::TooSmallBuffer);
+        }
+        let out_ptr = out.as_mut_ptr();
+        let result = unsafe {
+            speex_sys::speex_decode_int(self.encoder_handle as *mut c_void, null_mut(), out_ptr)
+        };
+        match result {
+            0 => Ok(()),
+            code => Err(DecoderError::Unknown(code)),
+        }
+    }
+
     /// Decode one frame of speex data from the bitstream, as i16
+    ///
+    /// Panics with a [`FatalError`] if libspeex raises one.
     pub fn decode_int(
         &mut self,
         bits: &mut SpeexBits,
@@ -161,7 +242,7 @@ impl<T: CoderMode> SpeexDecoder<T> {
             0 => Ok(()),
             -1 => Err(DecoderError::EndOfStream),
             -2 => Err(DecoderError::CorruptStream),
-            _ => panic!("Unexpected return value from speex_decode"),
+            code => Err(DecoderError::Unknown(code)),
         }
     }
 
@@ -173,13 +254,77 @@ impl<T: CoderMode> SpeexDecoder<T> {
         Ok(out)
     }
 
-    fn get_low_submode_internal(&mut self) -> NbSubmodeId {
+    fn stereo_state(&mut self) -> &mut SpeexStereoState {
+        if self.stereo.is_none() {
+            self.set_stereo_state(SpeexStereoState::new());
+        }
+        self.stereo.as_mut().unwrap()
+    }
+
+    /// Registers `stereo` as the handler of in-band stereo messages
+    fn set_stereo_state(&mut self, mut stereo: SpeexStereoState) {
+        let mut callback = SpeexCallback {
+            callback_id: speex_sys::SPEEX_INBAND_STEREO,
+            func: Some(speex_sys::speex_std_stereo_request_handler),
+            data: stereo.backing_mut_ptr() as *mut c_void,
+            reserved1: null_mut(),
+            reserved2: 0,
+        };
+        let ptr = &mut callback as *mut SpeexCallback as *mut c_void;
+        unsafe {
+            self.ctl(speex_sys::SPEEX_SET_HANDLER, ptr).unwrap();
+        }
+        self.stereo = Some(stereo);
+    }
+
+    /// Decode one frame of stereo speex data from the bitstream, as
+    /// interleaved samples
+    ///
+    /// `out` must be able to hold two channels worth of samples. Streams
+    /// without stereo information decode to two identical channels.
+    ///
+    /// Panics with a [`FatalError`] if libspeex raises one.
+    pub fn decode_stereo(
+        &mut self,
+        bits: &mut SpeexBits,
+        out: &mut [f32],
+    ) -> Result<(), DecoderError> {
+        let frame_size = self.get_frame_size() as usize;
+        if out.len() < frame_size * 2 {
+            return Err(DecoderError::TooSmallBuffer);
+        }
+        self.stereo_state();
+        self.decode(bits, out)?;
+        self.stereo_state().decode(out, frame_size);
+        Ok(())
+    }
+
+    /// Decode one frame of stereo speex data from the bitstream, as
+    /// interleaved i16 samples
+    ///
+    /// Panics with a [`FatalError`] if libspeex raises one.
+    pub fn decode_stereo_int(
+        &mut self,
+        bits: &mut SpeexBits,
+        out: &mut [i16],
+    ) -> Result<(), DecoderError> {
+        let frame_size = self.get_frame_size() as usize;
+        if out.len() < frame_size * 2 {
+            return Err(DecoderError::TooSmallBuffer);
+        }
+        self.stereo_state();
+        self.decode_int(bits, out)?;
+        self.stereo_state().decode_int(out, frame_size);
+        Ok(())
+    }
+
+    fn get_low_submode_internal(&mut self) -> Option<NbSubmodeId> {
         let mut low_mode = 0;
         let ptr = &mut low_mode as *mut i32 as *mut c_void;
         unsafe {
             self.ctl(speex_sys::SPEEX_GET_LOW_MODE, ptr).unwrap();
         }
-        low_mode.into()
+        NbSubmodeId::from_id(low_mode)
     }
 
     fn set_low_submode_internal(&mut self, low_mode: NbSubmodeId) {
@@ -198,13 +343,43 @@ impl<T: CoderMode> SpeexDecoder<T> {
         }
     }
 
-    fn get_high_submode_internal(&mut self) -> WbSubmodeId {
+    fn get_high_submode_internal(&mut self) -> Result<Option<WbSubmodeId>, ControlError> {
         let mut high_mode = 0;
         let ptr = &mut high_mode as *mut i32 as *mut c_void;
         unsafe {
-            self.ctl(speex_sys::SPEEX_GET_HIGH_MODE, ptr).unwrap();
+            self.ctl(speex_sys::SPEEX_GET_HIGH_MODE, ptr)?;
         }
-        high_mode.into()
+        Ok(WbSubmodeId::from_id(high_mode))
+    }
+}
+
+impl<T: CoderMode> SpeexDecoder<T> {
+    fn create_in(mode: ModeId, arena: Arena) -> Result<Self, FatalError> {
+        let mode = mode.get_mode();
+        // The stereo state is taken up front, as running out of room while
+        // decoding would unwind through the decoding functions
+        let (encoder_handle, stereo) = catch_fatal(|| {
+            arena.scope(|| {
+                let handle = unsafe { SpeexDecoderHandle::create(mode) };
+                (handle, SpeexStereoState::new())
+            })
+        })?;
+        let mut decoder = Self {
+            encoder_handle,
+            mode,
+            #[cfg(feature = "introspection")]
+            innovation: None,
+            stereo: None,
+            arena: Some(arena),
+            _phantom: PhantomData,
+        };
+        decoder.set_stereo_state(stereo);
+        Ok(decoder)
+    }
+
+    /// Gets the arena the state was created in, if any
+    pub fn get_arena(&self) -> Option<&Arena> {
+        self.arena.as_ref()
     }
 }
 
@@ -216,17 +391,29 @@ impl SpeexDecoder<NbMode> {
         Self {
             encoder_handle,
             mode,
+            #[cfg(feature = "introspection")]
+            innovation: None,
+            stereo: None,
+            arena: None,
             _phantom: PhantomData,
         }
     }
 
+    /// Create a new narrowband decoder with its state in `arena`, failing if
+    /// the arena is too small. The state for decoding stereo is taken from the
+    /// arena too.
+    pub fn new_in(arena: Arena) -> Result<SpeexDecoder<NbMode>, FatalError> {
+        Self::create_in(ModeId::NarrowBand, arena)
+    }
+
     /// Sets the submode to use for encoding.
     pub fn set_submode(&mut self, submode: NbSubmodeId) {
         self.set_low_submode_internal(submode);
     }
 
-    /// Gets the submode currently in use for encoding.
-    pub fn get_submode(&mut self) -> NbSubmodeId {
+    /// Gets the submode of the last frame decoded, or `None` if it was a
+    /// silent frame.
+    pub fn get_submode(&mut self) -> Option<NbSubmodeId> {
         self.get_low_submode_internal()
     }
 }
@@ -245,17 +432,29 @@ impl SpeexDecoder<WbMode> {
         Self {
             encoder_handle,
             mode,
+            #[cfg(feature = "introspection")]
+            innovation: None,
+            stereo: None,
+            arena: None,
             _phantom: PhantomData,
         }
     }
 
+    /// Create a new wideband decoder with its state in `arena`, failing if
+    /// the arena is too small. The state for decoding stereo is taken from the
+    /// arena too.
+    pub fn new_in(arena: Arena) -> Result<SpeexDecoder<WbMode>, FatalError> {
+        Self::create_in(ModeId::WideBand, arena)
+    }
+
     /// Sets the submode of the narrowband part of the encoder.
     pub fn set_low_submode(&mut self, low_mode: NbSubmodeId) {
         self.set_low_submode_internal(low_mode);
     }
 
-    /// Gets the submode of the narrowband part of the encoder.
-    pub fn get_low_submode(&mut self) -> NbSubmodeId {
+    /// Gets the submode of the narrowband part of the last frame decoded, or
+    /// `None` if it was silent.
+    pub fn get_low_submode(&mut self) -> Option<NbSubmodeId> {
         self.get_low_submode_internal()
     }
 
@@ -264,8 +463,11 @@ impl SpeexDecoder<WbMode> {
         self.set_high_submode_internal(high_mode);
     }
 
-    /// Gets the submode of the wideband part of the encoder.
-    pub fn get_high_submode(&mut self) -> WbSubmodeId {
+    /// Gets the submode of the wideband part of the decoder.
+    ///
+    /// The wideband decoder of libspeex doesn't answer this request, so this
+    /// returns [`ControlError::UnknownRequest`].
+    pub fn get_high_submode(&mut self) -> Result<Option<WbSubmodeId>, ControlError> {
         self.get_high_submode_internal()
     }
 }
@@ -284,17 +486,29 @@ impl SpeexDecoder<UwbMode> {
         Self {
             encoder_handle,
             mode,
+            #[cfg(feature = "introspection")]
+            innovation: None,
+            stereo: None,
+            arena: None,
             _phantom: PhantomData,
         }
     }
 
+    /// Create a new ultra-wideband decoder with its state in `arena`, failing
+    /// if the arena is too small. The state for decoding stereo is taken from
+    /// the arena too.
+    pub fn new_in(arena: Arena) -> Result<SpeexDecoder<UwbMode>, FatalError> {
+        Self::create_in(ModeId::UltraWideBand, arena)
+    }
+
     /// Sets the submode of the narrowband part of the encoder.
     pub fn set_low_submode(&mut self, low_mode: NbSubmodeId) {
         self.set_low_submode_internal(low_mode);
     }
 
-    /// Gets the submode of the narrowband part of the encoder.
-    pub fn get_low_submode(&mut self) -> NbSubmodeId {
+    /// Gets the submode of the narrowband part of the last frame decoded, or
+    /// `None` if it was silent.
+    pub fn get_low_submode(&mut self) -> Option<NbSubmodeId> {
         self.get_low_submode_internal()
     }
 }
@@ -333,6 +547,8 @@ impl DynamicDecoder {
     }
 
     /// Decode one frame of speex data from the bitstream
+    ///
+    /// Panics with a [`FatalError`] if libspeex raises one.
     pub fn decode(&mut self, bits: &mut SpeexBits, out: &mut [f32]) -> Result<(), DecoderError> {
         match self {
             DynamicDecoder::Nb(inner) => inner.decode(bits, out),
@@ -351,6 +567,8 @@ impl DynamicDecoder {
     }
 
     /// Decode one frame of speex data from the bitstream, as i16
+    ///
+    /// Panics with a [`FatalError`] if libspeex raises one.
     pub fn decode_int(
         &mut self,
         bits: &mut SpeexBits,
@@ -372,6 +590,76 @@ impl DynamicDecoder {
         }
     }
 
+    /// Produces one frame of audio for a packet that was lost, using the
+    /// decoder's packet loss concealment
+    ///
+    /// Panics with a [`FatalError`] if libspeex raises one.
+    pub fn decode_lost(&mut self, out: &mut [f32]) -> Result<(), DecoderError> {
+        dynamic_mapping!(self, DynamicDecoder, inner => inner.decode_lost(out))
+    }
+
+    /// Produces one frame of audio for a packet that was lost, as i16
+    ///
+    /// Panics with a [`FatalError`] if libspeex raises one.
+    pub fn decode_lost_int(&mut self, out: &mut [i16]) -> Result<(), DecoderError> {
+        dynamic_mapping!(self, DynamicDecoder, inner => inner.decode_lost_int(out))
+    }
+
+    /// Decode one frame of stereo speex data from the bitstream, as
+    /// interleaved samples
+    ///
+    /// Panics with a [`FatalError`] if libspeex raises one.
+    pub fn decode_stereo(
+        &mut self,
+        bits: &mut SpeexBits,
+        out: &mut [f32],
+    ) -> Result<(), DecoderError> {
+        dynamic_mapping!(self, DynamicDecoder, inner => inner.decode_stereo(bits, out))
+    }
+
+    /// Decode one frame of stereo speex data from the bitstream, as
+    /// interleaved i16 samples
+    ///
+    /// Panics with a [`FatalError`] if libspeex raises one.
+    pub fn decode_stereo_int(
+        &mut self,
+        bits: &mut SpeexBits,
+        out: &mut [i16],
+    ) -> Result<(), DecoderError> {
+        dynamic_mapping!(self, DynamicDecoder, inner => inner.decode_stereo_int(bits, out))
+    }
+
+    /// Decodes every frame of `packet`, or conceals one lost frame if there
+    /// is no packet
+    ///
+    /// `bits` is reset to the packet, and frames are decoded from it until
+    /// the end of the packet is reached.
+    pub fn decode_packet<T: DecoderSample>(
+        &mut self,
+        bits: &mut SpeexBits,
+        packet: Option<&[u8]>,
+    ) -> Result<Vec<T>, DecoderError> {
+        let mut frame = vec![T::default(); self.get_frame_size() as usize];
+        let mut decoded = Vec::new();
+        match packet {
+            Some(packet) => {
+                bits.read_from(&mut packet.to_vec());
+                loop {
+                    match T::decode(self, bits, &mut frame) {
+                        Ok(()) => decoded.extend_from_slice(&frame),
+                        Err(DecoderError::EndOfStream) => break,
+                        Err(err) => return Err(err),
+                    }
+                }
+            }
+            None => {
+                T::decode_lost(self, &mut frame)?;
+                decoded.extend_from_slice(&frame);
+            }
+        }
+        Ok(decoded)
+    }
+
     pub fn new(mode: ModeId) -> DynamicDecoder {
         match mode {
             ModeId::NarrowBand => DynamicDecoder::Nb(SpeexDecoder::<NbMode>::new()),
@@ -380,6 +668,15 @@ impl DynamicDecoder {
         }
     }
 
+    /// Same as `new`, with the state in `arena`
+    pub fn new_in(mode: ModeId, arena: Arena) -> Result<DynamicDecoder, FatalError> {
+        Ok(match mode {
+            ModeId::NarrowBand => DynamicDecoder::Nb(SpeexDecoder::<NbMode>::new_in(arena)?),
+            ModeId::WideBand => DynamicDecoder::Wb(SpeexDecoder::<WbMode>::new_in(arena)?),
+            ModeId::UltraWideBand => DynamicDecoder::Uwb(SpeexDecoder::<UwbMode>::new_in(arena)?),
+        })
+    }
+
     pub fn into_nb(self) -> Option<SpeexDecoder<NbMode>> {
         match self {
             DynamicDecoder::Nb(nb) => Some(nb),
@@ -401,3 +698,79 @@ impl DynamicDecoder {
         }
     }
 }
+
+/// Sample types a decoder can decode into, `f32` or `i16`
+///
+/// This trait is sealed, and cannot be implemented outside of this crate.
+pub trait DecoderSample: Copy + Default + mode::private::Sealed {
+    #[doc(hidden)]
+    fn decode(
+        decoder: &mut DynamicDecoder,
+        bits: &mut SpeexBits,
+        out: &mut [Self],
+    ) -> Result<(), DecoderError>;
+
+    #[doc(hidden)]
+    fn decode_lost(decoder: &mut DynamicDecoder, out: &mut [Self]) -> Result<(), DecoderError>;
+}
+
+impl mode::private::Sealed for f32 {}
+
+impl DecoderSample for f32 {
+    fn decode(
+        decoder: &mut DynamicDecoder,
+        bits: &mut SpeexBits,
+        out: &mut [f32],
+    ) -> Result<(), DecoderError> {
+        decoder.decode(bits, out)
+    }
+
+    fn decode_lost(decoder: &mut DynamicDecoder, out: &mut [f32]) -> Result<(), DecoderError> {
+        decoder.decode_lost(out)
+    }
+}
+
+impl mode::private::Sealed for i16 {}
+
+impl DecoderSample for i16 {
+    fn decode(
+        decoder: &mut DynamicDecoder,
+        bits: &mut SpeexBits,
+        out: &mut [i16],
+    ) -> Result<(), DecoderError> {
+        decoder.decode_int(bits, out)
+    }
+
+    fn decode_lost(decoder: &mut DynamicDecoder, out: &mut [i16]) -> Result<(), DecoderError> {
+        decoder.decode_lost_int(out)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::DynamicEncoder;
+
+    #[test]
+    fn decodes_every_frame_of_packet() {
+        let mut encoder = DynamicEncoder::new(ModeId::WideBand);
+        let frame_size = encoder.get_frame_size() as usize;
+        let mut bits = SpeexBits::new();
+        for _ in 0..3 {
+            encoder
+                .encode_int(&mut vec![1000; frame_size], &mut bits)
+                .unwrap();
+        }
+        let mut packet = vec![0; 200];
+        let len = bits.write(&mut packet) as usize;
+
+        let mut decoder = DynamicDecoder::new(ModeId::WideBand);
+        let mut bits = SpeexBits::new();
+        let decoded: Vec<i16> = decoder
+            .decode_packet(&mut bits, Some(&packet[..len]))
+            .unwrap();
+        assert_eq!(decoded.len(), 3 * frame_size);
+        let lost: Vec<f32> = decoder.decode_packet(&mut bits, None).unwrap();
+        assert_eq!(lost.len(), frame_size);
+    }
+}
@@ -5,13 +5,23 @@
 // obtain one at http://mozilla.org/MPL/2.0/.                                  /
 ////////////////////////////////////////////////////////////////////////////////
 
+use std::error::Error;
 use std::ffi::c_void;
+use std::fmt::{Display, Formatter};
 use std::marker::{PhantomData, PhantomPinned};
 
-use speex_sys::SpeexMode;
+use speex_sys::{catch_fatal, Arena, FatalError, SpeexMode};
 
 use crate::mode::{CoderMode, ControlError, ControlFunctions, ModeId, NbMode, UwbMode, WbMode};
-use crate::{dynamic_mapping, mode, shared_functions, NbSubmodeId, SpeexBits, WbSubmodeId};
+use crate::{
+    dynamic_mapping,
+    mode,
+    shared_functions,
+    NbSubmodeId,
+    SpeexBits,
+    SpeexStereoState,
+    WbSubmodeId,
+};
 
 /// Handle for the encoder, speex represents this as an opaque pointer so this
 /// is an unconstructable type that is always intended to be behind a pointer.
@@ -51,14 +61,52 @@ impl SpeexEncoderHandle {
 }
 
 /// A struct representing a speex encoder.
+///
+/// # Fatal errors
+///
+/// Internal errors libspeex can't carry on from, such as a failed assertion,
+/// unwind out of the methods calling into it as a panic carrying a
+/// [`FatalError`]. Only `new_in` catches them, as running out of room in an
+/// arena is to be expected. Elsewhere [`catch_fatal`] turns them back into an
+/// error, after which the encoder shouldn't be used again.
 pub struct SpeexEncoder<T: CoderMode> {
     encoder_handle: *mut SpeexEncoderHandle,
     pub mode: &'static SpeexMode,
+    // Holds the state when created with `new_in`, so dropped after it
+    arena: Option<Arena>,
+    // Where libspeex saves the innovation of each frame, once enabled
+    #[cfg(feature = "introspection")]
+    pub(crate) innovation: Option<Box<[f32]>>,
     _phantom: PhantomData<T>,
 }
 
 impl<T: CoderMode> mode::private::Sealed for SpeexEncoder<T> {}
 
+// SAFETY: the state behind `encoder_handle` is only reachable through this
+// struct, and libspeex keeps no global mutable state of its own, so it can be
+// used from whichever thread owns the encoder. `mode` points into the mode
+// definitions libspeex keeps as constants, which are never written to. State
+// created by `new_in` lives in `arena`, whose memory moves along with the
+// encoder; the arena is only put in the thread local that libspeex allocates
+// from while `new_in` runs, and freeing memory taken from it does nothing. It
+// isn't Sync, as every call touching the state takes `&mut self`.
+unsafe impl<T: CoderMode> Send for SpeexEncoder<T> {}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum EncoderError {
+    TooSmallBuffer,
+}
+
+impl Display for EncoderError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EncoderError::TooSmallBuffer => write!(f, "Buffer is too small to hold a frame"),
+        }
+    }
+}
+
+impl Error for EncoderError {}
+
 impl<T: CoderMode> ControlFunctions for SpeexEncoder<T> {
     unsafe fn ctl(&mut self, request: i32, ptr: *mut c_void) -> Result<(), ControlError> {
         let result = speex_sys::speex_encoder_ctl(self.encoder_handle as *mut c_void, request, ptr);
@@ -119,8 +167,33 @@ impl<T: CoderMode> SpeexEncoder<T> {
         state
     }
 
+    /// Sets whether Discontinuous Transmission is enabled or not
+    pub fn set_dtx(&mut self, dtx: bool) {
+        let state = if dtx { 1 } else { 0 };
+        let ptr = &state as *const i32 as *mut c_void;
+        unsafe {
+            self.ctl(speex_sys::SPEEX_SET_DTX, ptr).unwrap();
+        }
+    }
+
+    /// Gets whether Discontinuous Transmission is enabled or not
+    pub fn get_dtx(&mut self) -> bool {
+        let mut state = 0;
+        let ptr = &mut state as *mut i32 as *mut c_void;
+        unsafe {
+            self.ctl(speex_sys::SPEEX_GET_DTX, ptr).unwrap();
+        }
+        state != 0
+    }
+
     /// Encode one frame of audio into the given bits.
-    pub fn encode(&mut self, input: &mut [f32], bits: &mut SpeexBits) {
+    ///
+    /// Panics with a [`FatalError`] if libspeex raises one.
+    pub fn encode(&mut self, input: &mut [f32], bits: &mut SpeexBits) -> Result<(), EncoderError> {
+        let frame_size = self.get_frame_size() as usize;
+        if input.len() < frame_size {
+            return Err(EncoderError::TooSmallBuffer);
+        }
         let input_ptr = input.as_mut_ptr();
         unsafe {
             speex_sys::speex_encode(
@@ -129,16 +202,85 @@ impl<T: CoderMode> SpeexEncoder<T> {
                 bits.backing_mut_ptr(),
             );
         }
+        Ok(())
     }
 
     /// Encode one frame of audio into the given bits, using an integer
     /// representation.
-    pub fn encode_int(&mut self, input: &mut [i16], bits: &mut SpeexBits) {
+    ///
+    /// Panics with a [`FatalError`] if libspeex raises one.
+    pub fn encode_int(
+        &mut self,
+        input: &mut [i16],
+        bits: &mut SpeexBits,
+    ) -> Result<(), EncoderError> {
+        let frame_size = self.get_frame_size() as usize;
+        if input.len() < frame_size {
+            return Err(EncoderError::TooSmallBuffer);
+        }
         let bits_ptr = bits.backing_mut_ptr();
         let input_ptr = input.as_mut_ptr();
         unsafe {
             speex_sys::speex_encode_int(self.encoder_handle as *mut c_void, input_ptr, bits_ptr);
         }
+        Ok(())
+    }
+
+    /// Encode one frame of interleaved stereo audio into the given bits.
+    ///
+    /// The stereo parameters are written as an in-band message ahead of the
+    /// frame, and `input` is downmixed to mono in place before encoding.
+    ///
+    /// Panics with a [`FatalError`] if libspeex raises one.
+    pub fn encode_stereo(
+        &mut self,
+        input: &mut [f32],
+        bits: &mut SpeexBits,
+    ) -> Result<(), EncoderError> {
+        let frame_size = self.get_frame_size() as usize;
+        if input.len() < frame_size * 2 {
+            return Err(EncoderError::TooSmallBuffer);
+        }
+        SpeexStereoState::encode(input, frame_size, bits);
+        self.encode(&mut input[..frame_size], bits)
+    }
+
+    /// Encode one frame of interleaved stereo audio into the given bits, using
+    /// an integer representation.
+    ///
+    /// Panics with a [`FatalError`] if libspeex raises one.
+    pub fn encode_stereo_int(
+        &mut self,
+        input: &mut [i16],
+        bits: &mut SpeexBits,
+    ) -> Result<(), EncoderError> {
+        let frame_size = self.get_frame_size() as usize;
+        if input.len() < frame_size * 2 {
+            return Err(EncoderError::TooSmallBuffer);
+        }
+        SpeexStereoState::encode_int(input, frame_size, bits);
+        self.encode_int(&mut input[..frame_size], bits)
+    }
+}
+
+impl<T: CoderMode> SpeexEncoder<T> {
+    fn create_in(mode: ModeId, arena: Arena) -> Result<Self, FatalError> {
+        let mode = mode.get_mode();
+        let encoder_handle =
+            catch_fatal(|| arena.scope(|| unsafe { SpeexEncoderHandle::create(mode) }))?;
+        Ok(Self {
+            encoder_handle,
+            mode,
+            arena: Some(arena),
+            #[cfg(feature = "introspection")]
+            innovation: None,
+            _phantom: PhantomData,
+        })
+    }
+
+    /// Gets the arena the state was created in, if any
+    pub fn get_arena(&self) -> Option<&Arena> {
+        self.arena.as_ref()
     }
 }
 
@@ -150,10 +292,19 @@ impl SpeexEncoder<NbMode> {
         Self {
             encoder_handle,
             mode,
+            arena: None,
+            #[cfg(feature = "introspection")]
+            innovation: None,
             _phantom: PhantomData,
         }
     }
 
+    /// Create a new narrowband encoder with its state in `arena`, failing if
+    /// the arena is too small.
+    pub fn new_in(arena: Arena) -> Result<SpeexEncoder<NbMode>, FatalError> {
+        Self::create_in(ModeId::NarrowBand, arena)
+    }
+
     /// Sets the submode to use for encoding.
     pub fn set_submode(&mut self, submode: NbSubmodeId) {
         self.set_low_submode_internal(submode);
@@ -179,10 +330,19 @@ impl SpeexEncoder<WbMode> {
         Self {
             encoder_handle,
             mode,
+            arena: None,
+            #[cfg(feature = "introspection")]
+            innovation: None,
             _phantom: PhantomData,
         }
     }
 
+    /// Create a new wideband encoder with its state in `arena`, failing if
+    /// the arena is too small.
+    pub fn new_in(arena: Arena) -> Result<SpeexEncoder<WbMode>, FatalError> {
+        Self::create_in(ModeId::WideBand, arena)
+    }
+
     /// Sets the submode of the narrowband part of the encoder.
     pub fn set_low_submode(&mut self, low_mode: NbSubmodeId) {
         self.set_low_submode_internal(low_mode);
@@ -218,10 +378,19 @@ impl SpeexEncoder<UwbMode> {
         Self {
             encoder_handle,
             mode,
+            arena: None,
+            #[cfg(feature = "introspection")]
+            innovation: None,
             _phantom: PhantomData,
         }
     }
 
+    /// Create a new ultra-wideband encoder with its state in `arena`, failing
+    /// if the arena is too small.
+    pub fn new_in(arena: Arena) -> Result<SpeexEncoder<UwbMode>, FatalError> {
+        Self::create_in(ModeId::UltraWideBand, arena)
+    }
+
     /// Sets the submode of the narrowband part of the encoder.
     pub fn set_low_submode(&mut self, low_mode: NbSubmodeId) {
         self.set_low_submode_internal(low_mode);
@@ -268,8 +437,20 @@ impl DynamicEncoder {
         dynamic_mapping!(self, DynamicEncoder, inner => inner.get_complexity())
     }
 
+    /// Sets whether Discontinuous Transmission is enabled or not
+    pub fn set_dtx(&mut self, dtx: bool) {
+        dynamic_mapping!(self, DynamicEncoder, inner => inner.set_dtx(dtx))
+    }
+
+    /// Gets whether Discontinuous Transmission is enabled or not
+    pub fn get_dtx(&mut self) -> bool {
+        dynamic_mapping!(self, DynamicEncoder, inner => inner.get_dtx())
+    }
+
     /// Encode one frame of audio into the given bits.
-    pub fn encode(&mut self, input: &mut [f32], bits: &mut SpeexBits) {
+    ///
+    /// Panics with a [`FatalError`] if libspeex raises one.
+    pub fn encode(&mut self, input: &mut [f32], bits: &mut SpeexBits) -> Result<(), EncoderError> {
         match self {
             DynamicEncoder::Nb(inner) => inner.encode(input, bits),
             DynamicEncoder::Wb(inner) => inner.encode(input, bits),
@@ -279,7 +460,13 @@ impl DynamicEncoder {
 
     /// Encode one frame of audio into the given bits, using an integer
     /// representation.
-    pub fn encode_int(&mut self, input: &mut [i16], bits: &mut SpeexBits) {
+    ///
+    /// Panics with a [`FatalError`] if libspeex raises one.
+    pub fn encode_int(
+        &mut self,
+        input: &mut [i16],
+        bits: &mut SpeexBits,
+    ) -> Result<(), EncoderError> {
         match self {
             DynamicEncoder::Nb(inner) => inner.encode_int(input, bits),
             DynamicEncoder::Wb(inner) => inner.encode_int(input, bits),
@@ -287,6 +474,29 @@ impl DynamicEncoder {
         }
     }
 
+    /// Encode one frame of interleaved stereo audio into the given bits.
+    ///
+    /// Panics with a [`FatalError`] if libspeex raises one.
+    pub fn encode_stereo(
+        &mut self,
+        input: &mut [f32],
+        bits: &mut SpeexBits,
+    ) -> Result<(), EncoderError> {
+        dynamic_mapping!(self, DynamicEncoder, inner => inner.encode_stereo(input, bits))
+    }
+
+    /// Encode one frame of interleaved stereo audio into the given bits, using
+    /// an integer representation.
+    ///
+    /// Panics with a [`FatalError`] if libspeex raises one.
+    pub fn encode_stereo_int(
+        &mut self,
+        input: &mut [i16],
+        bits: &mut SpeexBits,
+    ) -> Result<(), EncoderError> {
+        dynamic_mapping!(self, DynamicEncoder, inner => inner.encode_stereo_int(input, bits))
+    }
+
     pub fn new(mode: ModeId) -> DynamicEncoder {
         match mode {
             ModeId::NarrowBand => DynamicEncoder::Nb(SpeexEncoder::<NbMode>::new()),
@@ -295,6 +505,15 @@ impl DynamicEncoder {
         }
     }
 
+    /// Same as `new`, with the state in `arena`
+    pub fn new_in(mode: ModeId, arena: Arena) -> Result<DynamicEncoder, FatalError> {
+        Ok(match mode {
+            ModeId::NarrowBand => DynamicEncoder::Nb(SpeexEncoder::<NbMode>::new_in(arena)?),
+            ModeId::WideBand => DynamicEncoder::Wb(SpeexEncoder::<WbMode>::new_in(arena)?),
+            ModeId::UltraWideBand => DynamicEncoder::Uwb(SpeexEncoder::<UwbMode>::new_in(arena)?),
+        })
+    }
+
     pub fn into_nb(self) -> Option<SpeexEncoder<NbMode>> {
         match self {
             DynamicEncoder::Nb(nb) => Some(nb),
@@ -344,10 +563,39 @@ mod test {
 
     set_get_test!(set_get_vbr, set_vbr, get_vbr, true);
 
+    #[test]
+    fn rejects_short_frame() {
+        let mut encoder = SpeexEncoder::<NbMode>::new();
+        assert_eq!(
+            encoder.encode(&mut [0.0; 100], &mut SpeexBits::new()),
+            Err(EncoderError::TooSmallBuffer)
+        );
+    }
+
+    #[test]
+    fn rejects_short_int_frame() {
+        let mut encoder = SpeexEncoder::<WbMode>::new();
+        assert_eq!(
+            encoder.encode_int(&mut [0; 160], &mut SpeexBits::new()),
+            Err(EncoderError::TooSmallBuffer)
+        );
+    }
+
+    #[test]
+    fn rejects_short_stereo_frame() {
+        let mut encoder = SpeexEncoder::<NbMode>::new();
+        assert_eq!(
+            encoder.encode_stereo(&mut [0.0; 160], &mut SpeexBits::new()),
+            Err(EncoderError::TooSmallBuffer)
+        );
+    }
+
     set_get_test!(set_get_vbr_quality, set_vbr_quality, get_vbr_quality, 8.0);
 
     set_get_test!(set_get_vad, set_vad, get_vad, true);
 
+    set_get_test!(set_get_dtx, set_dtx, get_dtx, true);
+
     set_get_test!(set_get_abr, set_abr, get_abr, 2000);
 
     #[test]
@@ -378,6 +626,6 @@ mod test {
         let frame_size = encoder.get_frame_size();
         let mut input = vec![23i16; frame_size as usize];
 
-        encoder.encode_int(&mut input, &mut bits);
+        encoder.encode_int(&mut input, &mut bits).unwrap();
     }
 }
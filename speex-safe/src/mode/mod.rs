@@ -12,13 +12,16 @@ use std::error::Error;
 use std::ffi::c_void;
 use std::fmt::Display;
 
-pub use decoder::{DynamicDecoder, SpeexDecoder};
-pub use encoder::{DynamicEncoder, SpeexEncoder};
+pub use decoder::{DecoderError, DecoderSample, DynamicDecoder, SpeexDecoder};
+pub use encoder::{DynamicEncoder, EncoderError, SpeexEncoder};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 use speex_sys::{SpeexMode, SPEEX_MODEID_NB, SPEEX_MODEID_UWB, SPEEX_MODEID_WB};
 
 /// Possible modes for the encoder and decoder.
 #[repr(i32)]
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum ModeId {
     NarrowBand = SPEEX_MODEID_NB,
     WideBand = SPEEX_MODEID_WB,
@@ -42,6 +45,7 @@ impl From<i32> for ModeId {
 /// used for those.
 #[repr(i32)]
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum NbSubmodeId {
     /// 2150 bps "vocoder-like" mode for comfort noise
     VocoderLike = 1,
@@ -61,25 +65,46 @@ pub enum NbSubmodeId {
     ExtremeHigh = 7,
 }
 
-impl From<i32> for NbSubmodeId {
-    fn from(value: i32) -> Self {
+impl NbSubmodeId {
+    /// Every submode, from the lowest bitrate to the highest
+    pub const ALL: [NbSubmodeId; 8] = [
+        NbSubmodeId::VocoderLike,
+        NbSubmodeId::ExtremeLow,
+        NbSubmodeId::VeryLow,
+        NbSubmodeId::Low,
+        NbSubmodeId::Medium,
+        NbSubmodeId::High,
+        NbSubmodeId::VeryHigh,
+        NbSubmodeId::ExtremeHigh,
+    ];
+
+    /// Converts a submode id from libspeex, or `None` if it isn't one of the
+    /// submodes, like the id 0 of a silent frame
+    pub fn from_id(value: i32) -> Option<Self> {
         match value {
-            1 => NbSubmodeId::VocoderLike,
-            2 => NbSubmodeId::VeryLow,
-            3 => NbSubmodeId::Low,
-            4 => NbSubmodeId::Medium,
-            5 => NbSubmodeId::High,
-            6 => NbSubmodeId::VeryHigh,
-            7 => NbSubmodeId::ExtremeHigh,
-            8 => NbSubmodeId::ExtremeLow,
-            _ => panic!("Invalid submode id"),
+            1 => Some(NbSubmodeId::VocoderLike),
+            2 => Some(NbSubmodeId::VeryLow),
+            3 => Some(NbSubmodeId::Low),
+            4 => Some(NbSubmodeId::Medium),
+            5 => Some(NbSubmodeId::High),
+            6 => Some(NbSubmodeId::VeryHigh),
+            7 => Some(NbSubmodeId::ExtremeHigh),
+            8 => Some(NbSubmodeId::ExtremeLow),
+            _ => None,
         }
     }
 }
 
+impl From<i32> for NbSubmodeId {
+    fn from(value: i32) -> Self {
+        Self::from_id(value).expect("Invalid submode id")
+    }
+}
+
 /// Possible submodes for the Wideband mode.
 #[repr(i32)]
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum WbSubmodeId {
     /// disables innovation quantization entirely
     NoQuantize = 1,
@@ -91,18 +116,34 @@ pub enum WbSubmodeId {
     QuantizedHigh = 4,
 }
 
-impl From<i32> for WbSubmodeId {
-    fn from(value: i32) -> Self {
+impl WbSubmodeId {
+    /// Every submode, from the lowest bitrate to the highest
+    pub const ALL: [WbSubmodeId; 4] = [
+        WbSubmodeId::NoQuantize,
+        WbSubmodeId::QuantizedLow,
+        WbSubmodeId::QuantizedMedium,
+        WbSubmodeId::QuantizedHigh,
+    ];
+
+    /// Converts a submode id from libspeex, or `None` if it isn't one of the
+    /// submodes
+    pub fn from_id(value: i32) -> Option<Self> {
         match value {
-            1 => WbSubmodeId::NoQuantize,
-            2 => WbSubmodeId::QuantizedLow,
-            3 => WbSubmodeId::QuantizedMedium,
-            4 => WbSubmodeId::QuantizedHigh,
-            _ => panic!("Invalid submode id"),
+            1 => Some(WbSubmodeId::NoQuantize),
+            2 => Some(WbSubmodeId::QuantizedLow),
+            3 => Some(WbSubmodeId::QuantizedMedium),
+            4 => Some(WbSubmodeId::QuantizedHigh),
+            _ => None,
         }
     }
 }
 
+impl From<i32> for WbSubmodeId {
+    fn from(value: i32) -> Self {
+        Self::from_id(value).expect("Invalid submode id")
+    }
+}
+
 /// Possible submodes for the UWB mode.
 ///
 /// While this is an enum, UWB mode only has one submode, so it's effectively a
@@ -146,6 +187,42 @@ impl ModeId {
             frame_size
         }
     }
+
+    /// Gets the sampling rate the mode codes, in Hz
+    pub fn get_sampling_rate(self) -> u32 {
+        match self {
+            ModeId::NarrowBand => 8000,
+            ModeId::WideBand => 16000,
+            ModeId::UltraWideBand => 32000,
+        }
+    }
+
+    /// Gets the number of bits a frame of the given submode takes up in this
+    /// mode's layer of the bitstream, including the wideband bit and submode
+    /// id. Returns `None` if the mode has no such submode.
+    ///
+    /// Narrowband submodes are numbered as in `NbSubmodeId`, the wideband and
+    /// ultra-wideband layers as in `WbSubmodeId`. Submode 0 is the empty
+    /// frame, which is just the header bits.
+    pub fn get_submode_bits_per_frame(self, submode: i32) -> Option<i32> {
+        // Size of the submode tables in the C mode definitions
+        let num_submodes = match self {
+            ModeId::NarrowBand => 16,
+            ModeId::WideBand | ModeId::UltraWideBand => 8,
+        };
+        if !(0..num_submodes).contains(&submode) {
+            return None;
+        }
+        let mut bits = submode;
+        unsafe {
+            speex_sys::speex_mode_query(
+                self.get_mode(),
+                speex_sys::SPEEX_SUBMODE_BITS_PER_FRAME,
+                &mut bits as *mut i32 as *mut c_void,
+            );
+        }
+        (bits >= 0).then_some(bits)
+    }
 }
 
 /// Error type for the control functions of the encoder and decoder.
@@ -187,6 +264,9 @@ mod private {
 /// `ctl` is the only function that needs to be implemented, and is used to call
 /// the control functions of the underlying speex library.
 ///
+/// Like the rest of the encoder and decoder, every function panics with a
+/// [`FatalError`](crate::FatalError) if libspeex raises one.
+///
 /// This trait is sealed, and cannot be implemented outside of this crate.
 pub trait ControlFunctions: private::Sealed {
     /// Internal function used to convert the error codes returned by the
@@ -604,3 +684,36 @@ impl CoderMode for WbMode {}
 /// This is a marker type used to specify the mode of the de/encoder.
 pub enum UwbMode {}
 impl CoderMode for UwbMode {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn submode_bits_per_frame() {
+        // 160 bits every 20ms for the 8 kbps submode
+        let nb = ModeId::NarrowBand;
+        assert_eq!(nb.get_submode_bits_per_frame(0), Some(5));
+        assert_eq!(
+            nb.get_submode_bits_per_frame(NbSubmodeId::Low as i32),
+            Some(160)
+        );
+        assert_eq!(nb.get_submode_bits_per_frame(9), None);
+        assert_eq!(nb.get_submode_bits_per_frame(-1), None);
+
+        let wb = ModeId::WideBand;
+        assert_eq!(wb.get_submode_bits_per_frame(0), Some(4));
+        assert_eq!(
+            wb.get_submode_bits_per_frame(WbSubmodeId::NoQuantize as i32),
+            Some(36)
+        );
+        assert_eq!(wb.get_submode_bits_per_frame(5), None);
+
+        let uwb = ModeId::UltraWideBand;
+        assert_eq!(
+            uwb.get_submode_bits_per_frame(UwbSubmodeId::Only as i32),
+            Some(36)
+        );
+        assert_eq!(uwb.get_submode_bits_per_frame(2), None);
+    }
+}
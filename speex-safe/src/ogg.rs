@@ -0,0 +1,418 @@
+////////////////////////////////////////////////////////////////////////////////
+// Copyright (c) 2023.                                                         /
+// This Source Code Form is subject to the terms of the Mozilla Public License,/
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can    /
+// obtain one at http://mozilla.org/MPL/2.0/.                                  /
+////////////////////////////////////////////////////////////////////////////////
+
+//! Minimal Ogg container support.
+//!
+//! Speex streams are normally stored in Ogg, with the `SpeexHeader` packet in
+//! the first page, a comment packet in the second and the audio packets after
+//! that. This module only deals with splitting packets into pages and back, it
+//! knows nothing about what is inside the packets.
+
+use std::collections::VecDeque;
+use std::io;
+use std::io::{ErrorKind, Read, Write};
+
+const CAPTURE_PATTERN: &[u8; 4] = b"OggS";
+const HEADER_SIZE: usize = 27;
+const MAX_SEGMENTS: usize = 255;
+/// Page bodies are flushed once they grow past this size, same as libogg
+const PAGE_FILL_SIZE: usize = 4096;
+
+const FLAG_CONTINUED: u8 = 0x01;
+const FLAG_BOS: u8 = 0x02;
+const FLAG_EOS: u8 = 0x04;
+
+/// CRC-32 lookup table with the polynomial used by Ogg (0x04C11DB7, no
+/// reflection)
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = (i as u32) << 24;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04C1_1DB7
+            } else {
+                crc << 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+fn crc32(crc: u32, data: &[u8]) -> u32 {
+    data.iter().fold(crc, |crc, &byte| {
+        (crc << 8) ^ CRC_TABLE[((crc >> 24) as u8 ^ byte) as usize]
+    })
+}
+
+/// A single packet read from an Ogg stream
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct OggPacket {
+    /// The packet contents
+    pub data: Vec<u8>,
+    /// Granule position of the page the packet ends on, if the packet is the
+    /// last one to end on that page, -1 otherwise
+    pub granule_position: i64,
+    /// Serial number of the logical stream the packet belongs to
+    pub serial: u32,
+    /// Whether this is the first packet of the logical stream
+    pub bos: bool,
+    /// Whether this is the last packet of the logical stream
+    pub eos: bool,
+}
+
+/// Writes packets of a single logical stream into Ogg pages
+pub struct OggWriter<W: Write> {
+    inner: W,
+    serial: u32,
+    sequence: u32,
+    // Lacing values and body of the page currently being filled
+    segments: Vec<u8>,
+    body: Vec<u8>,
+    // Granule position of the last packet completed on the current page, -1
+    // if none has been
+    granule_position: i64,
+    // Whether the page being filled starts with the tail of a packet
+    continued: bool,
+    bos_written: bool,
+    eos: bool,
+}
+
+impl<W: Write> OggWriter<W> {
+    /// Creates a new writer for the logical stream with the given serial
+    /// number
+    pub fn new(inner: W, serial: u32) -> Self {
+        Self {
+            inner,
+            serial,
+            sequence: 0,
+            segments: Vec::with_capacity(MAX_SEGMENTS),
+            body: Vec::new(),
+            granule_position: -1,
+            continued: false,
+            bos_written: false,
+            eos: false,
+        }
+    }
+
+    /// Appends a packet to the stream
+    ///
+    /// Pages are written out as they fill up. `granule_position` is the
+    /// position at the end of this packet, and `eos` marks the packet as the
+    /// last one of the stream, which also flushes it.
+    pub fn write_packet(
+        &mut self,
+        packet: &[u8],
+        granule_position: i64,
+        eos: bool,
+    ) -> io::Result<()> {
+        if self.eos {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "packet written after the end of the stream",
+            ));
+        }
+
+        let mut remaining = packet;
+        let mut started = false;
+        loop {
+            if self.segments.len() == MAX_SEGMENTS {
+                // A packet that doesn't fit carries over onto the next page,
+                // while one that hasn't started yet starts the next page
+                self.write_page(false)?;
+                self.continued = started;
+            }
+            let chunk = remaining.len().min(255);
+            self.segments.push(chunk as u8);
+            self.body.extend_from_slice(&remaining[..chunk]);
+            remaining = &remaining[chunk..];
+            started = true;
+            if chunk < 255 {
+                break;
+            }
+        }
+
+        self.granule_position = granule_position;
+        if eos {
+            self.eos = true;
+            self.flush()
+        } else if self.body.len() >= PAGE_FILL_SIZE {
+            self.flush()
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Writes out any buffered packets as a page, even if it isn't full
+    ///
+    /// Speex streams keep their header and comment packets on pages of their
+    /// own, which is done by flushing after writing each of them.
+    pub fn flush(&mut self) -> io::Result<()> {
+        if self.segments.is_empty() {
+            return self.inner.flush();
+        }
+        self.write_page(self.eos)?;
+        self.continued = false;
+        self.inner.flush()
+    }
+
+    /// Flushes any buffered packets and returns the underlying writer
+    pub fn into_inner(mut self) -> io::Result<W> {
+        self.flush()?;
+        Ok(self.inner)
+    }
+
+    fn write_page(&mut self, eos: bool) -> io::Result<()> {
+        let mut flags = 0;
+        if self.continued {
+            flags |= FLAG_CONTINUED;
+        }
+        if !self.bos_written {
+            flags |= FLAG_BOS;
+            self.bos_written = true;
+        }
+        if eos {
+            flags |= FLAG_EOS;
+        }
+
+        let mut header = Vec::with_capacity(HEADER_SIZE + self.segments.len());
+        header.extend_from_slice(CAPTURE_PATTERN);
+        header.push(0);
+        header.push(flags);
+        header.extend_from_slice(&self.granule_position.to_le_bytes());
+        header.extend_from_slice(&self.serial.to_le_bytes());
+        header.extend_from_slice(&self.sequence.to_le_bytes());
+        header.extend_from_slice(&[0; 4]);
+        header.push(self.segments.len() as u8);
+        header.extend_from_slice(&self.segments);
+
+        let crc = crc32(crc32(0, &header), &self.body);
+        header[22..26].copy_from_slice(&crc.to_le_bytes());
+
+        self.inner.write_all(&header)?;
+        self.inner.write_all(&self.body)?;
+
+        self.sequence += 1;
+        self.segments.clear();
+        self.body.clear();
+        self.granule_position = -1;
+        Ok(())
+    }
+}
+
+/// Reads the packets of a single logical stream out of Ogg pages
+///
+/// Pages that belong to a different logical stream than the first page read
+/// are skipped.
+pub struct OggReader<R: Read> {
+    inner: R,
+    serial: Option<u32>,
+    packets: VecDeque<OggPacket>,
+    // Start of a packet that continues on the next page
+    partial: Vec<u8>,
+    finished: bool,
+}
+
+impl<R: Read> OggReader<R> {
+    /// Creates a new reader
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            serial: None,
+            packets: VecDeque::new(),
+            partial: Vec::new(),
+            finished: false,
+        }
+    }
+
+    /// Reads the next packet, returning `None` at the end of the stream
+    pub fn read_packet(&mut self) -> io::Result<Option<OggPacket>> {
+        while self.packets.is_empty() {
+            if self.finished || !self.read_page()? {
+                return Ok(None);
+            }
+        }
+        Ok(self.packets.pop_front())
+    }
+
+    /// Gets the serial number of the logical stream being read, once the
+    /// first page has been read
+    pub fn serial(&self) -> Option<u32> {
+        self.serial
+    }
+
+    fn read_page(&mut self) -> io::Result<bool> {
+        let mut header = [0u8; HEADER_SIZE];
+        match self.inner.read_exact(&mut header[..1]) {
+            Ok(()) => {}
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(false),
+            Err(err) => return Err(err),
+        }
+        self.inner.read_exact(&mut header[1..])?;
+        if &header[..4] != CAPTURE_PATTERN {
+            return Err(invalid_data("missing Ogg capture pattern"));
+        }
+        if header[4] != 0 {
+            return Err(invalid_data("unsupported Ogg stream structure version"));
+        }
+
+        let flags = header[5];
+        let granule_position = i64::from_le_bytes(header[6..14].try_into().unwrap());
+        let serial = u32::from_le_bytes(header[14..18].try_into().unwrap());
+        let crc = u32::from_le_bytes(header[22..26].try_into().unwrap());
+
+        let mut segments = vec![0u8; header[26] as usize];
+        self.inner.read_exact(&mut segments)?;
+        let body_len = segments.iter().map(|&s| s as usize).sum();
+        let mut body = vec![0u8; body_len];
+        self.inner.read_exact(&mut body)?;
+
+        header[22..26].fill(0);
+        let computed = crc32(crc32(crc32(0, &header), &segments), &body);
+        if computed != crc {
+            return Err(invalid_data("Ogg page checksum mismatch"));
+        }
+
+        match self.serial {
+            None => self.serial = Some(serial),
+            Some(expected) if expected != serial => return Ok(true),
+            Some(_) => {}
+        }
+
+        if flags & FLAG_CONTINUED == 0 {
+            // A packet left unfinished by the previous page was lost
+            self.partial.clear();
+        }
+
+        let mut complete = Vec::new();
+        let mut offset = 0;
+        for &lacing in &segments {
+            let len = lacing as usize;
+            self.partial.extend_from_slice(&body[offset..offset + len]);
+            offset += len;
+            if lacing < 255 {
+                complete.push(std::mem::take(&mut self.partial));
+            }
+        }
+
+        let eos = flags & FLAG_EOS != 0;
+        let count = complete.len();
+        for (i, data) in complete.into_iter().enumerate() {
+            let last = i + 1 == count;
+            self.packets.push_back(OggPacket {
+                data,
+                granule_position: if last { granule_position } else { -1 },
+                serial,
+                bos: flags & FLAG_BOS != 0 && i == 0,
+                eos: eos && last,
+            });
+        }
+        if eos {
+            self.finished = true;
+        }
+        Ok(true)
+    }
+}
+
+impl<R: Read> Iterator for OggReader<R> {
+    type Item = io::Result<OggPacket>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_packet().transpose()
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn write_packets(packets: &[Vec<u8>]) -> Vec<u8> {
+        let mut writer = OggWriter::new(Vec::new(), 1234);
+        for (i, packet) in packets.iter().enumerate() {
+            let last = i + 1 == packets.len();
+            writer.write_packet(packet, i as i64, last).unwrap();
+        }
+        writer.into_inner().unwrap()
+    }
+
+    #[test]
+    fn crc_matches_reference() {
+        // Standard check value for CRC-32/POSIX, without the final inversion
+        assert_eq!(crc32(0, b"123456789"), 0x89A1897F);
+    }
+
+    #[test]
+    fn round_trips_packets() {
+        let packets: Vec<Vec<u8>> = vec![
+            b"first".to_vec(),
+            vec![],
+            vec![7; 255],
+            vec![9; 100_000],
+            b"last".to_vec(),
+        ];
+        let stream = write_packets(&packets);
+
+        let mut reader = OggReader::new(stream.as_slice());
+        let read: Vec<OggPacket> = (&mut reader).map(Result::unwrap).collect();
+
+        assert_eq!(reader.serial(), Some(1234));
+        assert_eq!(read.len(), packets.len());
+        for (read, written) in read.iter().zip(&packets) {
+            assert_eq!(&read.data, written);
+        }
+        assert!(read[0].bos);
+        assert!(read.last().unwrap().eos);
+        assert_eq!(read.last().unwrap().granule_position, 4);
+    }
+
+    #[test]
+    fn small_packets_start_new_pages() {
+        // Fills the segment table long before the body is full
+        let packets = vec![vec![6; 6]; 300];
+        let stream = write_packets(&packets);
+
+        let mut flags = Vec::new();
+        let mut page = stream.as_slice();
+        while !page.is_empty() {
+            flags.push(page[5]);
+            let segments = page[HEADER_SIZE - 1] as usize;
+            let lacing = &page[HEADER_SIZE..HEADER_SIZE + segments];
+            let body: usize = lacing.iter().map(|&s| s as usize).sum();
+            page = &page[HEADER_SIZE + segments + body..];
+        }
+        assert_eq!(flags.len(), 2);
+        assert_eq!(flags[1] & FLAG_CONTINUED, 0);
+
+        let read: Vec<OggPacket> = OggReader::new(stream.as_slice())
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(read.len(), packets.len());
+        for (read, written) in read.iter().zip(&packets) {
+            assert_eq!(&read.data, written);
+        }
+    }
+
+    #[test]
+    fn detects_corruption() {
+        let mut stream = write_packets(&[b"some packet".to_vec()]);
+        let last = stream.len() - 1;
+        stream[last] ^= 0xFF;
+
+        let mut reader = OggReader::new(stream.as_slice());
+        let err = reader.read_packet().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,666 @@
+////////////////////////////////////////////////////////////////////////////////
+// Copyright (c) 2023.                                                         /
+// This Source Code Form is subject to the terms of the Mozilla Public License,/
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can    /
+// obtain one at http://mozilla.org/MPL/2.0/.                                  /
+////////////////////////////////////////////////////////////////////////////////
+
+//! Noise suppression, automatic gain control and voice activity detection
+//! ahead of the encoder
+//!
+//! This follows the preprocessor of speexdsp. Each frame is windowed with the
+//! one before it and transformed, the noise is tracked by the minima of the
+//! smoothed spectrum, and the spectrum is weighted by the MMSE gain of each
+//! bin and the probability of speech in it. The probability of speech in the
+//! whole frame drives voice activity detection and gain control.
+//!
+//! The output is delayed by a frame, [`Preprocessor::get_latency`].
+
+use crate::fft::{Complex, Fft};
+use crate::mode::CoderMode;
+use crate::{ControlFunctions, EncoderError, SpeexBits, SpeexEncoder};
+
+/// Amplitude of an `i16` sample, which frames are scaled to
+const FULL_SCALE: f32 = 32767.0;
+
+/// Gain of the MMSE log-spectral amplitude estimator over the Wiener gain,
+/// at half steps of `x`
+#[rustfmt::skip]
+const HYPERGEOMETRIC_GAIN: [f32; 21] = [
+    0.82157, 1.02017, 1.20461, 1.37534, 1.53363, 1.68092, 1.81865, 1.94811, 2.07038, 2.18638,
+    2.29688, 2.40255, 2.50391, 2.60144, 2.69551, 2.78647, 2.87458, 2.96015, 3.04333, 3.12431,
+    3.20326,
+];
+
+fn hypergeometric_gain(x: f32) -> f32 {
+    let scaled = 2.0 * x;
+    let index = scaled.floor() as usize;
+    if index > 19 {
+        return 1.0 + 0.1296 / x;
+    }
+    let frac = scaled - index as f32;
+    ((1.0 - frac) * HYPERGEOMETRIC_GAIN[index] + frac * HYPERGEOMETRIC_GAIN[index + 1])
+        / (x + 0.0001).sqrt()
+}
+
+/// Probability of speech from an a priori SNR
+fn qcurve(x: f32) -> f32 {
+    1.0 / (1.0 + 0.15 / x)
+}
+
+/// Decision directed estimate of the SNR of a bin, or of a whole frame
+struct SnrEstimate {
+    /// SNR of the clean speech estimate of the last frame and this one's
+    prior: f32,
+    theta: f32,
+    /// MMSE log-spectral amplitude gain
+    gain: f32,
+}
+
+impl SnrEstimate {
+    fn new(power: f32, noise: f32, old_power: f32) -> Self {
+        let noise = noise + 1.0;
+        let posterior = (power / noise - 1.0).clamp(0.0, 100.0);
+        let ratio = old_power / (old_power + noise);
+        let gamma = 0.1 + 0.89 * ratio * ratio;
+        let prior = (gamma * posterior + (1.0 - gamma) * old_power / noise).min(100.0);
+        let prior_ratio = prior / (prior + 1.0);
+        let theta = prior_ratio * (1.0 + posterior);
+        let gain = (prior_ratio * hypergeometric_gain(theta)).min(1.0);
+        Self { prior, theta, gain }
+    }
+}
+
+fn db_to_amplitude(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
+
+/// Cleans up frames of speech before they're encoded
+pub struct Preprocessor {
+    frame_size: usize,
+    sampling_rate: u32,
+
+    denoise: bool,
+    noise_suppress: i32,
+    vad: bool,
+    prob_start: f32,
+    prob_continue: f32,
+    agc_level: Option<f32>,
+    agc_max_gain: i32,
+    agc_increment: i32,
+    agc_decrement: i32,
+
+    fft: Fft,
+    /// Square root of a Hann window twice the frame size, used both ways
+    window: Vec<f32>,
+    /// Last frame of input
+    previous: Vec<f32>,
+    /// Second half of the last output window
+    overlap: Vec<f32>,
+    spectrum: Vec<Complex>,
+
+    /// Power spectrum of the current frame, up to Nyquist
+    power: Vec<f32>,
+    noise: Vec<f32>,
+    /// Power of the last frame's clean speech estimate
+    old_power: Vec<f32>,
+    /// Power spectrum smoothed over time and frequency, and its minima
+    smoothed: Vec<f32>,
+    minimum: Vec<f32>,
+    minimum_next: Vec<f32>,
+    /// Whether each bin is likely to hold speech, and not to update the noise
+    speech_present: Vec<bool>,
+    /// A priori SNR smoothed over time
+    zeta: Vec<f32>,
+    /// The same estimate over the whole frame, for the probability of speech
+    frame_old_power: f32,
+    gain: Vec<f32>,
+    frames: usize,
+    minimum_count: usize,
+
+    speech_prob: f32,
+    was_speech: bool,
+    loudness: f32,
+    agc_gain: f32,
+}
+
+impl Preprocessor {
+    /// Creates a preprocessor for frames of `frame_size` samples at
+    /// `sampling_rate`, with noise suppression on and the rest off
+    pub fn new(frame_size: usize, sampling_rate: u32) -> Self {
+        assert!(frame_size > 0, "Frame size must be positive");
+        let window_len = 2 * frame_size;
+        let window = (0..window_len)
+            .map(|i| (std::f32::consts::PI * (i as f32 + 0.5) / window_len as f32).sin())
+            .collect();
+        let bins = frame_size + 1;
+        Self {
+            frame_size,
+            sampling_rate,
+            denoise: true,
+            noise_suppress: -15,
+            vad: false,
+            prob_start: 0.35,
+            prob_continue: 0.20,
+            agc_level: None,
+            agc_max_gain: 30,
+            agc_increment: 12,
+            agc_decrement: -40,
+            fft: Fft::new(window_len),
+            window,
+            previous: vec![0.0; frame_size],
+            overlap: vec![0.0; frame_size],
+            spectrum: vec![Complex::default(); window_len],
+            power: vec![0.0; bins],
+            noise: vec![0.0; bins],
+            old_power: vec![0.0; bins],
+            smoothed: vec![0.0; bins],
+            minimum: vec![0.0; bins],
+            minimum_next: vec![0.0; bins],
+            speech_present: vec![false; bins],
+            zeta: vec![0.0; bins],
+            frame_old_power: 0.0,
+            gain: vec![1.0; bins],
+            frames: 0,
+            minimum_count: 0,
+            speech_prob: 0.0,
+            was_speech: false,
+            loudness: 0.0,
+            agc_gain: 1.0,
+        }
+    }
+
+    /// Creates a preprocessor for the frames `encoder` takes
+    pub fn for_encoder<T: CoderMode>(encoder: &mut SpeexEncoder<T>) -> Self {
+        let frame_size = encoder.get_frame_size() as usize;
+        let sampling_rate = encoder.get_sampling_rate() as u32;
+        Self::new(frame_size, sampling_rate)
+    }
+
+    pub fn get_frame_size(&self) -> usize {
+        self.frame_size
+    }
+
+    pub fn get_sampling_rate(&self) -> u32 {
+        self.sampling_rate
+    }
+
+    /// Gets how many samples the output is delayed by, which is a frame
+    pub fn get_latency(&self) -> usize {
+        self.frame_size
+    }
+
+    /// Sets whether noise is suppressed
+    pub fn set_denoise(&mut self, denoise: bool) {
+        self.denoise = denoise;
+    }
+
+    pub fn get_denoise(&self) -> bool {
+        self.denoise
+    }
+
+    /// Sets how far noise is suppressed, in dB. This is negative, -15 by
+    /// default.
+    pub fn set_noise_suppress(&mut self, db: i32) {
+        self.noise_suppress = -db.abs();
+    }
+
+    pub fn get_noise_suppress(&self) -> i32 {
+        self.noise_suppress
+    }
+
+    /// Sets the level speech is brought to, as an RMS in the scale of an
+    /// `i16`, or `None` to turn off gain control
+    pub fn set_agc(&mut self, level: Option<f32>) {
+        self.agc_level = level.map(|level| level.clamp(1.0, FULL_SCALE));
+    }
+
+    pub fn get_agc(&self) -> Option<f32> {
+        self.agc_level
+    }
+
+    /// Sets the most gain control amplifies by, in dB. 30 by default.
+    pub fn set_agc_max_gain(&mut self, db: i32) {
+        self.agc_max_gain = db;
+    }
+
+    pub fn get_agc_max_gain(&self) -> i32 {
+        self.agc_max_gain
+    }
+
+    /// Sets how fast gain control may raise the gain, in dB per second. 12
+    /// by default.
+    pub fn set_agc_increment(&mut self, db_per_second: i32) {
+        self.agc_increment = db_per_second.abs();
+    }
+
+    pub fn get_agc_increment(&self) -> i32 {
+        self.agc_increment
+    }
+
+    /// Sets how fast gain control may lower the gain, in dB per second. -40
+    /// by default.
+    pub fn set_agc_decrement(&mut self, db_per_second: i32) {
+        self.agc_decrement = -db_per_second.abs();
+    }
+
+    pub fn get_agc_decrement(&self) -> i32 {
+        self.agc_decrement
+    }
+
+    /// Gets the gain gain control applied to the last frame, in dB
+    pub fn get_agc_gain(&self) -> f32 {
+        20.0 * self.agc_gain.log10()
+    }
+
+    /// Sets whether `run` detects voice activity, rather than always reporting
+    /// speech
+    pub fn set_vad(&mut self, vad: bool) {
+        self.vad = vad;
+    }
+
+    pub fn get_vad(&self) -> bool {
+        self.vad
+    }
+
+    /// Sets the probability of speech above which silence turns to speech.
+    /// 0.35 by default.
+    pub fn set_prob_start(&mut self, probability: f32) {
+        self.prob_start = probability.clamp(0.0, 1.0);
+    }
+
+    pub fn get_prob_start(&self) -> f32 {
+        self.prob_start
+    }
+
+    /// Sets the probability of speech above which speech carries on. 0.20
+    /// by default.
+    pub fn set_prob_continue(&mut self, probability: f32) {
+        self.prob_continue = probability.clamp(0.0, 1.0);
+    }
+
+    pub fn get_prob_continue(&self) -> f32 {
+        self.prob_continue
+    }
+
+    /// Gets the probability that the last frame was speech
+    pub fn get_speech_probability(&self) -> f32 {
+        self.speech_prob
+    }
+
+    /// Gets the estimated power spectrum of the noise, from DC to Nyquist
+    pub fn get_noise_psd(&self) -> &[f32] {
+        &self.noise
+    }
+
+    /// Processes a frame in place. Returns whether it holds speech, which is
+    /// always true unless voice activity detection is on.
+    pub fn run(&mut self, frame: &mut [f32]) -> bool {
+        assert_eq!(frame.len(), self.frame_size, "Frame is the wrong size");
+        let n = self.frame_size;
+
+        self.analyse(frame);
+        self.update_noise();
+        self.compute_gain();
+
+        if self.denoise {
+            for (i, &gain) in self.gain.iter().enumerate() {
+                self.spectrum[i] = self.spectrum[i].scale(gain);
+                if i != 0 && i != n {
+                    self.spectrum[2 * n - i] = self.spectrum[2 * n - i].scale(gain);
+                }
+            }
+        }
+        self.fft.inverse(&mut self.spectrum);
+
+        self.previous.copy_from_slice(frame);
+        for (i, sample) in frame.iter_mut().enumerate() {
+            *sample = self.overlap[i] + self.spectrum[i].re * self.window[i];
+            self.overlap[i] = self.spectrum[n + i].re * self.window[n + i];
+        }
+
+        if let Some(level) = self.agc_level {
+            self.apply_agc(frame, level);
+        }
+        for sample in frame.iter_mut() {
+            *sample = sample.clamp(-FULL_SCALE - 1.0, FULL_SCALE);
+        }
+
+        let speech = self.speech_prob > self.prob_start
+            || (self.was_speech && self.speech_prob > self.prob_continue);
+        self.was_speech = speech;
+        !self.vad || speech
+    }
+
+    /// Same as `run`, for frames of `i16`s
+    pub fn run_int(&mut self, frame: &mut [i16]) -> bool {
+        let mut float_frame = frame.iter().map(|&x| x as f32).collect::<Vec<_>>();
+        let speech = self.run(&mut float_frame);
+        for (out, x) in frame.iter_mut().zip(float_frame) {
+            *out = x.round() as i16;
+        }
+        speech
+    }
+
+    /// Processes a frame and encodes it, returning whether it holds speech
+    pub fn encode<T: CoderMode>(
+        &mut self,
+        encoder: &mut SpeexEncoder<T>,
+        frame: &mut [f32],
+        bits: &mut SpeexBits,
+    ) -> Result<bool, EncoderError> {
+        let speech = self.run(frame);
+        encoder.encode(frame, bits)?;
+        Ok(speech)
+    }
+
+    /// Windows the last frame and `frame` into `spectrum` and `power`
+    fn analyse(&mut self, frame: &[f32]) {
+        let n = self.frame_size;
+        for (i, out) in self.spectrum.iter_mut().enumerate() {
+            let x = if i < n {
+                self.previous[i]
+            } else {
+                frame[i - n]
+            };
+            *out = Complex::new(x * self.window[i], 0.0);
+        }
+        self.fft.forward(&mut self.spectrum);
+        for (power, bin) in self.power.iter_mut().zip(&self.spectrum) {
+            *power = bin.norm_sqr();
+        }
+    }
+
+    /// Tracks the noise through the minima of the smoothed power spectrum,
+    /// updating it where there's likely no speech
+    fn update_noise(&mut self) {
+        self.frames += 1;
+        let bins = self.power.len();
+        for i in 0..bins {
+            let below = self.power[i.saturating_sub(1)];
+            let above = self.power[(i + 1).min(bins - 1)];
+            let across = 0.25 * below + 0.5 * self.power[i] + 0.25 * above;
+            // Seeded by the first frame rather than ramping up from nothing,
+            // which would hold the minimum low for its first windows
+            self.smoothed[i] = if self.frames == 1 {
+                across
+            } else {
+                0.8 * self.smoothed[i] + 0.2 * across
+            };
+        }
+
+        // Windows of a second at least, so the minimum reaches past a word
+        let minimum_range = match self.frames {
+            ..=999 => 50,
+            1000..=9999 => 150,
+            _ => 300,
+        };
+        if self.frames == 1 {
+            self.minimum.copy_from_slice(&self.smoothed);
+            self.minimum_next.copy_from_slice(&self.smoothed);
+        } else if self.minimum_count > minimum_range {
+            self.minimum_count = 0;
+            for i in 0..bins {
+                self.minimum[i] = self.minimum_next[i].min(self.smoothed[i]);
+                self.minimum_next[i] = self.smoothed[i];
+            }
+        } else {
+            for i in 0..bins {
+                self.minimum[i] = self.minimum[i].min(self.smoothed[i]);
+                self.minimum_next[i] = self.minimum_next[i].min(self.smoothed[i]);
+            }
+        }
+        self.minimum_count += 1;
+
+        let beta = (1.0 / self.frames as f32).max(0.03);
+        for i in 0..bins {
+            // Speech is where the power stands well above its recent minimum
+            self.speech_present[i] = self.smoothed[i] > 5.0 * self.minimum[i];
+            if self.power[i] < self.noise[i] {
+                self.noise[i] = (1.0 - beta) * self.noise[i] + beta * self.power[i];
+            } else if !self.speech_present[i] {
+                // Held back in frames likely to be speech, and by the minimum,
+                // but never stopped, so the noise can still rise
+                let beta = beta * (1.0 - 0.9 * self.speech_prob);
+                let power = self.power[i].min(5.0 * self.minimum[i]);
+                self.noise[i] = (1.0 - beta) * self.noise[i] + beta * power;
+            }
+        }
+    }
+
+    /// Finds the gain of each bin, and the probability of speech in the frame
+    fn compute_gain(&mut self) {
+        // The SNR of the whole frame above DC, where the noise of each bin
+        // averages out
+        let power = self.power[1..].iter().sum::<f32>();
+        let noise = self.noise[1..].iter().sum::<f32>();
+        let estimate = SnrEstimate::new(power, noise, self.frame_old_power);
+        self.frame_old_power = estimate.gain.powi(2) * power;
+        // Much sharper than the curve of a single bin, as the SNR of the whole
+        // frame hardly varies in noise. Smoothed as a probability, so it falls
+        // as soon as speech stops
+        let frame_prob = 0.1 + 0.899 / (1.0 + 0.25 / (estimate.prior * estimate.prior));
+        self.speech_prob = 0.7 * self.speech_prob + 0.3 * frame_prob;
+        let floor = db_to_amplitude(self.noise_suppress as f32);
+        for i in 0..self.power.len() {
+            let estimate = SnrEstimate::new(self.power[i], self.noise[i], self.old_power[i]);
+            self.old_power[i] =
+                0.2 * self.old_power[i] + 0.8 * estimate.gain.powi(2) * self.power[i];
+            self.zeta[i] = 0.7 * self.zeta[i] + 0.3 * estimate.prior;
+
+            // Speech presence in the bin, weighted by that of the frame
+            let p1 = 0.199 + 0.8 * qcurve(self.zeta[i]);
+            let q = (1.0 - self.speech_prob * p1).clamp(0.0001, 0.9999);
+            let p = 1.0 / (1.0 + q / (1.0 - q) * (1.0 + estimate.prior) * (-estimate.theta).exp());
+            let amplitude = p * estimate.gain.max(floor).sqrt() + (1.0 - p) * floor.sqrt();
+            self.gain[i] = amplitude * amplitude;
+        }
+    }
+
+    /// Moves the gain towards what brings speech to `level`, tracking the
+    /// loudness of speech frames only
+    fn apply_agc(&mut self, frame: &mut [f32], level: f32) {
+        let power = frame.iter().map(|x| x * x).sum::<f32>() / frame.len() as f32;
+        let rate = 0.05 * self.speech_prob * self.speech_prob;
+        if self.speech_prob > self.prob_start {
+            self.loudness = if self.loudness == 0.0 {
+                power
+            } else {
+                (1.0 - rate) * self.loudness + rate * power
+            };
+        }
+
+        let previous_gain = self.agc_gain;
+        if self.loudness > 0.0 {
+            let frame_seconds = self.frame_size as f32 / self.sampling_rate as f32;
+            let target =
+                (level / self.loudness.sqrt()).min(db_to_amplitude(self.agc_max_gain as f32));
+            let highest =
+                previous_gain * db_to_amplitude(self.agc_increment as f32 * frame_seconds);
+            let lowest = previous_gain * db_to_amplitude(self.agc_decrement as f32 * frame_seconds);
+            self.agc_gain = target.clamp(lowest, highest);
+        }
+
+        // Ramped over the frame, so the gain never jumps
+        let step = (self.agc_gain - previous_gain) / frame.len() as f32;
+        for (i, sample) in frame.iter_mut().enumerate() {
+            *sample *= previous_gain + step * (i + 1) as f32;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{support, NbMode};
+
+    const RATE: u32 = 8000;
+    const FRAME: usize = 160;
+
+    /// Half a second of speech, then half a second of silence, over and
+    /// over
+    fn speech(seconds: usize, amplitude: f32) -> Vec<f32> {
+        let len = RATE as usize * seconds;
+        let mut speech = support::speech_like(RATE as usize, len);
+        for (n, sample) in speech.iter_mut().enumerate() {
+            if n % RATE as usize >= RATE as usize / 2 {
+                *sample = 0.0;
+            }
+        }
+        let gain = amplitude / (rms(&speech) * 2f32.sqrt());
+        speech.iter().map(|x| x * gain).collect()
+    }
+
+    fn rms(signal: &[f32]) -> f32 {
+        (signal.iter().map(|x| x * x).sum::<f32>() / signal.len() as f32).sqrt()
+    }
+
+    fn snr_db(clean: &[f32], noisy: &[f32]) -> f32 {
+        let noise = clean
+            .iter()
+            .zip(noisy)
+            .map(|(x, y)| (x - y).powi(2))
+            .sum::<f32>();
+        10.0 * (clean.iter().map(|x| x * x).sum::<f32>() / noise).log10()
+    }
+
+    /// Runs every frame of `input`, returning the output lined up with the
+    /// input and whether each frame was speech
+    fn process(preprocessor: &mut Preprocessor, input: &[f32]) -> (Vec<f32>, Vec<bool>) {
+        let mut output = Vec::with_capacity(input.len());
+        let mut speech = Vec::new();
+        for frame in input.chunks_exact(FRAME) {
+            let mut frame = frame.to_vec();
+            speech.push(preprocessor.run(&mut frame));
+            output.extend(frame);
+        }
+        (output.split_off(preprocessor.get_latency()), speech)
+    }
+
+    #[test]
+    fn passes_through_when_disabled() {
+        let input = speech(1, 3000.0);
+        let mut preprocessor = Preprocessor::new(FRAME, RATE);
+        preprocessor.set_denoise(false);
+        let (output, speech) = process(&mut preprocessor, &input);
+        assert!(speech.iter().all(|&speech| speech));
+        for (a, b) in output.iter().zip(&input) {
+            assert!((a - b).abs() < 0.05, "{a} {b}");
+        }
+    }
+
+    #[test]
+    fn improves_snr_of_noisy_speech() {
+        let clean = speech(6, 3000.0);
+        // The noisier the speech, the more there is to gain
+        for (noise_rms, improvement) in [(300.0, 0.5), (1000.0, 3.0), (2000.0, 5.0)] {
+            let noisy = clean
+                .iter()
+                .zip(support::white_noise(1234, noise_rms, clean.len()))
+                .map(|(x, n)| x + n)
+                .collect::<Vec<_>>();
+            let mut preprocessor = Preprocessor::new(FRAME, RATE);
+            let (output, _) = process(&mut preprocessor, &noisy);
+
+            // After the first two seconds, once the noise is known
+            let settled = 2 * RATE as usize..output.len();
+            let before = snr_db(&clean[settled.clone()], &noisy[settled.clone()]);
+            let after = snr_db(&clean[settled.clone()], &output[settled]);
+            assert!(
+                after > before + improvement,
+                "noise at {noise_rms}: {before} dB to {after} dB"
+            );
+        }
+    }
+
+    #[test]
+    fn suppresses_noise_alone() {
+        let input = support::white_noise(1234, 1000.0, RATE as usize * 3);
+        let mut preprocessor = Preprocessor::new(FRAME, RATE);
+        let (output, _) = process(&mut preprocessor, &input);
+        let settled = RATE as usize..output.len();
+        let attenuation = 20.0 * (rms(&output[settled.clone()]) / rms(&input[settled])).log10();
+        assert!(attenuation < -10.0, "{attenuation} dB");
+
+        preprocessor.set_noise_suppress(-30);
+        let (output, _) = process(&mut preprocessor, &input);
+        let deeper = 20.0 * (rms(&output) / rms(&input[..output.len()])).log10();
+        assert!(deeper < attenuation - 5.0, "{deeper} dB");
+    }
+
+    #[test]
+    fn detects_voice_activity() {
+        let clean = speech(6, 3000.0);
+        let noisy = clean
+            .iter()
+            .zip(support::white_noise(1234, 300.0, clean.len()))
+            .map(|(x, n)| x + n)
+            .collect::<Vec<_>>();
+        let mut preprocessor = Preprocessor::new(FRAME, RATE);
+        preprocessor.set_vad(true);
+        let mut voiced = (0, 0);
+        let mut silent = (0, 0);
+        for (n, frame) in noisy.chunks_exact(FRAME).enumerate() {
+            let speech = preprocessor.run(&mut frame.to_vec());
+            let probability = preprocessor.get_speech_probability();
+            assert!((0.0..=1.0).contains(&probability));
+            // Skip the first two seconds, and frames around the edges of
+            // bursts, where the probability takes a few frames to fall
+            let t = (n * FRAME) as f32 / RATE as f32;
+            if t < 2.0 || (t % 0.5) < 0.15 || (t % 0.5) > 0.4 {
+                continue;
+            }
+            let counts = if t % 1.0 < 0.5 {
+                &mut voiced
+            } else {
+                &mut silent
+            };
+            counts.0 += speech as usize;
+            counts.1 += 1;
+        }
+        assert!(voiced.0 * 10 >= voiced.1 * 9, "speech found in {voiced:?}");
+        assert!(silent.0 * 10 <= silent.1, "speech found in {silent:?}");
+    }
+
+    #[test]
+    fn agc_brings_speech_to_level() {
+        for amplitude in [300.0, 12000.0] {
+            let input = speech(8, amplitude);
+            let mut preprocessor = Preprocessor::new(FRAME, RATE);
+            preprocessor.set_agc(Some(4000.0));
+            let (output, _) = process(&mut preprocessor, &input);
+
+            // Speech in the last two seconds
+            let end = &output[RATE as usize * 6..RATE as usize * 7 - FRAME];
+            let level =
+                rms(end) / (rms(&input[RATE as usize * 6..RATE as usize * 7]) / amplitude * 4000.0);
+            assert!(
+                (0.7..1.4).contains(&level),
+                "{amplitude} came out at {level} of the level, gain {} dB",
+                preprocessor.get_agc_gain()
+            );
+        }
+    }
+
+    #[test]
+    fn chains_into_encoder() {
+        let mut encoder = SpeexEncoder::<NbMode>::new();
+        let mut preprocessor = Preprocessor::for_encoder(&mut encoder);
+        assert_eq!(
+            (
+                preprocessor.get_frame_size(),
+                preprocessor.get_sampling_rate()
+            ),
+            (FRAME, RATE)
+        );
+        let input = speech(1, 3000.0);
+        let mut bits = SpeexBits::new();
+        for frame in input.chunks_exact(FRAME) {
+            bits.reset();
+            preprocessor
+                .encode(&mut encoder, &mut frame.to_vec(), &mut bits)
+                .unwrap();
+            assert!(bits.num_bytes() > 0);
+        }
+    }
+}
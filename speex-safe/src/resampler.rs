@@ -0,0 +1,547 @@
+////////////////////////////////////////////////////////////////////////////////
+// Copyright (c) 2023.                                                         /
+// This Source Code Form is subject to the terms of the Mozilla Public License,/
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can    /
+// obtain one at http://mozilla.org/MPL/2.0/.                                  /
+////////////////////////////////////////////////////////////////////////////////
+
+//! Polyphase resampling of mono audio between any two rates, to get audio to
+//! and from the rates speex codes
+//!
+//! This follows the resampler of speexdsp: a Kaiser windowed sinc filter,
+//! tabulated at every phase the ratio between the rates needs, or oversampled
+//! and interpolated when that would take too many phases. The quality levels
+//! are the same, from 0 to 10.
+
+use std::error::Error;
+use std::f64::consts::PI;
+use std::fmt::{Display, Formatter};
+
+/// Quality used by speexdsp unless told otherwise
+pub const DEFAULT_QUALITY: u8 = 4;
+/// Highest quality, with the longest filter
+pub const MAX_QUALITY: u8 = 10;
+
+struct QualityMapping {
+    /// Filter length when upsampling, in taps
+    base_length: usize,
+    /// Phases per tap of the interpolated table
+    oversample: usize,
+    /// Cutoff when downsampling, relative to the output Nyquist frequency
+    downsample_bandwidth: f64,
+    /// Cutoff when upsampling, relative to the input Nyquist frequency
+    upsample_bandwidth: f64,
+    /// Kaiser window parameter
+    beta: f64,
+}
+
+// Kaiser windows for about 60, 80, 100 and 120 dB of stopband attenuation
+const KAISER6: f64 = 5.653;
+const KAISER8: f64 = 7.857;
+const KAISER10: f64 = 10.056;
+const KAISER12: f64 = 12.265;
+
+/// Longest filter table, which like speexdsp's has to be indexable by an i32
+const MAX_TABLE_LEN: usize = i32::MAX as usize / std::mem::size_of::<f32>();
+
+#[rustfmt::skip]
+const QUALITY_MAP: [QualityMapping; 11] = [
+    QualityMapping { base_length: 8, oversample: 4, downsample_bandwidth: 0.830, upsample_bandwidth: 0.860, beta: KAISER6 },
+    QualityMapping { base_length: 16, oversample: 4, downsample_bandwidth: 0.850, upsample_bandwidth: 0.880, beta: KAISER6 },
+    QualityMapping { base_length: 32, oversample: 4, downsample_bandwidth: 0.882, upsample_bandwidth: 0.910, beta: KAISER6 },
+    QualityMapping { base_length: 48, oversample: 8, downsample_bandwidth: 0.895, upsample_bandwidth: 0.917, beta: KAISER8 },
+    QualityMapping { base_length: 64, oversample: 8, downsample_bandwidth: 0.921, upsample_bandwidth: 0.940, beta: KAISER8 },
+    QualityMapping { base_length: 80, oversample: 16, downsample_bandwidth: 0.922, upsample_bandwidth: 0.940, beta: KAISER10 },
+    QualityMapping { base_length: 96, oversample: 16, downsample_bandwidth: 0.940, upsample_bandwidth: 0.945, beta: KAISER10 },
+    QualityMapping { base_length: 128, oversample: 16, downsample_bandwidth: 0.950, upsample_bandwidth: 0.950, beta: KAISER10 },
+    QualityMapping { base_length: 160, oversample: 16, downsample_bandwidth: 0.960, upsample_bandwidth: 0.960, beta: KAISER10 },
+    QualityMapping { base_length: 192, oversample: 32, downsample_bandwidth: 0.968, upsample_bandwidth: 0.968, beta: KAISER12 },
+    QualityMapping { base_length: 256, oversample: 32, downsample_bandwidth: 0.975, upsample_bandwidth: 0.975, beta: KAISER12 },
+];
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ResamplerError {
+    /// A sampling rate was zero
+    InvalidRate(u32),
+    /// The quality was above 10
+    InvalidQuality(u8),
+    /// The ratio between the rates needs a filter too long to build
+    RatioTooLarge,
+}
+
+impl Display for ResamplerError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ResamplerError::InvalidRate(rate) => write!(f, "Sampling rate {rate} is not positive"),
+            ResamplerError::InvalidQuality(quality) => {
+                write!(f, "Resampler quality {quality} is not between 0 and 10")
+            }
+            ResamplerError::RatioTooLarge => {
+                write!(
+                    f,
+                    "Ratio between the sampling rates is too large to resample"
+                )
+            }
+        }
+    }
+}
+
+impl Error for ResamplerError {}
+
+/// Modified Bessel function of the first kind of order 0
+fn bessel_i0(x: f64) -> f64 {
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    let half = x / 2.0;
+    for k in 1..64 {
+        term *= half / k as f64;
+        sum += term * term;
+        if term * term < sum * 1e-17 {
+            break;
+        }
+    }
+    sum
+}
+
+/// Windowed sinc with a cutoff relative to Nyquist, `x` taps from the centre
+/// of a filter `len` taps long
+fn sinc(cutoff: f64, x: f64, len: usize, beta: f64) -> f64 {
+    let half = len as f64 / 2.0;
+    if x.abs() < 1e-6 {
+        return cutoff;
+    }
+    if x.abs() > half {
+        return 0.0;
+    }
+    let xx = x * cutoff * PI;
+    let position = x / half;
+    let window = bessel_i0(beta * (1.0 - position * position).sqrt()) / bessel_i0(beta);
+    cutoff * xx.sin() / xx * window
+}
+
+/// Coefficients of the cubic interpolation between oversampled taps
+fn cubic_coefficients(frac: f32) -> [f32; 4] {
+    let frac2 = frac * frac;
+    let frac3 = frac2 * frac;
+    let first = -0.16667 * frac + 0.16667 * frac3;
+    let second = frac + 0.5 * frac2 - 0.5 * frac3;
+    let fourth = -0.33333 * frac + 0.5 * frac2 - 0.16667 * frac3;
+    [first, second, 1.0 - first - second - fourth, fourth]
+}
+
+fn gcd(a: usize, b: usize) -> usize {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+/// How the filter taps of each output sample are found
+enum Taps {
+    /// `den_rate` rows of taps, one per phase
+    Direct,
+    /// Taps at `oversample` times the input rate, padded with 4 on each side
+    Interpolated { oversample: usize },
+}
+
+/// Converts a stream of mono samples from one rate to another
+///
+/// Output is delayed by [`Resampler::get_input_latency`] input samples, unless
+/// [`Resampler::skip_zeros`] is called before the first input.
+pub struct Resampler {
+    in_rate: u32,
+    out_rate: u32,
+    quality: u8,
+    /// The rates divided by their greatest common divisor
+    num_rate: usize,
+    den_rate: usize,
+    filter_len: usize,
+    taps: Taps,
+    table: Vec<f32>,
+    /// Input not used up yet, from the start of the next filter window
+    buffer: Vec<f32>,
+    /// Start of the next filter window in `buffer`
+    last_sample: usize,
+    /// Phase of the next output, out of `den_rate`
+    phase: usize,
+    skipped_zeros: bool,
+    input_len: u64,
+    output_len: u64,
+}
+
+impl Resampler {
+    /// Creates a resampler at [`DEFAULT_QUALITY`]
+    pub fn new(in_rate: u32, out_rate: u32) -> Result<Self, ResamplerError> {
+        Self::with_quality(in_rate, out_rate, DEFAULT_QUALITY)
+    }
+
+    /// Creates a resampler with a quality between 0 and 10. Higher qualities
+    /// have a flatter passband and a lower stopband, for a longer filter.
+    pub fn with_quality(in_rate: u32, out_rate: u32, quality: u8) -> Result<Self, ResamplerError> {
+        for rate in [in_rate, out_rate] {
+            if rate == 0 {
+                return Err(ResamplerError::InvalidRate(rate));
+            }
+        }
+        let mapping = QUALITY_MAP
+            .get(quality as usize)
+            .ok_or(ResamplerError::InvalidQuality(quality))?;
+
+        let divisor = gcd(in_rate as usize, out_rate as usize);
+        let num_rate = in_rate as usize / divisor;
+        let den_rate = out_rate as usize / divisor;
+
+        let mut filter_len = mapping.base_length;
+        let mut oversample = mapping.oversample;
+        let cutoff = if num_rate > den_rate {
+            // The filter gets longer and narrower to stop at the output's
+            // Nyquist frequency, and the table less oversampled to make up
+            filter_len = filter_len
+                .checked_mul(num_rate)
+                .ok_or(ResamplerError::RatioTooLarge)?
+                .div_ceil(den_rate)
+                .next_multiple_of(8);
+            for factor in [2, 4, 8, 16] {
+                if factor * den_rate < num_rate {
+                    oversample /= 2;
+                }
+            }
+            oversample = oversample.max(1);
+            mapping.downsample_bandwidth * den_rate as f64 / num_rate as f64
+        } else {
+            mapping.upsample_bandwidth
+        };
+
+        // A table of every phase when it's no longer than one to interpolate
+        // from, as long as either fits
+        let direct_len = filter_len
+            .checked_mul(den_rate)
+            .filter(|&len| len <= MAX_TABLE_LEN);
+        let interpolated_len = filter_len
+            .checked_mul(oversample)
+            .and_then(|len| len.checked_add(8))
+            .filter(|&len| len <= MAX_TABLE_LEN);
+        let direct = match (direct_len, interpolated_len) {
+            (Some(direct), Some(interpolated)) => direct <= interpolated,
+            (Some(_), None) => true,
+            (None, Some(_)) => false,
+            (None, None) => return Err(ResamplerError::RatioTooLarge),
+        };
+
+        let half = (filter_len / 2) as f64;
+        let (taps, table) = if direct {
+            let table = (0..den_rate)
+                .flat_map(|phase| {
+                    (0..filter_len).map(move |j| {
+                        let x = j as f64 - half + 1.0 - phase as f64 / den_rate as f64;
+                        sinc(cutoff, x, filter_len, mapping.beta) as f32
+                    })
+                })
+                .collect();
+            (Taps::Direct, table)
+        } else {
+            let table = (0..interpolated_len.unwrap())
+                .map(|i| {
+                    let x = (i as f64 - 4.0) / oversample as f64 - half;
+                    sinc(cutoff, x, filter_len, mapping.beta) as f32
+                })
+                .collect();
+            (Taps::Interpolated { oversample }, table)
+        };
+
+        Ok(Self {
+            in_rate,
+            out_rate,
+            quality,
+            num_rate,
+            den_rate,
+            filter_len,
+            taps,
+            table,
+            buffer: vec![0.0; filter_len - 1],
+            last_sample: 0,
+            phase: 0,
+            skipped_zeros: false,
+            input_len: 0,
+            output_len: 0,
+        })
+    }
+
+    pub fn get_input_rate(&self) -> u32 {
+        self.in_rate
+    }
+
+    pub fn get_output_rate(&self) -> u32 {
+        self.out_rate
+    }
+
+    pub fn get_quality(&self) -> u8 {
+        self.quality
+    }
+
+    /// Gets the length of the filter, in input samples
+    pub fn get_filter_length(&self) -> usize {
+        self.filter_len
+    }
+
+    /// Gets the delay of the output, in input samples
+    pub fn get_input_latency(&self) -> usize {
+        self.filter_len / 2
+    }
+
+    /// Gets the delay of the output, in output samples
+    pub fn get_output_latency(&self) -> usize {
+        (self.filter_len / 2 * self.den_rate + self.num_rate / 2) / self.num_rate
+    }
+
+    /// Drops the delay of the filter, so that the first output lines up with
+    /// the first input. Only has an effect before any input.
+    pub fn skip_zeros(&mut self) {
+        if self.input_len == 0 && !self.skipped_zeros {
+            self.last_sample += self.filter_len / 2;
+            self.skipped_zeros = true;
+        }
+    }
+
+    /// Resamples `input`, appending every output sample it completes to
+    /// `output`
+    pub fn process(&mut self, input: &[f32], output: &mut Vec<f32>) {
+        self.buffer.extend_from_slice(input);
+        self.input_len += input.len() as u64;
+        let int_advance = self.num_rate / self.den_rate;
+        let frac_advance = self.num_rate % self.den_rate;
+
+        while self.last_sample + self.filter_len <= self.buffer.len() {
+            let window = &self.buffer[self.last_sample..self.last_sample + self.filter_len];
+            output.push(self.filter(window));
+            self.output_len += 1;
+            self.last_sample += int_advance;
+            self.phase += frac_advance;
+            if self.phase >= self.den_rate {
+                self.phase -= self.den_rate;
+                self.last_sample += 1;
+            }
+        }
+
+        let used = self.last_sample.min(self.buffer.len());
+        self.buffer.drain(..used);
+        self.last_sample -= used;
+    }
+
+    /// Pushes the rest of the input through the filter, appending the output
+    /// for every input sample given so far
+    pub fn finish(mut self, output: &mut Vec<f32>) {
+        let mut expected = (self.input_len * self.den_rate as u64).div_ceil(self.num_rate as u64);
+        if !self.skipped_zeros {
+            expected += self.get_output_latency() as u64;
+        }
+        let start = output.len();
+        let silence = vec![0.0; self.filter_len];
+        while self.output_len < expected {
+            self.process(&silence, output);
+        }
+        let extra = (self.output_len - expected) as usize;
+        output.truncate((output.len() - extra).max(start));
+    }
+
+    /// Clears the input so far, as if the resampler was just created
+    pub fn reset(&mut self) {
+        self.buffer.clear();
+        self.buffer.resize(self.filter_len - 1, 0.0);
+        self.last_sample = 0;
+        self.phase = 0;
+        self.skipped_zeros = false;
+        self.input_len = 0;
+        self.output_len = 0;
+    }
+
+    fn filter(&self, window: &[f32]) -> f32 {
+        match self.taps {
+            Taps::Direct => {
+                let taps = &self.table[self.phase * self.filter_len..][..self.filter_len];
+                taps.iter().zip(window).map(|(tap, x)| tap * x).sum()
+            }
+            Taps::Interpolated { oversample } => {
+                let offset = self.phase * oversample / self.den_rate;
+                let frac =
+                    ((self.phase * oversample) % self.den_rate) as f32 / self.den_rate as f32;
+                let mut accum = [0.0f32; 4];
+                for (j, x) in window.iter().enumerate() {
+                    let base = 4 + (j + 1) * oversample - offset - 2;
+                    for (k, sum) in accum.iter_mut().enumerate() {
+                        *sum += x * self.table[base + k];
+                    }
+                }
+                cubic_coefficients(frac)
+                    .iter()
+                    .zip(accum)
+                    .map(|(coefficient, sum)| coefficient * sum)
+                    .sum()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::support::tone;
+
+    fn resample(resampler: &mut Resampler, input: &[f32]) -> Vec<f32> {
+        let mut output = Vec::new();
+        // Odd chunks, to go through the streaming paths
+        for chunk in input.chunks(317) {
+            resampler.process(chunk, &mut output);
+        }
+        output
+    }
+
+    /// Amplitude of the output of a tone once the filter has settled, in dB
+    fn gain_db(in_rate: u32, out_rate: u32, quality: u8, frequency: f64) -> f64 {
+        let mut resampler = Resampler::with_quality(in_rate, out_rate, quality).unwrap();
+        let output = resample(
+            &mut resampler,
+            &tone(in_rate, frequency, 1.0, in_rate as usize),
+        );
+        let settled = &output[output.len() / 4..output.len() * 3 / 4];
+        let rms = (settled.iter().map(|&x| (x as f64).powi(2)).sum::<f64>() / settled.len() as f64)
+            .sqrt();
+        20.0 * (rms * 2f64.sqrt()).log10()
+    }
+
+    #[test]
+    fn invalid_parameters() {
+        assert_eq!(
+            Resampler::new(0, 8000).err(),
+            Some(ResamplerError::InvalidRate(0))
+        );
+        assert_eq!(
+            Resampler::with_quality(44100, 8000, 11).err(),
+            Some(ResamplerError::InvalidQuality(11))
+        );
+        assert_eq!(
+            Resampler::with_quality(4_000_000_000, 1, 4).err(),
+            Some(ResamplerError::RatioTooLarge)
+        );
+        assert_eq!(
+            Resampler::with_quality(u32::MAX, 1, 10).err(),
+            Some(ResamplerError::RatioTooLarge)
+        );
+    }
+
+    #[test]
+    fn passband_ripple() {
+        // Direct and interpolated tables, down and up
+        for (in_rate, out_rate) in [
+            (48000, 16000),
+            (44100, 32000),
+            (8000, 44100),
+            (16000, 48000),
+        ] {
+            for quality in [DEFAULT_QUALITY, MAX_QUALITY] {
+                let nyquist = in_rate.min(out_rate) as f64 / 2.0;
+                let gains = (1..=15)
+                    .map(|k| gain_db(in_rate, out_rate, quality, nyquist * 0.05 * k as f64))
+                    .collect::<Vec<_>>();
+                let ripple = gains.iter().map(|gain| gain.abs()).fold(0.0, f64::max);
+                assert!(
+                    ripple < 0.05,
+                    "{in_rate} to {out_rate} at quality {quality}: {gains:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn aliasing_rejection() {
+        for (quality, rejection) in [(2, 60.0), (DEFAULT_QUALITY, 80.0), (MAX_QUALITY, 120.0)] {
+            for frequency in [9000.0, 11000.0, 15000.0, 23000.0] {
+                let gain = gain_db(48000, 16000, quality, frequency);
+                assert!(
+                    gain < -rejection,
+                    "{frequency} Hz at quality {quality}: {gain} dB"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn imaging_rejection() {
+        // A tone at 3 kHz upsampled from 16 kHz has images at 13 and 19 kHz
+        let mut resampler = Resampler::new(16000, 48000).unwrap();
+        let output = resample(&mut resampler, &tone(16000, 3000.0, 1.0, 16000));
+        let settled = &output[12000..36000];
+        let amplitude = |frequency: f64| {
+            let (re, im) = settled
+                .iter()
+                .enumerate()
+                .fold((0.0, 0.0), |(re, im), (n, &x)| {
+                    let angle = 2.0 * PI * frequency * n as f64 / 48000.0;
+                    (re + x as f64 * angle.cos(), im - x as f64 * angle.sin())
+                });
+            2.0 * (re * re + im * im).sqrt() / settled.len() as f64
+        };
+        let tone = amplitude(3000.0);
+        for image in [13000.0, 19000.0] {
+            let rejection = 20.0 * (tone / amplitude(image)).log10();
+            assert!(rejection > 70.0, "{image} Hz: {rejection} dB");
+        }
+    }
+
+    #[test]
+    fn latency() {
+        for (in_rate, out_rate) in [
+            (48000, 16000),
+            (44100, 32000),
+            (8000, 44100),
+            (16000, 16000),
+        ] {
+            for quality in [0, DEFAULT_QUALITY, MAX_QUALITY] {
+                let mut resampler = Resampler::with_quality(in_rate, out_rate, quality).unwrap();
+                // An impulse at a multiple of both periods lands on an output sample
+                let at = in_rate as usize / 100;
+                let mut input = vec![0.0; at * 2 + resampler.get_filter_length()];
+                input[at] = 1.0;
+                let output = resample(&mut resampler, &input);
+                let peak = (0..output.len())
+                    .max_by(|&a, &b| output[a].total_cmp(&output[b]))
+                    .unwrap();
+                let expected = out_rate as usize / 100 + resampler.get_output_latency();
+                assert!(
+                    peak.abs_diff(expected) <= 1,
+                    "{in_rate} to {out_rate} at quality {quality}: peak at {peak}, expected \
+                     {expected}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn skip_zeros_and_finish() {
+        let input = tone(44100, 440.0, 1.0, 44100);
+        let mut resampler = Resampler::new(44100, 16000).unwrap();
+        resampler.skip_zeros();
+        let mut output = resample(&mut resampler, &input);
+        resampler.finish(&mut output);
+        assert_eq!(output.len(), 16000);
+
+        // Lined up with the input from the start
+        let expected = tone(16000, 440.0, 1.0, 16000);
+        let error = output[100..15900]
+            .iter()
+            .zip(&expected[100..15900])
+            .map(|(a, b)| (a - b).abs())
+            .fold(0.0, f32::max);
+        assert!(error < 0.01, "{error}");
+    }
+
+    #[test]
+    fn finish_without_skipping_zeros() {
+        let mut resampler = Resampler::new(48000, 8000).unwrap();
+        let latency = resampler.get_output_latency();
+        let mut output = resample(&mut resampler, &vec![0.5; 4801]);
+        resampler.finish(&mut output);
+        assert_eq!(output.len(), 801 + latency);
+    }
+}
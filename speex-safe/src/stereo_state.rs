@@ -7,26 +7,94 @@
 
 use speex_sys::SpeexStereoState as SysStereoState;
 
+use crate::SpeexBits;
+
 /// Handling for speex stereo files.
+///
+/// Speex stereo is coded as a mono stream plus an in-band message carrying the
+/// balance between the two channels. The encoding side is handled by
+/// `SpeexEncoder::encode_stereo`, this state is what the decoding side uses to
+/// expand the mono output back into two channels.
 pub struct SpeexStereoState {
-    backing: SysStereoState,
+    // Allocated by libspeex, so it has to stay behind the pointer it returned
+    // in order to be freed correctly
+    backing: *mut SysStereoState,
 }
 
 impl SpeexStereoState {
+    pub(crate) fn backing_mut_ptr(&mut self) -> *mut SysStereoState {
+        self.backing
+    }
+
     /// Creates a new SpeexStereoState.
     pub fn new() -> Self {
-        let backing = unsafe {
-            let ptr = speex_sys::speex_stereo_state_init();
-            *ptr
-        };
+        let backing = unsafe { speex_sys::speex_stereo_state_init() };
 
         Self { backing }
     }
 
     /// Resets a SpeexStereoState to its original state.
     pub fn reset(&mut self) {
-        let ptr = &mut self.backing as *mut SysStereoState;
-        unsafe { speex_sys::speex_stereo_state_reset(ptr) }
+        unsafe { speex_sys::speex_stereo_state_reset(self.backing) }
+    }
+
+    /// Expands a decoded mono frame in `data` into interleaved stereo, in
+    /// place.
+    ///
+    /// The first `frame_size` samples of `data` are read as mono, and
+    /// `2 * frame_size` interleaved samples are written back.
+    pub fn decode(&mut self, data: &mut [f32], frame_size: usize) {
+        assert!(
+            data.len() >= frame_size * 2,
+            "Buffer is too small to hold a stereo frame"
+        );
+        unsafe {
+            speex_sys::speex_decode_stereo(data.as_mut_ptr(), frame_size as i32, self.backing);
+        }
+    }
+
+    /// Expands a decoded mono frame in `data` into interleaved stereo, in
+    /// place, using an integer representation.
+    pub fn decode_int(&mut self, data: &mut [i16], frame_size: usize) {
+        assert!(
+            data.len() >= frame_size * 2,
+            "Buffer is too small to hold a stereo frame"
+        );
+        unsafe {
+            speex_sys::speex_decode_stereo_int(data.as_mut_ptr(), frame_size as i32, self.backing);
+        }
+    }
+
+    /// Writes the stereo in-band message for an interleaved frame to `bits`,
+    /// and downmixes the frame in place so the first `frame_size` samples of
+    /// `data` hold the mono signal to encode.
+    pub fn encode(data: &mut [f32], frame_size: usize, bits: &mut SpeexBits) {
+        assert!(
+            data.len() >= frame_size * 2,
+            "Buffer is too small to hold a stereo frame"
+        );
+        unsafe {
+            speex_sys::speex_encode_stereo(
+                data.as_mut_ptr(),
+                frame_size as i32,
+                bits.backing_mut_ptr(),
+            );
+        }
+    }
+
+    /// Same as `encode`, using an integer representation.
+    pub fn encode_int(data: &mut [i16], frame_size: usize, bits: &mut SpeexBits) {
+        assert!(
+            data.len() >= frame_size * 2,
+            "Buffer is too small to hold a stereo frame"
+        );
+        unsafe {
+            speex_sys::speex_encode_stereo_int(
+                data.as_mut_ptr(),
+                frame_size as i32,
+                bits.backing_mut_ptr(),
+            );
+        }
     }
 }
 
@@ -39,7 +107,7 @@ impl Default for SpeexStereoState {
 impl Drop for SpeexStereoState {
     fn drop(&mut self) {
         unsafe {
-            speex_sys::speex_stereo_state_destroy(&mut self.backing);
+            speex_sys::speex_stereo_state_destroy(self.backing);
         }
     }
 }
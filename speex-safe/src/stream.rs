@@ -0,0 +1,328 @@
+////////////////////////////////////////////////////////////////////////////////
+// Copyright (c) 2023.                                                         /
+// This Source Code Form is subject to the terms of the Mozilla Public License,/
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can    /
+// obtain one at http://mozilla.org/MPL/2.0/.                                  /
+////////////////////////////////////////////////////////////////////////////////
+
+//! Encoding and decoding mono audio at any sampling rate, resampled to and
+//! from the rate of the speex mode
+//!
+//! Input of any length goes in, and packets of one frame come out. The
+//! resamplers skip their own delay, so the only delay left is the codec's,
+//! given by `get_lookahead` on either end.
+
+use crate::resampler::{Resampler, ResamplerError, DEFAULT_QUALITY};
+use crate::{DecoderError, DynamicDecoder, DynamicEncoder, ModeId, SpeexBits};
+
+/// Picks the mode with the highest sampling rate not above `sample_rate`, so
+/// nothing is upsampled only to be coded. Rates below 8 kHz use narrowband.
+pub fn mode_for_input_rate(sample_rate: u32) -> ModeId {
+    match sample_rate {
+        32000.. => ModeId::UltraWideBand,
+        16000.. => ModeId::WideBand,
+        _ => ModeId::NarrowBand,
+    }
+}
+
+/// Converts a lookahead at the rate of `mode` to samples at `rate`
+fn lookahead_at(lookahead: i32, mode: ModeId, rate: u32) -> usize {
+    (lookahead as u64 * rate as u64).div_ceil(mode.get_sampling_rate() as u64) as usize
+}
+
+/// Resampler from `from` to `to`, or none if they're the same
+fn resampler(from: u32, to: u32, quality: u8) -> Result<Option<Resampler>, ResamplerError> {
+    if from == to {
+        return Ok(None);
+    }
+    let mut resampler = Resampler::with_quality(from, to, quality)?;
+    resampler.skip_zeros();
+    Ok(Some(resampler))
+}
+
+/// Encodes mono audio at any sampling rate into packets of one frame
+pub struct StreamEncoder {
+    encoder: DynamicEncoder,
+    mode: ModeId,
+    input_rate: u32,
+    resampler: Option<Resampler>,
+    bits: SpeexBits<'static>,
+    /// Samples at the mode's rate not yet making up a whole frame
+    pending: Vec<f32>,
+    frame_size: usize,
+}
+
+impl StreamEncoder {
+    /// Creates an encoder for audio at `input_rate`, resampling at
+    /// [`DEFAULT_QUALITY`]
+    pub fn new(input_rate: u32) -> Result<Self, ResamplerError> {
+        Self::with_quality(input_rate, DEFAULT_QUALITY)
+    }
+
+    /// Creates an encoder for audio at `input_rate`, resampling at a quality
+    /// between 0 and 10
+    pub fn with_quality(input_rate: u32, resampler_quality: u8) -> Result<Self, ResamplerError> {
+        let mode = mode_for_input_rate(input_rate);
+        let resampler = resampler(input_rate, mode.get_sampling_rate(), resampler_quality)?;
+        let encoder = DynamicEncoder::new(mode);
+        Ok(Self {
+            encoder,
+            mode,
+            input_rate,
+            resampler,
+            bits: SpeexBits::new(),
+            pending: Vec::new(),
+            frame_size: mode.get_frame_size() as usize,
+        })
+    }
+
+    pub fn get_mode(&self) -> ModeId {
+        self.mode
+    }
+
+    pub fn get_input_rate(&self) -> u32 {
+        self.input_rate
+    }
+
+    /// Gets the encoder, to change its settings
+    pub fn get_encoder(&mut self) -> &mut DynamicEncoder {
+        &mut self.encoder
+    }
+
+    /// Gets how far the encoder looks ahead, in input samples
+    pub fn get_lookahead(&mut self) -> usize {
+        lookahead_at(self.encoder.get_lookahead(), self.mode, self.input_rate)
+    }
+
+    /// Encodes `samples`, returning a packet for every frame they complete
+    pub fn encode(&mut self, samples: &[f32]) -> Vec<Vec<u8>> {
+        match &mut self.resampler {
+            Some(resampler) => resampler.process(samples, &mut self.pending),
+            None => self.pending.extend_from_slice(samples),
+        }
+        self.encode_pending()
+    }
+
+    /// Encodes the rest of the input, padding the last frame with silence
+    pub fn finish(mut self) -> Vec<Vec<u8>> {
+        if let Some(resampler) = self.resampler.take() {
+            resampler.finish(&mut self.pending);
+        }
+        if !self.pending.is_empty() {
+            let padded = self.pending.len().next_multiple_of(self.frame_size);
+            self.pending.resize(padded, 0.0);
+        }
+        self.encode_pending()
+    }
+
+    fn encode_pending(&mut self) -> Vec<Vec<u8>> {
+        let frames = self.pending.len() / self.frame_size;
+        let packets = self.pending[..frames * self.frame_size]
+            .chunks_exact_mut(self.frame_size)
+            .map(|frame| {
+                self.bits.reset();
+                self.encoder
+                    .encode(frame, &mut self.bits)
+                    .expect("frames are cut to the frame size");
+                let mut packet = vec![0; self.bits.num_bytes() as usize];
+                self.bits.write(&mut packet);
+                packet
+            })
+            .collect();
+        self.pending.drain(..frames * self.frame_size);
+        packets
+    }
+}
+
+/// Decodes packets of a mode into mono audio at any sampling rate
+pub struct StreamDecoder {
+    decoder: DynamicDecoder,
+    mode: ModeId,
+    output_rate: u32,
+    resampler: Option<Resampler>,
+    bits: SpeexBits<'static>,
+}
+
+impl StreamDecoder {
+    /// Creates a decoder of `mode` producing audio at `output_rate`,
+    /// resampling at [`DEFAULT_QUALITY`]
+    pub fn new(mode: ModeId, output_rate: u32) -> Result<Self, ResamplerError> {
+        Self::with_quality(mode, output_rate, DEFAULT_QUALITY)
+    }
+
+    /// Creates a decoder of `mode` producing audio at `output_rate`,
+    /// resampling at a quality between 0 and 10
+    pub fn with_quality(
+        mode: ModeId,
+        output_rate: u32,
+        resampler_quality: u8,
+    ) -> Result<Self, ResamplerError> {
+        let resampler = resampler(mode.get_sampling_rate(), output_rate, resampler_quality)?;
+        Ok(Self {
+            decoder: DynamicDecoder::new(mode),
+            mode,
+            output_rate,
+            resampler,
+            bits: SpeexBits::new(),
+        })
+    }
+
+    pub fn get_mode(&self) -> ModeId {
+        self.mode
+    }
+
+    pub fn get_output_rate(&self) -> u32 {
+        self.output_rate
+    }
+
+    /// Gets the decoder, to change its settings
+    pub fn get_decoder(&mut self) -> &mut DynamicDecoder {
+        &mut self.decoder
+    }
+
+    /// Gets the delay of the decoder, in output samples
+    pub fn get_lookahead(&mut self) -> usize {
+        lookahead_at(self.decoder.get_lookahead(), self.mode, self.output_rate)
+    }
+
+    /// Decodes every frame in `packet`, or conceals a lost frame if there's
+    /// no packet, returning the samples completed at the output rate
+    pub fn decode(&mut self, packet: Option<&[u8]>) -> Result<Vec<f32>, DecoderError> {
+        let decoded = self.decoder.decode_packet(&mut self.bits, packet)?;
+
+        match &mut self.resampler {
+            Some(resampler) => {
+                let mut resampled = Vec::new();
+                resampler.process(&decoded, &mut resampled);
+                Ok(resampled)
+            }
+            None => Ok(decoded),
+        }
+    }
+
+    /// Returns the samples still held back by the resampler
+    pub fn finish(self) -> Vec<f32> {
+        let mut output = Vec::new();
+        if let Some(resampler) = self.resampler {
+            resampler.finish(&mut output);
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::f64::consts::PI;
+
+    use super::*;
+    use crate::support::tone;
+
+    /// Encodes `input` in uneven chunks and decodes it back at the same rate
+    fn round_trip(rate: u32, input: &[f32]) -> (Vec<f32>, usize) {
+        let mut encoder = StreamEncoder::new(rate).unwrap();
+        encoder.get_encoder().set_quality(8);
+        let mut decoder = StreamDecoder::new(encoder.get_mode(), rate).unwrap();
+        let delay = encoder.get_lookahead() + decoder.get_lookahead();
+
+        let mut packets = Vec::new();
+        for chunk in input.chunks(1234) {
+            packets.extend(encoder.encode(chunk));
+        }
+        packets.extend(encoder.finish());
+        let mut output = Vec::new();
+        for packet in &packets {
+            output.extend(decoder.decode(Some(packet)).unwrap());
+        }
+        output.extend(decoder.finish());
+        (output, delay)
+    }
+
+    /// Frequency of the strongest of the candidates in `signal`
+    fn strongest(signal: &[f32], rate: u32, candidates: &[f64]) -> f64 {
+        let power = |frequency: f64| {
+            let (re, im) = signal
+                .iter()
+                .enumerate()
+                .fold((0.0, 0.0), |(re, im), (n, &x)| {
+                    let angle = 2.0 * PI * frequency * n as f64 / rate as f64;
+                    (re + x as f64 * angle.cos(), im - x as f64 * angle.sin())
+                });
+            re * re + im * im
+        };
+        candidates
+            .iter()
+            .copied()
+            .max_by(|&a, &b| power(a).total_cmp(&power(b)))
+            .unwrap()
+    }
+
+    #[test]
+    fn picks_mode_from_rate() {
+        assert_eq!(mode_for_input_rate(6000), ModeId::NarrowBand);
+        assert_eq!(mode_for_input_rate(11025), ModeId::NarrowBand);
+        assert_eq!(mode_for_input_rate(22050), ModeId::WideBand);
+        assert_eq!(mode_for_input_rate(44100), ModeId::UltraWideBand);
+        assert_eq!(mode_for_input_rate(48000), ModeId::UltraWideBand);
+    }
+
+    #[test]
+    fn round_trip_keeps_length_and_pitch() {
+        for rate in [11025, 22050, 44100, 48000] {
+            let input = tone(rate, 440.0, 8000.0, rate as usize);
+            let (output, delay) = round_trip(rate, &input);
+            let frame = mode_for_input_rate(rate).get_frame_size() as usize * rate as usize
+                / mode_for_input_rate(rate).get_sampling_rate() as usize;
+            assert!(
+                output.len() >= input.len() && output.len() <= input.len() + frame + 1,
+                "{rate}: {} samples out of {}",
+                output.len(),
+                input.len()
+            );
+
+            let settled = &output[delay + rate as usize / 4..delay + rate as usize * 3 / 4];
+            let frequency = strongest(settled, rate, &[220.0, 330.0, 440.0, 550.0, 880.0]);
+            assert_eq!(frequency, 440.0, "{rate}");
+        }
+    }
+
+    #[test]
+    fn round_trip_latency() {
+        for rate in [8000, 22050, 44100, 48000] {
+            // A burst of tone after silence, to time the onset
+            let mut input = vec![0.0; rate as usize / 5];
+            input.extend(tone(rate, 600.0, 8000.0, rate as usize / 5));
+            input.resize(rate as usize, 0.0);
+            let (output, delay) = round_trip(rate, &input);
+
+            // Lag that best lines the output up with the input
+            let correlation = |lag: usize| {
+                input
+                    .iter()
+                    .zip(&output[lag..])
+                    .map(|(&x, &y)| x as f64 * y as f64)
+                    .sum::<f64>()
+            };
+            let lag = (0..delay * 2)
+                .max_by(|&a, &b| correlation(a).total_cmp(&correlation(b)))
+                .unwrap();
+            assert!(
+                lag.abs_diff(delay) <= rate as usize / 1000,
+                "{rate}: lag {lag}, expected {delay}"
+            );
+        }
+    }
+
+    #[test]
+    fn lost_packets_are_concealed() {
+        let mut encoder = StreamEncoder::new(44100).unwrap();
+        let mut decoder = StreamDecoder::new(encoder.get_mode(), 44100).unwrap();
+        let packets = encoder.encode(&tone(44100, 440.0, 8000.0, 44100 / 5));
+        let mut output = Vec::new();
+        for (n, packet) in packets.iter().enumerate() {
+            let packet = (n % 3 != 1).then_some(packet.as_slice());
+            output.extend(decoder.decode(packet).unwrap());
+        }
+        output.extend(decoder.finish());
+        assert_eq!(output.len(), packets.len() * 640 * 44100 / 32000);
+    }
+}
@@ -0,0 +1,213 @@
+////////////////////////////////////////////////////////////////////////////////
+// Copyright (c) 2023.                                                         /
+// This Source Code Form is subject to the terms of the Mozilla Public License,/
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can    /
+// obtain one at http://mozilla.org/MPL/2.0/.                                  /
+////////////////////////////////////////////////////////////////////////////////
+
+//! JavaScript bindings through `wasm-bindgen`
+//!
+//! Samples are passed as `Float32Array`s or `Int16Array`s and packets as
+//! `Uint8Array`s. Modes are given by their id: 0 for narrowband, 1 for
+//! wideband and 2 for ultra-wideband.
+
+use wasm_bindgen::prelude::*;
+
+use crate::wav::{decode_ogg, DecodeOptions};
+use crate::{DynamicDecoder, DynamicEncoder, ModeId, SpeexBits};
+
+/// Largest packet a single frame can be encoded into
+const MAX_PACKET_SIZE: usize = 200;
+
+fn mode_from_id(mode: i32) -> Result<ModeId, JsError> {
+    match mode {
+        0 => Ok(ModeId::NarrowBand),
+        1 => Ok(ModeId::WideBand),
+        2 => Ok(ModeId::UltraWideBand),
+        _ => Err(JsError::new(&format!("Invalid mode id {mode}"))),
+    }
+}
+
+/// Encodes frames of audio into packets, one frame per packet
+#[wasm_bindgen(js_name = Encoder)]
+pub struct WasmEncoder {
+    encoder: DynamicEncoder,
+    bits: SpeexBits<'static>,
+    frame_size: usize,
+}
+
+#[wasm_bindgen(js_class = Encoder)]
+impl WasmEncoder {
+    #[wasm_bindgen(constructor)]
+    pub fn new(mode: i32) -> Result<WasmEncoder, JsError> {
+        let mut encoder = DynamicEncoder::new(mode_from_id(mode)?);
+        let frame_size = encoder.get_frame_size() as usize;
+        Ok(Self {
+            encoder,
+            bits: SpeexBits::new(),
+            frame_size,
+        })
+    }
+
+    /// Number of samples in a frame
+    #[wasm_bindgen(getter, js_name = frameSize)]
+    pub fn frame_size(&self) -> usize {
+        self.frame_size
+    }
+
+    #[wasm_bindgen(js_name = setQuality)]
+    pub fn set_quality(&mut self, quality: i32) {
+        self.encoder.set_quality(quality);
+    }
+
+    #[wasm_bindgen(js_name = setComplexity)]
+    pub fn set_complexity(&mut self, complexity: i32) {
+        self.encoder.set_complexity(complexity);
+    }
+
+    #[wasm_bindgen(js_name = setVbr)]
+    pub fn set_vbr(&mut self, vbr: bool) {
+        self.encoder.set_vbr(vbr);
+    }
+
+    #[wasm_bindgen(js_name = setVbrQuality)]
+    pub fn set_vbr_quality(&mut self, quality: f32) {
+        self.encoder.set_vbr_quality(quality);
+    }
+
+    #[wasm_bindgen(js_name = setBitrate)]
+    pub fn set_bitrate(&mut self, bitrate: i32) {
+        self.encoder.set_bitrate(bitrate);
+    }
+
+    #[wasm_bindgen(js_name = setDtx)]
+    pub fn set_dtx(&mut self, dtx: bool) {
+        self.encoder.set_dtx(dtx);
+    }
+
+    /// Encodes one frame of samples in the range of an `i16`
+    pub fn encode(&mut self, frame: &[f32]) -> Result<Vec<u8>, JsError> {
+        self.check_frame(frame.len())?;
+        let mut frame = frame.to_vec();
+        self.bits.reset();
+        self.encoder.encode(&mut frame, &mut self.bits)?;
+        Ok(self.write_packet())
+    }
+
+    /// Encodes one frame of 16 bit samples
+    #[wasm_bindgen(js_name = encodeInt)]
+    pub fn encode_int(&mut self, frame: &[i16]) -> Result<Vec<u8>, JsError> {
+        self.check_frame(frame.len())?;
+        let mut frame = frame.to_vec();
+        self.bits.reset();
+        self.encoder.encode_int(&mut frame, &mut self.bits)?;
+        Ok(self.write_packet())
+    }
+}
+
+impl WasmEncoder {
+    fn check_frame(&self, len: usize) -> Result<(), JsError> {
+        if len != self.frame_size {
+            return Err(JsError::new(&format!(
+                "Expected a frame of {} samples, got {len}",
+                self.frame_size
+            )));
+        }
+        Ok(())
+    }
+
+    fn write_packet(&mut self) -> Vec<u8> {
+        let mut packet = vec![0u8; MAX_PACKET_SIZE];
+        let len = self.bits.write(&mut packet) as usize;
+        packet.truncate(len);
+        packet
+    }
+}
+
+/// Decodes packets back into audio
+#[wasm_bindgen(js_name = Decoder)]
+pub struct WasmDecoder {
+    decoder: DynamicDecoder,
+    bits: SpeexBits<'static>,
+    frame_size: usize,
+}
+
+#[wasm_bindgen(js_class = Decoder)]
+impl WasmDecoder {
+    #[wasm_bindgen(constructor)]
+    pub fn new(mode: i32) -> Result<WasmDecoder, JsError> {
+        let mut decoder = DynamicDecoder::new(mode_from_id(mode)?);
+        let frame_size = decoder.get_frame_size() as usize;
+        Ok(Self {
+            decoder,
+            bits: SpeexBits::new(),
+            frame_size,
+        })
+    }
+
+    /// Number of samples in a frame
+    #[wasm_bindgen(getter, js_name = frameSize)]
+    pub fn frame_size(&self) -> usize {
+        self.frame_size
+    }
+
+    #[wasm_bindgen(js_name = setEnhancement)]
+    pub fn set_enhancement(&mut self, enhancement: bool) {
+        self.decoder.set_enhancement(enhancement);
+    }
+
+    /// Decodes every frame in a packet, with samples in the range of an `i16`
+    pub fn decode(&mut self, packet: &[u8]) -> Result<Vec<f32>, JsError> {
+        Ok(self.decoder.decode_packet(&mut self.bits, Some(packet))?)
+    }
+
+    /// Decodes every frame in a packet into 16 bit samples
+    #[wasm_bindgen(js_name = decodeInt)]
+    pub fn decode_int(&mut self, packet: &[u8]) -> Result<Vec<i16>, JsError> {
+        Ok(self.decoder.decode_packet(&mut self.bits, Some(packet))?)
+    }
+
+    /// Conceals a lost packet, producing one frame of audio
+    #[wasm_bindgen(js_name = decodeLost)]
+    pub fn decode_lost(&mut self) -> Result<Vec<f32>, JsError> {
+        Ok(self.decoder.decode_packet(&mut self.bits, None)?)
+    }
+}
+
+/// Audio decoded from an `.spx` file
+#[wasm_bindgen(js_name = SpxAudio)]
+pub struct WasmSpxAudio {
+    sample_rate: u32,
+    channels: u16,
+    samples: Vec<i16>,
+}
+
+#[wasm_bindgen(js_class = SpxAudio)]
+impl WasmSpxAudio {
+    #[wasm_bindgen(getter, js_name = sampleRate)]
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    /// Interleaved 16 bit samples
+    #[wasm_bindgen(getter)]
+    pub fn samples(&self) -> Vec<i16> {
+        self.samples.clone()
+    }
+}
+
+/// Decodes a whole Ogg Speex file, such as a `.spx` voicemail
+#[wasm_bindgen(js_name = decodeSpx)]
+pub fn decode_spx(file: &[u8]) -> Result<WasmSpxAudio, JsError> {
+    let audio = decode_ogg(file, &DecodeOptions::default())?;
+    Ok(WasmSpxAudio {
+        sample_rate: audio.sample_rate,
+        channels: audio.channels,
+        samples: audio.samples,
+    })
+}
@@ -0,0 +1,1018 @@
+////////////////////////////////////////////////////////////////////////////////
+// Copyright (c) 2023.                                                         /
+// This Source Code Form is subject to the terms of the Mozilla Public License,/
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can    /
+// obtain one at http://mozilla.org/MPL/2.0/.                                  /
+////////////////////////////////////////////////////////////////////////////////
+
+//! WAV file support, and conversion between WAV files and Ogg Speex files.
+//!
+//! Samples are handled as `f32` in the range of an `i16`, which is the range
+//! the speex float API expects.
+
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::io::{Read, Write};
+
+use crate::ogg::{OggReader, OggWriter};
+use crate::preprocess::Preprocessor;
+use crate::stereo_state::SpeexStereoState;
+use crate::{
+    dynamic_mapping,
+    DecoderError,
+    DynamicDecoder,
+    DynamicEncoder,
+    ModeId,
+    SpeexBits,
+    SpeexComments,
+    SpeexHeader,
+    SpeexHeaders,
+};
+
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+/// Size of the longest fmt chunk parsed, that of `WAVE_FORMAT_EXTENSIBLE`
+const FMT_EXTENSIBLE_SIZE: u32 = 40;
+/// Level automatic gain control brings speech to, the default of speexdsp
+const AGC_LEVEL: f32 = 8000.0;
+
+/// Vendor string written to the comment packet of encoded streams
+pub const VENDOR_STRING: &str = "Encoded with speex-safe";
+
+/// Error type for reading and writing WAV and Ogg Speex files.
+#[derive(Debug)]
+pub enum WavError {
+    /// An error from the underlying reader or writer
+    Io(io::Error),
+    /// The input is not a well formed WAV file
+    InvalidWav(&'static str),
+    /// The WAV file uses a sample format that isn't supported
+    UnsupportedFormat {
+        format_tag: u16,
+        bits_per_sample: u16,
+    },
+    /// No speex mode can encode audio at this sampling rate
+    UnsupportedSampleRate(u32),
+    /// The number of channels can't be encoded
+    UnsupportedChannels(u16),
+    /// The audio doesn't fit in the sizes of a WAV header
+    TooLarge(&'static str),
+    /// The input is not a well formed Ogg Speex stream
+    InvalidSpeexStream(&'static str),
+    /// The decoder failed on an audio packet
+    Decoder(DecoderError),
+}
+
+impl Display for WavError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WavError::Io(err) => write!(f, "I/O error: {err}"),
+            WavError::InvalidWav(reason) => write!(f, "Invalid WAV file: {reason}"),
+            WavError::UnsupportedFormat {
+                format_tag,
+                bits_per_sample,
+            } => {
+                write!(
+                    f,
+                    "Unsupported WAV sample format (format tag {format_tag}, {bits_per_sample} \
+                     bits per sample)"
+                )
+            }
+            WavError::UnsupportedSampleRate(rate) => {
+                write!(f, "No speex mode supports a sampling rate of {rate} Hz")
+            }
+            WavError::UnsupportedChannels(channels) => {
+                write!(f, "Unsupported number of channels ({channels})")
+            }
+            WavError::TooLarge(reason) => write!(f, "Too large for a WAV file: {reason}"),
+            WavError::InvalidSpeexStream(reason) => write!(f, "Invalid Ogg Speex stream: {reason}"),
+            WavError::Decoder(err) => write!(f, "Decoding failed: {err}"),
+        }
+    }
+}
+
+impl Error for WavError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            WavError::Io(err) => Some(err),
+            WavError::Decoder(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for WavError {
+    fn from(value: io::Error) -> Self {
+        WavError::Io(value)
+    }
+}
+
+impl From<DecoderError> for WavError {
+    fn from(value: DecoderError) -> Self {
+        WavError::Decoder(value)
+    }
+}
+
+/// How samples are stored in a WAV file
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum SampleFormat {
+    /// Integer PCM, unsigned for 8 bits and signed otherwise
+    Int,
+    /// IEEE floating point
+    Float,
+}
+
+/// Format description of a WAV file
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct WavSpec {
+    pub channels: u16,
+    pub sample_rate: u32,
+    pub bits_per_sample: u16,
+    pub sample_format: SampleFormat,
+}
+
+/// Reads a PCM WAV file
+///
+/// 8, 16, 24 and 32 bit integer and 32 and 64 bit float files are supported.
+/// Returns the format of the file, and its interleaved samples scaled to the
+/// range of an `i16`.
+pub fn read_wav<R: Read>(mut reader: R) -> Result<(WavSpec, Vec<f32>), WavError> {
+    let mut riff = [0u8; 12];
+    reader.read_exact(&mut riff)?;
+    if &riff[0..4] != b"RIFF" || &riff[8..12] != b"WAVE" {
+        return Err(WavError::InvalidWav("missing RIFF/WAVE header"));
+    }
+
+    let mut spec = None;
+    loop {
+        let mut chunk_header = [0u8; 8];
+        if let Err(err) = reader.read_exact(&mut chunk_header) {
+            return match err.kind() {
+                io::ErrorKind::UnexpectedEof => Err(WavError::InvalidWav("missing data chunk")),
+                _ => Err(err.into()),
+            };
+        }
+        let id = &chunk_header[0..4];
+        let size = u32::from_le_bytes(chunk_header[4..8].try_into().unwrap());
+
+        match id {
+            b"fmt " => {
+                if size < 16 {
+                    return Err(WavError::InvalidWav("fmt chunk is too short"));
+                }
+                // Only the extensible format's 40 bytes are parsed, so skip
+                // anything past them rather than trusting the size
+                let mut fmt = vec![0u8; size.min(FMT_EXTENSIBLE_SIZE) as usize];
+                reader.read_exact(&mut fmt)?;
+                let rest = size as u64 - fmt.len() as u64 + (size as u64 & 1);
+                io::copy(&mut (&mut reader).take(rest), &mut io::sink())?;
+                spec = Some(parse_fmt(&fmt)?);
+            }
+            b"data" => {
+                let spec = spec.ok_or(WavError::InvalidWav("data chunk before fmt chunk"))?;
+                let mut data = Vec::new();
+                // Streamed files often leave the size at its maximum, so read
+                // what is there rather than trusting it
+                reader.take(size as u64).read_to_end(&mut data)?;
+                let samples = convert_samples(&spec, &data);
+                return Ok((spec, samples));
+            }
+            _ => {
+                let padded = size as u64 + (size as u64 & 1);
+                io::copy(&mut (&mut reader).take(padded), &mut io::sink())?;
+            }
+        }
+    }
+}
+
+fn parse_fmt(fmt: &[u8]) -> Result<WavSpec, WavError> {
+    let read_u16 = |offset: usize| u16::from_le_bytes([fmt[offset], fmt[offset + 1]]);
+    let mut format_tag = read_u16(0);
+    let channels = read_u16(2);
+    let sample_rate = u32::from_le_bytes(fmt[4..8].try_into().unwrap());
+    let bits_per_sample = read_u16(14);
+
+    if format_tag == WAVE_FORMAT_EXTENSIBLE {
+        if fmt.len() < 26 {
+            return Err(WavError::InvalidWav("extensible fmt chunk is too short"));
+        }
+        // The first two bytes of the sub-format GUID hold the actual format tag
+        format_tag = read_u16(24);
+    }
+
+    let sample_format = match (format_tag, bits_per_sample) {
+        (WAVE_FORMAT_PCM, 8 | 16 | 24 | 32) => SampleFormat::Int,
+        (WAVE_FORMAT_IEEE_FLOAT, 32 | 64) => SampleFormat::Float,
+        _ => {
+            return Err(WavError::UnsupportedFormat {
+                format_tag,
+                bits_per_sample,
+            })
+        }
+    };
+    if channels == 0 {
+        return Err(WavError::UnsupportedChannels(channels));
+    }
+
+    Ok(WavSpec {
+        channels,
+        sample_rate,
+        bits_per_sample,
+        sample_format,
+    })
+}
+
+fn convert_samples(spec: &WavSpec, data: &[u8]) -> Vec<f32> {
+    let width = spec.bits_per_sample as usize / 8;
+    let frame_width = width * spec.channels as usize;
+    // Drop any trailing partial frame
+    let usable = data.len() - data.len() % frame_width;
+    data[..usable]
+        .chunks_exact(width)
+        .map(|sample| {
+            match (spec.sample_format, width) {
+                (SampleFormat::Int, 1) => (sample[0] as f32 - 128.0) * 256.0,
+                (SampleFormat::Int, 2) => i16::from_le_bytes([sample[0], sample[1]]) as f32,
+                (SampleFormat::Int, 3) => {
+                    let value = i32::from_le_bytes([0, sample[0], sample[1], sample[2]]) >> 8;
+                    value as f32 / 256.0
+                }
+                (SampleFormat::Int, _) => {
+                    i32::from_le_bytes(sample.try_into().unwrap()) as f32 / 65536.0
+                }
+                (SampleFormat::Float, 4) => {
+                    f32::from_le_bytes(sample.try_into().unwrap()) * 32768.0
+                }
+                (SampleFormat::Float, _) => {
+                    (f64::from_le_bytes(sample.try_into().unwrap()) * 32768.0) as f32
+                }
+            }
+        })
+        .collect()
+}
+
+/// Writes interleaved samples as a 16 bit PCM WAV file
+pub fn write_wav<W: Write>(
+    mut writer: W,
+    channels: u16,
+    sample_rate: u32,
+    samples: &[i16],
+) -> Result<(), WavError> {
+    writer.write_all(&wav_header(channels, sample_rate, samples.len())?)?;
+    let data: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+    writer.write_all(&data)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Builds the header of a 16 bit PCM WAV file holding `len` samples
+fn wav_header(channels: u16, sample_rate: u32, len: usize) -> Result<Vec<u8>, WavError> {
+    let block_align = channels
+        .checked_mul(2)
+        .ok_or(WavError::UnsupportedChannels(channels))?;
+    let byte_rate = sample_rate
+        .checked_mul(block_align as u32)
+        .ok_or(WavError::TooLarge("byte rate overflows"))?;
+    // The RIFF chunk holds the data and 36 bytes of headers
+    let data_size = len
+        .checked_mul(2)
+        .and_then(|size| u32::try_from(size).ok())
+        .filter(|&size| size <= u32::MAX - 36)
+        .ok_or(WavError::TooLarge("more than 4 GiB of samples"))?;
+
+    let mut header = Vec::with_capacity(44);
+    header.extend_from_slice(b"RIFF");
+    header.extend_from_slice(&(36 + data_size).to_le_bytes());
+    header.extend_from_slice(b"WAVE");
+    header.extend_from_slice(b"fmt ");
+    header.extend_from_slice(&16u32.to_le_bytes());
+    header.extend_from_slice(&WAVE_FORMAT_PCM.to_le_bytes());
+    header.extend_from_slice(&channels.to_le_bytes());
+    header.extend_from_slice(&sample_rate.to_le_bytes());
+    header.extend_from_slice(&byte_rate.to_le_bytes());
+    header.extend_from_slice(&block_align.to_le_bytes());
+    header.extend_from_slice(&16u16.to_le_bytes());
+    header.extend_from_slice(b"data");
+    header.extend_from_slice(&data_size.to_le_bytes());
+    Ok(header)
+}
+
+/// Picks the speex mode used to encode audio at the given sampling rate
+///
+/// Rates close to 8 kHz use narrowband, close to 16 kHz wideband, and close
+/// to 32 kHz ultra-wideband, following the reference `speexenc`.
+pub fn mode_for_sample_rate(sample_rate: u32) -> Option<ModeId> {
+    match sample_rate {
+        6000..=12499 => Some(ModeId::NarrowBand),
+        12500..=24999 => Some(ModeId::WideBand),
+        25000..=48000 => Some(ModeId::UltraWideBand),
+        _ => None,
+    }
+}
+
+/// How multi-channel input is encoded
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ChannelMode {
+    /// Average all channels into a mono stream
+    Downmix,
+    /// Use speex's intensity stereo for 2 channel input, other channel counts
+    /// are downmixed
+    Stereo,
+}
+
+/// Settings used when encoding to Ogg Speex
+///
+/// The encoder is configured in the same order as the reference `speexenc`,
+/// so an explicit bitrate takes precedence over the quality, and VBR, ABR
+/// and VAD are mutually exclusive in the same way.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct EncodeOptions {
+    /// Mode to encode with, picked from the sampling rate when `None`
+    pub mode: Option<ModeId>,
+    /// Quality between 0 and 10, also used as the VBR quality. Uses the
+    /// library default when `None`
+    pub quality: Option<i32>,
+    /// Target bitrate, overriding the quality
+    pub bitrate: Option<i32>,
+    /// Whether to use Variable BitRate
+    pub vbr: bool,
+    /// Maximum bitrate allowed in VBR mode
+    pub vbr_max_bitrate: Option<i32>,
+    /// Target bitrate for Average BitRate mode
+    pub abr: Option<i32>,
+    /// Whether to use Voice Activity Detection
+    pub vad: bool,
+    /// Whether to use Discontinuous Transmission
+    pub dtx: bool,
+    /// Analysis complexity between 1 and 10
+    pub complexity: i32,
+    /// Whether the encoder's highpass filter is used
+    pub highpass: bool,
+    /// Whether noise is suppressed before encoding
+    pub denoise: bool,
+    /// Whether automatic gain control is applied before encoding
+    pub agc: bool,
+    /// Number of speex frames stored in each Ogg packet
+    pub frames_per_packet: i32,
+    /// How input with more than one channel is encoded
+    pub channel_mode: ChannelMode,
+    /// Serial number of the Ogg stream
+    pub serial: u32,
+}
+
+impl Default for EncodeOptions {
+    fn default() -> Self {
+        Self {
+            mode: None,
+            quality: None,
+            bitrate: None,
+            vbr: false,
+            vbr_max_bitrate: None,
+            abr: None,
+            vad: false,
+            dtx: false,
+            complexity: 3,
+            highpass: true,
+            denoise: false,
+            agc: false,
+            frames_per_packet: 1,
+            channel_mode: ChannelMode::Stereo,
+            serial: 0,
+        }
+    }
+}
+
+fn configure_encoder(encoder: &mut DynamicEncoder, options: &EncodeOptions, sample_rate: u32) {
+    encoder.set_complexity(options.complexity);
+    encoder.set_sampling_rate(sample_rate as i32);
+    if let Some(quality) = options.quality {
+        if options.vbr {
+            if let Some(max_bitrate) = options.vbr_max_bitrate {
+                encoder.set_vbr_max_bitrate(max_bitrate);
+            }
+            encoder.set_vbr_quality(quality as f32);
+        } else {
+            encoder.set_quality(quality);
+        }
+    }
+    if let Some(bitrate) = options.bitrate {
+        encoder.set_bitrate(bitrate);
+    }
+    if options.vbr {
+        encoder.set_vbr(true);
+    } else if options.vad {
+        encoder.set_vad(true);
+    }
+    if options.dtx {
+        encoder.set_dtx(true);
+    }
+    if let Some(abr) = options.abr {
+        encoder.set_abr(abr);
+    }
+    if !options.highpass {
+        encoder.set_highpass(false);
+    }
+}
+
+/// Encodes interleaved samples into an Ogg Speex stream
+///
+/// `samples` are scaled to the range of an `i16`. Unless the options specify
+/// a mode, it is chosen from `sample_rate` with `mode_for_sample_rate`.
+pub fn encode_ogg<W: Write>(
+    samples: &[f32],
+    sample_rate: u32,
+    channels: u16,
+    options: &EncodeOptions,
+    writer: W,
+) -> Result<(), WavError> {
+    let comments = SpeexComments::new(VENDOR_STRING);
+    encode_ogg_with_comments(samples, sample_rate, channels, options, &comments, writer)
+}
+
+/// Same as `encode_ogg`, writing the given comments to the stream
+pub fn encode_ogg_with_comments<W: Write>(
+    samples: &[f32],
+    sample_rate: u32,
+    channels: u16,
+    options: &EncodeOptions,
+    comments: &SpeexComments,
+    writer: W,
+) -> Result<(), WavError> {
+    let mode = match options.mode {
+        Some(mode) => mode,
+        None => {
+            mode_for_sample_rate(sample_rate).ok_or(WavError::UnsupportedSampleRate(sample_rate))?
+        }
+    };
+    if channels == 0 {
+        return Err(WavError::UnsupportedChannels(channels));
+    }
+    let stereo = channels == 2 && options.channel_mode == ChannelMode::Stereo;
+    let frames_per_packet = options.frames_per_packet.max(1);
+
+    let mut encoder = DynamicEncoder::new(mode);
+    configure_encoder(&mut encoder, options, sample_rate);
+    let frame_size = encoder.get_frame_size() as usize;
+    let lookahead = encoder.get_lookahead() as i64;
+
+    let mut header = SpeexHeader::new(
+        sample_rate as i32,
+        if stereo { 2 } else { 1 },
+        mode.get_mode(),
+    );
+    header.set_frames_per_packet(frames_per_packet);
+    header.set_vbr(options.vbr);
+    header.set_bitrate(encoder.get_bitrate());
+
+    let mut writer = OggWriter::new(writer, options.serial);
+    let mut headers = SpeexHeaders {
+        header,
+        comments: comments.clone(),
+    };
+    headers.write_ogg(&mut writer)?;
+
+    let channels = channels as usize;
+    let total_samples = (samples.len() / channels) as i64;
+    let input_channels = if stereo { 2 } else { 1 };
+    let mut frame = vec![0f32; frame_size * input_channels];
+    let mut preprocessor = (options.denoise || options.agc).then(|| {
+        let mut preprocessor =
+            dynamic_mapping!(&mut encoder, DynamicEncoder, inner => Preprocessor::for_encoder(inner));
+        preprocessor.set_denoise(options.denoise);
+        preprocessor.set_agc(options.agc.then_some(AGC_LEVEL));
+        preprocessor
+    });
+    // The output of the preprocessor lags its input by a frame, so it's run a
+    // frame ahead of the encoder
+    let mut next = vec![0f32; frame_size];
+    if let Some(preprocessor) = &mut preprocessor {
+        read_frame(samples, channels, 0, false, &mut next);
+        preprocessor.run(&mut next);
+    }
+    let mut bits = SpeexBits::new();
+    let mut frames_encoded = 0;
+    // Position of the end of the last encoded frame in the output, which lags
+    // the input by the encoder's lookahead
+    let mut granule_position = -lookahead;
+
+    loop {
+        let start = frames_encoded * frame_size;
+        if stereo {
+            read_frame(samples, channels, start, true, &mut frame);
+            // Downmixes the frame in place
+            SpeexStereoState::encode(&mut frame, frame_size, &mut bits);
+        }
+        let mono = &mut frame[..frame_size];
+        match &mut preprocessor {
+            Some(preprocessor) => {
+                read_frame(samples, channels, start + frame_size, false, &mut next);
+                preprocessor.run(&mut next);
+                mono.copy_from_slice(&next);
+            }
+            None if !stereo => read_frame(samples, channels, start, false, mono),
+            None => {}
+        }
+        encoder
+            .encode(mono, &mut bits)
+            .expect("frames are cut to the frame size");
+        frames_encoded += 1;
+        granule_position += frame_size as i64;
+
+        let finished = granule_position >= total_samples;
+        if frames_encoded % frames_per_packet as usize != 0 && !finished {
+            continue;
+        }
+        if finished {
+            // Fill the last packet with terminator frames
+            while frames_encoded % frames_per_packet as usize != 0 {
+                bits.pack(15, 5);
+                frames_encoded += 1;
+            }
+        }
+
+        bits.insert_terminator();
+        let mut packet = vec![0u8; bits.num_bytes() as usize];
+        bits.write(&mut packet);
+        bits.reset();
+        writer.write_packet(&packet, granule_position.min(total_samples), finished)?;
+
+        if finished {
+            break;
+        }
+    }
+
+    writer.into_inner()?;
+    Ok(())
+}
+
+/// Reads the samples from `start` on into `frame`, keeping both channels if
+/// `stereo` and downmixing to mono otherwise. Past the end of the input the
+/// frame is filled with silence.
+fn read_frame(samples: &[f32], channels: usize, start: usize, stereo: bool, frame: &mut [f32]) {
+    let frame_channels = if stereo { 2 } else { 1 };
+    for (i, out) in frame.chunks_exact_mut(frame_channels).enumerate() {
+        let index = (start + i) * channels;
+        match samples.get(index..index + channels) {
+            Some(input) if stereo => out.copy_from_slice(input),
+            Some(input) => out[0] = input.iter().sum::<f32>() / channels as f32,
+            None => out.fill(0.0),
+        }
+    }
+}
+
+/// Settings used when decoding Ogg Speex
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct DecodeOptions {
+    /// Whether to use the decoder's perceptual enhancement
+    pub enhancement: bool,
+    /// Decode with a different mode than the stream was encoded with. A lower
+    /// mode only decodes the lower bands, a higher one leaves the upper bands
+    /// empty. The output sampling rate is adjusted to match.
+    pub mode: Option<ModeId>,
+    /// Number of channels to output, taken from the stream when `None`
+    pub channels: Option<u16>,
+    /// Percentage of packets to drop, to simulate packet loss
+    pub packet_loss: u32,
+}
+
+impl Default for DecodeOptions {
+    fn default() -> Self {
+        Self {
+            enhancement: true,
+            mode: None,
+            channels: None,
+            packet_loss: 0,
+        }
+    }
+}
+
+/// Audio decoded from an Ogg Speex stream
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct DecodedAudio {
+    pub sample_rate: u32,
+    pub channels: u16,
+    /// Interleaved samples
+    pub samples: Vec<i16>,
+    /// Comments of the stream, `None` if the comment packet is malformed
+    pub comments: Option<SpeexComments>,
+}
+
+/// Decodes an Ogg Speex stream
+pub fn decode_ogg<R: Read>(reader: R, options: &DecodeOptions) -> Result<DecodedAudio, WavError> {
+    let mut reader = OggReader::new(reader);
+
+    let header_packet = reader
+        .read_packet()?
+        .ok_or(WavError::InvalidSpeexStream("stream is empty"))?;
+    let header = SpeexHeader::from_packet(&header_packet.data).ok_or(
+        WavError::InvalidSpeexStream("first packet is not a Speex header"),
+    )?;
+    // Bad comments shouldn't stop the audio from being decoded, so they
+    // aren't read with SpeexHeaders::read_ogg
+    let comment_packet = reader.read_packet()?.ok_or(WavError::InvalidSpeexStream(
+        "stream ends within the headers",
+    ))?;
+    let comments = SpeexComments::from_packet(&comment_packet.data).ok();
+    for _ in 0..header.get_extra_headers().max(0) {
+        reader.read_packet()?.ok_or(WavError::InvalidSpeexStream(
+            "stream ends within the headers",
+        ))?;
+    }
+
+    let mode = options.mode.unwrap_or(header.get_mode());
+    let mut decoder = DynamicDecoder::new(mode);
+    decoder.set_enhancement(options.enhancement);
+    let frame_size = decoder.get_frame_size() as usize;
+    let lookahead = decoder.get_lookahead() as usize;
+    let channels = match options.channels {
+        Some(channels) => channels.clamp(1, 2) as usize,
+        None => header.get_nb_channels().clamp(1, 2) as usize,
+    };
+    // The header is untrusted, and every lost packet is concealed this many
+    // times, so it's kept to the range the reference tools write
+    let frames_per_packet = header.get_frames_per_packet().clamp(1, 10);
+    // Each mode doubles the sampling rate of the one below it
+    let mode_shift = mode as i32 - header.get_mode() as i32;
+    let sample_rate = if mode_shift >= 0 {
+        header.get_rate() << mode_shift
+    } else {
+        header.get_rate() >> -mode_shift
+    };
+
+    let mut samples = Vec::new();
+    let mut frame = vec![0i16; frame_size * 2];
+    let mut last_granule = None;
+    let mut bits = SpeexBits::new();
+    let mut loss_state = 0x2545_F491u32;
+    while let Some(mut packet) = reader.read_packet()? {
+        if packet.granule_position >= 0 {
+            last_granule = Some(packet.granule_position as usize);
+        }
+
+        loss_state = loss_state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        let lost = (loss_state >> 16) % 100 < options.packet_loss;
+        if !lost {
+            bits.read_from(&mut packet.data);
+        }
+
+        for _ in 0..frames_per_packet {
+            let result = match (lost, channels) {
+                (true, _) => decoder.decode_lost_int(&mut frame),
+                (false, 2) => decoder.decode_stereo_int(&mut bits, &mut frame),
+                (false, _) => decoder.decode_int(&mut bits, &mut frame),
+            };
+            match result {
+                Ok(()) if lost && channels == 2 => {
+                    samples.extend(frame[..frame_size].iter().flat_map(|&s| [s, s]));
+                }
+                Ok(()) => samples.extend_from_slice(&frame[..frame_size * channels]),
+                Err(DecoderError::EndOfStream) => break,
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    // Drop the decoder delay from the start, and the padding of the last
+    // frames from the end
+    let skip = (lookahead * channels).min(samples.len());
+    samples.drain(..skip);
+    if let Some(granule) = last_granule {
+        let header_frame_size = header.get_frame_size().max(1) as usize;
+        // The granule position comes from the file, so a length it can't
+        // describe leaves the samples as they are
+        let len = granule
+            .checked_mul(frame_size)
+            .and_then(|len| (len / header_frame_size).checked_mul(channels));
+        if let Some(len) = len {
+            samples.truncate(len);
+        }
+    }
+
+    Ok(DecodedAudio {
+        sample_rate: sample_rate as u32,
+        channels: channels as u16,
+        samples,
+        comments,
+    })
+}
+
+/// Encodes a WAV file into an Ogg Speex file
+pub fn wav_to_ogg<R: Read, W: Write>(
+    reader: R,
+    writer: W,
+    options: &EncodeOptions,
+) -> Result<(), WavError> {
+    let (spec, samples) = read_wav(reader)?;
+    encode_ogg(&samples, spec.sample_rate, spec.channels, options, writer)
+}
+
+/// Decodes an Ogg Speex file into a 16 bit WAV file
+pub fn ogg_to_wav<R: Read, W: Write>(
+    reader: R,
+    writer: W,
+    options: &DecodeOptions,
+) -> Result<(), WavError> {
+    let decoded = decode_ogg(reader, options)?;
+    write_wav(
+        writer,
+        decoded.channels,
+        decoded.sample_rate,
+        &decoded.samples,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ogg::OggPacket;
+    use crate::support;
+
+    /// A 440Hz tone, quieter on each further channel
+    fn sine(sample_rate: u32, channels: u16, seconds: f32) -> Vec<i16> {
+        let len = (sample_rate as f32 * seconds) as usize;
+        let tones: Vec<Vec<f32>> = (0..channels)
+            .map(|c| support::tone(sample_rate, 440.0, 8000.0 / (c + 1) as f32, len))
+            .collect();
+        support::interleave(&tones.iter().map(Vec::as_slice).collect::<Vec<_>>())
+    }
+
+    fn energy(samples: impl Iterator<Item = i16>) -> f64 {
+        samples.map(|s| (s as f64).powi(2)).sum()
+    }
+
+    #[test]
+    fn wav_round_trip() {
+        let samples = sine(8000, 2, 0.1);
+        let mut file = Vec::new();
+        write_wav(&mut file, 2, 8000, &samples).unwrap();
+
+        let (spec, read) = read_wav(file.as_slice()).unwrap();
+        assert_eq!(
+            spec,
+            WavSpec {
+                channels: 2,
+                sample_rate: 8000,
+                bits_per_sample: 16,
+                sample_format: SampleFormat::Int,
+            }
+        );
+        let read: Vec<i16> = read.iter().map(|&s| s as i16).collect();
+        assert_eq!(read, samples);
+    }
+
+    #[test]
+    fn reads_24_bit_and_float() {
+        let mut file = Vec::new();
+        write_wav(&mut file, 1, 16000, &[]).unwrap();
+        // Patch the header into a 24 bit file with one sample
+        file[32..34].copy_from_slice(&3u16.to_le_bytes());
+        file[34..36].copy_from_slice(&24u16.to_le_bytes());
+        file[40..44].copy_from_slice(&3u32.to_le_bytes());
+        file.extend_from_slice(&[0x00, 0x00, 0x80]);
+        let (_, samples) = read_wav(file.as_slice()).unwrap();
+        assert_eq!(samples, vec![-32768.0]);
+
+        file[20..22].copy_from_slice(&WAVE_FORMAT_IEEE_FLOAT.to_le_bytes());
+        file[34..36].copy_from_slice(&32u16.to_le_bytes());
+        file[40..44].copy_from_slice(&4u32.to_le_bytes());
+        file.truncate(44);
+        file.extend_from_slice(&0.5f32.to_le_bytes());
+        let (spec, samples) = read_wav(file.as_slice()).unwrap();
+        assert_eq!(spec.sample_format, SampleFormat::Float);
+        assert_eq!(samples, vec![16384.0]);
+    }
+
+    #[test]
+    fn rejects_oversized_fmt_chunk() {
+        let mut file = Vec::new();
+        write_wav(&mut file, 1, 8000, &[0; 16]).unwrap();
+        file[16..20].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(read_wav(file.as_slice()).is_err());
+    }
+
+    #[test]
+    fn rejects_sizes_beyond_wav() {
+        assert!(matches!(
+            write_wav(Vec::new(), u16::MAX, 8000, &[]),
+            Err(WavError::UnsupportedChannels(u16::MAX))
+        ));
+        assert!(matches!(
+            write_wav(Vec::new(), 2, u32::MAX, &[]),
+            Err(WavError::TooLarge(_))
+        ));
+        assert!(matches!(
+            wav_header(1, 8000, u32::MAX as usize / 2 - 17),
+            Err(WavError::TooLarge(_))
+        ));
+        assert_eq!(
+            wav_header(1, 8000, u32::MAX as usize / 2 - 18).unwrap()[4..8],
+            (u32::MAX - 1).to_le_bytes()
+        );
+    }
+
+    #[test]
+    fn clamps_frames_per_packet() {
+        let floats: Vec<f32> = sine(8000, 1, 0.1).iter().map(|&s| s as f32).collect();
+        let mut ogg = Vec::new();
+        encode_ogg(&floats, 8000, 1, &EncodeOptions::default(), &mut ogg).unwrap();
+        let expected = decode_ogg(ogg.as_slice(), &DecodeOptions::default()).unwrap();
+
+        // Rewrite the header to claim far more frames per packet than there are
+        let packets: Vec<OggPacket> = OggReader::new(ogg.as_slice()).map(Result::unwrap).collect();
+        let mut header = SpeexHeader::from_packet(&packets[0].data).unwrap();
+        header.set_frames_per_packet(i32::MAX);
+        let mut writer = OggWriter::new(Vec::new(), 1);
+        writer
+            .write_packet(&header.make_packet(), 0, false)
+            .unwrap();
+        for (i, packet) in packets.iter().enumerate().skip(1) {
+            let last = i + 1 == packets.len();
+            writer
+                .write_packet(&packet.data, packet.granule_position, last)
+                .unwrap();
+        }
+        let ogg = writer.into_inner().unwrap();
+
+        // Every packet is lost and concealed as 10 frames, which the granule
+        // position then cuts back to the length of the audio
+        let options = DecodeOptions {
+            packet_loss: 100,
+            ..DecodeOptions::default()
+        };
+        let decoded = decode_ogg(ogg.as_slice(), &options).unwrap();
+        assert_eq!(decoded.samples.len(), expected.samples.len());
+    }
+
+    #[test]
+    fn ignores_overflowing_granule_position() {
+        let floats: Vec<f32> = sine(8000, 1, 0.1).iter().map(|&s| s as f32).collect();
+        let mut ogg = Vec::new();
+        encode_ogg(&floats, 8000, 1, &EncodeOptions::default(), &mut ogg).unwrap();
+        let expected = decode_ogg(ogg.as_slice(), &DecodeOptions::default()).unwrap();
+
+        // Rewrite the stream with the last granule position as large as it goes
+        let packets: Vec<OggPacket> = OggReader::new(ogg.as_slice()).map(Result::unwrap).collect();
+        let mut writer = OggWriter::new(Vec::new(), 1);
+        for (i, packet) in packets.iter().enumerate() {
+            let last = i + 1 == packets.len();
+            let granule = if last {
+                i64::MAX
+            } else {
+                packet.granule_position
+            };
+            writer.write_packet(&packet.data, granule, last).unwrap();
+        }
+        let ogg = writer.into_inner().unwrap();
+
+        let decoded = decode_ogg(ogg.as_slice(), &DecodeOptions::default()).unwrap();
+        assert!(decoded.samples.len() >= expected.samples.len());
+    }
+
+    #[test]
+    fn rejects_unsupported_rate() {
+        let samples = vec![0.0; 100];
+        let result = encode_ogg(&samples, 96000, 1, &EncodeOptions::default(), Vec::new());
+        assert!(matches!(
+            result,
+            Err(WavError::UnsupportedSampleRate(96000))
+        ));
+    }
+
+    #[test]
+    fn picks_mode_from_rate() {
+        assert_eq!(mode_for_sample_rate(8000), Some(ModeId::NarrowBand));
+        assert_eq!(mode_for_sample_rate(16000), Some(ModeId::WideBand));
+        assert_eq!(mode_for_sample_rate(32000), Some(ModeId::UltraWideBand));
+        assert_eq!(mode_for_sample_rate(4000), None);
+    }
+
+    #[test]
+    fn mono_round_trip_per_mode() {
+        for rate in [8000, 16000, 32000] {
+            let samples = sine(rate, 1, 0.5);
+            let mut wav = Vec::new();
+            write_wav(&mut wav, 1, rate, &samples).unwrap();
+
+            let mut ogg = Vec::new();
+            let options = EncodeOptions {
+                frames_per_packet: 3,
+                ..Default::default()
+            };
+            wav_to_ogg(wav.as_slice(), &mut ogg, &options).unwrap();
+
+            let decoded = decode_ogg(ogg.as_slice(), &DecodeOptions::default()).unwrap();
+            assert_eq!(decoded.sample_rate, rate);
+            assert_eq!(decoded.channels, 1);
+            assert_eq!(decoded.samples.len(), samples.len());
+            let ratio = energy(decoded.samples.into_iter()) / energy(samples.into_iter());
+            assert!(
+                ratio > 0.5 && ratio < 2.0,
+                "energy ratio {ratio} at {rate} Hz"
+            );
+        }
+    }
+
+    #[test]
+    fn writes_comments() {
+        let floats: Vec<f32> = sine(8000, 1, 0.1).iter().map(|&s| s as f32).collect();
+        let mut comments = SpeexComments::new(VENDOR_STRING);
+        comments.add("TITLE", "sine").unwrap();
+
+        let mut ogg = Vec::new();
+        let options = EncodeOptions::default();
+        encode_ogg_with_comments(&floats, 8000, 1, &options, &comments, &mut ogg).unwrap();
+        let decoded = decode_ogg(ogg.as_slice(), &DecodeOptions::default()).unwrap();
+        assert_eq!(decoded.comments, Some(comments));
+    }
+
+    #[test]
+    fn stereo_round_trip() {
+        let samples = sine(16000, 2, 0.5);
+        let floats: Vec<f32> = samples.iter().map(|&s| s as f32).collect();
+
+        let mut ogg = Vec::new();
+        encode_ogg(&floats, 16000, 2, &EncodeOptions::default(), &mut ogg).unwrap();
+        let decoded = decode_ogg(ogg.as_slice(), &DecodeOptions::default()).unwrap();
+
+        assert_eq!(decoded.channels, 2);
+        assert_eq!(decoded.samples.len(), samples.len());
+        // The first channel is louder, which should survive the balance coding
+        let left = energy(decoded.samples.iter().step_by(2).copied());
+        let right = energy(decoded.samples.iter().skip(1).step_by(2).copied());
+        assert!(left > right * 2.0, "left {left}, right {right}");
+    }
+
+    #[test]
+    fn decodes_lower_band_only() {
+        let samples: Vec<f32> = sine(16000, 1, 0.5).iter().map(|&s| s as f32).collect();
+        let mut ogg = Vec::new();
+        encode_ogg(&samples, 16000, 1, &EncodeOptions::default(), &mut ogg).unwrap();
+
+        let options = DecodeOptions {
+            mode: Some(ModeId::NarrowBand),
+            packet_loss: 20,
+            ..Default::default()
+        };
+        let decoded = decode_ogg(ogg.as_slice(), &options).unwrap();
+        assert_eq!(decoded.sample_rate, 8000);
+        assert_eq!(decoded.samples.len(), samples.len() / 2);
+    }
+
+    #[test]
+    fn preprocesses_before_encoding() {
+        // Quiet speech, for gain control to bring up
+        let speech: Vec<f32> = support::speech_like(8000, 16000)
+            .iter()
+            .flat_map(|x| [x / 20.0, x / 40.0])
+            .collect();
+        let options = EncodeOptions {
+            agc: true,
+            ..Default::default()
+        };
+        let mut ogg = Vec::new();
+        encode_ogg(&speech, 8000, 2, &options, &mut ogg).unwrap();
+        let decoded = decode_ogg(ogg.as_slice(), &DecodeOptions::default()).unwrap();
+        assert_eq!(decoded.channels, 2);
+        assert_eq!(decoded.samples.len(), speech.len());
+        let end = speech.len() / 2..;
+        let gain = energy(decoded.samples[end.clone()].iter().copied())
+            / energy(speech[end].iter().map(|&s| s as i16));
+        assert!(gain > 4.0, "gain control raised the energy by {gain}");
+
+        let noise: Vec<f32> = support::noise(16000).iter().map(|n| n / 16.0).collect();
+        let options = EncodeOptions {
+            denoise: true,
+            ..Default::default()
+        };
+        let mut ogg = Vec::new();
+        encode_ogg(&noise, 8000, 1, &options, &mut ogg).unwrap();
+        let decoded = decode_ogg(ogg.as_slice(), &DecodeOptions::default()).unwrap();
+        assert_eq!(decoded.samples.len(), noise.len());
+        let end = noise.len() / 2..;
+        let attenuation = energy(decoded.samples[end.clone()].iter().copied())
+            / energy(noise[end].iter().map(|&n| n as i16));
+        assert!(attenuation < 0.5, "noise kept {attenuation} of its energy");
+    }
+
+    #[test]
+    fn downmixes_stereo() {
+        let samples: Vec<f32> = sine(8000, 2, 0.2).iter().map(|&s| s as f32).collect();
+        let options = EncodeOptions {
+            channel_mode: ChannelMode::Downmix,
+            quality: Some(4),
+            vbr: true,
+            ..Default::default()
+        };
+
+        let mut ogg = Vec::new();
+        encode_ogg(&samples, 8000, 2, &options, &mut ogg).unwrap();
+        let mut wav = Vec::new();
+        ogg_to_wav(ogg.as_slice(), &mut wav, &DecodeOptions::default()).unwrap();
+
+        let (spec, decoded) = read_wav(wav.as_slice()).unwrap();
+        assert_eq!(spec.channels, 1);
+        assert_eq!(decoded.len(), samples.len() / 2);
+    }
+}
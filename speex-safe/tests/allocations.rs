@@ -0,0 +1,185 @@
+////////////////////////////////////////////////////////////////////////////////
+// Copyright (c) 2023.                                                         /
+// This Source Code Form is subject to the terms of the Mozilla Public License,/
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can    /
+// obtain one at http://mozilla.org/MPL/2.0/.                                  /
+////////////////////////////////////////////////////////////////////////////////
+
+//! Counts the allocations made through the global allocator, which libspeex
+//! allocates from unless an arena is in use
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+use speex_safe::{
+    Arena,
+    ControlFunctions,
+    DynamicDecoder,
+    DynamicEncoder,
+    ModeId,
+    NbMode,
+    SpeexBits,
+    SpeexDecoder,
+    SpeexEncoder,
+};
+
+mod support;
+
+struct Counting;
+
+thread_local! {
+    // Per thread, as the tests run in parallel
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.set(ALLOCATIONS.get() + 1);
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.set(ALLOCATIONS.get() + 1);
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.set(ALLOCATIONS.get() + 1);
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+/// Number of allocations `f` makes on this thread
+fn count_allocations<R>(f: impl FnOnce() -> R) -> (R, usize) {
+    let before = ALLOCATIONS.get();
+    let result = f();
+    (result, ALLOCATIONS.get() - before)
+}
+
+/// Encodes and decodes `frames` frames, returning the allocations made per
+/// frame
+fn code_frames(
+    encoder: &mut DynamicEncoder,
+    decoder: &mut DynamicDecoder,
+    frames: usize,
+) -> Vec<usize> {
+    let frame_size = encoder.get_frame_size() as usize;
+    let mut enc_bits = SpeexBits::new();
+    let mut dec_bits = SpeexBits::new();
+    let mut packet = [0u8; 512];
+    let mut out = vec![0.0; frame_size];
+    let inputs = support::tone(8000, 440.0, 8000.0, frames * frame_size)
+        .chunks(frame_size)
+        .map(<[f32]>::to_vec)
+        .collect::<Vec<_>>();
+
+    inputs
+        .into_iter()
+        .map(|mut input| {
+            let ((), count) = count_allocations(|| {
+                enc_bits.reset();
+                encoder.encode(&mut input, &mut enc_bits).unwrap();
+                let len = enc_bits.write(&mut packet) as usize;
+                dec_bits.read_from(&mut packet[..len]);
+                decoder.decode(&mut dec_bits, &mut out).unwrap();
+            });
+            count
+        })
+        .collect()
+}
+
+#[test]
+fn libspeex_allocates_from_global_allocator() {
+    for mode in [ModeId::NarrowBand, ModeId::WideBand, ModeId::UltraWideBand] {
+        let (encoder, count) = count_allocations(|| DynamicEncoder::new(mode));
+        assert!(count > 0, "{mode:?} encoder state wasn't allocated in Rust");
+        drop(encoder);
+    }
+}
+
+#[test]
+fn steady_state_coding_is_allocation_free() {
+    for mode in [ModeId::NarrowBand, ModeId::WideBand, ModeId::UltraWideBand] {
+        let mut encoder = DynamicEncoder::new(mode);
+        let mut decoder = DynamicDecoder::new(mode);
+        let counts = code_frames(&mut encoder, &mut decoder, 50);
+        assert_eq!(counts, vec![0; 50], "{mode:?}");
+    }
+}
+
+#[test]
+fn arena_coders_never_use_global_allocator() {
+    for mode in [ModeId::NarrowBand, ModeId::WideBand, ModeId::UltraWideBand] {
+        let encoder_arena = Arena::new(1 << 20);
+        let decoder_arena = Arena::new(1 << 20);
+        let ((mut encoder, mut decoder), count) = count_allocations(|| {
+            (
+                DynamicEncoder::new_in(mode, encoder_arena).unwrap(),
+                DynamicDecoder::new_in(mode, decoder_arena).unwrap(),
+            )
+        });
+        assert_eq!(count, 0, "{mode:?}");
+
+        let counts = code_frames(&mut encoder, &mut decoder, 50);
+        assert_eq!(counts, vec![0; 50], "{mode:?}");
+    }
+}
+
+#[test]
+fn arena_too_small() {
+    let err = DynamicEncoder::new_in(ModeId::WideBand, Arena::new(1024))
+        .err()
+        .unwrap();
+    assert!(err.message.contains("exhausted"), "{err}");
+}
+
+/// A packet of stereo frames, coded outside of any arena
+fn stereo_packet(frames: usize) -> Vec<u8> {
+    let mut encoder = SpeexEncoder::<NbMode>::new();
+    let frame_size = encoder.get_frame_size() as usize;
+    let mut bits = SpeexBits::new();
+    for left in support::tone(8000, 440.0, 8000.0, frames * frame_size).chunks(frame_size) {
+        let mut frame: Vec<f32> = left.iter().flat_map(|&x| [x, x / 2.0]).collect();
+        encoder.encode_stereo(&mut frame, &mut bits).unwrap();
+    }
+    let mut packet = vec![0; 2000];
+    let len = bits.write(&mut packet) as usize;
+    packet.truncate(len);
+    packet
+}
+
+#[test]
+fn arena_decoder_drops_after_decoding_stereo() {
+    let mut decoder = SpeexDecoder::<NbMode>::new_in(Arena::new(1 << 20)).unwrap();
+    let mut bits = SpeexBits::new();
+    bits.read_from(&mut stereo_packet(5));
+    let mut out = vec![0.0; 2 * decoder.get_frame_size() as usize];
+    for _ in 0..5 {
+        decoder.decode_stereo(&mut bits, &mut out).unwrap();
+    }
+    // The stereo state is destroyed before the arena it lives in is freed
+    drop(decoder);
+}
+
+#[test]
+fn arena_holds_stereo_state_from_the_start() {
+    let decoder = SpeexDecoder::<NbMode>::new_in(Arena::new(1 << 20)).unwrap();
+    let used = decoder.get_arena().unwrap().used();
+    drop(decoder);
+    assert!(SpeexDecoder::<NbMode>::new_in(Arena::new(used - 1)).is_err());
+
+    // Decoding stereo in an arena only as large as that takes nothing more
+    let mut decoder = SpeexDecoder::<NbMode>::new_in(Arena::new(used)).unwrap();
+    let mut bits = SpeexBits::new();
+    bits.read_from(&mut stereo_packet(2));
+    let mut out = vec![0; 2 * decoder.get_frame_size() as usize];
+    decoder.decode_stereo_int(&mut bits, &mut out).unwrap();
+    assert_eq!(decoder.get_arena().unwrap().used(), used);
+}
@@ -0,0 +1,199 @@
+////////////////////////////////////////////////////////////////////////////////
+// Copyright (c) 2023.                                                         /
+// This Source Code Form is subject to the terms of the Mozilla Public License,/
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can    /
+// obtain one at http://mozilla.org/MPL/2.0/.                                  /
+////////////////////////////////////////////////////////////////////////////////
+
+//! Golden bitstreams, coded with the settings of the `testenc` programs that
+//! ship with libspeex
+//!
+//! Like `testenc.c`, `testenc_wb.c` and `testenc_uwb.c`, each case encodes a
+//! frame with `encode_int`, rewinds the bits and decodes them straight back
+//! with `decode_int`. The packets and the decoded samples are hashed and
+//! checked against `tests/golden`, so anything that changes the bitstream or
+//! the decoder output, like a compiler flag, the math library or a change to
+//! the vendored sources, fails here. The hashes were taken on x86_64.
+//!
+//! After an intended change, write the new hashes with
+//!
+//! ```sh
+//! SPEEX_BLESS=1 cargo test -p speex-safe --test conformance
+//! ```
+
+use std::fs;
+use std::path::Path;
+
+use speex_safe::{DynamicDecoder, DynamicEncoder, ModeId, SpeexBits};
+
+mod support;
+
+/// The Vorbis psychoacoustic model changes the narrowband bitstream, and so
+/// the others that embed it, so it has hashes of its own
+const GOLDEN: &str = if cfg!(feature = "vorbis-psy") {
+    "conformance-vorbis-psy.txt"
+} else {
+    "conformance.txt"
+};
+#[cfg(not(feature = "vorbis-psy"))]
+const EXPECTED: &str = include_str!("golden/conformance.txt");
+#[cfg(feature = "vorbis-psy")]
+const EXPECTED: &str = include_str!("golden/conformance-vorbis-psy.txt");
+
+const SECONDS: usize = 2;
+const QUALITIES: [i32; 6] = [0, 2, 4, 6, 8, 10];
+
+/// Settings of one of the `testenc` programs
+struct Program {
+    name: &'static str,
+    mode: ModeId,
+    complexity: i32,
+    enhancement: bool,
+    /// Whether the highpass filters are set explicitly, rather than left at
+    /// their default
+    highpass: bool,
+}
+
+const PROGRAMS: [Program; 3] = [
+    Program {
+        name: "nb",
+        mode: ModeId::NarrowBand,
+        complexity: 1,
+        enhancement: true,
+        highpass: true,
+    },
+    Program {
+        name: "wb",
+        mode: ModeId::WideBand,
+        complexity: 3,
+        enhancement: true,
+        highpass: false,
+    },
+    Program {
+        name: "uwb",
+        mode: ModeId::UltraWideBand,
+        complexity: 1,
+        enhancement: false,
+        highpass: false,
+    },
+];
+
+/// 64 bit FNV-1a
+struct Hash(u64);
+
+impl Hash {
+    fn new() -> Self {
+        Hash(0xCBF29CE484222325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ byte as u64).wrapping_mul(0x100000001B3);
+        }
+    }
+}
+
+/// Speech, then quiet noise for the last quarter
+fn signal(rate: usize) -> Vec<i16> {
+    let len = SECONDS * rate;
+    let mut signal = support::speech_like(rate, len);
+    for (sample, noise) in signal.iter_mut().zip(support::noise(len)).skip(len * 3 / 4) {
+        *sample = noise / 64.0;
+    }
+    signal.iter().map(|&sample| sample as i16).collect()
+}
+
+/// Codes `signal` the way `program` does, returning the number of bits written
+/// and the hashes of the packets and of the decoded samples
+fn code(program: &Program, quality: i32, vbr: bool, signal: &[i16]) -> (usize, u64, u64) {
+    let mut encoder = DynamicEncoder::new(program.mode);
+    let mut decoder = DynamicDecoder::new(program.mode);
+    decoder.set_enhancement(program.enhancement);
+    encoder.set_vbr(vbr);
+    if vbr {
+        encoder.set_vbr_quality(quality as f32);
+    } else {
+        encoder.set_quality(quality);
+    }
+    encoder.set_complexity(program.complexity);
+    if program.highpass {
+        encoder.set_highpass(true);
+        decoder.set_highpass(true);
+    }
+
+    let frame_size = encoder.get_frame_size() as usize;
+    let mut bits = SpeexBits::new();
+    let mut packet = [0u8; 200];
+    let mut out = vec![0i16; frame_size];
+    let mut bit_count = 0;
+    let (mut packets, mut decoded) = (Hash::new(), Hash::new());
+    for frame in signal.chunks_exact(frame_size) {
+        bits.reset();
+        encoder.encode_int(&mut frame.to_vec(), &mut bits).unwrap();
+        let len = bits.write(&mut packet) as usize;
+        bit_count += len * 8;
+        packets.write(&(len as u16).to_le_bytes());
+        packets.write(&packet[..len]);
+
+        bits.rewind();
+        decoder.decode_int(&mut bits, &mut out).unwrap();
+        for sample in &out {
+            decoded.write(&sample.to_le_bytes());
+        }
+    }
+    (bit_count, packets.0, decoded.0)
+}
+
+/// One line per case, as in the golden files
+fn cases() -> String {
+    let mut lines = String::new();
+    for program in &PROGRAMS {
+        let signal = signal(program.mode.get_sampling_rate() as usize);
+        for vbr in [false, true] {
+            for quality in QUALITIES {
+                let (bit_count, packets, decoded) = code(program, quality, vbr, &signal);
+                let rate_control = if vbr { "vbr" } else { "cbr" };
+                lines += &format!(
+                    "{} {rate_control} {quality:>2} {bit_count:>7} {packets:016x} {decoded:016x}\n",
+                    program.name
+                );
+            }
+        }
+    }
+    lines
+}
+
+#[test]
+fn matches_golden() {
+    let actual = cases();
+    if std::env::var_os("SPEEX_BLESS").is_some() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/golden")
+            .join(GOLDEN);
+        fs::write(path, &actual).unwrap();
+        return;
+    }
+
+    let mismatched: Vec<_> = EXPECTED
+        .lines()
+        .zip(actual.lines())
+        .filter(|(expected, actual)| expected != actual)
+        .map(|(expected, actual)| format!("expected {expected}\n   found {actual}"))
+        .collect();
+    assert_eq!(EXPECTED.lines().count(), actual.lines().count());
+    assert!(
+        mismatched.is_empty(),
+        "bitstream differs from tests/golden/{GOLDEN}:\n{}",
+        mismatched.join("\n")
+    );
+}
+
+#[test]
+fn coding_is_deterministic() {
+    let program = &PROGRAMS[1];
+    let signal = signal(program.mode.get_sampling_rate() as usize);
+    assert_eq!(
+        code(program, 8, true, &signal),
+        code(program, 8, true, &signal)
+    );
+}
@@ -0,0 +1,113 @@
+////////////////////////////////////////////////////////////////////////////////
+// Copyright (c) 2023.                                                         /
+// This Source Code Form is subject to the terms of the Mozilla Public License,/
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can    /
+// obtain one at http://mozilla.org/MPL/2.0/.                                  /
+////////////////////////////////////////////////////////////////////////////////
+
+//! Inputs the fuzz targets crashed on, and their seed corpora, run through the
+//! same harnesses
+
+use std::fs;
+use std::path::Path;
+
+use speex_safe::{
+    ControlError,
+    DecoderError,
+    DynamicDecoder,
+    ModeId,
+    NbMode,
+    SpeexBits,
+    SpeexDecoder,
+    WbMode,
+};
+
+#[path = "../fuzz/src/lib.rs"]
+mod harness;
+
+/// A narrowband frame of submode 0, which is a silent frame
+const NB_SILENT_FRAME: [u8; 4] = [0x00, 0x00, 0x00, 0x00];
+/// A silent narrowband frame followed by a wideband layer of submode 0
+const WB_SILENT_FRAME: [u8; 4] = [0x04, 0x00, 0x00, 0x00];
+
+#[test]
+fn nb_submode_of_silent_frame() {
+    harness::decode_nb(&NB_SILENT_FRAME);
+
+    let mut decoder = SpeexDecoder::<NbMode>::new();
+    let mut bits = SpeexBits::new();
+    bits.read_from(&mut NB_SILENT_FRAME.to_vec());
+    decoder.decode_to_owned(&mut bits).unwrap();
+    assert_eq!(decoder.get_submode(), None);
+}
+
+#[test]
+fn wb_submodes_of_silent_frame() {
+    harness::decode_wb(&WB_SILENT_FRAME);
+
+    let mut decoder = SpeexDecoder::<WbMode>::new();
+    let mut bits = SpeexBits::new();
+    bits.read_from(&mut WB_SILENT_FRAME.to_vec());
+    decoder.decode_to_owned(&mut bits).unwrap();
+    assert_eq!(decoder.get_low_submode(), None);
+    assert!(matches!(
+        decoder.get_high_submode(),
+        Err(ControlError::UnknownRequest(_))
+    ));
+}
+
+#[test]
+fn silent_frames_in_other_targets() {
+    harness::decode_uwb(&WB_SILENT_FRAME);
+    for mode in 0..3 {
+        harness::inband(&[&[mode][..], &NB_SILENT_FRAME].concat());
+    }
+}
+
+#[test]
+fn unknown_return_is_an_error() {
+    let err = DecoderError::Unknown(-3);
+    assert_eq!(
+        err.to_string(),
+        "Unexpected return value from speex_decode (-3)"
+    );
+}
+
+#[test]
+fn seed_corpora() {
+    let seeds = Path::new(env!("CARGO_MANIFEST_DIR")).join("fuzz/seeds");
+    let targets = [
+        ("decode_nb", harness::decode_nb as fn(&[u8])),
+        ("decode_wb", harness::decode_wb),
+        ("decode_uwb", harness::decode_uwb),
+        ("header", harness::header),
+        ("inband", harness::inband),
+    ];
+    for (target, run) in targets {
+        let mut count = 0;
+        for entry in fs::read_dir(seeds.join(target)).unwrap() {
+            let seed = fs::read(entry.unwrap().path()).unwrap();
+            run(&seed);
+            count += 1;
+        }
+        assert!(count > 0, "no seeds for {target}");
+    }
+}
+
+#[test]
+fn decode_seeds_are_valid() {
+    let seeds = Path::new(env!("CARGO_MANIFEST_DIR")).join("fuzz/seeds");
+    for (target, mode) in [
+        ("decode_nb", ModeId::NarrowBand),
+        ("decode_wb", ModeId::WideBand),
+        ("decode_uwb", ModeId::UltraWideBand),
+    ] {
+        for entry in fs::read_dir(seeds.join(target)).unwrap() {
+            let mut seed = fs::read(entry.unwrap().path()).unwrap();
+            let mut decoder = DynamicDecoder::new(mode);
+            let mut bits = SpeexBits::new();
+            bits.read_from(&mut seed);
+            assert!(decoder.decode_to_owned(&mut bits).is_ok(), "{target}");
+        }
+    }
+}
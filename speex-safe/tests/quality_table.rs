@@ -0,0 +1,128 @@
+////////////////////////////////////////////////////////////////////////////////
+// Copyright (c) 2023.                                                         /
+// This Source Code Form is subject to the terms of the Mozilla Public License,/
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can    /
+// obtain one at http://mozilla.org/MPL/2.0/.                                  /
+////////////////////////////////////////////////////////////////////////////////
+
+//! Quality against bitrate for every submode of narrowband, and every pair of
+//! submodes of wideband
+//!
+//! ```sh
+//! cargo test -p speex-safe --release --test quality_table -- --nocapture
+//! ```
+//!
+//! prints the tables.
+
+use speex_safe::metrics::{get_coding_delay, Metrics};
+use speex_safe::{
+    CoderMode,
+    ControlFunctions,
+    NbMode,
+    NbSubmodeId,
+    SpeexBits,
+    SpeexDecoder,
+    SpeexEncoder,
+    WbMode,
+    WbSubmodeId,
+};
+
+mod support;
+
+const SECONDS: usize = 3;
+
+/// Codes `signal` and scores it, returning the bitrate it was coded at
+fn measure<T: CoderMode>(
+    mut encoder: SpeexEncoder<T>,
+    mut decoder: SpeexDecoder<T>,
+    signal: &[f32],
+) -> (usize, Metrics) {
+    let frame_size = encoder.get_frame_size() as usize;
+    let rate = encoder.get_sampling_rate() as u32;
+    let delay = get_coding_delay(&mut encoder, &mut decoder);
+
+    let mut bits = SpeexBits::new();
+    let mut packet = [0u8; 200];
+    let mut out = vec![0.0f32; frame_size];
+    let mut bytes = 0;
+    let mut decoded = Vec::with_capacity(signal.len());
+    for frame in signal.chunks_exact(frame_size) {
+        bits.reset();
+        encoder.encode(&mut frame.to_vec(), &mut bits).unwrap();
+        let len = bits.write(&mut packet) as usize;
+        bytes += len;
+        bits.read_from(&mut packet[..len]);
+        decoder.decode(&mut bits, &mut out).unwrap();
+        decoded.extend_from_slice(&out);
+    }
+    let bitrate = bytes * 8 * rate as usize / decoded.len();
+    (bitrate, Metrics::measure(signal, &decoded, rate, delay))
+}
+
+fn print_row(submode: &str, bitrate: usize, metrics: &Metrics) {
+    println!(
+        "{submode:<32} {bitrate:>6} {:>8.2} {:>8.2} {:>6.2}",
+        metrics.segmental_snr, metrics.log_spectral_distance, metrics.perceptual
+    );
+}
+
+fn print_header(mode: &str) {
+    println!(
+        "{mode:<32} {:>6} {:>8} {:>8} {:>6}",
+        "bps", "segSNR", "LSD", "MOS"
+    );
+}
+
+#[test]
+fn narrowband_submodes() {
+    let signal = support::speech_like(8000, SECONDS * 8000);
+    print_header("narrowband");
+    let rows = NbSubmodeId::ALL.map(|submode| {
+        let mut encoder = SpeexEncoder::<NbMode>::new();
+        encoder.set_submode(submode);
+        let (bitrate, metrics) = measure(encoder, SpeexDecoder::<NbMode>::new(), &signal);
+        print_row(&format!("{submode:?}"), bitrate, &metrics);
+        (bitrate, metrics)
+    });
+
+    for pair in rows.windows(2) {
+        assert!(pair[0].0 < pair[1].0, "bitrates out of order: {pair:?}");
+    }
+    // Past the vocoder, every step up in bitrate codes the waveform closer
+    for pair in rows[1..].windows(2) {
+        assert!(
+            pair[0].1.segmental_snr < pair[1].1.segmental_snr,
+            "no better for the bitrate: {pair:?}"
+        );
+    }
+    let (lowest, highest) = (&rows[1].1, &rows[7].1);
+    assert!(lowest.log_spectral_distance > highest.log_spectral_distance);
+    assert!(lowest.perceptual < highest.perceptual);
+}
+
+#[test]
+fn wideband_submodes() {
+    let signal = support::speech_like(16000, SECONDS * 16000);
+    print_header("wideband");
+    let rows = NbSubmodeId::ALL.map(|low| {
+        WbSubmodeId::ALL.map(|high| {
+            let mut encoder = SpeexEncoder::<WbMode>::new();
+            encoder.set_low_submode(low);
+            encoder.set_high_submode(high);
+            let (bitrate, metrics) = measure(encoder, SpeexDecoder::<WbMode>::new(), &signal);
+            print_row(&format!("{low:?} + {high:?}"), bitrate, &metrics);
+            (bitrate, metrics)
+        })
+    });
+
+    for row in &rows {
+        for pair in row.windows(2) {
+            assert!(pair[0].0 < pair[1].0, "bitrates out of order: {pair:?}");
+        }
+    }
+    // The high band adds detail to the spectrum on top of any low band
+    for row in &rows[1..] {
+        assert!(row[0].1.log_spectral_distance > row[3].1.log_spectral_distance);
+    }
+    assert!(rows[1][0].1.perceptual < rows[7][3].1.perceptual);
+}
@@ -0,0 +1,133 @@
+////////////////////////////////////////////////////////////////////////////////
+// Copyright (c) 2023.                                                         /
+// This Source Code Form is subject to the terms of the Mozilla Public License,/
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can    /
+// obtain one at http://mozilla.org/MPL/2.0/.                                  /
+////////////////////////////////////////////////////////////////////////////////
+
+//! Test signals and helpers shared by the tests and the examples of
+//! speex-safe, and by the crates testing against it
+//!
+//! [`noise`] and [`speech_like`] only use integer arithmetic and float
+//! additions and multiplications, so they're the same on every platform.
+
+// Not every test uses every helper
+#![allow(dead_code)]
+
+// speex-rs includes these in its tests too, where the crate may be no_std
+use std::vec::Vec;
+
+use speex_safe::{DynamicEncoder, SpeexBits};
+
+/// White noise from a linear congruential generator started at `seed`, in
+/// [-16384, 16384)
+pub fn seeded_noise(seed: u32, len: usize) -> Vec<f32> {
+    let mut seed = seed;
+    (0..len)
+        .map(|_| {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            ((seed >> 16) & 0x7FFF) as f32 - 16384.0
+        })
+        .collect()
+}
+
+/// White noise from a linear congruential generator, in [-16384, 16384)
+pub fn noise(len: usize) -> Vec<f32> {
+    seeded_noise(4242, len)
+}
+
+/// Uniform white noise with an RMS of `rms`
+pub fn white_noise(seed: u32, rms: f32, len: usize) -> Vec<f32> {
+    let gain = rms * 3f32.sqrt() / 16384.0;
+    seeded_noise(seed, len)
+        .into_iter()
+        .map(|x| x * gain)
+        .collect()
+}
+
+/// A sine of `frequency` Hz with a peak of `amplitude`
+pub fn tone(rate: u32, frequency: f64, amplitude: f32, len: usize) -> Vec<f32> {
+    (0..len)
+        .map(|n| {
+            let phase = 2.0 * std::f64::consts::PI * frequency * n as f64 / rate as f64;
+            amplitude * phase.sin() as f32
+        })
+        .collect()
+}
+
+/// Interleaves `channels` into 16 bit samples, saturating out of range ones
+pub fn interleave(channels: &[&[f32]]) -> Vec<i16> {
+    let len = channels.iter().map(|c| c.len()).min().unwrap_or(0);
+    (0..len)
+        .flat_map(|n| channels.iter().map(move |c| c[n] as i16))
+        .collect()
+}
+
+/// Pitch pulses and noise through three fixed formants, in the range of an
+/// `i16`
+///
+/// Over `len` samples the pitch glides from 100Hz up to 180Hz and back.
+pub fn speech_like(rate: usize, len: usize) -> Vec<f32> {
+    let mut formants = [
+        (1.620f32, -0.903f32, 0.050f32),
+        (1.120, -0.947, 0.027),
+        (-0.853, -0.882, 0.061),
+    ]
+    .map(|(a1, a2, gain)| (a1, a2, gain, [0.0f32; 2]));
+    let mut next_pulse = 0;
+    noise(len)
+        .into_iter()
+        .enumerate()
+        .map(|(n, noise)| {
+            let pulse = if n == next_pulse {
+                let step = n * 160 / len;
+                next_pulse += rate / (100 + step.min(160 - step));
+                30000.0
+            } else {
+                0.0
+            };
+            let excitation = pulse + noise / 16.0;
+            let y: f32 = formants
+                .iter_mut()
+                .map(|(a1, a2, gain, mem)| {
+                    let y = *gain * excitation + *a1 * mem[0] + *a2 * mem[1];
+                    *mem = [y, mem[0]];
+                    y
+                })
+                .sum();
+            y.clamp(-32768.0, 32767.0)
+        })
+        .collect()
+}
+
+/// Encodes `signal` with `encoder`, one packet per frame
+pub fn encode_packets(encoder: &mut DynamicEncoder, signal: &[f32]) -> Vec<Vec<u8>> {
+    let frame_size = encoder.get_frame_size() as usize;
+    let mut bits = SpeexBits::new();
+    signal
+        .chunks_exact(frame_size)
+        .map(|frame| {
+            bits.reset();
+            encoder.encode(&mut frame.to_vec(), &mut bits).unwrap();
+            let mut packet = vec![0; bits.num_bytes() as usize];
+            let len = bits.write(&mut packet) as usize;
+            packet.truncate(len);
+            packet
+        })
+        .collect()
+}
+
+/// Signal to noise ratio of `decoded` against `reference`, in dB
+pub fn snr(reference: &[f32], decoded: &[f32]) -> f64 {
+    let signal: f64 = reference.iter().map(|&x| (x as f64).powi(2)).sum();
+    let noise: f64 = reference
+        .iter()
+        .zip(decoded)
+        .map(|(&x, &y)| (x as f64 - y as f64).powi(2))
+        .sum();
+    if noise == 0.0 {
+        f64::INFINITY
+    } else {
+        10.0 * (signal / noise).log10()
+    }
+}
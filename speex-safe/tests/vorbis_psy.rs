@@ -0,0 +1,176 @@
+////////////////////////////////////////////////////////////////////////////////
+// Copyright (c) 2023.                                                         /
+// This Source Code Form is subject to the terms of the Mozilla Public License,/
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can    /
+// obtain one at http://mozilla.org/MPL/2.0/.                                  /
+////////////////////////////////////////////////////////////////////////////////
+
+//! Spectral distance of narrowband speech coded with and without the Vorbis
+//! psychoacoustic model, at the same bitrate
+//!
+//! The model is chosen when libspeex is built, so each build measures itself
+//! and leaves its numbers behind for the other. Running both
+//!
+//! ```sh
+//! cargo test -p speex-safe --test vorbis_psy -- --nocapture
+//! cargo test -p speex-safe --test vorbis_psy --features vorbis-psy -- --nocapture
+//! ```
+//!
+//! prints the difference on the second run.
+
+use std::f32::consts::PI;
+use std::fs;
+use std::path::PathBuf;
+
+use speex_safe::{ControlFunctions, SpeexBits, SpeexDecoder, SpeexEncoder};
+
+mod support;
+
+const VARIANT: &str = if cfg!(feature = "vorbis-psy") {
+    "with-vorbis-psy"
+} else {
+    "without-vorbis-psy"
+};
+const OTHER_VARIANT: &str = if cfg!(feature = "vorbis-psy") {
+    "without-vorbis-psy"
+} else {
+    "with-vorbis-psy"
+};
+
+const FRAME_SIZE: usize = 160;
+const FRAMES: usize = 250;
+const QUALITY: i32 = 4;
+/// Bitrate of narrowband at quality 4
+const BITRATE: i32 = 8000;
+
+/// Encodes and decodes `signal`, returning the decoded signal lined up with
+/// the original, the number of bytes it was coded in and the bitrate
+fn code(signal: &[f32]) -> (Vec<f32>, usize, i32) {
+    let mut encoder = SpeexEncoder::<speex_safe::NbMode>::new();
+    let mut decoder = SpeexDecoder::<speex_safe::NbMode>::new();
+    encoder.set_quality(QUALITY);
+    let bitrate = encoder.get_bitrate();
+    // The decoder delays the signal by as much as the encoder looks ahead
+    let delay = (encoder.get_lookahead() + decoder.get_lookahead()) as usize;
+
+    let mut bits = SpeexBits::new();
+    let mut packet = [0u8; 200];
+    let mut out = [0.0f32; FRAME_SIZE];
+    let mut bytes = 0;
+    let mut decoded = Vec::with_capacity(signal.len());
+    for frame in signal.chunks(FRAME_SIZE) {
+        let mut frame = frame.to_vec();
+        bits.reset();
+        encoder.encode(&mut frame, &mut bits).unwrap();
+        let len = bits.write(&mut packet) as usize;
+        bytes += len;
+        bits.read_from(&mut packet[..len]);
+        decoder.decode(&mut bits, &mut out).unwrap();
+        decoded.extend_from_slice(&out);
+    }
+    (decoded.split_off(delay), bytes, bitrate)
+}
+
+/// Power spectrum of a Hann windowed block, in dB
+fn spectrum_db(block: &[f32]) -> Vec<f32> {
+    let n = block.len();
+    let windowed = block
+        .iter()
+        .enumerate()
+        .map(|(i, x)| x * (0.5 - 0.5 * (2.0 * PI * i as f32 / n as f32).cos()))
+        .collect::<Vec<_>>();
+    (1..n / 2)
+        .map(|k| {
+            let (mut re, mut im) = (0.0f32, 0.0f32);
+            for (i, x) in windowed.iter().enumerate() {
+                let angle = 2.0 * PI * (k * i % n) as f32 / n as f32;
+                re += x * angle.cos();
+                im -= x * angle.sin();
+            }
+            10.0 * (re * re + im * im + 1.0).log10()
+        })
+        .collect()
+}
+
+struct Metrics {
+    /// RMS difference of the log power spectra, averaged over blocks, in dB
+    log_spectral_distance: f32,
+    /// Signal to noise ratio averaged over blocks, in dB
+    segmental_snr: f32,
+}
+
+fn measure(original: &[f32], decoded: &[f32]) -> Metrics {
+    const BLOCK: usize = 256;
+    let blocks = original
+        .chunks_exact(BLOCK)
+        .zip(decoded.chunks_exact(BLOCK))
+        .collect::<Vec<_>>();
+    let mut distance = 0.0;
+    let mut snr = 0.0;
+    for (original, decoded) in &blocks {
+        let (a, b) = (spectrum_db(original), spectrum_db(decoded));
+        let squared = a.iter().zip(&b).map(|(a, b)| (a - b).powi(2)).sum::<f32>();
+        distance += (squared / a.len() as f32).sqrt();
+
+        let signal = original.iter().map(|x| x * x).sum::<f32>();
+        let noise = original
+            .iter()
+            .zip(decoded.iter())
+            .map(|(x, y)| (x - y).powi(2))
+            .sum::<f32>();
+        snr += 10.0 * ((signal + 1.0) / (noise + 1.0)).log10();
+    }
+    Metrics {
+        log_spectral_distance: distance / blocks.len() as f32,
+        segmental_snr: snr / blocks.len() as f32,
+    }
+}
+
+fn results_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("vorbis-psy")
+}
+
+#[test]
+fn compare_spectral_distance() {
+    let signal = support::speech_like(8000, FRAME_SIZE * FRAMES);
+    let (decoded, bytes, bitrate) = code(&signal);
+    assert_eq!(bitrate, BITRATE);
+    assert_eq!(bytes, FRAMES * BITRATE as usize / 50 / 8);
+
+    let metrics = measure(&signal, &decoded);
+    println!(
+        "{VARIANT}: log spectral distance {:.3} dB, segmental SNR {:.3} dB",
+        metrics.log_spectral_distance, metrics.segmental_snr
+    );
+    assert!(
+        metrics.log_spectral_distance < 10.0 && metrics.segmental_snr > 3.0,
+        "{VARIANT} decoded far from the original"
+    );
+
+    let dir = results_dir();
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(
+        dir.join(VARIANT),
+        format!(
+            "{} {}",
+            metrics.log_spectral_distance, metrics.segmental_snr
+        ),
+    )
+    .unwrap();
+
+    match fs::read_to_string(dir.join(OTHER_VARIANT)) {
+        Ok(other) => {
+            let other = other
+                .split_whitespace()
+                .map(|value| value.parse::<f32>().unwrap())
+                .collect::<Vec<_>>();
+            println!(
+                "{VARIANT} minus {OTHER_VARIANT}: log spectral distance {:+.3} dB, segmental SNR \
+                 {:+.3} dB",
+                metrics.log_spectral_distance - other[0],
+                metrics.segmental_snr - other[1]
+            );
+        }
+        Err(_) => println!("Run the test {OTHER_VARIANT} to compare"),
+    }
+}
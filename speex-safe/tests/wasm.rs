@@ -0,0 +1,73 @@
+////////////////////////////////////////////////////////////////////////////////
+// Copyright (c) 2023.                                                         /
+// This Source Code Form is subject to the terms of the Mozilla Public License,/
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can    /
+// obtain one at http://mozilla.org/MPL/2.0/.                                  /
+////////////////////////////////////////////////////////////////////////////////
+
+//! Tests of the JavaScript bindings, run under Node with
+//! `cargo test -p speex-safe --features wasm --target wasm32-unknown-unknown`
+//! and `wasm-bindgen-test-runner` on the path
+
+#![cfg(all(target_arch = "wasm32", feature = "wasm"))]
+
+use speex_safe::wasm::{decode_spx, WasmDecoder, WasmEncoder};
+use speex_safe::wav::{encode_ogg, EncodeOptions};
+use wasm_bindgen_test::wasm_bindgen_test;
+
+mod support;
+
+fn sine(len: usize) -> Vec<f32> {
+    support::tone(8000, 440.0, 8000.0, len)
+}
+
+#[wasm_bindgen_test]
+fn round_trips_float_frames() {
+    for mode in 0..3 {
+        let mut encoder = WasmEncoder::new(mode).unwrap();
+        let mut decoder = WasmDecoder::new(mode).unwrap();
+        assert_eq!(encoder.frame_size(), decoder.frame_size());
+        encoder.set_quality(8);
+        let frame_size = encoder.frame_size();
+        let mut energy = 0.0;
+        for frame in sine(frame_size * 10).chunks(frame_size) {
+            let packet = encoder.encode(frame).unwrap();
+            let decoded = decoder.decode(&packet).unwrap();
+            assert_eq!(decoded.len(), frame_size);
+            energy += decoded.iter().map(|&x| x * x).sum::<f32>();
+        }
+        assert!(energy > 0.0, "mode {mode} decoded silence");
+    }
+}
+
+#[wasm_bindgen_test]
+fn round_trips_int_frames() {
+    let mut encoder = WasmEncoder::new(1).unwrap();
+    let mut decoder = WasmDecoder::new(1).unwrap();
+    let frame: Vec<i16> = sine(encoder.frame_size())
+        .into_iter()
+        .map(|x| x as i16)
+        .collect();
+    let packet = encoder.encode_int(&frame).unwrap();
+    assert_eq!(decoder.decode_int(&packet).unwrap().len(), frame.len());
+    assert_eq!(decoder.decode_lost().unwrap().len(), frame.len());
+}
+
+#[wasm_bindgen_test]
+fn rejects_bad_input() {
+    assert!(WasmEncoder::new(3).is_err());
+    let mut encoder = WasmEncoder::new(0).unwrap();
+    assert!(encoder.encode(&[0.0; 10]).is_err());
+}
+
+#[wasm_bindgen_test]
+fn decodes_spx_files() {
+    let samples = sine(8000);
+    let mut file = Vec::new();
+    encode_ogg(&samples, 8000, 1, &EncodeOptions::default(), &mut file).unwrap();
+
+    let audio = decode_spx(&file).unwrap();
+    assert_eq!(audio.sample_rate(), 8000);
+    assert_eq!(audio.channels(), 1);
+    assert_eq!(audio.samples().len(), samples.len());
+}
@@ -0,0 +1,144 @@
+////////////////////////////////////////////////////////////////////////////////
+// Copyright (c) 2023.                                                         /
+// This Source Code Form is subject to the terms of the Mozilla Public License,/
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can    /
+// obtain one at http://mozilla.org/MPL/2.0/.                                  /
+////////////////////////////////////////////////////////////////////////////////
+
+//! Decodes Ogg Speex into WAV or raw PCM audio, following the options of the
+//! reference `speexdec`.
+
+use std::process::ExitCode;
+
+use speex_safe::wav::{decode_ogg, write_wav, DecodeOptions};
+use speex_safe::ModeId;
+use speex_tools::{read_input, version_string, write_output, write_raw_samples, Args};
+
+const USAGE: &str = "\
+Usage: speexdec [options] input_file.spx output_file
+
+Decodes an Ogg Speex file. The output is a WAV file if output_file ends in
+.wav, raw 16-bit little-endian PCM otherwise. Either file can be '-' to use
+stdin or stdout.
+
+Options:
+ --enh               Enable perceptual enhancement (default)
+ --no-enh            Disable perceptual enhancement
+ --force-nb          Force decoding in narrowband
+ --force-wb          Force decoding in wideband
+ --force-uwb         Force decoding in ultra-wideband
+ --mono              Force decoding in mono
+ --stereo            Force decoding in stereo
+ --rate n            Sampling rate written to the output file
+ --packet-loss n     Simulate n % random packet loss
+ -h, --help          This help
+ -v, --version       Version information
+ --quiet             Don't print status information";
+
+struct Options {
+    decode: DecodeOptions,
+    rate: Option<u32>,
+    quiet: bool,
+    input: String,
+    output: String,
+}
+
+enum Command {
+    Decode(Options),
+    Help,
+    Version,
+}
+
+fn parse_args(mut args: Args) -> Result<Command, String> {
+    let mut decode = DecodeOptions::default();
+    let mut rate = None;
+    let mut quiet = false;
+    let mut files = Vec::new();
+
+    while let Some(arg) = args.next_arg()? {
+        match arg.as_str() {
+            "--enh" => decode.enhancement = true,
+            "--no-enh" => decode.enhancement = false,
+            "--force-nb" => decode.mode = Some(ModeId::NarrowBand),
+            "--force-wb" => decode.mode = Some(ModeId::WideBand),
+            "--force-uwb" => decode.mode = Some(ModeId::UltraWideBand),
+            "--mono" => decode.channels = Some(1),
+            "--stereo" => decode.channels = Some(2),
+            "--rate" => rate = Some(args.value(&arg)?),
+            "--packet-loss" => {
+                let loss: u32 = args.value(&arg)?;
+                if loss > 100 {
+                    return Err(format!("packet loss must be between 0 and 100, got {loss}"));
+                }
+                decode.packet_loss = loss;
+            }
+            "--quiet" => quiet = true,
+            "-h" | "--help" => return Ok(Command::Help),
+            "-v" | "--version" => return Ok(Command::Version),
+            _ if arg.starts_with('-') && arg != "-" => {
+                return Err(format!("unknown option '{arg}'"));
+            }
+            _ => files.push(arg),
+        }
+    }
+
+    let [input, output]: [String; 2] = files
+        .try_into()
+        .map_err(|_| "expected an input and an output file".to_string())?;
+    Ok(Command::Decode(Options {
+        decode,
+        rate,
+        quiet,
+        input,
+        output,
+    }))
+}
+
+fn run(options: Options) -> Result<(), String> {
+    let data = read_input(&options.input).map_err(|e| format!("{}: {e}", options.input))?;
+    let decoded = decode_ogg(data.as_slice(), &options.decode).map_err(|e| e.to_string())?;
+    let sample_rate = options.rate.unwrap_or(decoded.sample_rate);
+
+    if !options.quiet {
+        eprintln!(
+            "Decoding {sample_rate} Hz audio ({})",
+            if decoded.channels == 2 {
+                "stereo"
+            } else {
+                "mono"
+            }
+        );
+    }
+
+    let output = if options.output.to_ascii_lowercase().ends_with(".wav") {
+        let mut wav = Vec::new();
+        write_wav(&mut wav, decoded.channels, sample_rate, &decoded.samples)
+            .map_err(|e| e.to_string())?;
+        wav
+    } else {
+        write_raw_samples(&decoded.samples)
+    };
+    write_output(&options.output, &output).map_err(|e| format!("{}: {e}", options.output))
+}
+
+fn main() -> ExitCode {
+    let result = match parse_args(Args::from_env()) {
+        Ok(Command::Decode(options)) => run(options),
+        Ok(Command::Help) => {
+            println!("{USAGE}");
+            return ExitCode::SUCCESS;
+        }
+        Ok(Command::Version) => {
+            println!("{}", version_string("speexdec"));
+            return ExitCode::SUCCESS;
+        }
+        Err(err) => Err(format!("{err}\n\n{USAGE}")),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("speexdec: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
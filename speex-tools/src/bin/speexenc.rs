@@ -0,0 +1,248 @@
+////////////////////////////////////////////////////////////////////////////////
+// Copyright (c) 2023.                                                         /
+// This Source Code Form is subject to the terms of the Mozilla Public License,/
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can    /
+// obtain one at http://mozilla.org/MPL/2.0/.                                  /
+////////////////////////////////////////////////////////////////////////////////
+
+//! Encodes WAV or raw PCM audio into Ogg Speex, following the options of the
+//! reference `speexenc`.
+
+use std::process::ExitCode;
+
+use speex_safe::wav::{
+    encode_ogg_with_comments,
+    mode_for_sample_rate,
+    read_wav,
+    ChannelMode,
+    EncodeOptions,
+    VENDOR_STRING,
+};
+use speex_safe::{ModeId, SpeexComments};
+use speex_tools::{mode_name, read_input, version_string, write_output, Args, RawFormat};
+
+const USAGE: &str = "\
+Usage: speexenc [options] input_file output_file
+
+Encodes input_file (WAV or raw PCM) into an Ogg Speex file. Either file can be
+'-' to use stdin or stdout.
+
+Options:
+ -n, --narrowband    Narrowband (8 kHz) input file
+ -w, --wideband      Wideband (16 kHz) input file
+ -u, --ultra-wideband
+                     \"Ultra-wideband\" (32 kHz) input file
+ --quality n         Encoding quality (0-10), libspeex's default if not given
+ --bitrate n         Encoding bit-rate (use bit-rate n or lower)
+ --vbr               Enable variable bit-rate (VBR)
+ --vbr-max-bitrate n Maximum bit-rate allowed in VBR mode
+ --abr n             Enable average bit-rate (ABR) at n bps
+ --vad               Enable voice activity detection (VAD)
+ --dtx               Enable file-based discontinuous transmission (DTX)
+ --comp n            Set encoding complexity (1-10), default 3
+ --nframes n         Number of frames per Ogg packet (1-10), default 1
+ --denoise           Denoise the input before encoding
+ --agc               Apply adaptive gain control
+ --no-highpass       Disable the encoder's highpass filter
+ --comment KEY=value Add the given string as an extra comment, may be used
+                     more than once
+ --author name       Author of this track
+ --title name        Title of this track
+ -h, --help          This help
+ -v, --version       Version information
+ --quiet             Don't print status information
+
+Raw input options:
+ --rate n            Sampling rate for raw input
+ --stereo            Consider raw input as stereo
+ --le                Raw input is little-endian
+ --be                Raw input is big-endian
+ --8bit              Raw input is 8-bit unsigned
+ --16bit             Raw input is 16-bit signed";
+
+struct Options {
+    encode: EncodeOptions,
+    comments: SpeexComments,
+    raw: RawFormat,
+    rate: Option<u32>,
+    stereo: bool,
+    quiet: bool,
+    input: String,
+    output: String,
+}
+
+enum Command {
+    Encode(Options),
+    Help,
+    Version,
+}
+
+fn parse_args(mut args: Args) -> Result<Command, String> {
+    let mut encode = EncodeOptions::default();
+    let mut comments = SpeexComments::new(VENDOR_STRING);
+    let mut raw = RawFormat::default();
+    let mut rate = None;
+    let mut stereo = false;
+    let mut quiet = false;
+    let mut files = Vec::new();
+
+    while let Some(arg) = args.next_arg()? {
+        match arg.as_str() {
+            "-n" | "--narrowband" => encode.mode = Some(ModeId::NarrowBand),
+            "-w" | "--wideband" => encode.mode = Some(ModeId::WideBand),
+            "-u" | "--ultra-wideband" => encode.mode = Some(ModeId::UltraWideBand),
+            "--quality" => {
+                let quality: i32 = args.value(&arg)?;
+                if !(0..=10).contains(&quality) {
+                    return Err(format!("quality must be between 0 and 10, got {quality}"));
+                }
+                encode.quality = Some(quality);
+            }
+            "--bitrate" => encode.bitrate = Some(args.value(&arg)?),
+            "--vbr" => encode.vbr = true,
+            "--vbr-max-bitrate" => encode.vbr_max_bitrate = Some(args.value(&arg)?),
+            "--abr" => encode.abr = Some(args.value(&arg)?),
+            "--vad" => encode.vad = true,
+            "--dtx" => encode.dtx = true,
+            "--comp" => {
+                let complexity: i32 = args.value(&arg)?;
+                if !(1..=10).contains(&complexity) {
+                    return Err(format!(
+                        "complexity must be between 1 and 10, got {complexity}"
+                    ));
+                }
+                encode.complexity = complexity;
+            }
+            "--nframes" => {
+                let frames: i32 = args.value(&arg)?;
+                if !(1..=10).contains(&frames) {
+                    return Err(format!(
+                        "frames per packet must be between 1 and 10, got {frames}"
+                    ));
+                }
+                encode.frames_per_packet = frames;
+            }
+            "--denoise" => encode.denoise = true,
+            "--agc" => encode.agc = true,
+            "--no-highpass" => encode.highpass = false,
+            "--comment" => {
+                let comment: String = args.value(&arg)?;
+                let (key, value) = comment
+                    .split_once('=')
+                    .ok_or_else(|| format!("comment '{comment}' is not of the form KEY=value"))?;
+                comments.add(key, value).map_err(|e| e.to_string())?;
+            }
+            "--author" => {
+                let author: String = args.value(&arg)?;
+                comments.add("author", &author).map_err(|e| e.to_string())?;
+            }
+            "--title" => {
+                let title: String = args.value(&arg)?;
+                comments.add("title", &title).map_err(|e| e.to_string())?;
+            }
+            "--rate" => rate = Some(args.value(&arg)?),
+            "--stereo" => stereo = true,
+            "--le" => raw.big_endian = false,
+            "--be" => raw.big_endian = true,
+            "--8bit" => raw.bits = 8,
+            "--16bit" => raw.bits = 16,
+            "--quiet" => quiet = true,
+            "-h" | "--help" => return Ok(Command::Help),
+            "-v" | "--version" => return Ok(Command::Version),
+            _ if arg.starts_with('-') && arg != "-" => {
+                return Err(format!("unknown option '{arg}'"));
+            }
+            _ => files.push(arg),
+        }
+    }
+
+    let [input, output]: [String; 2] = files
+        .try_into()
+        .map_err(|_| "expected an input and an output file".to_string())?;
+    Ok(Command::Encode(Options {
+        encode,
+        comments,
+        raw,
+        rate,
+        stereo,
+        quiet,
+        input,
+        output,
+    }))
+}
+
+fn run(options: Options) -> Result<(), String> {
+    let data = read_input(&options.input).map_err(|e| format!("{}: {e}", options.input))?;
+
+    let (sample_rate, channels, samples) = if data.starts_with(b"RIFF") {
+        let (spec, samples) = read_wav(data.as_slice()).map_err(|e| e.to_string())?;
+        if let Some(rate) = options.rate {
+            if rate != spec.sample_rate {
+                eprintln!(
+                    "speexenc: warning: ignoring --rate {rate}, the WAV file is {} Hz",
+                    spec.sample_rate
+                );
+            }
+        }
+        (spec.sample_rate, spec.channels, samples)
+    } else {
+        let rate = options
+            .rate
+            .unwrap_or_else(|| options.encode.mode.map_or(8000, ModeId::get_sampling_rate));
+        let channels = if options.stereo { 2 } else { 1 };
+        (rate, channels, options.raw.read_samples(&data))
+    };
+
+    let mode = options
+        .encode
+        .mode
+        .or_else(|| mode_for_sample_rate(sample_rate))
+        .ok_or_else(|| format!("unsupported sampling rate {sample_rate} Hz"))?;
+    let encode = EncodeOptions {
+        mode: Some(mode),
+        ..options.encode
+    };
+
+    if !options.quiet {
+        let stereo = channels == 2 && encode.channel_mode == ChannelMode::Stereo;
+        eprintln!(
+            "Encoding {sample_rate} Hz audio using {} mode ({})",
+            mode_name(mode),
+            if stereo { "stereo" } else { "mono" }
+        );
+    }
+
+    let mut ogg = Vec::new();
+    encode_ogg_with_comments(
+        &samples,
+        sample_rate,
+        channels,
+        &encode,
+        &options.comments,
+        &mut ogg,
+    )
+    .map_err(|e| e.to_string())?;
+    write_output(&options.output, &ogg).map_err(|e| format!("{}: {e}", options.output))
+}
+
+fn main() -> ExitCode {
+    let result = match parse_args(Args::from_env()) {
+        Ok(Command::Encode(options)) => run(options),
+        Ok(Command::Help) => {
+            println!("{USAGE}");
+            return ExitCode::SUCCESS;
+        }
+        Ok(Command::Version) => {
+            println!("{}", version_string("speexenc"));
+            return ExitCode::SUCCESS;
+        }
+        Err(err) => Err(format!("{err}\n\n{USAGE}")),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("speexenc: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,121 @@
+////////////////////////////////////////////////////////////////////////////////
+// Copyright (c) 2023.                                                         /
+// This Source Code Form is subject to the terms of the Mozilla Public License,/
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can    /
+// obtain one at http://mozilla.org/MPL/2.0/.                                  /
+////////////////////////////////////////////////////////////////////////////////
+
+//! Dumps the header, comments and per-frame details of a Speex stream.
+
+use std::io;
+use std::io::Write;
+use std::process::ExitCode;
+
+use speex_safe::ModeId;
+use speex_tools::info::{read_ogg, read_raw};
+use speex_tools::{read_input, version_string, Args};
+
+const USAGE: &str = "\
+Usage: speexinfo [options] input_file
+
+Prints the header fields, comments and per-frame details of an Ogg Speex file,
+or of a raw packet stream. input_file can be '-' to read from stdin.
+
+Options:
+ --json              Print the information as JSON
+ --raw               Read a raw packet stream, where each packet is preceded
+                     by its length as a 32-bit little-endian integer
+ --mode m            Mode of a raw packet stream: nb, wb or uwb (default nb)
+ -h, --help          This help
+ -v, --version       Version information";
+
+struct Options {
+    json: bool,
+    raw: Option<ModeId>,
+    input: String,
+}
+
+enum Command {
+    Info(Options),
+    Help,
+    Version,
+}
+
+fn parse_args(mut args: Args) -> Result<Command, String> {
+    let mut json = false;
+    let mut raw = false;
+    let mut mode = ModeId::NarrowBand;
+    let mut files = Vec::new();
+
+    while let Some(arg) = args.next_arg()? {
+        match arg.as_str() {
+            "--json" => json = true,
+            "--raw" => raw = true,
+            "--mode" => {
+                mode = match args.value::<String>(&arg)?.as_str() {
+                    "nb" => ModeId::NarrowBand,
+                    "wb" => ModeId::WideBand,
+                    "uwb" => ModeId::UltraWideBand,
+                    other => return Err(format!("unknown mode '{other}'")),
+                }
+            }
+            "-h" | "--help" => return Ok(Command::Help),
+            "-v" | "--version" => return Ok(Command::Version),
+            _ if arg.starts_with('-') && arg != "-" => {
+                return Err(format!("unknown option '{arg}'"));
+            }
+            _ => files.push(arg),
+        }
+    }
+
+    let [input]: [String; 1] = files
+        .try_into()
+        .map_err(|_| "expected a single input file".to_string())?;
+    Ok(Command::Info(Options {
+        json,
+        raw: raw.then_some(mode),
+        input,
+    }))
+}
+
+fn run(options: Options) -> Result<(), String> {
+    let data = read_input(&options.input).map_err(|e| format!("{}: {e}", options.input))?;
+    let info = match options.raw {
+        Some(mode) => read_raw(data.as_slice(), mode),
+        None => read_ogg(data.as_slice()),
+    }
+    .map_err(|e| e.to_string())?;
+
+    let output = if options.json {
+        info.to_json()
+    } else {
+        info.to_string()
+    };
+    match writeln!(io::stdout().lock(), "{output}") {
+        // Stop quietly when piped into something like `head`
+        Err(err) if err.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        result => result.map_err(|e| e.to_string()),
+    }
+}
+
+fn main() -> ExitCode {
+    let result = match parse_args(Args::from_env()) {
+        Ok(Command::Info(options)) => run(options),
+        Ok(Command::Help) => {
+            println!("{USAGE}");
+            return ExitCode::SUCCESS;
+        }
+        Ok(Command::Version) => {
+            println!("{}", version_string("speexinfo"));
+            return ExitCode::SUCCESS;
+        }
+        Err(err) => Err(format!("{err}\n\n{USAGE}")),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("speexinfo: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,752 @@
+////////////////////////////////////////////////////////////////////////////////
+// Copyright (c) 2023.                                                         /
+// This Source Code Form is subject to the terms of the Mozilla Public License,/
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can    /
+// obtain one at http://mozilla.org/MPL/2.0/.                                  /
+////////////////////////////////////////////////////////////////////////////////
+
+//! Walks the bitstream of Speex packets without decoding them, for
+//! `speexinfo`.
+//!
+//! Frames are parsed the same way the libspeex decoders read them: a
+//! narrowband frame, preceded by any in-band messages, followed by one
+//! wideband layer for wideband streams and two for ultra-wideband ones. Only
+//! the headers of each part are read, the rest is skipped using the submode
+//! sizes from `ModeId::get_submode_bits_per_frame`.
+
+use std::error::Error;
+use std::fmt::{Display, Formatter, Write as _};
+use std::io;
+use std::io::Read;
+
+use speex_safe::ogg::OggReader;
+use speex_safe::{CommentsError, ModeId, SpeexBits, SpeexComments, SpeexHeader};
+
+use crate::mode_name;
+
+/// Bits taken by the wideband bit and submode id of a narrowband frame
+const NB_HEADER_BITS: u32 = 5;
+/// Bits taken by the wideband bit and submode id of a wideband layer
+const SB_HEADER_BITS: u32 = 4;
+/// Submode id marking a speex in-band message
+const INBAND_SPEEX: u32 = 14;
+/// Submode id marking a user in-band message
+const INBAND_USER: u32 = 13;
+/// Submode id marking the end of the frames in a packet
+const TERMINATOR: u32 = 15;
+
+/// Errors that stop a stream from being read
+#[derive(Debug)]
+pub enum InfoError {
+    Io(io::Error),
+    InvalidStream(&'static str),
+}
+
+impl Display for InfoError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InfoError::Io(err) => write!(f, "{err}"),
+            InfoError::InvalidStream(reason) => write!(f, "Invalid Speex stream: {reason}"),
+        }
+    }
+}
+
+impl Error for InfoError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            InfoError::Io(err) => Some(err),
+            InfoError::InvalidStream(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for InfoError {
+    fn from(err: io::Error) -> Self {
+        InfoError::Io(err)
+    }
+}
+
+/// Problems found in the bitstream of a packet
+///
+/// Parsing of a packet stops at the first one, keeping the frames read
+/// before it.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum FrameError {
+    /// A layer of the given mode uses a submode the mode doesn't have
+    InvalidSubmode { mode: ModeId, submode: u32 },
+    /// More than two wideband layers precede a narrowband frame
+    TooManyWidebandLayers,
+    /// The packet ends in the middle of a frame
+    Truncated,
+}
+
+impl Display for FrameError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FrameError::InvalidSubmode { mode, submode } => {
+                write!(f, "invalid {} submode {submode}", mode_name(*mode))
+            }
+            FrameError::TooManyWidebandLayers => write!(f, "more than two wideband layers"),
+            FrameError::Truncated => write!(f, "packet ends within a frame"),
+        }
+    }
+}
+
+impl Error for FrameError {}
+
+/// Fields of the `SpeexHeader` at the start of an Ogg Speex stream
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct HeaderInfo {
+    pub version: String,
+    pub version_id: i32,
+    pub mode: ModeId,
+    pub mode_bitstream_version: i32,
+    pub rate: i32,
+    pub channels: i32,
+    pub bitrate: i32,
+    pub frame_size: i32,
+    pub vbr: bool,
+    pub frames_per_packet: i32,
+    pub extra_headers: i32,
+}
+
+impl From<&SpeexHeader> for HeaderInfo {
+    fn from(header: &SpeexHeader) -> Self {
+        Self {
+            version: header.get_version(),
+            version_id: header.get_version_id(),
+            mode: header.get_mode(),
+            mode_bitstream_version: header.get_mode_bitstream_version(),
+            rate: header.get_rate(),
+            channels: header.get_nb_channels(),
+            bitrate: header.get_bitrate(),
+            frame_size: header.get_frame_size(),
+            vbr: header.get_vbr(),
+            frames_per_packet: header.get_frames_per_packet(),
+            extra_headers: header.get_extra_headers(),
+        }
+    }
+}
+
+/// An in-band message found before a narrowband frame
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum InbandMessage {
+    /// A message for the speex decoder, such as a mode request or stereo
+    /// information. `value` is `None` for the 64 bit messages.
+    Speex { id: u32, value: Option<u32> },
+    /// A user-defined message of `bytes` bytes
+    User { bytes: u32 },
+}
+
+impl InbandMessage {
+    /// Gets the name of the message, from the `SPEEX_INBAND_*` definitions
+    pub fn name(&self) -> &'static str {
+        match self {
+            InbandMessage::Speex { id, .. } => {
+                match *id as i32 {
+                    speex_sys::SPEEX_INBAND_ENH_REQUEST => "enhancement request",
+                    speex_sys::SPEEX_INBAND_MODE_REQUEST => "mode request",
+                    speex_sys::SPEEX_INBAND_LOW_MODE_REQUEST => "low mode request",
+                    speex_sys::SPEEX_INBAND_HIGH_MODE_REQUEST => "high mode request",
+                    speex_sys::SPEEX_INBAND_VBR_QUALITY_REQUEST => "vbr quality request",
+                    speex_sys::SPEEX_INBAND_ACKNOWLEDGE_REQUEST => "acknowledge request",
+                    speex_sys::SPEEX_INBAND_VBR_REQUEST => "vbr request",
+                    speex_sys::SPEEX_INBAND_CHAR => "character",
+                    speex_sys::SPEEX_INBAND_STEREO => "stereo",
+                    speex_sys::SPEEX_INBAND_MAX_BITRATE => "max bitrate",
+                    speex_sys::SPEEX_INBAND_ACKNOWLEDGE => "acknowledge",
+                    _ => "reserved",
+                }
+            }
+            InbandMessage::User { .. } => "user",
+        }
+    }
+}
+
+/// Gets the payload size of a speex in-band message, the same way
+/// `speex_inband_handler` does when skipping unhandled messages
+fn inband_payload_bits(id: u32) -> u32 {
+    match id {
+        0..=1 => 1,
+        2..=7 => 4,
+        8..=9 => 8,
+        10..=11 => 16,
+        12..=13 => 32,
+        _ => 64,
+    }
+}
+
+/// The headers of one frame
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct FrameInfo {
+    /// In-band messages sent before the frame
+    pub inband: Vec<InbandMessage>,
+    /// Submode of the narrowband part, as in `NbSubmodeId`
+    pub nb_submode: u32,
+    /// Submode of each wideband layer the stream's mode has, `None` if the
+    /// layer's wideband bit wasn't set
+    pub wb_submodes: Vec<Option<u32>>,
+    /// Number of bits the frame took, including in-band messages
+    pub bits: u32,
+}
+
+impl FrameInfo {
+    /// Whether the wideband bit of the first layer above narrowband was set
+    pub fn wideband(&self) -> bool {
+        matches!(self.wb_submodes.first(), Some(Some(_)))
+    }
+}
+
+/// A packet and the frames found in it
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct PacketInfo {
+    /// Index of the packet among the audio packets of the stream
+    pub index: usize,
+    /// Granule position of the packet, for Ogg streams
+    pub granule_position: Option<i64>,
+    pub bytes: usize,
+    pub frames: Vec<FrameInfo>,
+    /// What stopped the packet from being parsed completely
+    pub error: Option<FrameError>,
+}
+
+/// Everything read from a stream
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct StreamInfo {
+    /// Mode used to parse the frames
+    pub mode: ModeId,
+    /// The stream header, absent for raw streams
+    pub header: Option<HeaderInfo>,
+    /// The comments, absent for raw streams or if the packet is malformed
+    pub comments: Option<SpeexComments>,
+    /// Why the comment packet couldn't be read
+    pub comments_error: Option<CommentsError>,
+    pub packets: Vec<PacketInfo>,
+}
+
+fn unpack(bits: &mut SpeexBits, num_bits: u32) -> Result<u32, FrameError> {
+    if bits.remaining() < num_bits {
+        return Err(FrameError::Truncated);
+    }
+    Ok(bits.unpacked_unsigned(num_bits as i32))
+}
+
+fn skip(bits: &mut SpeexBits, num_bits: u32) -> Result<(), FrameError> {
+    if bits.remaining() < num_bits {
+        return Err(FrameError::Truncated);
+    }
+    bits.advance(num_bits as i32);
+    Ok(())
+}
+
+/// Reads the submode of a wideband layer, whose wideband bit has already
+/// been read, and skips over the rest of it
+fn skip_layer(bits: &mut SpeexBits, mode: ModeId) -> Result<u32, FrameError> {
+    let submode = unpack(bits, SB_HEADER_BITS - 1)?;
+    let frame_bits = mode
+        .get_submode_bits_per_frame(submode as i32)
+        .filter(|&bits| bits > 0)
+        .ok_or(FrameError::InvalidSubmode { mode, submode })?;
+    skip(bits, frame_bits as u32 - SB_HEADER_BITS)?;
+    Ok(submode)
+}
+
+/// Reads the next frame, returning `None` at a terminator or once there are
+/// too few bits left for another frame
+pub fn read_frame(bits: &mut SpeexBits, mode: ModeId) -> Result<Option<FrameInfo>, FrameError> {
+    let start = bits.remaining();
+    let mut inband = Vec::new();
+    let mut skipped_layers = 0;
+
+    let nb_submode = loop {
+        if bits.remaining() < NB_HEADER_BITS {
+            return Ok(None);
+        }
+        if bits.unpacked_unsigned(1) == 1 {
+            // A wideband layer where a narrowband frame should start, which
+            // the narrowband decoder skips
+            skipped_layers += 1;
+            if skipped_layers > 2 {
+                return Err(FrameError::TooManyWidebandLayers);
+            }
+            skip_layer(bits, ModeId::WideBand)?;
+            continue;
+        }
+        match bits.unpacked_unsigned(NB_HEADER_BITS as i32 - 1) {
+            TERMINATOR => return Ok(None),
+            INBAND_SPEEX => {
+                let id = unpack(bits, 4)?;
+                let payload = inband_payload_bits(id);
+                let value = if payload <= 32 {
+                    Some(unpack(bits, payload)?)
+                } else {
+                    skip(bits, payload)?;
+                    None
+                };
+                inband.push(InbandMessage::Speex { id, value });
+            }
+            INBAND_USER => {
+                // Same layout speex_default_user_handler skips over
+                let bytes = unpack(bits, 4)?;
+                skip(bits, 5 + 8 * bytes)?;
+                inband.push(InbandMessage::User { bytes });
+            }
+            submode => break submode,
+        }
+    };
+    let nb_bits = ModeId::NarrowBand
+        .get_submode_bits_per_frame(nb_submode as i32)
+        .ok_or(FrameError::InvalidSubmode {
+            mode: ModeId::NarrowBand,
+            submode: nb_submode,
+        })?;
+    skip(bits, nb_bits as u32 - NB_HEADER_BITS)?;
+
+    let layers: &[ModeId] = match mode {
+        ModeId::NarrowBand => &[],
+        ModeId::WideBand => &[ModeId::WideBand],
+        ModeId::UltraWideBand => &[ModeId::WideBand, ModeId::UltraWideBand],
+    };
+    let mut wb_submodes = Vec::with_capacity(layers.len());
+    for &layer in layers {
+        if bits.remaining() > 0 && bits.peek() == 1 {
+            bits.advance(1);
+            wb_submodes.push(Some(skip_layer(bits, layer)?));
+        } else {
+            wb_submodes.push(None);
+        }
+    }
+
+    Ok(Some(FrameInfo {
+        inband,
+        nb_submode,
+        wb_submodes,
+        bits: start - bits.remaining(),
+    }))
+}
+
+/// Reads all frames of a packet
+pub fn read_packet(
+    index: usize,
+    packet: &[u8],
+    granule_position: Option<i64>,
+    mode: ModeId,
+) -> PacketInfo {
+    let mut data = packet.to_vec();
+    let mut bits = SpeexBits::new();
+    bits.read_from(&mut data);
+
+    let mut frames = Vec::new();
+    let error = loop {
+        match read_frame(&mut bits, mode) {
+            Ok(Some(frame)) => frames.push(frame),
+            Ok(None) => break None,
+            Err(err) => break Some(err),
+        }
+    };
+    PacketInfo {
+        index,
+        granule_position,
+        bytes: packet.len(),
+        frames,
+        error,
+    }
+}
+
+/// Reads an Ogg Speex stream
+pub fn read_ogg<R: Read>(reader: R) -> Result<StreamInfo, InfoError> {
+    let mut reader = OggReader::new(reader);
+
+    let header_packet = reader
+        .read_packet()?
+        .ok_or(InfoError::InvalidStream("stream is empty"))?;
+    let header = SpeexHeader::from_packet(&header_packet.data).ok_or(InfoError::InvalidStream(
+        "first packet is not a Speex header",
+    ))?;
+    let header = HeaderInfo::from(&header);
+
+    let comment_packet = reader
+        .read_packet()?
+        .ok_or(InfoError::InvalidStream("stream ends within the headers"))?;
+    let (comments, comments_error) = match SpeexComments::from_packet(&comment_packet.data) {
+        Ok(comments) => (Some(comments), None),
+        Err(err) => (None, Some(err)),
+    };
+    for _ in 0..header.extra_headers.max(0) {
+        reader
+            .read_packet()?
+            .ok_or(InfoError::InvalidStream("stream ends within the headers"))?;
+    }
+
+    let mut packets = Vec::new();
+    while let Some(packet) = reader.read_packet()? {
+        let granule = (packet.granule_position >= 0).then_some(packet.granule_position);
+        packets.push(read_packet(
+            packets.len(),
+            &packet.data,
+            granule,
+            header.mode,
+        ));
+    }
+
+    Ok(StreamInfo {
+        mode: header.mode,
+        header: Some(header),
+        comments,
+        comments_error,
+        packets,
+    })
+}
+
+/// Reads a raw packet stream, where each packet is preceded by its length as
+/// a 32 bit little-endian integer, as written by the libspeex sample encoder
+pub fn read_raw<R: Read>(mut reader: R, mode: ModeId) -> Result<StreamInfo, InfoError> {
+    let mut packets = Vec::new();
+    loop {
+        let mut len = [0u8; 4];
+        match reader.read_exact(&mut len) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(err.into()),
+        }
+        let len = u32::from_le_bytes(len) as usize;
+        let mut packet = Vec::new();
+        reader.by_ref().take(len as u64).read_to_end(&mut packet)?;
+        if packet.len() != len {
+            return Err(InfoError::InvalidStream("stream ends within a packet"));
+        }
+        packets.push(read_packet(packets.len(), &packet, None, mode));
+    }
+
+    Ok(StreamInfo {
+        mode,
+        header: None,
+        comments: None,
+        comments_error: None,
+        packets,
+    })
+}
+
+impl Display for StreamInfo {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if let Some(header) = &self.header {
+            writeln!(f, "Speex header:")?;
+            writeln!(
+                f,
+                "  version: {} (id {})",
+                header.version, header.version_id
+            )?;
+            writeln!(
+                f,
+                "  mode: {} (bitstream version {})",
+                mode_name(header.mode),
+                header.mode_bitstream_version
+            )?;
+            writeln!(f, "  rate: {} Hz", header.rate)?;
+            writeln!(f, "  channels: {}", header.channels)?;
+            writeln!(f, "  bitrate: {}", header.bitrate)?;
+            writeln!(f, "  frame size: {}", header.frame_size)?;
+            writeln!(f, "  vbr: {}", if header.vbr { "yes" } else { "no" })?;
+            writeln!(f, "  frames per packet: {}", header.frames_per_packet)?;
+            writeln!(f, "  extra headers: {}", header.extra_headers)?;
+        } else {
+            writeln!(f, "Raw {} stream", mode_name(self.mode))?;
+        }
+        if let Some(comments) = &self.comments {
+            writeln!(f, "Comments:")?;
+            writeln!(f, "  vendor: {}", comments.get_vendor())?;
+            for comment in comments.get_raw_comments() {
+                writeln!(f, "  {}", String::from_utf8_lossy(comment))?;
+            }
+            for field in comments.get_utf8_errors() {
+                writeln!(f, "  warning: invalid UTF-8 in {field}")?;
+            }
+        }
+        if let Some(err) = &self.comments_error {
+            writeln!(f, "Comments: {err}")?;
+        }
+
+        for packet in &self.packets {
+            write!(f, "Packet {}: {} bytes", packet.index, packet.bytes)?;
+            if let Some(granule) = packet.granule_position {
+                write!(f, ", granule {granule}")?;
+            }
+            writeln!(f)?;
+            for (i, frame) in packet.frames.iter().enumerate() {
+                for message in &frame.inband {
+                    match message {
+                        InbandMessage::Speex {
+                            id,
+                            value: Some(value),
+                        } => writeln!(f, "    in-band {} ({id}): {value}", message.name())?,
+                        InbandMessage::Speex { id, value: None } => {
+                            writeln!(f, "    in-band {} ({id})", message.name())?
+                        }
+                        InbandMessage::User { bytes } => {
+                            writeln!(f, "    in-band user message: {bytes} bytes")?
+                        }
+                    }
+                }
+                write!(f, "  frame {i}: nb submode {}", frame.nb_submode)?;
+                for submode in &frame.wb_submodes {
+                    match submode {
+                        Some(submode) => write!(f, ", wb submode {submode}")?,
+                        None => write!(f, ", no wb layer")?,
+                    }
+                }
+                writeln!(f, ", {} bits", frame.bits)?;
+            }
+            if let Some(err) = &packet.error {
+                writeln!(f, "  error: {err}")?;
+            }
+        }
+
+        let frames: usize = self.packets.iter().map(|p| p.frames.len()).sum();
+        let errors = self.packets.iter().filter(|p| p.error.is_some()).count();
+        write!(
+            f,
+            "{} packets, {frames} frames, {errors} packets with errors",
+            self.packets.len()
+        )
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn json_option<T: Display>(value: Option<T>) -> String {
+    value.map_or_else(|| "null".to_string(), |v| v.to_string())
+}
+
+impl StreamInfo {
+    /// Formats everything read as a JSON object
+    pub fn to_json(&self) -> String {
+        let header = match &self.header {
+            Some(h) => {
+                format!(
+                    "{{\"version\":{},\"version_id\":{},\"mode\":{},\"mode_bitstream_version\":{},\
+                     \"rate\":{},\"channels\":{},\"bitrate\":{},\"frame_size\":{},\"vbr\":{},\"\
+                     frames_per_packet\":{},\"extra_headers\":{}}}",
+                    json_string(&h.version),
+                    h.version_id,
+                    json_string(mode_name(h.mode)),
+                    h.mode_bitstream_version,
+                    h.rate,
+                    h.channels,
+                    h.bitrate,
+                    h.frame_size,
+                    h.vbr,
+                    h.frames_per_packet,
+                    h.extra_headers
+                )
+            }
+            None => "null".to_string(),
+        };
+        let comments = match &self.comments {
+            Some(c) => {
+                let strings = |items: Vec<String>| {
+                    let quoted: Vec<String> = items.iter().map(|s| json_string(s)).collect();
+                    quoted.join(",")
+                };
+                let raw = c
+                    .get_raw_comments()
+                    .iter()
+                    .map(|c| String::from_utf8_lossy(c).into_owned())
+                    .collect();
+                let utf8_errors = c.get_utf8_errors().iter().map(|f| f.to_string()).collect();
+                format!(
+                    "{{\"vendor\":{},\"comments\":[{}],\"utf8_errors\":[{}]}}",
+                    json_string(&c.get_vendor()),
+                    strings(raw),
+                    strings(utf8_errors)
+                )
+            }
+            None => "null".to_string(),
+        };
+        let comments_error = json_option(
+            self.comments_error
+                .as_ref()
+                .map(|e| json_string(&e.to_string())),
+        );
+        let packets: Vec<String> = self.packets.iter().map(packet_json).collect();
+        format!(
+            "{{\"mode\":{},\"header\":{header},\"comments\":{comments},\"comments_error\":\
+             {comments_error},\"packets\":[{}]}}",
+            json_string(mode_name(self.mode)),
+            packets.join(",")
+        )
+    }
+}
+
+fn packet_json(packet: &PacketInfo) -> String {
+    let frames: Vec<String> = packet
+        .frames
+        .iter()
+        .map(|frame| {
+            let inband: Vec<String> = frame
+                .inband
+                .iter()
+                .map(|message| {
+                    match message {
+                        InbandMessage::Speex { id, value } => {
+                            format!(
+                                "{{\"type\":\"speex\",\"id\":{id},\"name\":{},\"value\":{}}}",
+                                json_string(message.name()),
+                                json_option(*value)
+                            )
+                        }
+                        InbandMessage::User { bytes } => {
+                            format!("{{\"type\":\"user\",\"bytes\":{bytes}}}")
+                        }
+                    }
+                })
+                .collect();
+            let wb_submodes: Vec<String> =
+                frame.wb_submodes.iter().map(|s| json_option(*s)).collect();
+            format!(
+                "{{\"wideband\":{},\"nb_submode\":{},\"wb_submodes\":[{}],\"inband\":[{}],\"bits\"\
+                 :{}}}",
+                frame.wideband(),
+                frame.nb_submode,
+                wb_submodes.join(","),
+                inband.join(","),
+                frame.bits
+            )
+        })
+        .collect();
+    format!(
+        "{{\"index\":{},\"granule_position\":{},\"bytes\":{},\"frames\":[{}],\"error\":{}}}",
+        packet.index,
+        json_option(packet.granule_position),
+        packet.bytes,
+        frames.join(","),
+        json_option(packet.error.map(|e| json_string(&e.to_string())))
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use speex_safe::{DynamicEncoder, NbSubmodeId};
+
+    use super::*;
+
+    fn encode_frame(mode: ModeId, bits: &mut SpeexBits) {
+        let mut encoder = DynamicEncoder::new(mode);
+        let frame_size = encoder.get_frame_size() as usize;
+        let mut input: Vec<f32> = (0..frame_size)
+            .map(|i| (i as f32 * 0.3).sin() * 3000.0)
+            .collect();
+        encoder.encode(&mut input, bits).unwrap();
+    }
+
+    fn packet(bits: &mut SpeexBits) -> Vec<u8> {
+        let mut buffer = vec![0u8; 2000];
+        let len = bits.write(&mut buffer) as usize;
+        buffer.truncate(len);
+        buffer
+    }
+
+    #[test]
+    fn reads_frames_of_each_mode() {
+        for mode in [ModeId::NarrowBand, ModeId::WideBand, ModeId::UltraWideBand] {
+            let mut bits = SpeexBits::new();
+            encode_frame(mode, &mut bits);
+            encode_frame(mode, &mut bits);
+            bits.insert_terminator();
+            let info = read_packet(0, &packet(&mut bits), None, mode);
+
+            assert_eq!(info.error, None);
+            assert_eq!(info.frames.len(), 2);
+            let frame = &info.frames[0];
+            let layers = mode as usize;
+            assert_eq!(frame.wb_submodes.len(), layers);
+            assert_eq!(frame.wideband(), layers > 0);
+            let expected: i32 = ModeId::NarrowBand
+                .get_submode_bits_per_frame(frame.nb_submode as i32)
+                .unwrap()
+                + [ModeId::WideBand, ModeId::UltraWideBand][..layers]
+                    .iter()
+                    .zip(&frame.wb_submodes)
+                    .map(|(m, s)| m.get_submode_bits_per_frame(s.unwrap() as i32).unwrap())
+                    .sum::<i32>();
+            assert_eq!(frame.bits as i32, expected);
+        }
+    }
+
+    #[test]
+    fn reads_inband_messages() {
+        let mut bits = SpeexBits::new();
+        // Mode request for the 8 kbps submode
+        bits.pack(0, 1);
+        bits.pack(INBAND_SPEEX as i32, 4);
+        bits.pack(speex_sys::SPEEX_INBAND_MODE_REQUEST, 4);
+        bits.pack(NbSubmodeId::Low as i32, 4);
+        // User message of 2 bytes
+        bits.pack(0, 1);
+        bits.pack(INBAND_USER as i32, 4);
+        bits.pack(2, 4);
+        bits.pack(0, 5 + 16);
+        encode_frame(ModeId::NarrowBand, &mut bits);
+
+        let info = read_packet(0, &packet(&mut bits), None, ModeId::NarrowBand);
+        assert_eq!(info.error, None);
+        assert_eq!(info.frames.len(), 1);
+        let frame = &info.frames[0];
+        assert_eq!(
+            frame.inband,
+            vec![
+                InbandMessage::Speex {
+                    id: speex_sys::SPEEX_INBAND_MODE_REQUEST as u32,
+                    value: Some(NbSubmodeId::Low as u32)
+                },
+                InbandMessage::User { bytes: 2 },
+            ]
+        );
+        assert_eq!(frame.inband[0].name(), "mode request");
+    }
+
+    #[test]
+    fn reports_bad_frames() {
+        let mut bits = SpeexBits::new();
+        bits.pack(0, 1);
+        bits.pack(11, 4);
+        let info = read_packet(0, &packet(&mut bits), None, ModeId::NarrowBand);
+        assert_eq!(
+            info.error,
+            Some(FrameError::InvalidSubmode {
+                mode: ModeId::NarrowBand,
+                submode: 11
+            })
+        );
+
+        let mut bits = SpeexBits::new();
+        encode_frame(ModeId::NarrowBand, &mut bits);
+        let mut data = packet(&mut bits);
+        data.truncate(10);
+        let info = read_packet(0, &data, None, ModeId::NarrowBand);
+        assert_eq!(info.error, Some(FrameError::Truncated));
+        assert!(info.frames.is_empty());
+    }
+
+    #[test]
+    fn escapes_json() {
+        assert_eq!(json_string("a\"b\\c\n\u{1}"), "\"a\\\"b\\\\c\\n\\u0001\"");
+    }
+}
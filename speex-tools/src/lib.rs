@@ -0,0 +1,193 @@
+////////////////////////////////////////////////////////////////////////////////
+// Copyright (c) 2023.                                                         /
+// This Source Code Form is subject to the terms of the Mozilla Public License,/
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can    /
+// obtain one at http://mozilla.org/MPL/2.0/.                                  /
+////////////////////////////////////////////////////////////////////////////////
+
+//! Pieces shared between the speex command-line tools.
+
+pub mod info;
+
+use std::fs::File;
+use std::io;
+use std::io::{Read, Write};
+use std::str::FromStr;
+
+use speex_safe::ModeId;
+
+/// Command-line arguments, split into `--name` and an optional inline
+/// `--name=value`
+pub struct Args {
+    args: std::vec::IntoIter<String>,
+    inline_value: Option<String>,
+}
+
+impl Args {
+    /// Reads the arguments the program was started with, skipping the program
+    /// name
+    pub fn from_env() -> Self {
+        Self::new(std::env::args().skip(1).collect())
+    }
+
+    pub fn new(args: Vec<String>) -> Self {
+        Self {
+            args: args.into_iter(),
+            inline_value: None,
+        }
+    }
+
+    /// Gets the next flag or positional argument
+    pub fn next_arg(&mut self) -> Result<Option<String>, String> {
+        if let Some(value) = self.inline_value.take() {
+            return Err(format!("unexpected value '{value}'"));
+        }
+        let Some(arg) = self.args.next() else {
+            return Ok(None);
+        };
+        match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => {
+                self.inline_value = Some(value.to_string());
+                Ok(Some(flag.to_string()))
+            }
+            _ => Ok(Some(arg)),
+        }
+    }
+
+    /// Gets the value of the flag that was just read
+    pub fn value<T: FromStr>(&mut self, flag: &str) -> Result<T, String> {
+        let value = self
+            .inline_value
+            .take()
+            .or_else(|| self.args.next())
+            .ok_or_else(|| format!("{flag} requires a value"))?;
+        value
+            .parse()
+            .map_err(|_| format!("invalid value '{value}' for {flag}"))
+    }
+}
+
+/// Reads a whole file, or stdin for `-`
+pub fn read_input(path: &str) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    if path == "-" {
+        io::stdin().lock().read_to_end(&mut data)?;
+    } else {
+        File::open(path)?.read_to_end(&mut data)?;
+    }
+    Ok(data)
+}
+
+/// Writes a whole file, or stdout for `-`
+pub fn write_output(path: &str, data: &[u8]) -> io::Result<()> {
+    if path == "-" {
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(data)?;
+        stdout.flush()
+    } else {
+        File::create(path)?.write_all(data)
+    }
+}
+
+/// Sample layout of headerless PCM input
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct RawFormat {
+    /// Either 8 (unsigned) or 16 (signed)
+    pub bits: u16,
+    pub big_endian: bool,
+}
+
+impl Default for RawFormat {
+    fn default() -> Self {
+        Self {
+            bits: 16,
+            big_endian: false,
+        }
+    }
+}
+
+impl RawFormat {
+    /// Converts raw PCM into samples in the range of an `i16`
+    pub fn read_samples(&self, data: &[u8]) -> Vec<f32> {
+        match self.bits {
+            8 => data.iter().map(|&s| (s as f32 - 128.0) * 256.0).collect(),
+            _ => {
+                data.chunks_exact(2)
+                    .map(|s| {
+                        let bytes = [s[0], s[1]];
+                        let sample = if self.big_endian {
+                            i16::from_be_bytes(bytes)
+                        } else {
+                            i16::from_le_bytes(bytes)
+                        };
+                        sample as f32
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+/// Encodes samples as raw 16 bit little-endian PCM
+pub fn write_raw_samples(samples: &[i16]) -> Vec<u8> {
+    samples.iter().flat_map(|s| s.to_le_bytes()).collect()
+}
+
+/// Gets the name the reference tools use for a mode
+pub fn mode_name(mode: ModeId) -> &'static str {
+    match mode {
+        ModeId::NarrowBand => "narrowband",
+        ModeId::WideBand => "wideband",
+        ModeId::UltraWideBand => "ultra-wideband",
+    }
+}
+
+/// Formats the `--version` output of a tool
+pub fn version_string(program: &str) -> String {
+    format!(
+        "{program} (speex-tools {}) linked against {}",
+        env!("CARGO_PKG_VERSION"),
+        speex_safe::get_version_string()
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn args(args: &[&str]) -> Args {
+        Args::new(args.iter().map(|s| s.to_string()).collect())
+    }
+
+    #[test]
+    fn splits_inline_values() {
+        let mut args = args(&["--quality=4", "--comp", "2", "in.wav"]);
+        assert_eq!(args.next_arg().unwrap().as_deref(), Some("--quality"));
+        assert_eq!(args.value::<i32>("--quality"), Ok(4));
+        assert_eq!(args.next_arg().unwrap().as_deref(), Some("--comp"));
+        assert_eq!(args.value::<i32>("--comp"), Ok(2));
+        assert_eq!(args.next_arg().unwrap().as_deref(), Some("in.wav"));
+        assert_eq!(args.next_arg().unwrap(), None);
+    }
+
+    #[test]
+    fn rejects_unused_inline_value() {
+        let mut args = args(&["--vbr=1"]);
+        args.next_arg().unwrap();
+        assert!(args.next_arg().is_err());
+    }
+
+    #[test]
+    fn reads_raw_formats() {
+        let big_endian = RawFormat {
+            bits: 16,
+            big_endian: true,
+        };
+        assert_eq!(big_endian.read_samples(&[0x01, 0x02]), vec![258.0]);
+        let unsigned = RawFormat {
+            bits: 8,
+            big_endian: false,
+        };
+        assert_eq!(unsigned.read_samples(&[0, 128]), vec![-32768.0, 0.0]);
+    }
+}
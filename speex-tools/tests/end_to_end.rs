@@ -0,0 +1,346 @@
+////////////////////////////////////////////////////////////////////////////////
+// Copyright (c) 2023.                                                         /
+// This Source Code Form is subject to the terms of the Mozilla Public License,/
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can    /
+// obtain one at http://mozilla.org/MPL/2.0/.                                  /
+////////////////////////////////////////////////////////////////////////////////
+
+//! Runs the built speexenc and speexdec binaries on generated audio.
+
+use std::f32::consts::PI;
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+use speex_safe::wav::{read_wav, write_wav};
+
+const SPEEXENC: &str = env!("CARGO_BIN_EXE_speexenc");
+const SPEEXDEC: &str = env!("CARGO_BIN_EXE_speexdec");
+const SPEEXINFO: &str = env!("CARGO_BIN_EXE_speexinfo");
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("speex-tools-{}-{name}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn run(program: &str, args: &[&str]) -> Output {
+    Command::new(program).args(args).output().unwrap()
+}
+
+fn run_ok(program: &str, args: &[&str]) {
+    let output = run(program, args);
+    assert!(
+        output.status.success(),
+        "{program} {args:?} failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+#[path = "../../speex-safe/tests/support/mod.rs"]
+mod support;
+
+/// A speech-like signal: a 200 Hz tone with a few harmonics, fading in and out
+fn tone(sample_rate: u32, channels: u16, seconds: f32) -> Vec<i16> {
+    let len = (sample_rate as f32 * seconds) as usize;
+    let mut signal = vec![0.0; len];
+    for h in 1..=4 {
+        let harmonic = support::tone(sample_rate, 200.0 * h as f64, 6000.0 / h as f32, len);
+        for (n, (sample, x)) in signal.iter_mut().zip(harmonic).enumerate() {
+            *sample += x * (PI * n as f32 / len as f32).sin();
+        }
+    }
+    let quieter: Vec<f32> = signal.iter().map(|x| x * 0.5).collect();
+    let both = [signal.as_slice(), quieter.as_slice()];
+    support::interleave(&both[..channels as usize])
+}
+
+/// White noise, as 16-bit samples
+fn noise(len: usize) -> Vec<i16> {
+    support::interleave(&[&support::noise(len)])
+}
+
+fn write_wav_file(path: &PathBuf, channels: u16, sample_rate: u32, samples: &[i16]) {
+    let mut data = Vec::new();
+    write_wav(&mut data, channels, sample_rate, samples).unwrap();
+    fs::write(path, data).unwrap();
+}
+
+fn energy(samples: impl Iterator<Item = i16>) -> f64 {
+    samples.map(|s| s as f64 * s as f64).sum()
+}
+
+/// Highest normalized cross-correlation between the signals over small lags
+fn correlation(a: &[f32], b: &[f32]) -> f64 {
+    (0..64)
+        .map(|lag| {
+            let (mut ab, mut aa, mut bb) = (0.0f64, 0.0f64, 0.0f64);
+            for (x, y) in a.iter().zip(b.iter().skip(lag)) {
+                ab += (*x as f64) * (*y as f64);
+                aa += (*x as f64) * (*x as f64);
+                bb += (*y as f64) * (*y as f64);
+            }
+            ab / (aa * bb).sqrt().max(1.0)
+        })
+        .fold(f64::MIN, f64::max)
+}
+
+/// Encodes and decodes a tone, checking the decoded audio resembles it
+fn round_trip(name: &str, sample_rate: u32, channels: u16, encode_args: &[&str]) {
+    let dir = temp_dir(name);
+    let input = dir.join("input.wav");
+    let encoded = dir.join("encoded.spx");
+    let decoded = dir.join("decoded.wav");
+    let original = tone(sample_rate, channels, 1.0);
+    write_wav_file(&input, channels, sample_rate, &original);
+
+    let mut args = encode_args.to_vec();
+    args.extend([
+        "--quiet",
+        input.to_str().unwrap(),
+        encoded.to_str().unwrap(),
+    ]);
+    run_ok(SPEEXENC, &args);
+    assert!(fs::read(&encoded).unwrap().starts_with(b"OggS"));
+
+    run_ok(
+        SPEEXDEC,
+        &[
+            "--quiet",
+            encoded.to_str().unwrap(),
+            decoded.to_str().unwrap(),
+        ],
+    );
+    let (spec, samples) = read_wav(fs::read(&decoded).unwrap().as_slice()).unwrap();
+    assert_eq!(spec.sample_rate, sample_rate);
+    assert_eq!(spec.channels, channels);
+    assert_eq!(samples.len(), original.len());
+
+    let original: Vec<f32> = original
+        .iter()
+        .step_by(channels as usize)
+        .map(|&s| s as f32)
+        .collect();
+    let first_channel: Vec<f32> = samples.iter().step_by(channels as usize).copied().collect();
+    let correlation = correlation(&original, &first_channel);
+    assert!(
+        correlation > 0.6,
+        "{name}: correlation {correlation} is too low"
+    );
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn narrowband() {
+    round_trip("nb", 8000, 1, &[]);
+}
+
+#[test]
+fn wideband() {
+    round_trip("wb", 16000, 1, &["--quality", "6"]);
+}
+
+#[test]
+fn ultra_wideband() {
+    round_trip("uwb", 32000, 1, &["--comp=5"]);
+}
+
+#[test]
+fn stereo() {
+    round_trip("stereo", 16000, 2, &[]);
+}
+
+#[test]
+fn rate_control() {
+    round_trip("vbr", 8000, 1, &["--vbr", "--quality", "8"]);
+    round_trip("abr", 16000, 1, &["--abr", "20000"]);
+    round_trip("bitrate", 8000, 1, &["--bitrate", "15000"]);
+    round_trip("vad", 8000, 1, &["--vad", "--dtx"]);
+    round_trip("nframes", 8000, 1, &["--nframes", "4", "--no-highpass"]);
+}
+
+#[test]
+fn preprocessing() {
+    round_trip("denoise", 8000, 1, &["--denoise"]);
+    round_trip("agc", 16000, 2, &["--agc"]);
+}
+
+#[test]
+fn lower_bitrate_is_smaller() {
+    let dir = temp_dir("bitrate-size");
+    let input = dir.join("input.wav");
+    write_wav_file(&input, 1, 16000, &noise(32000));
+
+    let size = |quality: &str| {
+        let output = dir.join(format!("q{quality}.spx"));
+        run_ok(
+            SPEEXENC,
+            &[
+                "--quiet",
+                "--quality",
+                quality,
+                input.to_str().unwrap(),
+                output.to_str().unwrap(),
+            ],
+        );
+        fs::metadata(output).unwrap().len()
+    };
+    assert!(size("2") < size("9"));
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn raw_input_and_output() {
+    let dir = temp_dir("raw");
+    let input = dir.join("input.raw");
+    let encoded = dir.join("encoded.spx");
+    let decoded = dir.join("decoded.raw");
+    let original = tone(8000, 1, 0.5);
+    let raw: Vec<u8> = original.iter().flat_map(|s| s.to_be_bytes()).collect();
+    fs::write(&input, raw).unwrap();
+
+    run_ok(
+        SPEEXENC,
+        &[
+            "--quiet",
+            "-n",
+            "--be",
+            input.to_str().unwrap(),
+            encoded.to_str().unwrap(),
+        ],
+    );
+    run_ok(
+        SPEEXDEC,
+        &[
+            "--quiet",
+            "--no-enh",
+            encoded.to_str().unwrap(),
+            decoded.to_str().unwrap(),
+        ],
+    );
+
+    let decoded = fs::read(&decoded).unwrap();
+    assert_eq!(decoded.len(), original.len() * 2);
+    let samples = decoded
+        .chunks_exact(2)
+        .map(|s| i16::from_le_bytes([s[0], s[1]]));
+    let ratio = energy(samples) / energy(original.iter().copied());
+    assert!((0.25..4.0).contains(&ratio), "energy ratio {ratio}");
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn decodes_with_forced_mode_and_loss() {
+    let dir = temp_dir("forced");
+    let input = dir.join("input.wav");
+    let encoded = dir.join("encoded.spx");
+    let decoded = dir.join("decoded.wav");
+    write_wav_file(&input, 1, 16000, &tone(16000, 1, 1.0));
+
+    run_ok(
+        SPEEXENC,
+        &[
+            "--quiet",
+            input.to_str().unwrap(),
+            encoded.to_str().unwrap(),
+        ],
+    );
+    run_ok(
+        SPEEXDEC,
+        &[
+            "--quiet",
+            "--force-nb",
+            "--packet-loss",
+            "10",
+            "--stereo",
+            encoded.to_str().unwrap(),
+            decoded.to_str().unwrap(),
+        ],
+    );
+
+    let (spec, samples) = read_wav(fs::read(&decoded).unwrap().as_slice()).unwrap();
+    assert_eq!(spec.sample_rate, 8000);
+    assert_eq!(spec.channels, 2);
+    assert_eq!(samples.len(), 16000);
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn dumps_stream_info() {
+    let dir = temp_dir("info");
+    let input = dir.join("input.wav");
+    let encoded = dir.join("encoded.spx");
+    write_wav_file(&input, 2, 16000, &tone(16000, 2, 0.5));
+    run_ok(
+        SPEEXENC,
+        &[
+            "--quiet",
+            "--nframes",
+            "2",
+            "--title",
+            "A tone",
+            "--comment=LOCATION=field",
+            input.to_str().unwrap(),
+            encoded.to_str().unwrap(),
+        ],
+    );
+
+    let output = run(SPEEXINFO, &[encoded.to_str().unwrap()]);
+    assert!(output.status.success());
+    let text = String::from_utf8(output.stdout).unwrap();
+    assert!(text.contains("mode: wideband"), "{text}");
+    assert!(text.contains("channels: 2"));
+    assert!(text.contains("frames per packet: 2"));
+    assert!(text.contains("in-band stereo (9)"));
+    assert!(text.contains("  title=A tone\n  LOCATION=field\n"));
+    assert!(text.contains("0 packets with errors"));
+
+    let output = run(SPEEXINFO, &["--json", encoded.to_str().unwrap()]);
+    assert!(output.status.success());
+    let json = String::from_utf8(output.stdout).unwrap();
+    assert!(json.starts_with("{\"mode\":\"wideband\""), "{json}");
+    assert!(json.contains("\"wideband\":true"));
+    assert!(json.contains("\"name\":\"stereo\""));
+    assert!(!json.contains("\"error\":\""));
+
+    let output = run(SPEEXINFO, &["--raw", "--mode", "xb", "-"]);
+    assert!(!output.status.success());
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn rejects_bad_arguments() {
+    for args in [
+        &["--quality", "11", "in.wav", "out.spx"][..],
+        &["--quality"],
+        &["--no-such-flag", "in.wav", "out.spx"],
+        &["--comment", "no equals sign", "in.wav", "out.spx"],
+        &["only-one-file.wav"],
+    ] {
+        let output = run(SPEEXENC, args);
+        assert!(!output.status.success(), "speexenc accepted {args:?}");
+        assert!(String::from_utf8_lossy(&output.stderr).starts_with("speexenc: "));
+    }
+
+    let output = run(SPEEXDEC, &["--packet-loss", "150", "in.spx", "out.wav"]);
+    assert!(!output.status.success());
+
+    let output = run(SPEEXDEC, &["does-not-exist.spx", "out.wav"]);
+    assert!(!output.status.success());
+}
+
+#[test]
+fn prints_help_and_version() {
+    let output = run(SPEEXENC, &["--help"]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Usage: speexenc"));
+
+    let output = run(SPEEXDEC, &["-v"]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("speex-1.2"));
+}
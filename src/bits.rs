@@ -5,4 +5,410 @@
 // obtain one at http://mozilla.org/MPL/2.0/.                                  /
 ////////////////////////////////////////////////////////////////////////////////
 
-pub enum Bits {}
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Size of the buffer a new `Bits` starts with, as in libspeex
+const INITIAL_BUFFER_SIZE: usize = 2000;
+
+/// A bitstream that Speex frames are packed into and unpacked from
+///
+/// This is a port of libspeex's `SpeexBits`, and behaves the same way down to
+/// its quirks. Bits are stored most significant bit first, and reading and
+/// writing share a single position: packing after reading writes at the read
+/// position, so a stream is usually either `reset` and packed, or filled with
+/// `read_from` and unpacked.
+///
+/// Reading past the end of the stream returns zeros and marks the stream as
+/// overflowed, after which every read returns zero until it is rewound.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Bits {
+    chars: Vec<u8>,
+    /// Number of bits in the stream
+    num_bits: usize,
+    /// Byte of the current position
+    char_ptr: usize,
+    /// Bit of the current position within its byte
+    bit_ptr: usize,
+    overflow: bool,
+}
+
+impl Default for Bits {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Bits {
+    /// Creates an empty bitstream
+    pub fn new() -> Self {
+        Self {
+            chars: vec![0; INITIAL_BUFFER_SIZE],
+            num_bits: 0,
+            char_ptr: 0,
+            bit_ptr: 0,
+            overflow: false,
+        }
+    }
+
+    /// Creates a bitstream to be read from the given bytes
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        let mut bits = Self::new();
+        bits.read_from(bytes);
+        bits
+    }
+
+    /// Resets the bitstream to the initial state, erasing all content
+    pub fn reset(&mut self) {
+        self.chars[0] = 0;
+        self.num_bits = 0;
+        self.char_ptr = 0;
+        self.bit_ptr = 0;
+        self.overflow = false;
+    }
+
+    /// Moves the position back to the beginning, without erasing the content
+    pub fn rewind(&mut self) {
+        self.char_ptr = 0;
+        self.bit_ptr = 0;
+        self.overflow = false;
+    }
+
+    /// Replaces the content of the bitstream with the given bytes
+    pub fn read_from(&mut self, bytes: &[u8]) {
+        if bytes.len() > self.chars.len() {
+            self.chars.resize(bytes.len(), 0);
+        }
+        self.chars[..bytes.len()].copy_from_slice(bytes);
+        self.num_bits = bytes.len() * 8;
+        self.char_ptr = 0;
+        self.bit_ptr = 0;
+        self.overflow = false;
+    }
+
+    /// Drops the bytes before the current position
+    fn flush(&mut self) {
+        let num_chars = self.num_bytes();
+        if self.char_ptr > 0 {
+            self.chars.copy_within(self.char_ptr..num_chars, 0);
+        }
+        self.num_bits -= self.char_ptr * 8;
+        self.char_ptr = 0;
+    }
+
+    /// Appends bytes to the bitstream, dropping the bytes that have already
+    /// been read
+    pub fn read_whole_bytes(&mut self, bytes: &[u8]) {
+        self.flush();
+        let pos = self.num_bits / 8;
+        if pos + bytes.len() + 1 > self.chars.len() {
+            self.chars.resize(pos + bytes.len() + 1, 0);
+        }
+        self.chars[pos..pos + bytes.len()].copy_from_slice(bytes);
+        self.num_bits += bytes.len() * 8;
+    }
+
+    /// Writes the content of the bitstream to a buffer, followed by a
+    /// terminator, and returns the number of bytes written
+    ///
+    /// The position is left where it was, so more bits can still be packed.
+    pub fn write(&mut self, buffer: &mut [u8]) -> usize {
+        let (char_ptr, bit_ptr, num_bits) = (self.char_ptr, self.bit_ptr, self.num_bits);
+        self.insert_terminator();
+        self.char_ptr = char_ptr;
+        self.bit_ptr = bit_ptr;
+        self.num_bits = num_bits;
+
+        let len = buffer.len().min(self.num_bytes());
+        buffer[..len].copy_from_slice(&self.chars[..len]);
+        len
+    }
+
+    /// Writes the whole bytes of the bitstream to a buffer, removing them from
+    /// the bitstream, and returns the number of bytes written
+    ///
+    /// This is meant for streaming out a bitstream as it's being packed, and
+    /// leaves the position in an inconsistent state unless it is at the end.
+    pub fn write_whole_bytes(&mut self, buffer: &mut [u8]) -> usize {
+        let len = buffer.len().min(self.num_bits / 8);
+        buffer[..len].copy_from_slice(&self.chars[..len]);
+
+        self.chars[0] = if self.bit_ptr > 0 { self.chars[len] } else { 0 };
+        self.char_ptr = 0;
+        self.num_bits &= 7;
+        len
+    }
+
+    /// Appends the lowest `num_bits` bits of `data` to the bitstream
+    ///
+    /// Negative values are packed in two's complement.
+    pub fn pack(&mut self, data: u32, num_bits: u32) {
+        debug_assert!(num_bits <= 32, "Can't pack more than 32 bits at once");
+        // The byte after the last written one is cleared as well
+        let needed = self.char_ptr + (num_bits as usize + self.bit_ptr) / 8 + 2;
+        if needed > self.chars.len() {
+            self.chars.resize(needed.max(self.chars.len() * 3 / 2), 0);
+        }
+
+        for i in (0..num_bits).rev() {
+            let bit = ((data >> i) & 1) as u8;
+            self.chars[self.char_ptr] |= bit << (7 - self.bit_ptr);
+            self.bit_ptr += 1;
+            if self.bit_ptr == 8 {
+                self.bit_ptr = 0;
+                self.char_ptr += 1;
+                self.chars[self.char_ptr] = 0;
+            }
+            self.num_bits += 1;
+        }
+    }
+
+    /// Whether the next `num_bits` can be read, marking the stream as
+    /// overflowed if they can't
+    fn check_remaining(&mut self, num_bits: usize) -> bool {
+        if self.char_ptr * 8 + self.bit_ptr + num_bits > self.num_bits {
+            self.overflow = true;
+        }
+        !self.overflow
+    }
+
+    fn bit_at(&self, char_ptr: usize, bit_ptr: usize) -> u32 {
+        ((self.chars[char_ptr] >> (7 - bit_ptr)) & 1) as u32
+    }
+
+    /// Reads the next `num_bits` as an unsigned integer, advancing the
+    /// position
+    pub fn unpack_unsigned(&mut self, num_bits: u32) -> u32 {
+        let value = self.peek_unsigned(num_bits);
+        if !self.overflow {
+            let pos = self.bit_ptr + num_bits as usize;
+            self.char_ptr += pos / 8;
+            self.bit_ptr = pos % 8;
+        }
+        value
+    }
+
+    /// Reads the next `num_bits` as a two's complement signed integer,
+    /// advancing the position
+    pub fn unpack_signed(&mut self, num_bits: u32) -> i32 {
+        debug_assert!(num_bits > 0, "Can't unpack a signed value of 0 bits");
+        let mut value = self.unpack_unsigned(num_bits);
+        if num_bits < 32 && value >> (num_bits - 1) != 0 {
+            value |= u32::MAX << num_bits;
+        }
+        value as i32
+    }
+
+    /// Reads the next `num_bits` as an unsigned integer, without advancing the
+    /// position
+    pub fn peek_unsigned(&mut self, num_bits: u32) -> u32 {
+        debug_assert!(num_bits <= 32, "Can't unpack more than 32 bits at once");
+        if !self.check_remaining(num_bits as usize) {
+            return 0;
+        }
+        let (mut char_ptr, mut bit_ptr) = (self.char_ptr, self.bit_ptr);
+        let mut value = 0u32;
+        for _ in 0..num_bits {
+            value = (value << 1) | self.bit_at(char_ptr, bit_ptr);
+            bit_ptr += 1;
+            if bit_ptr == 8 {
+                bit_ptr = 0;
+                char_ptr += 1;
+            }
+        }
+        value
+    }
+
+    /// Gets the next bit, without advancing the position
+    pub fn peek(&mut self) -> u32 {
+        if !self.check_remaining(1) {
+            return 0;
+        }
+        self.bit_at(self.char_ptr, self.bit_ptr)
+    }
+
+    /// Skips the next `n` bits
+    pub fn advance(&mut self, n: u32) {
+        if !self.check_remaining(n as usize) {
+            return;
+        }
+        let pos = self.bit_ptr + n as usize;
+        self.char_ptr += pos / 8;
+        self.bit_ptr = pos % 8;
+    }
+
+    /// Gets the number of bits left to read, or -1 if the stream has
+    /// overflowed
+    pub fn remaining(&self) -> i32 {
+        if self.overflow {
+            -1
+        } else {
+            // Can be negative after write_whole_bytes, as in libspeex
+            self.num_bits as i32 - (self.char_ptr * 8 + self.bit_ptr) as i32
+        }
+    }
+
+    /// Whether a read went past the end of the stream since it was last
+    /// rewound
+    pub fn is_overflowed(&self) -> bool {
+        self.overflow
+    }
+
+    /// Gets the number of bits in the stream
+    pub fn num_bits(&self) -> usize {
+        self.num_bits
+    }
+
+    /// Gets the number of bytes in the stream, including the last partial
+    /// byte
+    pub fn num_bytes(&self) -> usize {
+        self.num_bits.div_ceil(8)
+    }
+
+    /// Pads the stream to a whole number of bytes with a zero bit followed by
+    /// ones, which decoders read as the end of the packet
+    pub fn insert_terminator(&mut self) {
+        if self.bit_ptr > 0 {
+            self.pack(0, 1);
+        }
+        while self.bit_ptr > 0 {
+            self.pack(1, 1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+    use speex_safe::SpeexBits;
+
+    use super::*;
+
+    #[test]
+    fn packs_msb_first() {
+        let mut bits = Bits::new();
+        bits.pack(0b101, 3);
+        bits.pack(0x1FF, 6);
+        assert_eq!(bits.num_bits(), 9);
+        assert_eq!(bits.num_bytes(), 2);
+
+        let mut buffer = [0u8; 4];
+        assert_eq!(bits.write(&mut buffer), 2);
+        // The second byte ends in a terminator
+        assert_eq!(buffer[..2], [0b1011_1111, 0b1011_1111]);
+
+        bits.rewind();
+        assert_eq!(bits.unpack_unsigned(3), 0b101);
+        assert_eq!(bits.unpack_signed(6), -1);
+        assert_eq!(bits.remaining(), 0);
+        assert_eq!(bits.unpack_unsigned(1), 0);
+        assert!(bits.is_overflowed());
+        assert_eq!(bits.remaining(), -1);
+    }
+
+    #[derive(Clone, Debug)]
+    enum Op {
+        Pack(u32, u32),
+        UnpackUnsigned(u32),
+        UnpackSigned(u32),
+        PeekUnsigned(u32),
+        Peek,
+        Advance(u32),
+        InsertTerminator,
+        Write(usize),
+        WriteWholeBytes(usize),
+        ReadFrom(Vec<u8>),
+        ReadWholeBytes(Vec<u8>),
+        Rewind,
+        Reset,
+    }
+
+    fn op() -> impl Strategy<Value = Op> {
+        prop_oneof![
+            8 => (any::<u32>(), 0..=32u32).prop_map(|(data, n)| Op::Pack(data, n)),
+            4 => (0..=32u32).prop_map(Op::UnpackUnsigned),
+            // libspeex shifts by the bit count when sign extending, which is
+            // undefined for 32 bits
+            4 => (1..=31u32).prop_map(Op::UnpackSigned),
+            2 => (0..=32u32).prop_map(Op::PeekUnsigned),
+            2 => Just(Op::Peek),
+            2 => (0..64u32).prop_map(Op::Advance),
+            1 => Just(Op::InsertTerminator),
+            1 => (0..32usize).prop_map(Op::Write),
+            1 => (0..32usize).prop_map(Op::WriteWholeBytes),
+            1 => prop::collection::vec(any::<u8>(), 0..32).prop_map(Op::ReadFrom),
+            1 => prop::collection::vec(any::<u8>(), 0..32).prop_map(Op::ReadWholeBytes),
+            1 => Just(Op::Rewind),
+            1 => Just(Op::Reset),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn matches_libspeex(ops in prop::collection::vec(op(), 0..200)) {
+            let mut bits = Bits::new();
+            let mut reference = SpeexBits::new();
+
+            for op in ops {
+                match op {
+                    Op::Pack(data, n) => {
+                        bits.pack(data, n);
+                        reference.pack(data as i32, n as i32);
+                    }
+                    Op::UnpackUnsigned(n) => {
+                        prop_assert_eq!(bits.unpack_unsigned(n), reference.unpacked_unsigned(n as i32));
+                    }
+                    Op::UnpackSigned(n) => {
+                        prop_assert_eq!(bits.unpack_signed(n), reference.unpack_signed(n as i32));
+                    }
+                    Op::PeekUnsigned(n) => {
+                        prop_assert_eq!(bits.peek_unsigned(n), reference.peek_unsigned(n as i32));
+                    }
+                    Op::Peek => prop_assert_eq!(bits.peek() as i32, reference.peek()),
+                    Op::Advance(n) => {
+                        bits.advance(n);
+                        reference.advance(n as i32);
+                    }
+                    Op::InsertTerminator => {
+                        bits.insert_terminator();
+                        reference.insert_terminator();
+                    }
+                    Op::Write(len) => {
+                        let (mut ours, mut theirs) = (vec![0; len], vec![0; len]);
+                        prop_assert_eq!(bits.write(&mut ours), reference.write(&mut theirs) as usize);
+                        prop_assert_eq!(ours, theirs);
+                    }
+                    // Anywhere but at the end, libspeex ends up with a negative
+                    // number of bits and reads out of bounds
+                    Op::WriteWholeBytes(_) if reference.remaining() != 0 => {}
+                    Op::WriteWholeBytes(len) => {
+                        let (mut ours, mut theirs) = (vec![0; len], vec![0; len]);
+                        prop_assert_eq!(
+                            bits.write_whole_bytes(&mut ours),
+                            reference.write_whole_bytes(&mut theirs) as usize
+                        );
+                        prop_assert_eq!(ours, theirs);
+                    }
+                    Op::ReadFrom(mut bytes) => {
+                        bits.read_from(&bytes);
+                        reference.read_from(&mut bytes);
+                    }
+                    Op::ReadWholeBytes(bytes) => {
+                        bits.read_whole_bytes(&bytes);
+                        reference.read_whole_bytes(&bytes);
+                    }
+                    Op::Rewind => {
+                        bits.rewind();
+                        reference.rewind();
+                    }
+                    Op::Reset => {
+                        bits.reset();
+                        reference.reset();
+                    }
+                }
+                prop_assert_eq!(bits.remaining(), reference.remaining() as i32);
+                prop_assert_eq!(bits.num_bytes(), reference.num_bytes() as usize);
+            }
+        }
+    }
+}
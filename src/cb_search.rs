@@ -0,0 +1,334 @@
+////////////////////////////////////////////////////////////////////////////////
+// Copyright (c) 2023.                                                         /
+// This Source Code Form is subject to the terms of the Mozilla Public License,/
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can    /
+// obtain one at http://mozilla.org/MPL/2.0/.                                  /
+////////////////////////////////////////////////////////////////////////////////
+
+//! Fixed (innovation) codebooks, ported from libspeex's `cb_search.c`
+
+use crate::filters::{residue_percep_zero16, speex_rand, syn_percep_zero16, WeightedSynthesis};
+use crate::modes::{Innovation, SplitCbParams};
+use crate::vq::{vq_nbest, vq_nbest_sign};
+use crate::Bits;
+
+/// Largest subframe the codebook search works on
+const MAX_SUBFRAME_SIZE: usize = 40;
+/// Largest number of shape entries times their size over all codebooks
+const MAX_CODEBOOK_LEN: usize = 1280;
+/// Largest number of shape entries over all codebooks
+const MAX_CODEBOOK_SIZE: usize = 256;
+/// Largest number of candidates kept by the n-best search
+const MAX_NBEST: usize = 6;
+
+/// Decodes a split shape codebook, adding the shapes to `exc`
+pub(crate) fn split_cb_shape_sign_unquant(
+    exc: &mut [f32],
+    params: &SplitCbParams,
+    bits: &mut Bits,
+) {
+    let mut signs = [false; 16];
+    let mut ind = [0usize; 16];
+    for i in 0..params.nb_subvect {
+        signs[i] = params.have_sign && bits.unpack_unsigned(1) != 0;
+        ind[i] = bits.unpack_unsigned(params.shape_bits) as usize;
+    }
+
+    let size = params.subvect_size;
+    for i in 0..params.nb_subvect {
+        let s: f64 = if signs[i] { -1.0 } else { 1.0 };
+        let shape = &params.shape_cb[ind[i] * size..][..size];
+        for (e, &c) in exc[i * size..][..size].iter_mut().zip(shape) {
+            *e = (*e as f64 + s * 0.03125 * c as f64) as f32;
+        }
+    }
+}
+
+/// Filters every shape of the codebook with the impulse response `r`,
+/// writing the responses to `resp` and their energies to `e`
+fn compute_weighted_codebook(params: &SplitCbParams, r: &[f32], resp: &mut [f32], e: &mut [f32]) {
+    let size = params.subvect_size;
+    let shapes = params.shape_cb[..size << params.shape_bits].chunks_exact(size);
+    for ((shape, res), e) in shapes.zip(resp.chunks_exact_mut(size)).zip(e.iter_mut()) {
+        *e = 0.0;
+        for j in 0..size {
+            let mut resj = 0f32;
+            for k in 0..=j {
+                resj += shape[k] as f32 * r[j - k];
+            }
+            let res16 = 0.03125 * resj;
+            *e += res16 * res16;
+            res[j] = res16;
+        }
+    }
+}
+
+/// Sign and shape index of a codebook entry as packed in the bitstream
+fn split_index(index: usize, params: &SplitCbParams) -> (f64, usize) {
+    let size = 1 << params.shape_bits;
+    if index >= size {
+        (-1.0, index - size)
+    } else {
+        (1.0, index)
+    }
+}
+
+/// Removes the contribution of the shape `index` of subvector `i` from the
+/// rest of the target `t`
+fn update_rest_of_target(t: &mut [f32], r: &[f32], params: &SplitCbParams, i: usize, index: usize) {
+    let size = params.subvect_size;
+    let (sign, rind) = split_index(index, params);
+    let rest = (i + 1) * size;
+    for m in 0..size {
+        let g = (sign * 0.03125 * params.shape_cb[rind * size + m] as f64) as f32;
+        let r = &r[size - m..];
+        for (t, &r) in t[rest..].iter_mut().zip(r) {
+            *t -= g * r;
+        }
+    }
+}
+
+/// Searches the split shape codebook keeping only the best candidate for
+/// each subvector, writing the excitation to `e`
+fn split_cb_search_shape_sign_n1(
+    target: &[f32],
+    params: &SplitCbParams,
+    r: &[f32],
+    resp: &[f32],
+    energy: &[f32],
+    e: &mut [f32],
+    bits: &mut Bits,
+) {
+    let size = params.subvect_size;
+    let mut t = [0f32; MAX_SUBFRAME_SIZE];
+    let t = &mut t[..target.len()];
+    t.copy_from_slice(target);
+
+    for i in 0..params.nb_subvect {
+        let x = &t[size * i..][..size];
+        let mut best_index = [0usize];
+        let mut best_dist = [0f32];
+        if params.have_sign {
+            vq_nbest_sign(x, resp, energy, &mut best_index, &mut best_dist);
+        } else {
+            vq_nbest(x, resp, energy, &mut best_index, &mut best_dist);
+        }
+        let best_index = best_index[0];
+        bits.pack(
+            best_index as u32,
+            params.shape_bits + params.have_sign as u32,
+        );
+
+        let (sign, rind) = split_index(best_index, params);
+        let res = &resp[rind * size..][..size];
+        for (t, &res) in t[size * i..][..size].iter_mut().zip(res) {
+            if sign > 0.0 {
+                *t -= res;
+            } else {
+                *t += res;
+            }
+        }
+        for (e, &c) in e[size * i..][..size]
+            .iter_mut()
+            .zip(&params.shape_cb[rind * size..])
+        {
+            *e = (sign * 0.03125 * c as f64) as f32;
+        }
+
+        update_rest_of_target(t, r, params, i, best_index);
+    }
+}
+
+/// Searches the split shape codebook, keeping the `n` best sequences of
+/// shapes as it goes through the subvectors, writing the excitation to `e`
+#[allow(clippy::too_many_arguments)]
+fn split_cb_search_shape_sign_nbest(
+    target: &[f32],
+    params: &SplitCbParams,
+    r: &[f32],
+    resp: &[f32],
+    energy: &[f32],
+    n: usize,
+    e: &mut [f32],
+    bits: &mut Bits,
+) {
+    let nsf = target.len();
+    let size = params.subvect_size;
+    let nb_subvect = params.nb_subvect;
+
+    let mut ot = [[0f32; MAX_SUBFRAME_SIZE]; MAX_NBEST];
+    let mut nt = [[0f32; MAX_SUBFRAME_SIZE]; MAX_NBEST];
+    let mut oind = [[0usize; 16]; MAX_NBEST];
+    let mut nind = [[0usize; 16]; MAX_NBEST];
+    let mut odist = [0f32; MAX_NBEST];
+    let mut ndist = [0f32; MAX_NBEST];
+    for t in &mut ot[..n] {
+        t[..nsf].copy_from_slice(target);
+    }
+
+    for i in 0..nb_subvect {
+        ndist[..n].fill(1e15);
+        let mut best_nind = [0usize; MAX_NBEST];
+        let mut best_ntarget = [0usize; MAX_NBEST];
+
+        for j in 0..n {
+            let x = &ot[j][size * i..][..size];
+            let mut tener = 0f32;
+            for &v in x {
+                tener += v * v;
+            }
+            let tener = (tener as f64 * 0.5) as f32;
+
+            let mut best_index = [0usize; MAX_NBEST];
+            let mut best_dist = [0f32; MAX_NBEST];
+            if params.have_sign {
+                vq_nbest_sign(x, resp, energy, &mut best_index[..n], &mut best_dist[..n]);
+            } else {
+                vq_nbest(x, resp, energy, &mut best_index[..n], &mut best_dist[..n]);
+            }
+
+            for k in 0..n {
+                let err = (odist[j] + best_dist[k]) + tener;
+                if err < ndist[n - 1] {
+                    if let Some(m) = ndist[..n].iter().position(|&d| err < d) {
+                        for l in (m + 1..n).rev() {
+                            ndist[l] = ndist[l - 1];
+                            best_nind[l] = best_nind[l - 1];
+                            best_ntarget[l] = best_ntarget[l - 1];
+                        }
+                        ndist[m] = err;
+                        best_nind[m] = best_index[k];
+                        best_ntarget[m] = j;
+                    }
+                }
+            }
+            // All the candidates start from the same target
+            if i == 0 {
+                break;
+            }
+        }
+
+        for j in 0..n {
+            let rest = (i + 1) * size;
+            nt[j][rest..nsf].copy_from_slice(&ot[best_ntarget[j]][rest..nsf]);
+            update_rest_of_target(&mut nt[j][..nsf], r, params, i, best_nind[j]);
+            nind[j] = oind[best_ntarget[j]];
+            nind[j][i] = best_nind[j];
+        }
+
+        core::mem::swap(&mut ot, &mut nt);
+        oind = nind;
+        odist = ndist;
+    }
+
+    for (i, &index) in nind[0][..nb_subvect].iter().enumerate() {
+        bits.pack(index as u32, params.shape_bits + params.have_sign as u32);
+        let (sign, rind) = split_index(index, params);
+        for (e, &c) in e[size * i..][..size]
+            .iter_mut()
+            .zip(&params.shape_cb[rind * size..])
+        {
+            *e = (sign * 0.03125 * c as f64) as f32;
+        }
+    }
+}
+
+/// Quantizes `target` with a split shape codebook, adding the chosen shapes
+/// to `exc`
+///
+/// When `update_target` is set, the contribution of the shapes is removed
+/// from `target` so a second codebook can be searched.
+fn split_cb_search_shape_sign(
+    target: &mut [f32],
+    filters: &WeightedSynthesis,
+    params: &SplitCbParams,
+    exc: &mut [f32],
+    bits: &mut Bits,
+    complexity: i32,
+    update_target: bool,
+) {
+    let nsf = target.len();
+    let entries = 1 << params.shape_bits;
+    let mut resp = [0f32; MAX_CODEBOOK_LEN];
+    let resp = &mut resp[..entries * params.subvect_size];
+    let mut energy = [0f32; MAX_CODEBOOK_SIZE];
+    let energy = &mut energy[..entries];
+    compute_weighted_codebook(params, filters.resp, resp, energy);
+
+    // Complexity isn't as important for the codebooks as it is for the pitch
+    let n = ((2 * complexity.min(10)) / 3).max(1) as usize;
+    let mut e = [0f32; MAX_SUBFRAME_SIZE];
+    let e = &mut e[..nsf];
+    if n == 1 {
+        split_cb_search_shape_sign_n1(target, params, filters.resp, resp, energy, e, bits);
+    } else {
+        split_cb_search_shape_sign_nbest(target, params, filters.resp, resp, energy, n, e, bits);
+    }
+
+    for (x, &e) in exc.iter_mut().zip(e.iter()) {
+        *x += e;
+    }
+
+    if update_target {
+        syn_percep_zero16(e, filters);
+        for (t, &r) in target.iter_mut().zip(e.iter()) {
+            *t -= r;
+        }
+    }
+}
+
+/// Encodes the innovation of a subframe as inverse-filtered noise, adding
+/// it to `exc`
+fn noise_codebook_quant(target: &mut [f32], filters: &WeightedSynthesis, exc: &mut [f32]) {
+    let mut tmp = [0f32; MAX_SUBFRAME_SIZE];
+    let tmp = &mut tmp[..target.len()];
+    tmp.copy_from_slice(target);
+    residue_percep_zero16(tmp, filters);
+    for (x, &t) in exc.iter_mut().zip(tmp.iter()) {
+        *x += t;
+    }
+    target.fill(0.0);
+}
+
+/// Encodes the innovation that best matches `target`, adding it to `exc`
+pub(crate) fn innovation_quant(
+    innovation: &Innovation,
+    target: &mut [f32],
+    filters: &WeightedSynthesis,
+    exc: &mut [f32],
+    bits: &mut Bits,
+    complexity: i32,
+    update_target: bool,
+) {
+    match innovation {
+        Innovation::Noise => noise_codebook_quant(target, filters, exc),
+        Innovation::SplitCb(params) => {
+            split_cb_search_shape_sign(
+                target,
+                filters,
+                params,
+                exc,
+                bits,
+                complexity,
+                update_target,
+            )
+        }
+    }
+}
+
+/// Decodes the innovation of a subframe into `exc`
+pub(crate) fn innovation_unquant(
+    innovation: &Innovation,
+    exc: &mut [f32],
+    bits: &mut Bits,
+    seed: &mut u32,
+) {
+    match innovation {
+        Innovation::Noise => {
+            for e in exc.iter_mut() {
+                *e = speex_rand(1.0, seed);
+            }
+        }
+        Innovation::SplitCb(params) => split_cb_shape_sign_unquant(exc, params, bits),
+    }
+}
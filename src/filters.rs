@@ -0,0 +1,409 @@
+////////////////////////////////////////////////////////////////////////////////
+// Copyright (c) 2023.                                                         /
+// This Source Code Form is subject to the terms of the Mozilla Public License,/
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can    /
+// obtain one at http://mozilla.org/MPL/2.0/.                                  /
+////////////////////////////////////////////////////////////////////////////////
+
+//! Filtering helpers shared by the codecs, ported from libspeex's `filters.c`
+//!
+//! These follow the floating point build of libspeex, including where it
+//! promotes to double precision, so that the output matches it exactly.
+
+#[cfg(not(any(feature = "std", test)))]
+use num_traits::Float;
+
+/// Highpass filter for narrowband output
+pub(crate) const HIGHPASS_NARROWBAND: usize = 0;
+/// Selects the input variant of a highpass filter
+pub(crate) const HIGHPASS_INPUT: usize = 0;
+/// Selects the output variant of a highpass filter
+pub(crate) const HIGHPASS_OUTPUT: usize = 1;
+/// Highpass filter for the low band of a wideband codec
+pub(crate) const HIGHPASS_WIDEBAND: usize = 2;
+
+const HIGHPASS_POLES: [[f32; 3]; 5] = [
+    [1.00000, -1.91120, 0.91498],
+    [1.00000, -1.92683, 0.93071],
+    [1.00000, -1.93338, 0.93553],
+    [1.00000, -1.97226, 0.97332],
+    [1.00000, -1.37000, 0.39900],
+];
+
+const HIGHPASS_ZEROS: [[f32; 3]; 5] = [
+    [0.95654, -1.91309, 0.95654],
+    [0.96446, -1.92879, 0.96446],
+    [0.96723, -1.93445, 0.96723],
+    [0.98645, -1.97277, 0.98645],
+    [0.88000, -1.76000, 0.88000],
+];
+
+/// Interpolation filters for fractional pitch delays
+#[allow(clippy::excessive_precision)]
+const SHIFT_FILT: [[f32; 7]; 3] = [
+    [
+        -0.011915, 0.046995, -0.152373, 0.614108, 0.614108, -0.152373, 0.046995,
+    ],
+    [
+        -0.0324855, 0.0859768, -0.2042986, 0.9640297, 0.2086420, -0.0302054, -0.0063646,
+    ],
+    [
+        -0.0063646, -0.0302054, 0.2086420, 0.9640297, -0.2042986, 0.0859768, -0.0324855,
+    ],
+];
+
+/// Uniform noise with standard deviation `std`, from libspeex's generator
+pub(crate) fn speex_rand(std: f32, seed: &mut u32) -> f32 {
+    *seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+    let ran = f32::from_bits(0x3F80_0000 | (*seed & 0x007F_FFFF));
+    let ran = (ran as f64 - 1.5) as f32;
+    (3.4642 * std as f64 * ran as f64) as f32
+}
+
+/// Second order highpass filter, `filter` selecting one of libspeex's
+/// `HIGHPASS_*` combinations
+pub(crate) fn highpass(x: &mut [f32], filter: usize, mem: &mut [f32; 2]) {
+    let filter = filter.min(4);
+    let den = &HIGHPASS_POLES[filter];
+    let num = &HIGHPASS_ZEROS[filter];
+    for sample in x.iter_mut() {
+        let xi = *sample;
+        let vout = num[0] * xi + mem[0];
+        mem[0] = mem[1] + num[1] * xi + -den[1] * vout;
+        mem[1] = num[2] * xi + -den[2] * vout;
+        *sample = vout;
+    }
+}
+
+/// Root mean square of a signal
+pub(crate) fn compute_rms(x: &[f32]) -> f32 {
+    let mut sum = 0f32;
+    for &v in x {
+        sum += v * v;
+    }
+    (0.1 + (sum / x.len() as f32) as f64).sqrt() as f32
+}
+
+/// Replaces values outside of `[min, max]` by the nearest bound, and NaNs by
+/// zero
+pub(crate) fn sanitize_values(x: &mut [f32], min: f32, max: f32) {
+    for v in x.iter_mut() {
+        if !(*v >= min && *v <= max) {
+            if *v < min {
+                *v = min;
+            } else if *v > max {
+                *v = max;
+            } else {
+                *v = 0.0;
+            }
+        }
+    }
+}
+
+/// Applies bandwidth expansion to LPC coefficients
+pub(crate) fn bw_lpc(gamma: f32, lpc_in: &[f32], lpc_out: &mut [f32]) {
+    let mut tmp = gamma;
+    for (out, &lpc) in lpc_out.iter_mut().zip(lpc_in) {
+        *out = tmp * lpc;
+        tmp *= gamma;
+    }
+}
+
+/// All-pole synthesis filter, working in place on `x`
+pub(crate) fn iir_mem16(x: &mut [f32], den: &[f32], mem: &mut [f32]) {
+    let ord = den.len();
+    for sample in x.iter_mut() {
+        let yi = *sample + mem[0];
+        let nyi = -yi;
+        for j in 0..ord - 1 {
+            mem[j] = mem[j + 1] + den[j] * nyi;
+        }
+        mem[ord - 1] = den[ord - 1] * nyi;
+        *sample = yi;
+    }
+}
+
+/// Pole-zero filter, working in place on `x`
+pub(crate) fn filter_mem16(x: &mut [f32], num: &[f32], den: &[f32], mem: &mut [f32]) {
+    let ord = den.len();
+    for sample in x.iter_mut() {
+        let xi = *sample;
+        let yi = xi + mem[0];
+        let nyi = -yi;
+        for j in 0..ord - 1 {
+            mem[j] = mem[j + 1] + num[j] * xi + den[j] * nyi;
+        }
+        mem[ord - 1] = num[ord - 1] * xi + den[ord - 1] * nyi;
+        *sample = yi;
+    }
+}
+
+/// All-zero filter, working in place on `x`
+pub(crate) fn fir_mem16(x: &mut [f32], num: &[f32], mem: &mut [f32]) {
+    let ord = num.len();
+    for sample in x.iter_mut() {
+        let xi = *sample;
+        let yi = xi + mem[0];
+        for j in 0..ord - 1 {
+            mem[j] = mem[j + 1] + num[j] * xi;
+        }
+        mem[ord - 1] = num[ord - 1] * xi;
+        *sample = yi;
+    }
+}
+
+/// The filters an encoder searches its codebooks through: the synthesis
+/// filter `ak` followed by the perceptual weighting filter `awk1 / awk2`
+pub(crate) struct WeightedSynthesis<'a> {
+    pub ak: &'a [f32],
+    pub awk1: &'a [f32],
+    pub awk2: &'a [f32],
+    /// Impulse response of the combined filter
+    pub resp: &'a [f32],
+}
+
+/// Filters `x` in place through the weighted synthesis filter, starting from
+/// a zero state
+pub(crate) fn syn_percep_zero16(x: &mut [f32], filters: &WeightedSynthesis) {
+    let ord = filters.ak.len();
+    let mut mem = [0f32; 16];
+    iir_mem16(x, filters.ak, &mut mem[..ord]);
+    let mut mem = [0f32; 16];
+    filter_mem16(x, filters.awk1, filters.awk2, &mut mem[..ord]);
+}
+
+/// Inverse of [`syn_percep_zero16`], going from the weighted domain back to
+/// the excitation
+pub(crate) fn residue_percep_zero16(x: &mut [f32], filters: &WeightedSynthesis) {
+    let ord = filters.ak.len();
+    let mut mem = [0f32; 16];
+    filter_mem16(x, filters.ak, filters.awk1, &mut mem[..ord]);
+    let mut mem = [0f32; 16];
+    fir_mem16(x, filters.awk2, &mut mem[..ord]);
+}
+
+/// Impulse response of the synthesis filter `ak` followed by the weighting
+/// filter `awk1 / awk2`, truncated to the length of `y`
+pub(crate) fn compute_impulse_response(ak: &[f32], awk1: &[f32], awk2: &[f32], y: &mut [f32]) {
+    let ord = ak.len();
+    y[0] = 1.0;
+    y[1..=ord].copy_from_slice(awk1);
+    y[ord + 1..].fill(1e-15);
+
+    let mut mem1 = [0f32; 16];
+    let mut mem2 = [0f32; 16];
+    for yi in y.iter_mut() {
+        let y1 = *yi + mem1[0];
+        let ny1i = -y1;
+        *yi = y1 + mem2[0];
+        let ny2i = -*yi;
+        for j in 0..ord - 1 {
+            mem1[j] = mem1[j + 1] + awk2[j] * ny1i;
+            mem2[j] = mem2[j + 1] + ak[j] * ny2i;
+        }
+        mem1[ord - 1] = awk2[ord - 1] * ny1i;
+        mem2[ord - 1] = ak[ord - 1] * ny2i;
+    }
+}
+
+/// Dot product of `x` and `y`, summed in blocks of four samples like libspeex
+pub(crate) fn inner_prod(x: &[f32], y: &[f32]) -> f32 {
+    let mut sum = 0f32;
+    for (x, y) in x.chunks_exact(4).zip(y.chunks_exact(4)) {
+        let mut part = 0f32;
+        part += x[0] * y[0];
+        part += x[1] * y[1];
+        part += x[2] * y[2];
+        part += x[3] * y[3];
+        sum += part;
+    }
+    sum
+}
+
+/// Interpolates the excitation at `pos` delayed by a fractional pitch near
+/// `pitch`, which may be negative to look ahead
+fn interp_pitch(exc: &[f32], pos: usize, interp: &mut [f32], pitch: i32) {
+    let len = interp.len();
+    let at = |offset: i32| (pos as i32 + offset) as usize;
+
+    let mut corr = [[0f32; 7]; 4];
+    for (i, c) in corr[0].iter_mut().enumerate() {
+        let start = at(-pitch - 3 + i as i32);
+        *c = inner_prod(&exc[pos..pos + len], &exc[start..start + len]);
+    }
+    for i in 0..3 {
+        for j in 0..7 {
+            let i1 = 3usize.saturating_sub(j);
+            let i2 = (10 - j).min(7);
+            let mut tmp = 0f32;
+            for k in i1..i2 {
+                tmp += SHIFT_FILT[i][k] * corr[0][j + k - 3];
+            }
+            corr[i + 1][j] = tmp;
+        }
+    }
+
+    let (mut maxi, mut maxj) = (0, 0);
+    let mut maxcorr = corr[0][0];
+    for (i, row) in corr.iter().enumerate() {
+        for (j, &c) in row.iter().enumerate() {
+            if c > maxcorr {
+                maxcorr = c;
+                maxi = i;
+                maxj = j;
+            }
+        }
+    }
+
+    let delay = pitch - maxj as i32 + 3;
+    for (i, out) in interp.iter_mut().enumerate() {
+        let base = i as i32 - delay;
+        *out = if maxi > 0 {
+            let mut tmp = 0f32;
+            for (k, &filt) in SHIFT_FILT[maxi - 1].iter().enumerate() {
+                tmp += exc[at(base + k as i32 - 3)] * filt;
+            }
+            tmp
+        } else {
+            exc[at(base)]
+        };
+    }
+}
+
+/// Pitch enhancer applied to the excitation at `pos` before synthesis
+pub(crate) fn multicomb(
+    exc: &[f32],
+    pos: usize,
+    new_exc: &mut [f32],
+    pitch: i32,
+    max_pitch: i32,
+    comb_gain: f32,
+) {
+    let nsf = new_exc.len();
+    let corr_pitch = pitch;
+    let cur = &exc[pos..pos + nsf];
+
+    let mut iexc = [0f32; 160];
+    let (iexc0, iexc1) = iexc[..2 * nsf].split_at_mut(nsf);
+    interp_pitch(exc, pos, iexc0, corr_pitch);
+    if corr_pitch > max_pitch {
+        interp_pitch(exc, pos, iexc1, 2 * corr_pitch);
+    } else {
+        interp_pitch(exc, pos, iexc1, -corr_pitch);
+    }
+
+    let iexc0_mag = ((1000.0 + inner_prod(iexc0, iexc0)) as f64).sqrt() as f32;
+    let iexc1_mag = ((1000.0 + inner_prod(iexc1, iexc1)) as f64).sqrt() as f32;
+    let exc_mag = ((1.0 + inner_prod(cur, cur)) as f64).sqrt() as f32;
+    let corr0 = inner_prod(iexc0, cur).max(0.0);
+    let corr1 = inner_prod(iexc1, cur).max(0.0);
+
+    let pgain1 = if corr0 > iexc0_mag * exc_mag {
+        1.0
+    } else {
+        corr0 / exc_mag / iexc0_mag
+    };
+    let pgain2 = if corr1 > iexc1_mag * exc_mag {
+        1.0
+    } else {
+        corr1 / exc_mag / iexc1_mag
+    };
+    let gg1 = exc_mag / iexc0_mag;
+    let gg2 = exc_mag / iexc1_mag;
+
+    let (c1, c2) = if comb_gain > 0.0 {
+        let c1 = (0.4 * comb_gain as f64 + 0.07) as f32;
+        let c2 = (0.5 + 1.72 * (c1 as f64 - 0.07)) as f32;
+        (c1, c2)
+    } else {
+        (0.0, 0.0)
+    };
+    let g1 = c1 / (1.0 - c2 * pgain1 * pgain1).max(c1);
+    let g2 = c1 / (1.0 - c2 * pgain2 * pgain2).max(c1);
+
+    let (gain0, gain1) = if corr_pitch > max_pitch {
+        (0.7 * (g1 * gg1) as f64, 0.3 * (g2 * gg2) as f64)
+    } else {
+        (0.6 * (g1 * gg1) as f64, 0.6 * (g2 * gg2) as f64)
+    };
+    let (gain0, gain1) = (gain0 as f32, gain1 as f32);
+
+    for (i, out) in new_exc.iter_mut().enumerate() {
+        *out = cur[i] + (gain0 * iexc0[i] + gain1 * iexc1[i]);
+    }
+
+    let new_ener = compute_rms(new_exc).max(1.0);
+    let old_ener = compute_rms(cur).max(1.0).min(new_ener);
+    let ngain = old_ener / new_ener;
+    for out in new_exc.iter_mut() {
+        *out *= ngain;
+    }
+}
+
+/// Synthesis half of a QMF filter bank, merging in place the low band held in
+/// the first half of `y` with the high band held in the second half
+///
+/// `a` is the prototype filter, and `mem1`/`mem2` keep the history of each band
+/// between calls.
+pub(crate) fn qmf_synth(y: &mut [f32], a: &[f32], mem1: &mut [f32], mem2: &mut [f32]) {
+    let n2 = y.len() >> 1;
+    let m2 = a.len() >> 1;
+    let (x1, x2) = y.split_at(n2);
+
+    let mut xx1 = [0f32; 320 + 32];
+    let mut xx2 = [0f32; 320 + 32];
+    for i in 0..n2 {
+        xx1[i] = x1[n2 - 1 - i];
+        xx2[i] = x2[n2 - 1 - i];
+    }
+    for i in 0..m2 {
+        xx1[n2 + i] = mem1[2 * i + 1];
+        xx2[n2 + i] = mem2[2 * i + 1];
+    }
+
+    for i in (0..n2).step_by(2) {
+        let (mut y0, mut y1, mut y2, mut y3) = (0f32, 0f32, 0f32, 0f32);
+        let mut x10 = xx1[n2 - 2 - i];
+        let mut x20 = xx2[n2 - 2 - i];
+        for j in (0..m2).step_by(2) {
+            let (a0, a1) = (a[2 * j], a[2 * j + 1]);
+            let x11 = xx1[n2 - 1 + j - i];
+            let x21 = xx2[n2 - 1 + j - i];
+            y0 += a0 * (x11 - x21);
+            y1 += a1 * (x11 + x21);
+            y2 += a0 * (x10 - x20);
+            y3 += a1 * (x10 + x20);
+
+            let (a0, a1) = (a[2 * j + 2], a[2 * j + 3]);
+            x10 = xx1[n2 + j - i];
+            x20 = xx2[n2 + j - i];
+            y0 += a0 * (x10 - x20);
+            y1 += a1 * (x10 + x20);
+            y2 += a0 * (x11 - x21);
+            y3 += a1 * (x11 + x21);
+        }
+        y[2 * i] = 2.0 * y0;
+        y[2 * i + 1] = 2.0 * y1;
+        y[2 * i + 2] = 2.0 * y2;
+        y[2 * i + 3] = 2.0 * y3;
+    }
+
+    for i in 0..m2 {
+        mem1[2 * i + 1] = xx1[i];
+        mem2[2 * i + 1] = xx2[i];
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn iir_mem16_impulse_response() {
+        let den = [0.5f32, -0.25];
+        let mut mem = [0f32; 2];
+        let mut x = [1f32, 0.0, 0.0, 0.0];
+        iir_mem16(&mut x, &den, &mut mem);
+        assert_eq!(x, [1.0, -0.5, 0.5, -0.375]);
+    }
+}
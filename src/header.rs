@@ -5,19 +5,265 @@
 // obtain one at http://mozilla.org/MPL/2.0/.                                  /
 ////////////////////////////////////////////////////////////////////////////////
 
+//! The header packet that starts every Speex stream
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::{Display, Formatter};
+
+#[cfg(feature = "libspeex")]
+use speex_safe::SpeexHeader;
 
 use crate::Mode;
 
+/// Length of the version string field
+const VERSION_LENGTH: usize = 20;
+
+/// Errors from parsing a header packet
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum HeaderError {
+    /// The packet is shorter than [`Header::SIZE`]
+    TooShort,
+    /// The packet doesn't start with [`Header::MAGIC`]
+    BadMagic,
+    /// The mode field isn't a known mode
+    InvalidMode(i32),
+}
+
+impl Display for HeaderError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            HeaderError::TooShort => write!(f, "Speex header too small"),
+            HeaderError::BadMagic => write!(f, "Packet is not a Speex header"),
+            HeaderError::InvalidMode(mode) => {
+                write!(f, "Invalid mode {mode} specified in Speex header")
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for HeaderError {}
+
+/// Standard speex stream header, laid out the same way as libspeex's
+/// `SpeexHeader`
+#[derive(Clone, Eq, PartialEq, Debug)]
 pub struct Header {
-    pub sample_rate: u32,
+    /// Version of the library that wrote the stream, at most 20 bytes
+    pub version: String,
+    /// Version of the header format
+    pub version_id: i32,
+    pub sample_rate: i32,
     pub mode: Mode,
+    /// Bitstream version of the mode
+    pub mode_bitstream_version: i32,
+    /// Number of channels, either 1 or 2
+    pub channels: i32,
+    /// Bitrate of the stream, or -1 if it is unknown
+    pub bitrate: i32,
+    /// Size (in samples) of a frame
+    pub frame_size: i32,
     pub variable_bit_rate: bool,
+    pub frames_per_packet: i32,
+    /// Number of additional headers following the comment packet
+    pub extra_headers: i32,
 }
 
 impl Header {
     pub const MAGIC: &'static [u8; 8] = b"Speex   ";
+    /// Size of a header packet
+    pub const SIZE: usize = 80;
+
+    /// Creates the header of a mono stream, stamped with the version of the
+    /// linked libspeex, or of this crate without the `libspeex` feature
+    pub fn new(mode: Mode, sample_rate: i32) -> Self {
+        #[cfg(feature = "libspeex")]
+        let version = Self::get_version_string();
+        #[cfg(not(feature = "libspeex"))]
+        let version = String::from(concat!("speex-rs-", env!("CARGO_PKG_VERSION")));
+        Self {
+            version,
+            version_id: 1,
+            sample_rate,
+            mode,
+            mode_bitstream_version: 4,
+            channels: 1,
+            bitrate: -1,
+            frame_size: mode.get_frame_size(),
+            variable_bit_rate: false,
+            frames_per_packet: 0,
+            extra_headers: 0,
+        }
+    }
+
+    /// Gets the version string of the linked libspeex, as written in new
+    /// headers
+    #[cfg(feature = "libspeex")]
+    pub fn get_version_string() -> String {
+        speex_safe::get_version_string()
+    }
+
+    /// Parses a header from the first packet of a speex stream
+    ///
+    /// Like libspeex, out of range channel counts are clamped rather than
+    /// rejected.
+    pub fn from_packet(packet: &[u8]) -> Result<Self, HeaderError> {
+        if packet.len() < Self::SIZE {
+            return Err(HeaderError::TooShort);
+        }
+        if !packet.starts_with(Self::MAGIC) {
+            return Err(HeaderError::BadMagic);
+        }
+
+        let version = &packet[8..8 + VERSION_LENGTH];
+        let version_len = version.iter().position(|&c| c == 0);
+        let version = &version[..version_len.unwrap_or(VERSION_LENGTH)];
+        let field = |index: usize| {
+            let start = 8 + VERSION_LENGTH + 4 * index;
+            i32::from_le_bytes(packet[start..start + 4].try_into().unwrap())
+        };
+
+        let mode = match field(3) {
+            0 => Mode::NarrowBand,
+            1 => Mode::WideBand,
+            2 => Mode::UltraWideBand,
+            mode => return Err(HeaderError::InvalidMode(mode)),
+        };
+        Ok(Self {
+            version: String::from_utf8_lossy(version).into_owned(),
+            version_id: field(0),
+            sample_rate: field(2),
+            mode,
+            mode_bitstream_version: field(4),
+            channels: field(5).clamp(1, 2),
+            bitrate: field(6),
+            frame_size: field(7),
+            variable_bit_rate: field(8) != 0,
+            frames_per_packet: field(9),
+            extra_headers: field(10),
+        })
+    }
+
+    /// Serializes the header into the packet that starts a speex stream
+    pub fn to_packet(&self) -> Vec<u8> {
+        let mut packet = Vec::with_capacity(Self::SIZE);
+        packet.extend_from_slice(Self::MAGIC);
+        let mut version = [0u8; VERSION_LENGTH];
+        let len = self.version.len().min(VERSION_LENGTH);
+        version[..len].copy_from_slice(&self.version.as_bytes()[..len]);
+        packet.extend_from_slice(&version);
+
+        let fields = [
+            self.version_id,
+            Self::SIZE as i32,
+            self.sample_rate,
+            self.mode as i32,
+            self.mode_bitstream_version,
+            self.channels,
+            self.bitrate,
+            self.frame_size,
+            self.variable_bit_rate as i32,
+            self.frames_per_packet,
+            self.extra_headers,
+            // Reserved
+            0,
+            0,
+        ];
+        for field in fields {
+            packet.extend_from_slice(&field.to_le_bytes());
+        }
+        packet
+    }
+}
+
+/// Nothing is lost, as libspeex only holds what fits in a header packet
+#[cfg(feature = "libspeex")]
+impl From<&SpeexHeader> for Header {
+    fn from(header: &SpeexHeader) -> Self {
+        Self {
+            version: header.get_version(),
+            version_id: header.get_version_id(),
+            sample_rate: header.get_rate(),
+            mode: header.get_mode(),
+            mode_bitstream_version: header.get_mode_bitstream_version(),
+            channels: header.get_nb_channels(),
+            bitrate: header.get_bitrate(),
+            frame_size: header.get_frame_size(),
+            variable_bit_rate: header.get_vbr(),
+            frames_per_packet: header.get_frames_per_packet(),
+            extra_headers: header.get_extra_headers(),
+        }
+    }
+}
+
+/// Converts through [`Header::to_packet`], so a `version` longer than 20 bytes
+/// is cut short, and libspeex clamps `channels` to 1 or 2 like
+/// [`Header::from_packet`]. Headers parsed from a packet convert back
+/// unchanged.
+#[cfg(feature = "libspeex")]
+impl From<&Header> for SpeexHeader {
+    fn from(header: &Header) -> Self {
+        SpeexHeader::from_packet(&header.to_packet())
+            .expect("libspeex rejected a header packet with a valid mode")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[cfg(feature = "libspeex")]
+    use speex_safe::ModeId;
+
+    use super::*;
+
+    #[test]
+    #[cfg(feature = "libspeex")]
+    fn matches_libspeex_packets() {
+        let mut sys = SpeexHeader::new(16000, 2, ModeId::WideBand.get_mode());
+        sys.set_vbr(true);
+        sys.set_frames_per_packet(3);
+        let packet = sys.make_packet();
+
+        let header = Header::from_packet(&packet).unwrap();
+        assert_eq!(header, Header::from(&sys));
+        assert_eq!(header.to_packet(), packet);
+        assert_eq!(header.mode, Mode::WideBand);
+        assert_eq!(header.channels, 2);
+        assert_eq!(header.frame_size, 320);
+
+        let sys = SpeexHeader::new(32000, 1, ModeId::UltraWideBand.get_mode());
+        let mut ours = Header::new(Mode::UltraWideBand, 32000);
+        assert_eq!(ours, Header::from(&sys));
+        ours.bitrate = 28000;
+        let mut converted = SpeexHeader::from(&ours);
+        assert_eq!(Header::from(&converted), ours);
+        assert_eq!(converted.make_packet(), ours.to_packet());
+    }
+
+    #[test]
+    #[cfg(feature = "libspeex")]
+    fn clamps_what_packets_cannot_hold() {
+        let mut header = Header::new(Mode::NarrowBand, 8000);
+        header.version = "a".repeat(30);
+        header.channels = 5;
+        let converted = Header::from(&SpeexHeader::from(&header));
+        assert_eq!(converted.version, "a".repeat(20));
+        assert_eq!(converted.channels, 2);
+        assert_eq!(Header::from_packet(&header.to_packet()), Ok(converted));
+    }
 
-    pub fn get_version_string() -> &'static str {
-        "1.2rc2"
+    #[test]
+    fn rejects_bad_packets() {
+        let mut packet = Header::new(Mode::NarrowBand, 8000).to_packet();
+        assert_eq!(
+            Header::from_packet(&packet[..79]),
+            Err(HeaderError::TooShort)
+        );
+        packet[40..44].copy_from_slice(&3i32.to_le_bytes());
+        assert_eq!(
+            Header::from_packet(&packet),
+            Err(HeaderError::InvalidMode(3))
+        );
+        packet[0] = b's';
+        assert_eq!(Header::from_packet(&packet), Err(HeaderError::BadMagic));
     }
 }
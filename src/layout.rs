@@ -0,0 +1,140 @@
+////////////////////////////////////////////////////////////////////////////////
+// Copyright (c) 2023.                                                         /
+// This Source Code Form is subject to the terms of the Mozilla Public License,/
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can    /
+// obtain one at http://mozilla.org/MPL/2.0/.                                  /
+////////////////////////////////////////////////////////////////////////////////
+
+//! Bit layout of narrowband frames, used to tell which coded parameter two
+//! bitstreams disagree on
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::{Display, Formatter};
+
+use crate::modes::{Innovation, LspQuant, Ltp, NB_SUBMODE_TABLE};
+use crate::nb_decoder::NB_NB_SUBFRAMES;
+
+/// Coded parameter a group of bits belongs to
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Parameter {
+    /// Wideband bit, submode id and DTX flag
+    Header,
+    Lsp,
+    /// Pitch lags and adaptive codebook gains
+    Pitch,
+    /// Excitation gains
+    Gain,
+    Innovation,
+}
+
+impl Display for Parameter {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        let name = match self {
+            Parameter::Header => "header",
+            Parameter::Lsp => "LSP",
+            Parameter::Pitch => "pitch",
+            Parameter::Gain => "gain",
+            Parameter::Innovation => "innovation",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// A group of consecutive bits in a frame
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Field {
+    pub parameter: Parameter,
+    /// Subframe the bits are sent for, `None` for per-frame parameters
+    pub subframe: Option<usize>,
+    pub bits: u32,
+}
+
+impl Field {
+    fn frame(parameter: Parameter, bits: u32) -> Self {
+        Self {
+            parameter,
+            subframe: None,
+            bits,
+        }
+    }
+
+    fn subframe(parameter: Parameter, subframe: usize, bits: u32) -> Self {
+        Self {
+            parameter,
+            subframe: Some(subframe),
+            bits,
+        }
+    }
+}
+
+/// Lists the fields of a narrowband frame in the order they are packed,
+/// starting with the wideband bit and submode id.
+///
+/// Returns `None` for the null submode and the ids that are not submodes.
+pub fn nb_frame_layout(submode_id: usize) -> Option<Vec<Field>> {
+    let submode = (*NB_SUBMODE_TABLE.get(submode_id)?)?;
+    let mut fields = vec![Field::frame(Parameter::Header, 5)];
+
+    let lsp_bits = match submode.lsp {
+        LspQuant::Nb => 30,
+        LspQuant::Lbr => 18,
+    };
+    fields.push(Field::frame(Parameter::Lsp, lsp_bits));
+    if submode.lbr_pitch != -1 {
+        fields.push(Field::frame(Parameter::Pitch, 7));
+    }
+    if submode.forced_pitch_gain {
+        fields.push(Field::frame(Parameter::Pitch, 4));
+    }
+    fields.push(Field::frame(Parameter::Gain, 5));
+    if submode_id == 1 {
+        fields.push(Field::frame(Parameter::Header, 4));
+    }
+
+    for sub in 0..NB_NB_SUBFRAMES {
+        if let Ltp::ThreeTap(params) = submode.ltp {
+            fields.push(Field::subframe(
+                Parameter::Pitch,
+                sub,
+                params.pitch_bits + params.gain_bits,
+            ));
+        }
+        if submode.have_subframe_gain != 0 {
+            fields.push(Field::subframe(
+                Parameter::Gain,
+                sub,
+                submode.have_subframe_gain as u32,
+            ));
+        }
+        if let Innovation::SplitCb(params) = submode.innovation {
+            // One field per sub-vector, as a whole codebook can be wider
+            // than the 32 bits that can be unpacked at once
+            let bits = params.shape_bits + params.have_sign as u32;
+            let codebooks = if submode.double_codebook { 2 } else { 1 };
+            for _ in 0..codebooks * params.nb_subvect {
+                fields.push(Field::subframe(Parameter::Innovation, sub, bits));
+            }
+        }
+    }
+    Some(fields)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn layouts_add_up_to_submode_sizes() {
+        for (id, submode) in NB_SUBMODE_TABLE.iter().enumerate() {
+            let layout = nb_frame_layout(id);
+            match submode {
+                Some(submode) => {
+                    let bits: u32 = layout.unwrap().iter().map(|field| field.bits).sum();
+                    assert_eq!(bits as i32, submode.bits_per_frame, "submode {id}");
+                }
+                None => assert_eq!(layout, None),
+            }
+        }
+    }
+}
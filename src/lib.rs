@@ -1,20 +1,86 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+// The tests link std either way, which brings in its float functions too
+#[cfg(all(test, not(feature = "std")))]
+#[macro_use]
+extern crate std;
+
 mod bits;
+mod cb_search;
+mod filters;
 mod header;
+mod layout;
+mod lpc;
+mod lsp;
+mod ltp;
+mod modes;
+mod nb_decoder;
+mod nb_encoder;
+mod sb_decoder;
+mod tables;
+mod vbr;
+mod vq;
+
+#[cfg(test)]
+#[path = "../speex-safe/tests/support/mod.rs"]
+mod support;
+
+pub use bits::Bits;
+pub use header::{Header, HeaderError};
+pub use layout::{nb_frame_layout, Field, Parameter};
+pub use nb_decoder::{DecoderError, NbDecoder};
+pub use nb_encoder::{EncoderError, NbEncoder};
+pub use sb_decoder::SbDecoder;
+/// The modes a Speex stream can be coded in
+///
+/// With the `libspeex` feature this is `speex_safe::ModeId` itself.
+#[cfg(feature = "libspeex")]
+pub use speex_safe::ModeId as Mode;
 
+/// The modes a Speex stream can be coded in, numbered like libspeex's mode ids
+///
+/// With the `libspeex` feature this is `speex_safe::ModeId` itself. Without
+/// it, speex-safe and libspeex aren't available, so this stands in for it with
+/// the same variants and the methods that don't need libspeex.
+#[cfg(not(feature = "libspeex"))]
+#[repr(i32)]
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub enum Mode {
-    Narrowband,
-    Wideband,
-    UltraWideband,
+    NarrowBand = 0,
+    WideBand = 1,
+    UltraWideBand = 2,
+}
+
+#[cfg(not(feature = "libspeex"))]
+impl Mode {
+    /// Gets the number of samples in a frame of the mode
+    pub fn get_frame_size(self) -> i32 {
+        match self {
+            Mode::NarrowBand => 160,
+            Mode::WideBand => 320,
+            Mode::UltraWideBand => 640,
+        }
+    }
+
+    /// Gets the sampling rate the mode codes, in Hz
+    pub fn get_sampling_rate(self) -> u32 {
+        match self {
+            Mode::NarrowBand => 8000,
+            Mode::WideBand => 16000,
+            Mode::UltraWideBand => 32000,
+        }
+    }
 }
 
+#[cfg(not(feature = "libspeex"))]
 impl From<i32> for Mode {
     fn from(value: i32) -> Self {
         match value {
-            0 => Mode::Narrowband,
-            1 => Mode::Wideband,
-            2 => Mode::UltraWideband,
-            _ => panic!("Unexpected value for Mode: {}", value),
+            0 => Mode::NarrowBand,
+            1 => Mode::WideBand,
+            2 => Mode::UltraWideBand,
+            _ => panic!("Invalid mode id"),
         }
     }
 }
@@ -0,0 +1,62 @@
+////////////////////////////////////////////////////////////////////////////////
+// Copyright (c) 2023.                                                         /
+// This Source Code Form is subject to the terms of the Mozilla Public License,/
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can    /
+// obtain one at http://mozilla.org/MPL/2.0/.                                  /
+////////////////////////////////////////////////////////////////////////////////
+
+//! Linear prediction analysis, ported from libspeex's `lpc.c`
+
+/// Autocorrelation of `x` for the lags `0..ac.len()`, with a small bias on the
+/// energy
+pub(crate) fn autocorr(x: &[f32], ac: &mut [f32]) {
+    for lag in (0..ac.len()).rev() {
+        let mut d = 0f32;
+        for i in lag..x.len() {
+            d += x[i] * x[i - lag];
+        }
+        ac[lag] = d;
+    }
+    ac[0] += 10.0;
+}
+
+/// Levinson-Durbin recursion, computing LPC coefficients from the
+/// autocorrelation `ac`
+pub(crate) fn lpc_from_autocorr(ac: &[f32], lpc: &mut [f32]) {
+    let mut error = ac[0];
+    for i in 0..lpc.len() {
+        let mut rr = -ac[i + 1];
+        for j in 0..i {
+            rr -= lpc[j] * ac[i - j];
+        }
+        let r = (rr as f64 / (error as f64 + 0.003 * ac[0] as f64)) as f32;
+
+        lpc[i] = r;
+        for j in 0..(i + 1) >> 1 {
+            let tmp1 = lpc[j];
+            let tmp2 = lpc[i - 1 - j];
+            lpc[j] = tmp1 + r * tmp2;
+            lpc[i - 1 - j] = tmp2 + r * tmp1;
+        }
+        error -= r * (error * r);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::vec::Vec;
+
+    use super::*;
+
+    #[test]
+    fn predicts_a_first_order_process() {
+        // x[n] = 0.5 x[n-1] is predicted by a single coefficient of -0.5
+        let x: Vec<f32> = (0..200).map(|n| 1000.0 * 0.5f32.powi(n % 20)).collect();
+        let mut ac = [0f32; 3];
+        autocorr(&x, &mut ac);
+        let mut lpc = [0f32; 2];
+        lpc_from_autocorr(&ac, &mut lpc);
+        assert!((lpc[0] + 0.5).abs() < 0.05, "{lpc:?}");
+        assert!(lpc[1].abs() < 0.05, "{lpc:?}");
+    }
+}
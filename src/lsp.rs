@@ -0,0 +1,355 @@
+////////////////////////////////////////////////////////////////////////////////
+// Copyright (c) 2023.                                                         /
+// This Source Code Form is subject to the terms of the Mozilla Public License,/
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can    /
+// obtain one at http://mozilla.org/MPL/2.0/.                                  /
+////////////////////////////////////////////////////////////////////////////////
+
+//! Line spectral pair handling, ported from libspeex's `lsp.c` and
+//! `quant_lsp.c`
+
+use core::f64::consts::PI;
+
+#[cfg(not(any(feature = "std", test)))]
+use num_traits::Float;
+
+use crate::modes::LspQuant;
+use crate::tables::*;
+use crate::Bits;
+
+/// Minimum distance kept between interpolated LSPs
+pub(crate) const LSP_MARGIN: f32 = 0.002;
+
+/// Polynomial approximation of the cosine used by libspeex
+// The constants are kept exactly as libspeex writes them
+#[allow(clippy::excessive_precision, clippy::approx_constant)]
+fn spx_cos(x: f32) -> f32 {
+    const C1: f32 = 0.9999932946;
+    const C2: f32 = -0.4999124376;
+    const C3: f32 = 0.0414877472;
+    const C4: f32 = -0.0012712095;
+
+    if (x as f64) < 1.5707963268 {
+        let x = x * x;
+        C1 + x * (C2 + x * (C3 + C4 * x))
+    } else {
+        let x = (PI - x as f64) as f32;
+        let x = x * x;
+        -(C1 + x * (C2 + x * (C3 + C4 * x)))
+    }
+}
+
+/// Converts LSPs to LPC coefficients
+pub(crate) fn lsp_to_lpc(freq: &[f32], ak: &mut [f32]) {
+    let order = freq.len();
+    let m = order >> 1;
+    let mut wp = [0f32; 4 * 16 + 2];
+    let wp = &mut wp[..4 * m + 2];
+
+    let mut x_freq = [0f32; 16];
+    for (x, &f) in x_freq.iter_mut().zip(freq) {
+        *x = spx_cos(f);
+    }
+
+    let mut xin1 = 1f32;
+    let mut xin2 = 1f32;
+    for j in 0..=order {
+        let mut n4 = 0;
+        for i in 0..m {
+            let n1 = i * 4;
+            let (n2, n3) = (n1 + 1, n1 + 2);
+            n4 = n1 + 3;
+            let xout1 = xin1 - 2.0 * x_freq[2 * i] * wp[n1] + wp[n2];
+            let xout2 = xin2 - 2.0 * x_freq[2 * i + 1] * wp[n3] + wp[n4];
+            wp[n2] = wp[n1];
+            wp[n4] = wp[n3];
+            wp[n1] = xin1;
+            wp[n3] = xin2;
+            xin1 = xout1;
+            xin2 = xout2;
+        }
+        let xout1 = xin1 + wp[n4 + 1];
+        let xout2 = xin2 - wp[n4 + 2];
+        if j > 0 {
+            ak[j - 1] = (xout1 + xout2) * 0.5;
+        }
+        wp[n4 + 1] = xin1;
+        wp[n4 + 2] = xin2;
+
+        xin1 = 0.0;
+        xin2 = 0.0;
+    }
+}
+
+/// Evaluates a Chebyshev series at `x`
+fn cheb_poly_eva(coef: &[f32], x: f32) -> f32 {
+    let m = coef.len() - 1;
+    let x = 2.0 * x;
+    let mut b0 = 0f32;
+    let mut b1 = 0f32;
+    for k in (1..=m).rev() {
+        let tmp = b0;
+        b0 = x * b0 - b1 + coef[m - k];
+        b1 = tmp;
+    }
+    (-b1 as f64 + 0.5 * x as f64 * b0 as f64 + coef[m] as f64) as f32
+}
+
+/// Converts LPC coefficients to LSPs, searching for the roots on a grid of
+/// step `delta` refined by `nb` bisections
+///
+/// Returns the number of roots that were found, which is less than the order
+/// when the filter is unstable or too close to it.
+pub(crate) fn lpc_to_lsp(a: &[f32], freq: &mut [f32], nb: usize, delta: f32) -> usize {
+    let order = a.len();
+    let m = order / 2;
+    let mut p = [0f32; 9];
+    let mut q = [0f32; 9];
+    p[0] = 1.0;
+    q[0] = 1.0;
+    for i in 0..m {
+        p[i + 1] = (a[i] + a[order - 1 - i]) - p[i];
+        q[i + 1] = (a[i] - a[order - 1 - i]) + q[i];
+    }
+    for i in 0..m {
+        p[i] *= 2.0;
+        q[i] *= 2.0;
+    }
+
+    let mut roots = 0;
+    let mut xl = 1f32;
+    let mut xr = 0f32;
+    let mut xm = 0f32;
+    for (j, f) in freq.iter_mut().enumerate().take(order) {
+        // Alternate between the roots of P and Q
+        let pt = if j & 1 == 1 { &q[..=m] } else { &p[..=m] };
+
+        let mut psuml = cheb_poly_eva(pt, xl);
+        while xr >= -1.0 {
+            // Smaller steps close to +-1
+            let mut dd = (delta as f64 * (1.0 - 0.9 * xl as f64 * xl as f64)) as f32;
+            if (psuml as f64).abs() < 0.2 {
+                dd = (dd as f64 * 0.5) as f32;
+            }
+
+            xr = xl - dd;
+            let psumr = cheb_poly_eva(pt, xr);
+            if psumr * psuml < 0.0 {
+                roots += 1;
+                for _ in 0..=nb {
+                    xm = 0.5 * (xl + xr);
+                    let psumm = cheb_poly_eva(pt, xm);
+                    if psumm * psuml < 0.0 {
+                        xr = xm;
+                    } else {
+                        psuml = psumm;
+                        xl = xm;
+                    }
+                }
+                *f = (xm as f64).acos() as f32;
+                xl = xm;
+                break;
+            }
+            psuml = psumr;
+            xl = xr;
+        }
+    }
+    roots
+}
+
+/// Interpolates between the LSPs of two frames for the given subframe, keeping
+/// them at least `margin` apart
+pub(crate) fn lsp_interpolate(
+    old_lsp: &[f32],
+    new_lsp: &[f32],
+    lsp: &mut [f32],
+    subframe: usize,
+    nb_subframes: usize,
+    margin: f32,
+) {
+    let len = lsp.len();
+    let tmp = (1.0 + subframe as f32) / nb_subframes as f32;
+    for i in 0..len {
+        lsp[i] = (1.0 - tmp) * old_lsp[i] + tmp * new_lsp[i];
+    }
+    if lsp[0] < margin {
+        lsp[0] = margin;
+    }
+    let upper = PI - margin as f64;
+    if lsp[len - 1] as f64 > upper {
+        lsp[len - 1] = upper as f32;
+    }
+    for i in 1..len - 1 {
+        if lsp[i] < lsp[i - 1] + margin {
+            lsp[i] = lsp[i - 1] + margin;
+        }
+        if lsp[i] > lsp[i + 1] - margin {
+            lsp[i] = 0.5 * (lsp[i] + lsp[i + 1] - margin);
+        }
+    }
+}
+
+/// Adds a codebook entry scaled by `scale` to `lsp`
+fn add_cdbk(lsp: &mut [f32], cdbk: &[i8], id: u32, scale: f64) {
+    let entry = &cdbk[id as usize * lsp.len()..][..lsp.len()];
+    for (l, &c) in lsp.iter_mut().zip(entry) {
+        *l = (*l as f64 + scale * c as f64) as f32;
+    }
+}
+
+/// Weights for the quantization error of each LSP, larger where LSPs are
+/// close together
+fn compute_quant_weights(qlsp: &[f32], weight: &mut [f32]) {
+    let order = qlsp.len();
+    for i in 0..order {
+        let tmp1 = if i == 0 {
+            qlsp[i]
+        } else {
+            qlsp[i] - qlsp[i - 1]
+        };
+        let tmp2 = if i == order - 1 {
+            (PI - qlsp[i] as f64) as f32
+        } else {
+            qlsp[i + 1] - qlsp[i]
+        };
+        let tmp = tmp1.min(tmp2);
+        weight[i] = (10.0 / (0.04 + tmp as f64)) as f32;
+    }
+}
+
+/// Finds the entry of `cdbk` closest to `x` and subtracts it from `x`
+fn quant_cdbk(x: &mut [f32], cdbk: &[i8]) -> u32 {
+    let mut best_dist = 1e15f32;
+    let mut best_id = 0;
+    for (i, entry) in cdbk.chunks_exact(x.len()).enumerate() {
+        let mut dist = 0f32;
+        for (&x, &c) in x.iter().zip(entry) {
+            let tmp = x - c as f32;
+            dist += tmp * tmp;
+        }
+        if dist < best_dist {
+            best_dist = dist;
+            best_id = i;
+        }
+    }
+    let dim = x.len();
+    for (x, &c) in x.iter_mut().zip(&cdbk[best_id * dim..]) {
+        *x -= c as f32;
+    }
+    best_id as u32
+}
+
+/// Like [`quant_cdbk`], with the error of each dimension weighted
+fn weight_quant_cdbk(x: &mut [f32], weight: &[f32], cdbk: &[i8]) -> u32 {
+    let mut best_dist = 1e15f32;
+    let mut best_id = 0;
+    for (i, entry) in cdbk.chunks_exact(x.len()).enumerate() {
+        let mut dist = 0f32;
+        for ((&x, &c), &w) in x.iter().zip(entry).zip(weight) {
+            let tmp = x - c as f32;
+            dist += w * (tmp * tmp);
+        }
+        if dist < best_dist {
+            best_dist = dist;
+            best_id = i;
+        }
+    }
+    let dim = x.len();
+    for (x, &c) in x.iter_mut().zip(&cdbk[best_id * dim..]) {
+        *x -= c as f32;
+    }
+    best_id as u32
+}
+
+/// Quantizes the narrowband LSPs of a frame, writing them to the bitstream
+/// and their quantized value to `qlsp`
+pub(crate) fn lsp_quant(quant: LspQuant, lsp: &[f32], qlsp: &mut [f32], bits: &mut Bits) {
+    let mut weight = [0f32; 10];
+    compute_quant_weights(lsp, &mut weight);
+
+    for (i, (q, &l)) in qlsp.iter_mut().zip(lsp).enumerate() {
+        let q16 = (l as f64 - (0.25 * i as f64 + 0.25)) as f32;
+        *q = (256.0 * q16 as f64) as f32;
+    }
+    bits.pack(quant_cdbk(qlsp, &CDBK_NB), 6);
+    for q in qlsp.iter_mut() {
+        *q *= 2.0;
+    }
+
+    let (low, high) = qlsp.split_at_mut(5);
+    let (low_weight, high_weight) = weight.split_at(5);
+    let scale = match quant {
+        LspQuant::Nb => {
+            bits.pack(weight_quant_cdbk(low, low_weight, &CDBK_NB_LOW1), 6);
+            for q in low.iter_mut() {
+                *q *= 2.0;
+            }
+            bits.pack(weight_quant_cdbk(low, low_weight, &CDBK_NB_LOW2), 6);
+            bits.pack(weight_quant_cdbk(high, high_weight, &CDBK_NB_HIGH1), 6);
+            for q in high.iter_mut() {
+                *q *= 2.0;
+            }
+            bits.pack(weight_quant_cdbk(high, high_weight, &CDBK_NB_HIGH2), 6);
+            0.00097656
+        }
+        LspQuant::Lbr => {
+            bits.pack(weight_quant_cdbk(low, low_weight, &CDBK_NB_LOW1), 6);
+            bits.pack(weight_quant_cdbk(high, high_weight, &CDBK_NB_HIGH1), 6);
+            0.0019531
+        }
+    };
+
+    // What's left is the quantization error, scaled up
+    for (q, &l) in qlsp.iter_mut().zip(lsp) {
+        *q = l - (*q as f64 * scale) as f32;
+    }
+}
+
+/// Reads the narrowband LSPs of a frame
+pub(crate) fn lsp_unquant(quant: LspQuant, lsp: &mut [f32], bits: &mut Bits) {
+    for (i, l) in lsp.iter_mut().enumerate() {
+        *l = (0.25 * i as f64 + 0.25) as f32;
+    }
+
+    add_cdbk(lsp, &CDBK_NB, bits.unpack_unsigned(6), 0.0039062);
+    let (low, high) = lsp.split_at_mut(5);
+    match quant {
+        LspQuant::Nb => {
+            add_cdbk(low, &CDBK_NB_LOW1, bits.unpack_unsigned(6), 0.0019531);
+            add_cdbk(low, &CDBK_NB_LOW2, bits.unpack_unsigned(6), 0.00097656);
+            add_cdbk(high, &CDBK_NB_HIGH1, bits.unpack_unsigned(6), 0.0019531);
+            add_cdbk(high, &CDBK_NB_HIGH2, bits.unpack_unsigned(6), 0.00097656);
+        }
+        LspQuant::Lbr => {
+            add_cdbk(low, &CDBK_NB_LOW1, bits.unpack_unsigned(6), 0.0019531);
+            add_cdbk(high, &CDBK_NB_HIGH1, bits.unpack_unsigned(6), 0.0019531);
+        }
+    }
+}
+
+/// Reads the high-band LSPs of a sub-band frame
+pub(crate) fn lsp_unquant_high(lsp: &mut [f32], bits: &mut Bits) {
+    for (i, l) in lsp.iter_mut().enumerate() {
+        *l = (0.3125 * i as f64 + 0.75) as f32;
+    }
+
+    add_cdbk(lsp, &HIGH_LSP_CDBK, bits.unpack_unsigned(6), 0.0039062);
+    add_cdbk(lsp, &HIGH_LSP_CDBK2, bits.unpack_unsigned(6), 0.0019531);
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::vec::Vec;
+
+    use super::*;
+
+    #[test]
+    fn flat_lsps_give_flat_spectrum() {
+        // Evenly spaced LSPs describe a flat envelope, so every coefficient of
+        // the predictor is close to zero
+        let lsp: Vec<f32> = (0..10).map(|i| (i + 1) as f32 * PI as f32 / 11.0).collect();
+        let mut ak = [0f32; 10];
+        lsp_to_lpc(&lsp, &mut ak);
+        assert!(ak.iter().all(|a| a.abs() < 1e-3), "{ak:?}");
+    }
+}
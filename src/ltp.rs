@@ -0,0 +1,430 @@
+////////////////////////////////////////////////////////////////////////////////
+// Copyright (c) 2023.                                                         /
+// This Source Code Form is subject to the terms of the Mozilla Public License,/
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can    /
+// obtain one at http://mozilla.org/MPL/2.0/.                                  /
+////////////////////////////////////////////////////////////////////////////////
+
+//! Long-term (pitch) prediction, ported from libspeex's `ltp.c`
+
+#[cfg(not(any(feature = "std", test)))]
+use num_traits::Float;
+
+use crate::filters::{inner_prod, syn_percep_zero16, WeightedSynthesis};
+use crate::modes::LtpParams;
+use crate::Bits;
+
+/// Largest subframe the pitch search works on
+const MAX_SUBFRAME_SIZE: usize = 40;
+
+/// Collapses the gains of a 3-tap predictor into a single equivalent gain
+pub(crate) fn gain_3tap_to_1tap(gain: &[f32; 3]) -> f32 {
+    let side = |g: f32| if g > 0.0 { g as f64 } else { -0.5 * g as f64 };
+    (gain[1].abs() as f64 + side(gain[0]) + side(gain[2])) as f32
+}
+
+/// Writes the prediction of a 3-tap pitch filter from the past excitation
+/// into `exc_out`, repeating the last period when the pitch is shorter than
+/// the subframe
+fn predict_3tap(exc: &[f32], pos: usize, exc_out: &mut [f32], pitch: i32, gain: &[f32; 3]) {
+    exc_out.fill(0.0);
+    let nsf = exc_out.len() as i32;
+    for i in 0..3 {
+        let pp = pitch + 1 - i as i32;
+        let g = gain[2 - i];
+        let tmp1 = nsf.min(pp);
+        for j in 0..tmp1 {
+            exc_out[j as usize] += g * exc[(pos as i32 + j - pp) as usize];
+        }
+        let tmp3 = nsf.min(pp + pitch);
+        for j in tmp1..tmp3 {
+            exc_out[j as usize] += g * exc[(pos as i32 + j - pp - pitch) as usize];
+        }
+    }
+}
+
+/// State of packet loss concealment that the pitch decoder takes into account
+pub(crate) struct LostState {
+    pub count_lost: i32,
+    pub subframe_offset: i32,
+    pub last_pitch_gain: f32,
+}
+
+/// Decodes a 3-tap pitch predictor and writes its contribution to the
+/// subframe into `exc_out`
+///
+/// `exc` holds the past excitation, with the current subframe starting at
+/// `pos`. Returns the pitch and the gains that were decoded.
+pub(crate) fn pitch_unquant_3tap(
+    exc: &[f32],
+    pos: usize,
+    exc_out: &mut [f32],
+    start: i32,
+    params: &LtpParams,
+    bits: &mut Bits,
+    lost: &LostState,
+) -> (i32, [f32; 3]) {
+    let pitch = bits.unpack_unsigned(params.pitch_bits) as i32 + start;
+    let gain_index = bits.unpack_unsigned(params.gain_bits) as usize;
+    let entry = &params.gain_cdbk[gain_index * 4..];
+    let mut gain = [0f32; 3];
+    for (g, &c) in gain.iter_mut().zip(entry) {
+        *g = (0.015625 * c as f64 + 0.5) as f32;
+    }
+
+    if lost.count_lost != 0 && pitch > lost.subframe_offset {
+        let tmp = if lost.count_lost < 4 {
+            lost.last_pitch_gain
+        } else {
+            (0.5 * lost.last_pitch_gain as f64) as f32
+        };
+        let tmp = if tmp as f64 > 0.95 { 0.95 } else { tmp };
+        let gain_sum = gain_3tap_to_1tap(&gain);
+        if gain_sum > tmp {
+            let fact = tmp / gain_sum;
+            for g in gain.iter_mut() {
+                *g *= fact;
+            }
+        }
+    }
+
+    predict_3tap(exc, pos, exc_out, pitch, &gain);
+
+    (pitch, gain)
+}
+
+/// Applies a single pitch gain at a fixed pitch, updating the excitation at
+/// `pos` in place as well as writing `exc_out`
+pub(crate) fn forced_pitch_unquant(
+    exc: &mut [f32],
+    pos: usize,
+    exc_out: &mut [f32],
+    start: i32,
+    pitch_coef: f32,
+) -> (i32, [f32; 3]) {
+    let pitch_coef = if pitch_coef as f64 > 0.99 {
+        0.99
+    } else {
+        pitch_coef
+    };
+    for (i, out) in exc_out.iter_mut().enumerate() {
+        let at = pos + i;
+        *out = exc[at - start as usize] * pitch_coef;
+        exc[at] = *out;
+    }
+    (start, [0.0, pitch_coef, 0.0])
+}
+
+/// Finds the `pitch.len()` pitches between `start` and `end` that best
+/// predict the `len` samples of `sw` at `pos`, best first, along with their
+/// normalized correlations in `gain`
+pub(crate) fn open_loop_nbest_pitch(
+    sw: &[f32],
+    pos: usize,
+    start: i32,
+    end: i32,
+    len: usize,
+    pitch: &mut [i32],
+    gain: Option<&mut [f32]>,
+) {
+    let n = pitch.len();
+    let nb_pitch = (end - start + 1) as usize;
+    let at = |offset: i32| (pos as i32 + offset) as usize;
+    let x = &sw[pos..pos + len];
+
+    let mut best_score = [-1f32; 10];
+    let mut best_ener = [0f32; 10];
+    pitch.fill(start);
+
+    let mut energy = [0f32; 160];
+    let lagged = &sw[at(-start)..at(-start) + len];
+    energy[0] = inner_prod(lagged, lagged);
+    let e0 = inner_prod(x, x);
+    for i in start..end {
+        let k = (i - start) as usize;
+        let entering = sw[at(-i - 1)];
+        let leaving = sw[at(-i + len as i32 - 1)];
+        energy[k + 1] = (energy[k] + entering * entering - leaving * leaving).max(0.0);
+    }
+
+    let mut corr = [0f32; 160];
+    for i in 0..nb_pitch {
+        let y = &sw[at(-end) + i..][..len];
+        corr[nb_pitch - 1 - i] = inner_prod(x, y);
+    }
+
+    for i in start..=end {
+        let k = (i - start) as usize;
+        let tmp = corr[k] * corr[k];
+        // Compares tmp / energy to the scores without dividing
+        if tmp * best_ener[n - 1] > best_score[n - 1] * (1.0 + energy[k]) {
+            best_score[n - 1] = tmp;
+            best_ener[n - 1] = energy[k] + 1.0;
+            pitch[n - 1] = i;
+            for j in 0..n - 1 {
+                if tmp * best_ener[j] > best_score[j] * (1.0 + energy[k]) {
+                    for l in (j + 1..n).rev() {
+                        best_score[l] = best_score[l - 1];
+                        best_ener[l] = best_ener[l - 1];
+                        pitch[l] = pitch[l - 1];
+                    }
+                    best_score[j] = tmp;
+                    best_ener[j] = energy[k] + 1.0;
+                    pitch[j] = i;
+                    break;
+                }
+            }
+        }
+    }
+
+    if let Some(gain) = gain {
+        for (g, &p) in gain.iter_mut().zip(pitch.iter()) {
+            let k = (p - start) as usize;
+            let norm = (e0 as f64).sqrt() as f32 * (energy[k] as f64).sqrt() as f32;
+            *g = (corr[k] / (10.0 + norm)).max(0.0);
+        }
+    }
+}
+
+/// Prediction error of a 3-tap gain vector, from the correlations `c`
+/// between the target and the filtered excitation
+fn compute_pitch_error(c: &[f32; 9], g: &[f32; 3], pitch_control: f32) -> f32 {
+    let mut sum = 0f32;
+    sum += (g[0] * pitch_control) * c[0];
+    sum += (g[1] * pitch_control) * c[1];
+    sum += (g[2] * pitch_control) * c[2];
+    sum -= (g[0] * g[1]) * c[3];
+    sum -= (g[2] * g[1]) * c[4];
+    sum -= (g[2] * g[0]) * c[5];
+    sum -= (g[0] * g[0]) * c[6];
+    sum -= (g[1] * g[1]) * c[7];
+    sum -= (g[2] * g[2]) * c[8];
+    sum
+}
+
+/// Index of the gain vector of `gain_cdbk` that best predicts the target,
+/// skipping the ones whose overall gain exceeds `max_gain`
+fn pitch_gain_search_3tap_vq(gain_cdbk: &[i8], c: &[f32; 9], max_gain: f32) -> usize {
+    let mut best_cdbk = 0;
+    let mut best_sum = -1e15f32;
+    for (i, entry) in gain_cdbk.chunks_exact(4).enumerate() {
+        let g = [
+            entry[0] as f32 + 32.0,
+            entry[1] as f32 + 32.0,
+            entry[2] as f32 + 32.0,
+        ];
+        let gain_sum = entry[3] as f32;
+        let sum = compute_pitch_error(c, &g, 64.0);
+        if sum > best_sum && gain_sum <= max_gain {
+            best_sum = sum;
+            best_cdbk = i;
+        }
+    }
+    best_cdbk
+}
+
+/// Quantizes the 3-tap gains for a given pitch, writing the prediction to
+/// `exc_out` and what is left of the target to `new_target`
+///
+/// Returns the energy of the new target and the index of the gains.
+#[allow(clippy::too_many_arguments)]
+fn pitch_gain_search_3tap(
+    target: &[f32],
+    filters: &WeightedSynthesis,
+    exc: &[f32],
+    pos: usize,
+    pitch: i32,
+    gain_cdbk: &[i8],
+    plc_tuning: i32,
+    cumul_gain: f32,
+    exc_out: &mut [f32],
+    new_target: &mut [f32],
+) -> (f32, usize) {
+    let nsf = target.len();
+    let at = |offset: i32| (pos as i32 + offset) as usize;
+    let max_gain = if cumul_gain > 262144.0 { 31.0 } else { 128.0 };
+    new_target.copy_from_slice(target);
+
+    // Response of the weighted synthesis filter to each tap of the predictor
+    let mut x = [[0f32; MAX_SUBFRAME_SIZE]; 3];
+    {
+        let e = &mut x[2][..nsf];
+        let pp = pitch - 1;
+        let bound = if nsf as i32 - pp > 0 { pp } else { nsf as i32 };
+        for j in 0..bound {
+            e[j as usize] = exc[at(j - pp)];
+        }
+        let bound2 = if nsf as i32 - pp - pitch > 0 {
+            pp + pitch
+        } else {
+            nsf as i32
+        };
+        for j in bound..bound2 {
+            e[j as usize] = exc[at(j - pp - pitch)];
+        }
+        syn_percep_zero16(e, filters);
+    }
+    let r = filters.resp;
+    for i in (0..2).rev() {
+        let e0 = exc[at(-pitch - 1 + i as i32)];
+        x[i][0] = r[0] * e0;
+        for j in 0..nsf - 1 {
+            x[i][j + 1] = x[i + 1][j] + r[j + 1] * e0;
+        }
+    }
+
+    let mut corr = [0f32; 3];
+    let mut a = [[0f32; 3]; 3];
+    for i in 0..3 {
+        corr[i] = inner_prod(&x[i][..nsf], new_target);
+        for j in 0..=i {
+            a[i][j] = inner_prod(&x[i][..nsf], &x[j][..nsf]);
+            a[j][i] = a[i][j];
+        }
+    }
+
+    let mut c = [
+        corr[2], corr[1], corr[0], a[1][2], a[0][1], a[0][2], a[2][2], a[1][1], a[0][0],
+    ];
+    let plc_tuning = plc_tuning.clamp(2, 30);
+    let damping = 0.5 * (1.0 + 0.02 * plc_tuning as f64);
+    for v in &mut c[6..] {
+        *v = (*v as f64 * damping) as f32;
+    }
+
+    let best_cdbk = pitch_gain_search_3tap_vq(gain_cdbk, &c, max_gain);
+    let entry = &gain_cdbk[best_cdbk * 4..];
+    let mut gain = [0f32; 3];
+    for (g, &v) in gain.iter_mut().zip(entry) {
+        *g = (0.015625 * v as f64 + 0.5) as f32;
+    }
+
+    predict_3tap(exc, pos, exc_out, pitch, &gain);
+    for i in 0..nsf {
+        let tmp = gain[0] * x[2][i] + gain[1] * x[1][i] + gain[2] * x[0][i];
+        new_target[i] -= tmp;
+    }
+    (inner_prod(new_target, new_target), best_cdbk)
+}
+
+/// Searches the pitch and 3-tap gains between `start` and `end` that best
+/// predict `target`, by analysis by synthesis
+///
+/// `sw` and `exc` hold the weighted signal and past excitation, with the
+/// current subframe at `pos`. The prediction is written to `exc_out`, and
+/// `target` is left with what it doesn't explain. `complexity` sets how many
+/// open-loop candidates are tried.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn pitch_search_3tap(
+    target: &mut [f32],
+    filters: &WeightedSynthesis,
+    sw: &[f32],
+    exc: &[f32],
+    pos: usize,
+    exc_out: &mut [f32],
+    params: &LtpParams,
+    start: i32,
+    end: i32,
+    complexity: i32,
+    plc_tuning: i32,
+    cumul_gain: &mut f32,
+    bits: &mut Bits,
+) {
+    let nsf = target.len();
+    let gain_cdbk = &params.gain_cdbk[..4 << params.gain_bits];
+
+    if end < start {
+        bits.pack(0, params.pitch_bits);
+        bits.pack(0, params.gain_bits);
+        exc_out.fill(0.0);
+        return;
+    }
+
+    let n = (complexity.clamp(1, 10) as usize).min((end - start + 1) as usize);
+    let mut nbest = [0i32; 10];
+    if end != start {
+        open_loop_nbest_pitch(sw, pos, start, end, nsf, &mut nbest[..n], None);
+    } else {
+        nbest[0] = start;
+    }
+
+    let mut best_exc = [0f32; MAX_SUBFRAME_SIZE];
+    let mut best_target = [0f32; MAX_SUBFRAME_SIZE];
+    let mut new_target = [0f32; MAX_SUBFRAME_SIZE];
+    let mut best_err = -1f32;
+    let mut best_pitch = 0;
+    let mut best_gain_index = 0;
+    for &pitch in &nbest[..n] {
+        let (err, cdbk_index) = pitch_gain_search_3tap(
+            target,
+            filters,
+            exc,
+            pos,
+            pitch,
+            gain_cdbk,
+            plc_tuning,
+            *cumul_gain,
+            exc_out,
+            &mut new_target[..nsf],
+        );
+        if err < best_err || best_err < 0.0 {
+            best_exc[..nsf].copy_from_slice(exc_out);
+            best_target[..nsf].copy_from_slice(&new_target[..nsf]);
+            best_err = err;
+            best_pitch = pitch;
+            best_gain_index = cdbk_index;
+        }
+    }
+
+    bits.pack((best_pitch - start) as u32, params.pitch_bits);
+    bits.pack(best_gain_index as u32, params.gain_bits);
+    let best_gain_sum = gain_cdbk[4 * best_gain_index + 3];
+    *cumul_gain = (0.03125 * cumul_gain.max(1024.0) as f64 * best_gain_sum as f64) as f32;
+    exc_out.copy_from_slice(&best_exc[..nsf]);
+    target.copy_from_slice(&best_target[..nsf]);
+}
+
+/// Applies a single gain at a fixed pitch of `start`, writing the prediction
+/// to `exc_out` and removing its contribution from `target`
+pub(crate) fn forced_pitch_quant(
+    target: &mut [f32],
+    filters: &WeightedSynthesis,
+    exc: &[f32],
+    pos: usize,
+    exc_out: &mut [f32],
+    start: i32,
+    pitch_coef: f32,
+) {
+    let pitch_coef = if pitch_coef as f64 > 0.99 {
+        0.99
+    } else {
+        pitch_coef
+    };
+    let nsf = exc_out.len();
+    let start = start as usize;
+    for i in 0..nsf.min(start) {
+        exc_out[i] = pitch_coef * exc[pos + i - start];
+    }
+    for i in start..nsf {
+        exc_out[i] = pitch_coef * exc_out[i - start];
+    }
+
+    let mut res = [0f32; MAX_SUBFRAME_SIZE];
+    let res = &mut res[..nsf];
+    res.copy_from_slice(exc_out);
+    syn_percep_zero16(res, filters);
+    for (t, &r) in target.iter_mut().zip(res.iter()) {
+        *t -= r;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn one_tap_gain_penalizes_negative_side_taps() {
+        assert_eq!(gain_3tap_to_1tap(&[0.25, 0.5, 0.25]), 1.0);
+        assert_eq!(gain_3tap_to_1tap(&[-0.5, -0.5, 0.0]), 0.75);
+    }
+}
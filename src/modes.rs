@@ -0,0 +1,345 @@
+////////////////////////////////////////////////////////////////////////////////
+// Copyright (c) 2023.                                                         /
+// This Source Code Form is subject to the terms of the Mozilla Public License,/
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can    /
+// obtain one at http://mozilla.org/MPL/2.0/.                                  /
+////////////////////////////////////////////////////////////////////////////////
+
+//! Submode definitions, mirroring libspeex's `modes.c`
+
+use crate::tables::*;
+
+/// Number of narrowband submodes, including the null one
+pub(crate) const NB_SUBMODES: usize = 16;
+/// Bits used to signal the narrowband submode
+pub(crate) const NB_SUBMODE_BITS: u32 = 4;
+/// Number of sub-band submodes, including the null one
+pub(crate) const SB_SUBMODES: usize = 8;
+/// Bits used to signal the sub-band submode
+pub(crate) const SB_SUBMODE_BITS: u32 = 3;
+
+/// Parameters of the 3-tap long-term predictor
+pub(crate) struct LtpParams {
+    pub gain_cdbk: &'static [i8],
+    pub gain_bits: u32,
+    pub pitch_bits: u32,
+}
+
+/// Parameters of a split shape codebook
+pub(crate) struct SplitCbParams {
+    pub subvect_size: usize,
+    pub nb_subvect: usize,
+    pub shape_cb: &'static [i8],
+    pub shape_bits: u32,
+    pub have_sign: bool,
+}
+
+/// How the LSPs of a frame are quantized
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub(crate) enum LspQuant {
+    Nb,
+    Lbr,
+}
+
+/// How the adaptive codebook contribution is coded
+pub(crate) enum Ltp {
+    /// A single gain sent once per frame, pitch from the open-loop estimate
+    Forced,
+    ThreeTap(&'static LtpParams),
+}
+
+/// How the fixed codebook contribution is coded
+pub(crate) enum Innovation {
+    /// Nothing is transmitted, the decoder uses white noise
+    Noise,
+    SplitCb(&'static SplitCbParams),
+}
+
+/// A narrowband submode
+pub(crate) struct NbSubmode {
+    /// Pitch search margin around the open-loop pitch, -1 for a full search
+    pub lbr_pitch: i32,
+    /// Whether the pitch gain is sent once per frame
+    pub forced_pitch_gain: bool,
+    /// Bits used for the per-subframe gain correction, 0 for none
+    pub have_subframe_gain: i32,
+    /// Whether a second innovation codebook is searched
+    pub double_codebook: bool,
+    pub lsp: LspQuant,
+    pub ltp: Ltp,
+    pub innovation: Innovation,
+    /// Gain of the enhancer's comb filter, negative to disable it
+    pub comb_gain: f32,
+    pub bits_per_frame: i32,
+}
+
+static LTP_PARAMS_NB: LtpParams = LtpParams {
+    gain_cdbk: &GAIN_CDBK_NB,
+    gain_bits: 7,
+    pitch_bits: 7,
+};
+
+static LTP_PARAMS_VLBR: LtpParams = LtpParams {
+    gain_cdbk: &GAIN_CDBK_LBR,
+    gain_bits: 5,
+    pitch_bits: 0,
+};
+
+static LTP_PARAMS_LBR: LtpParams = LtpParams {
+    gain_cdbk: &GAIN_CDBK_LBR,
+    gain_bits: 5,
+    pitch_bits: 7,
+};
+
+static LTP_PARAMS_MED: LtpParams = LtpParams {
+    gain_cdbk: &GAIN_CDBK_LBR,
+    gain_bits: 5,
+    pitch_bits: 7,
+};
+
+static SPLIT_CB_NB_VLBR: SplitCbParams = SplitCbParams {
+    subvect_size: 10,
+    nb_subvect: 4,
+    shape_cb: &EXC_10_16,
+    shape_bits: 4,
+    have_sign: false,
+};
+
+static SPLIT_CB_NB_ULBR: SplitCbParams = SplitCbParams {
+    subvect_size: 20,
+    nb_subvect: 2,
+    shape_cb: &EXC_20_32,
+    shape_bits: 5,
+    have_sign: false,
+};
+
+static SPLIT_CB_NB_LBR: SplitCbParams = SplitCbParams {
+    subvect_size: 10,
+    nb_subvect: 4,
+    shape_cb: &EXC_10_32,
+    shape_bits: 5,
+    have_sign: false,
+};
+
+static SPLIT_CB_NB: SplitCbParams = SplitCbParams {
+    subvect_size: 5,
+    nb_subvect: 8,
+    shape_cb: &EXC_5_64,
+    shape_bits: 6,
+    have_sign: false,
+};
+
+static SPLIT_CB_NB_MED: SplitCbParams = SplitCbParams {
+    subvect_size: 8,
+    nb_subvect: 5,
+    shape_cb: &EXC_8_128,
+    shape_bits: 7,
+    have_sign: false,
+};
+
+static SPLIT_CB_SB: SplitCbParams = SplitCbParams {
+    subvect_size: 5,
+    nb_subvect: 8,
+    shape_cb: &EXC_5_256,
+    shape_bits: 8,
+    have_sign: false,
+};
+
+/// 2150 bps "vocoder-like" mode for comfort noise
+static NB_SUBMODE1: NbSubmode = NbSubmode {
+    lbr_pitch: 0,
+    forced_pitch_gain: true,
+    have_subframe_gain: 0,
+    double_codebook: false,
+    lsp: LspQuant::Lbr,
+    ltp: Ltp::Forced,
+    innovation: Innovation::Noise,
+    comb_gain: -1.0,
+    bits_per_frame: 43,
+};
+
+/// 3.95 kbps very low bit-rate mode
+static NB_SUBMODE8: NbSubmode = NbSubmode {
+    lbr_pitch: 0,
+    forced_pitch_gain: true,
+    have_subframe_gain: 0,
+    double_codebook: false,
+    lsp: LspQuant::Lbr,
+    ltp: Ltp::Forced,
+    innovation: Innovation::SplitCb(&SPLIT_CB_NB_ULBR),
+    comb_gain: 0.5,
+    bits_per_frame: 79,
+};
+
+/// 5.95 kbps very low bit-rate mode
+static NB_SUBMODE2: NbSubmode = NbSubmode {
+    lbr_pitch: 0,
+    forced_pitch_gain: false,
+    have_subframe_gain: 0,
+    double_codebook: false,
+    lsp: LspQuant::Lbr,
+    ltp: Ltp::ThreeTap(&LTP_PARAMS_VLBR),
+    innovation: Innovation::SplitCb(&SPLIT_CB_NB_VLBR),
+    comb_gain: 0.6,
+    bits_per_frame: 119,
+};
+
+/// 8 kbps low bit-rate mode
+static NB_SUBMODE3: NbSubmode = NbSubmode {
+    lbr_pitch: -1,
+    forced_pitch_gain: false,
+    have_subframe_gain: 1,
+    double_codebook: false,
+    lsp: LspQuant::Lbr,
+    ltp: Ltp::ThreeTap(&LTP_PARAMS_LBR),
+    innovation: Innovation::SplitCb(&SPLIT_CB_NB_LBR),
+    comb_gain: 0.55,
+    bits_per_frame: 160,
+};
+
+/// 11 kbps medium bit-rate mode
+static NB_SUBMODE4: NbSubmode = NbSubmode {
+    lbr_pitch: -1,
+    forced_pitch_gain: false,
+    have_subframe_gain: 1,
+    double_codebook: false,
+    lsp: LspQuant::Lbr,
+    ltp: Ltp::ThreeTap(&LTP_PARAMS_MED),
+    innovation: Innovation::SplitCb(&SPLIT_CB_NB_MED),
+    comb_gain: 0.45,
+    bits_per_frame: 220,
+};
+
+/// 15 kbps high bit-rate mode
+static NB_SUBMODE5: NbSubmode = NbSubmode {
+    lbr_pitch: -1,
+    forced_pitch_gain: false,
+    have_subframe_gain: 3,
+    double_codebook: false,
+    lsp: LspQuant::Nb,
+    ltp: Ltp::ThreeTap(&LTP_PARAMS_NB),
+    innovation: Innovation::SplitCb(&SPLIT_CB_NB),
+    comb_gain: 0.25,
+    bits_per_frame: 300,
+};
+
+/// 18.2 kbps high bit-rate mode
+static NB_SUBMODE6: NbSubmode = NbSubmode {
+    lbr_pitch: -1,
+    forced_pitch_gain: false,
+    have_subframe_gain: 3,
+    double_codebook: false,
+    lsp: LspQuant::Nb,
+    ltp: Ltp::ThreeTap(&LTP_PARAMS_NB),
+    innovation: Innovation::SplitCb(&SPLIT_CB_SB),
+    comb_gain: 0.15,
+    bits_per_frame: 364,
+};
+
+/// 24.6 kbps high bit-rate mode
+static NB_SUBMODE7: NbSubmode = NbSubmode {
+    lbr_pitch: -1,
+    forced_pitch_gain: false,
+    have_subframe_gain: 3,
+    double_codebook: true,
+    lsp: LspQuant::Nb,
+    ltp: Ltp::ThreeTap(&LTP_PARAMS_NB),
+    innovation: Innovation::SplitCb(&SPLIT_CB_NB),
+    comb_gain: 0.05,
+    bits_per_frame: 492,
+};
+
+/// Narrowband submodes indexed by their id, `None` for the null submode and
+/// the ids that are reserved
+pub(crate) static NB_SUBMODE_TABLE: [Option<&NbSubmode>; NB_SUBMODES] = [
+    None,
+    Some(&NB_SUBMODE1),
+    Some(&NB_SUBMODE2),
+    Some(&NB_SUBMODE3),
+    Some(&NB_SUBMODE4),
+    Some(&NB_SUBMODE5),
+    Some(&NB_SUBMODE6),
+    Some(&NB_SUBMODE7),
+    Some(&NB_SUBMODE8),
+    None,
+    None,
+    None,
+    None,
+    None,
+    None,
+    None,
+];
+
+/// Narrowband submode used for each quality setting
+pub(crate) const NB_QUALITY_MAP: [usize; 11] = [1, 8, 2, 3, 3, 4, 4, 5, 5, 6, 7];
+
+static SPLIT_CB_HIGH_LBR: SplitCbParams = SplitCbParams {
+    subvect_size: 10,
+    nb_subvect: 4,
+    shape_cb: &HEXC_10_32,
+    shape_bits: 5,
+    have_sign: false,
+};
+
+static SPLIT_CB_HIGH: SplitCbParams = SplitCbParams {
+    subvect_size: 8,
+    nb_subvect: 5,
+    shape_cb: &HEXC,
+    shape_bits: 7,
+    have_sign: true,
+};
+
+/// A sub-band submode, coding the high band of a wideband or ultra-wideband
+/// frame
+pub(crate) struct SbSubmode {
+    /// Whether a second innovation codebook is searched
+    pub double_codebook: bool,
+    /// Innovation codebook, or `None` to fold the low band's innovation into
+    /// the high band
+    pub innovation: Option<&'static SplitCbParams>,
+    pub bits_per_frame: i32,
+}
+
+/// Spectral folding, 1.8 kbps
+pub(crate) static WB_SUBMODE1: SbSubmode = SbSubmode {
+    double_codebook: false,
+    innovation: None,
+    bits_per_frame: 36,
+};
+
+/// 5.6 kbps
+pub(crate) static WB_SUBMODE2: SbSubmode = SbSubmode {
+    double_codebook: false,
+    innovation: Some(&SPLIT_CB_HIGH_LBR),
+    bits_per_frame: 112,
+};
+
+/// 9.6 kbps
+pub(crate) static WB_SUBMODE3: SbSubmode = SbSubmode {
+    double_codebook: false,
+    innovation: Some(&SPLIT_CB_HIGH),
+    bits_per_frame: 192,
+};
+
+/// 17.6 kbps
+pub(crate) static WB_SUBMODE4: SbSubmode = SbSubmode {
+    double_codebook: true,
+    innovation: Some(&SPLIT_CB_HIGH),
+    bits_per_frame: 352,
+};
+
+/// The submodes of the wideband layer, indexed by submode id
+pub(crate) static WB_SUBMODE_TABLE: [Option<&SbSubmode>; SB_SUBMODES] = [
+    None,
+    Some(&WB_SUBMODE1),
+    Some(&WB_SUBMODE2),
+    Some(&WB_SUBMODE3),
+    Some(&WB_SUBMODE4),
+    None,
+    None,
+    None,
+];
+
+/// The submodes of the ultra-wideband layer, indexed by submode id
+pub(crate) static UWB_SUBMODE_TABLE: [Option<&SbSubmode>; SB_SUBMODES] =
+    [None, Some(&WB_SUBMODE1), None, None, None, None, None, None];
@@ -0,0 +1,802 @@
+////////////////////////////////////////////////////////////////////////////////
+// Copyright (c) 2023.                                                         /
+// This Source Code Form is subject to the terms of the Mozilla Public License,/
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can    /
+// obtain one at http://mozilla.org/MPL/2.0/.                                  /
+////////////////////////////////////////////////////////////////////////////////
+
+use core::fmt::{Display, Formatter};
+
+#[cfg(not(any(feature = "std", test)))]
+use num_traits::Float;
+
+use crate::cb_search::innovation_unquant;
+use crate::filters::{
+    bw_lpc,
+    compute_rms,
+    highpass,
+    iir_mem16,
+    multicomb,
+    sanitize_values,
+    speex_rand,
+    HIGHPASS_NARROWBAND,
+    HIGHPASS_OUTPUT,
+    HIGHPASS_WIDEBAND,
+};
+use crate::lsp::{lsp_interpolate, lsp_to_lpc, lsp_unquant, LSP_MARGIN};
+use crate::ltp::{forced_pitch_unquant, gain_3tap_to_1tap, pitch_unquant_3tap, LostState};
+use crate::modes::{Ltp, NB_SUBMODE_BITS, NB_SUBMODE_TABLE, SB_SUBMODE_BITS};
+use crate::Bits;
+
+pub(crate) const NB_FRAME_SIZE: usize = 160;
+pub(crate) const NB_SUBFRAME_SIZE: usize = 40;
+pub(crate) const NB_NB_SUBFRAMES: usize = 4;
+pub(crate) const NB_ORDER: usize = 10;
+pub(crate) const NB_PITCH_START: i32 = 17;
+pub(crate) const NB_PITCH_END: i32 = 144;
+
+/// Size of the excitation history
+const NB_DEC_BUFFER: usize = NB_FRAME_SIZE + 2 * NB_PITCH_END as usize + NB_SUBFRAME_SIZE + 12;
+/// Position of the current frame in the excitation history
+const NB_EXC: usize = 2 * NB_PITCH_END as usize + NB_SUBFRAME_SIZE + 6;
+
+/// Bits to skip over for each wideband submode when only decoding the
+/// narrowband layer
+const WB_SKIP_TABLE: [u32; 8] = [0, 36, 112, 192, 352, 0, 0, 0];
+
+pub(crate) const EXC_GAIN_QUANT_SCAL3: [f32; 8] = [
+    0.061130, 0.163546, 0.310413, 0.428220, 0.555887, 0.719055, 0.938694, 1.326874,
+];
+pub(crate) const EXC_GAIN_QUANT_SCAL1: [f32; 2] = [0.70469, 1.05127];
+
+/// How much of the pitch gain is kept after each consecutive lost frame
+const ATTENUATION: [f32; 10] = [
+    1.0, 0.961, 0.852, 0.698, 0.527, 0.368, 0.237, 0.141, 0.077, 0.039,
+];
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum DecoderError {
+    TooSmallBuffer,
+    EndOfStream,
+    CorruptStream,
+}
+
+impl Display for DecoderError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            DecoderError::TooSmallBuffer => write!(f, "Buffer is too small to decode into"),
+            DecoderError::EndOfStream => write!(f, "End of stream reached while decoding"),
+            DecoderError::CorruptStream => write!(f, "Corrupt stream was unable to be decoded"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for DecoderError {}
+
+fn median3(a: f32, b: f32, c: f32) -> f32 {
+    if a < b {
+        if b < c {
+            b
+        } else if a < c {
+            c
+        } else {
+            a
+        }
+    } else if c < b {
+        b
+    } else if c < a {
+        c
+    } else {
+        a
+    }
+}
+
+/// Converts decoded samples to 16 bit the way `speex_decode_int` does
+pub(crate) fn float_to_int(input: &[f32], out: &mut [i16]) {
+    for (o, &x) in out.iter_mut().zip(input) {
+        *o = if x > 32767.0 {
+            32767
+        } else if x < -32768.0 {
+            -32768
+        } else {
+            (0.5 + x as f64).floor() as i16
+        };
+    }
+}
+
+/// A narrowband (8 kHz) decoder written in Rust
+///
+/// This is a port of libspeex's narrowband decoder, and produces the same
+/// output as [`speex_safe::SpeexDecoder<NbMode>`](speex_safe::SpeexDecoder)
+/// for the same stream.
+#[derive(Clone, Debug)]
+pub struct NbDecoder {
+    first: bool,
+    count_lost: i32,
+    submode_id: usize,
+    lpc_enh_enabled: bool,
+    highpass_enabled: bool,
+    dtx_enabled: bool,
+    is_wideband: bool,
+
+    exc_buf: [f32; NB_DEC_BUFFER],
+    old_qlsp: [f32; NB_ORDER],
+    interp_qlpc: [f32; NB_ORDER],
+    mem_sp: [f32; NB_ORDER],
+    mem_hp: [f32; 2],
+    pi_gain: [f32; NB_NB_SUBFRAMES],
+
+    last_pitch: i32,
+    last_pitch_gain: f32,
+    pitch_gain_buf: [f32; 3],
+    pitch_gain_buf_idx: usize,
+    seed: u32,
+
+    voc_m1: f32,
+    voc_m2: f32,
+    voc_mean: f32,
+    voc_offset: i32,
+}
+
+impl NbDecoder {
+    /// Creates a new narrowband decoder
+    pub fn new() -> Self {
+        Self {
+            first: true,
+            count_lost: 0,
+            submode_id: 5,
+            lpc_enh_enabled: true,
+            highpass_enabled: true,
+            dtx_enabled: false,
+            is_wideband: false,
+            exc_buf: [0.0; NB_DEC_BUFFER],
+            old_qlsp: [0.0; NB_ORDER],
+            interp_qlpc: [0.0; NB_ORDER],
+            mem_sp: [0.0; NB_ORDER],
+            mem_hp: [0.0; 2],
+            pi_gain: [0.0; NB_NB_SUBFRAMES],
+            last_pitch: 40,
+            last_pitch_gain: 0.0,
+            pitch_gain_buf: [0.0; 3],
+            pitch_gain_buf_idx: 0,
+            seed: 1000,
+            voc_m1: 0.0,
+            voc_m2: 0.0,
+            voc_mean: 0.0,
+            voc_offset: 0,
+        }
+    }
+
+    /// Set whether to use the perceptual enhancer
+    pub fn set_enhancement(&mut self, state: bool) {
+        self.lpc_enh_enabled = state;
+    }
+
+    /// Get whether the perceptual enhancer is turned on or not
+    pub fn get_enhancement(&self) -> bool {
+        self.lpc_enh_enabled
+    }
+
+    /// Set whether to highpass filter the output
+    pub fn set_highpass(&mut self, state: bool) {
+        self.highpass_enabled = state;
+    }
+
+    /// Get whether the output is highpass filtered
+    pub fn get_highpass(&self) -> bool {
+        self.highpass_enabled
+    }
+
+    /// Number of samples in a decoded frame
+    pub fn get_frame_size(&self) -> usize {
+        NB_FRAME_SIZE
+    }
+
+    /// Bitrate of the last decoded frame, in bits per second
+    pub fn get_bitrate(&self) -> i32 {
+        let bits = match NB_SUBMODE_TABLE[self.submode_id] {
+            Some(submode) => submode.bits_per_frame,
+            None => NB_SUBMODE_BITS as i32 + 1,
+        };
+        8000 * bits / NB_FRAME_SIZE as i32
+    }
+
+    /// Resets the filter memories and excitation history
+    pub fn reset_state(&mut self) {
+        self.mem_sp = [0.0; NB_ORDER];
+        self.exc_buf[..NB_FRAME_SIZE + NB_PITCH_END as usize + 1].fill(0.0);
+    }
+
+    /// Decode one frame of speex data from the bitstream
+    pub fn decode(&mut self, bits: &mut Bits, out: &mut [f32]) -> Result<(), DecoderError> {
+        if out.len() < NB_FRAME_SIZE {
+            return Err(DecoderError::TooSmallBuffer);
+        }
+        self.decode_frame(Some(bits), &mut out[..NB_FRAME_SIZE], None)
+    }
+
+    /// Decode one frame of speex data from the bitstream, as i16
+    pub fn decode_int(&mut self, bits: &mut Bits, out: &mut [i16]) -> Result<(), DecoderError> {
+        if out.len() < NB_FRAME_SIZE {
+            return Err(DecoderError::TooSmallBuffer);
+        }
+        let mut float_out = [0f32; NB_FRAME_SIZE];
+        self.decode_frame(Some(bits), &mut float_out, None)?;
+        float_to_int(&float_out, out);
+        Ok(())
+    }
+
+    /// Produces one frame of audio for a packet that was lost, using the
+    /// decoder's packet loss concealment
+    pub fn decode_lost(&mut self, out: &mut [f32]) -> Result<(), DecoderError> {
+        if out.len() < NB_FRAME_SIZE {
+            return Err(DecoderError::TooSmallBuffer);
+        }
+        self.decode_frame(None, &mut out[..NB_FRAME_SIZE], None)
+    }
+
+    /// Produces one frame of audio for a packet that was lost, as i16
+    pub fn decode_lost_int(&mut self, out: &mut [i16]) -> Result<(), DecoderError> {
+        if out.len() < NB_FRAME_SIZE {
+            return Err(DecoderError::TooSmallBuffer);
+        }
+        let mut float_out = [0f32; NB_FRAME_SIZE];
+        self.decode_frame(None, &mut float_out, None)?;
+        float_to_int(&float_out, out);
+        Ok(())
+    }
+
+    /// Marks this decoder as the low band of a sub-band decoder, which changes
+    /// the highpass filter applied to its output
+    pub(crate) fn set_wideband(&mut self, state: bool) {
+        self.is_wideband = state;
+    }
+
+    /// Whether the last frame enabled discontinuous transmission
+    pub(crate) fn get_dtx_status(&self) -> bool {
+        self.dtx_enabled
+    }
+
+    /// Response of the synthesis filter at half the sampling rate, for each
+    /// subframe of the last frame
+    pub(crate) fn get_pi_gain(&self) -> [f32; NB_NB_SUBFRAMES] {
+        self.pi_gain
+    }
+
+    /// RMS of the excitation, for each subframe of the last frame
+    pub(crate) fn get_exc_rms(&self) -> [f32; NB_NB_SUBFRAMES] {
+        let mut rms = [0f32; NB_NB_SUBFRAMES];
+        let exc = &self.exc_buf[NB_EXC..NB_EXC + NB_FRAME_SIZE];
+        for (r, sub) in rms.iter_mut().zip(exc.chunks_exact(NB_SUBFRAME_SIZE)) {
+            *r = compute_rms(sub);
+        }
+        rms
+    }
+
+    /// Decodes a frame as the low band of a sub-band decoder, or conceals it
+    /// when `bits` is `None`, saving the innovation into `innov_save`
+    pub(crate) fn decode_low_band(
+        &mut self,
+        bits: Option<&mut Bits>,
+        out: &mut [f32],
+        innov_save: &mut [f32],
+    ) -> Result<(), DecoderError> {
+        self.decode_frame(bits, out, Some(innov_save))
+    }
+
+    /// Skips a wideband layer after its leading bit
+    fn skip_wideband_layer(bits: &mut Bits) -> Result<(), DecoderError> {
+        let submode = bits.unpack_unsigned(SB_SUBMODE_BITS);
+        match WB_SKIP_TABLE[submode as usize] {
+            // libspeex seeks back onto the layer it just read for these, and
+            // always ends up reporting a corrupt stream
+            0 => Err(DecoderError::CorruptStream),
+            skip => {
+                bits.advance(skip - (SB_SUBMODE_BITS + 1));
+                Ok(())
+            }
+        }
+    }
+
+    /// Finds the submode of the next narrowband frame, skipping wideband
+    /// layers and in-band requests
+    fn read_submode(bits: &mut Bits) -> Result<usize, DecoderError> {
+        loop {
+            if bits.remaining() < 5 {
+                return Err(DecoderError::EndOfStream);
+            }
+            if bits.unpack_unsigned(1) != 0 {
+                Self::skip_wideband_layer(bits)?;
+
+                if bits.remaining() < 5 {
+                    return Err(DecoderError::EndOfStream);
+                }
+                if bits.unpack_unsigned(1) != 0 {
+                    Self::skip_wideband_layer(bits)?;
+                    if bits.unpack_unsigned(1) != 0 {
+                        return Err(DecoderError::CorruptStream);
+                    }
+                }
+            }
+            if bits.remaining() < 4 {
+                return Err(DecoderError::EndOfStream);
+            }
+            match bits.unpack_unsigned(NB_SUBMODE_BITS) {
+                15 => return Err(DecoderError::EndOfStream),
+                14 => {
+                    let adv = match bits.unpack_unsigned(4) {
+                        0..=1 => 1,
+                        2..=7 => 4,
+                        8..=9 => 8,
+                        10..=11 => 16,
+                        12..=13 => 32,
+                        _ => 64,
+                    };
+                    bits.advance(adv);
+                }
+                13 => {
+                    let req_size = bits.unpack_unsigned(4);
+                    bits.advance(5 + 8 * req_size);
+                }
+                m @ 0..=8 => return Ok(m as usize),
+                _ => return Err(DecoderError::CorruptStream),
+            }
+        }
+    }
+
+    fn decode_frame(
+        &mut self,
+        bits: Option<&mut Bits>,
+        out: &mut [f32],
+        mut innov_save: Option<&mut [f32]>,
+    ) -> Result<(), DecoderError> {
+        let bits = match bits {
+            Some(bits) => {
+                self.submode_id = Self::read_submode(bits)?;
+                Some(bits)
+            }
+            None if self.dtx_enabled => {
+                self.submode_id = 0;
+                None
+            }
+            None => {
+                self.decode_lost_frame(out);
+                return Ok(());
+            }
+        };
+
+        self.exc_buf.copy_within(NB_FRAME_SIZE.., 0);
+
+        let (submode, bits) = match (NB_SUBMODE_TABLE[self.submode_id], bits) {
+            (Some(submode), Some(bits)) => (submode, bits),
+            _ => {
+                self.decode_null_frame(out);
+                return Ok(());
+            }
+        };
+
+        let mut qlsp = [0f32; NB_ORDER];
+        lsp_unquant(submode.lsp, &mut qlsp, bits);
+
+        // Damp the filter memory if a frame was lost and the LSPs changed a lot
+        if self.count_lost != 0 {
+            let mut lsp_dist = 0f32;
+            for (old, new) in self.old_qlsp.iter().zip(&qlsp) {
+                lsp_dist += (old - new).abs();
+            }
+            let fact = (0.6 * (-0.2 * lsp_dist as f64).exp()) as f32;
+            for mem in self.mem_sp.iter_mut() {
+                *mem *= fact;
+            }
+        }
+
+        if self.first || self.count_lost != 0 {
+            self.old_qlsp = qlsp;
+        }
+
+        let mut ol_pitch = 0;
+        if submode.lbr_pitch != -1 {
+            ol_pitch = NB_PITCH_START + bits.unpack_unsigned(7) as i32;
+        }
+
+        let mut ol_pitch_coef = 0f32;
+        if submode.forced_pitch_gain {
+            let quant = bits.unpack_unsigned(4);
+            ol_pitch_coef = (0.066667 * quant as f64) as f32;
+        }
+
+        let qe = bits.unpack_unsigned(5);
+        let ol_gain = (qe as f64 / 3.5).exp() as f32;
+
+        if self.submode_id == 1 {
+            self.dtx_enabled = bits.unpack_unsigned(4) == 15;
+        } else {
+            self.dtx_enabled = false;
+        }
+
+        let mut best_pitch = 40;
+        let mut best_pitch_gain = 0f32;
+        let mut pitch_average = 0f32;
+        let mut exc32 = [0f32; NB_SUBFRAME_SIZE];
+        let mut innov = [0f32; NB_SUBFRAME_SIZE];
+
+        for sub in 0..NB_NB_SUBFRAMES {
+            let offset = NB_SUBFRAME_SIZE * sub;
+            let pos = NB_EXC + offset;
+            self.exc_buf[pos..pos + NB_SUBFRAME_SIZE].fill(0.0);
+
+            let pit_min = match submode.lbr_pitch {
+                -1 => NB_PITCH_START,
+                0 => ol_pitch,
+                margin => (ol_pitch - margin + 1).max(NB_PITCH_START),
+            };
+
+            let (pitch, pitch_gain) = match submode.ltp {
+                Ltp::Forced => {
+                    forced_pitch_unquant(&mut self.exc_buf, pos, &mut exc32, pit_min, ol_pitch_coef)
+                }
+                Ltp::ThreeTap(params) => {
+                    let lost = LostState {
+                        count_lost: self.count_lost,
+                        subframe_offset: offset as i32,
+                        last_pitch_gain: self.last_pitch_gain,
+                    };
+                    pitch_unquant_3tap(&self.exc_buf, pos, &mut exc32, pit_min, params, bits, &lost)
+                }
+            };
+
+            // Keep crafted packets from blowing up the decoder
+            sanitize_values(&mut exc32, -32000.0, 32000.0);
+
+            let tmp = gain_3tap_to_1tap(&pitch_gain);
+            pitch_average += tmp;
+            let bp = best_pitch;
+            if (tmp > best_pitch_gain
+                && (2 * bp - pitch).abs() >= 3
+                && (3 * bp - pitch).abs() >= 4
+                && (4 * bp - pitch).abs() >= 5)
+                || (tmp as f64 > 0.6 * best_pitch_gain as f64
+                    && ((bp - 2 * pitch).abs() < 3
+                        || (bp - 3 * pitch).abs() < 4
+                        || (bp - 4 * pitch).abs() < 5))
+                || (0.67 * tmp as f64 > best_pitch_gain as f64
+                    && ((2 * bp - pitch).abs() < 3
+                        || (3 * bp - pitch).abs() < 4
+                        || (4 * bp - pitch).abs() < 5))
+            {
+                best_pitch = pitch;
+                if tmp > best_pitch_gain {
+                    best_pitch_gain = tmp;
+                }
+            }
+
+            innov.fill(0.0);
+            let ener = match submode.have_subframe_gain {
+                3 => EXC_GAIN_QUANT_SCAL3[bits.unpack_unsigned(3) as usize] * ol_gain,
+                1 => EXC_GAIN_QUANT_SCAL1[bits.unpack_unsigned(1) as usize] * ol_gain,
+                _ => ol_gain,
+            };
+
+            innovation_unquant(&submode.innovation, &mut innov, bits, &mut self.seed);
+            for v in innov.iter_mut() {
+                *v *= ener;
+            }
+
+            if submode.double_codebook {
+                let mut innov2 = [0f32; NB_SUBFRAME_SIZE];
+                innovation_unquant(&submode.innovation, &mut innov2, bits, &mut self.seed);
+                let scale = 0.454545f32 * ener;
+                for (v, v2) in innov.iter_mut().zip(&innov2) {
+                    *v += scale * v2;
+                }
+            }
+
+            let exc = &mut self.exc_buf[pos..pos + NB_SUBFRAME_SIZE];
+            for i in 0..NB_SUBFRAME_SIZE {
+                exc[i] = exc32[i] + innov[i];
+            }
+            if let Some(innov_save) = innov_save.as_deref_mut() {
+                innov_save[offset..offset + NB_SUBFRAME_SIZE].copy_from_slice(&innov);
+            }
+
+            // Vocoder mode replaces the excitation by a pulse train mixed with
+            // the noise
+            if self.submode_id == 1 {
+                let g = (1.5f32 * (ol_pitch_coef - 0.2f32)).clamp(0.0, 1.0);
+
+                exc.fill(0.0);
+                while self.voc_offset < NB_SUBFRAME_SIZE as i32 {
+                    if self.voc_offset >= 0 {
+                        exc[self.voc_offset as usize] =
+                            ((2 * ol_pitch) as f64).sqrt() as f32 * (g * ol_gain);
+                    }
+                    self.voc_offset += ol_pitch;
+                }
+                self.voc_offset -= NB_SUBFRAME_SIZE as i32;
+
+                for i in 0..NB_SUBFRAME_SIZE {
+                    let exci = exc[i];
+                    exc[i] = (0.7f32 * exc[i] + 0.3f32 * self.voc_m1)
+                        + ((1.0 - 0.85f32 * g) * innov[i] - (0.15f32 * g) * self.voc_m2);
+                    self.voc_m1 = exci;
+                    self.voc_m2 = innov[i];
+                    self.voc_mean = 0.8f32 * self.voc_mean + 0.2f32 * exc[i];
+                    exc[i] -= self.voc_mean;
+                }
+            }
+        }
+
+        if self.lpc_enh_enabled && submode.comb_gain > 0.0 && self.count_lost == 0 {
+            let (first, second) = out.split_at_mut(2 * NB_SUBFRAME_SIZE);
+            let comb_gain = submode.comb_gain;
+            multicomb(
+                &self.exc_buf,
+                NB_EXC - NB_SUBFRAME_SIZE,
+                first,
+                best_pitch,
+                40,
+                comb_gain,
+            );
+            multicomb(
+                &self.exc_buf,
+                NB_EXC + NB_SUBFRAME_SIZE,
+                second,
+                best_pitch,
+                40,
+                comb_gain,
+            );
+        } else {
+            out.copy_from_slice(&self.exc_buf[NB_EXC - NB_SUBFRAME_SIZE..][..NB_FRAME_SIZE]);
+        }
+
+        // After a lost frame, rescale the excitation to the energy that was
+        // encoded
+        if self.count_lost != 0 {
+            let exc_ener = compute_rms(&self.exc_buf[NB_EXC..NB_EXC + NB_FRAME_SIZE]);
+            let gain = (ol_gain / (exc_ener + 1.0)).min(2.0);
+            for (i, o) in out.iter_mut().enumerate() {
+                self.exc_buf[NB_EXC + i] *= gain;
+                *o = self.exc_buf[NB_EXC + i - NB_SUBFRAME_SIZE];
+            }
+        }
+
+        let mut interp_qlsp = [0f32; NB_ORDER];
+        let mut ak = [0f32; NB_ORDER];
+        for (sub, sp) in out.chunks_exact_mut(NB_SUBFRAME_SIZE).enumerate() {
+            lsp_interpolate(
+                &self.old_qlsp,
+                &qlsp,
+                &mut interp_qlsp,
+                sub,
+                NB_NB_SUBFRAMES,
+                LSP_MARGIN,
+            );
+            lsp_to_lpc(&interp_qlsp, &mut ak);
+
+            let mut pi_g = 1f32;
+            for pair in ak.chunks_exact(2) {
+                pi_g += pair[1] - pair[0];
+            }
+            self.pi_gain[sub] = pi_g;
+
+            // The filter lags one subframe behind, matching the delayed output
+            iir_mem16(sp, &self.interp_qlpc, &mut self.mem_sp);
+            self.interp_qlpc = ak;
+        }
+
+        if self.highpass_enabled {
+            let filter = if self.is_wideband {
+                HIGHPASS_WIDEBAND
+            } else {
+                HIGHPASS_NARROWBAND
+            };
+            highpass(out, filter | HIGHPASS_OUTPUT, &mut self.mem_hp);
+        }
+
+        self.old_qlsp = qlsp;
+        self.first = false;
+        self.count_lost = 0;
+        self.last_pitch = best_pitch;
+        self.last_pitch_gain = (0.25 * pitch_average as f64) as f32;
+        self.push_pitch_gain(self.last_pitch_gain);
+
+        Ok(())
+    }
+
+    /// Synthesizes a frame from noise, for frames that carry no parameters
+    fn decode_null_frame(&mut self, out: &mut [f32]) {
+        let mut lpc = [0f32; NB_ORDER];
+        bw_lpc(0.93, &self.interp_qlpc, &mut lpc);
+
+        let exc = &mut self.exc_buf[NB_EXC..NB_EXC + NB_FRAME_SIZE];
+        let innov_gain = compute_rms(exc);
+        for e in exc.iter_mut() {
+            *e = speex_rand(innov_gain, &mut self.seed);
+        }
+
+        self.first = true;
+
+        out.copy_from_slice(exc);
+        iir_mem16(out, &lpc, &mut self.mem_sp);
+
+        self.count_lost = 0;
+    }
+
+    /// Conceals a lost frame by extrapolating the pitch and adding noise
+    fn decode_lost_frame(&mut self, out: &mut [f32]) {
+        let fact = ATTENUATION
+            .get(self.count_lost as usize)
+            .copied()
+            .unwrap_or(0.0);
+
+        let gain_med = median3(
+            self.pitch_gain_buf[0],
+            self.pitch_gain_buf[1],
+            self.pitch_gain_buf[2],
+        );
+        if gain_med < self.last_pitch_gain {
+            self.last_pitch_gain = gain_med;
+        }
+
+        let mut pitch_gain = self.last_pitch_gain;
+        if pitch_gain as f64 > 0.85 {
+            pitch_gain = 0.85;
+        }
+        pitch_gain = fact * pitch_gain + 1e-15;
+
+        let innov_gain = compute_rms(&self.exc_buf[NB_EXC..NB_EXC + NB_FRAME_SIZE]);
+        let noise_gain = innov_gain * (fact * (1.0 - pitch_gain * pitch_gain));
+
+        self.exc_buf.copy_within(NB_FRAME_SIZE.., 0);
+
+        let pitch_val = (self.last_pitch
+            + speex_rand((1 + self.count_lost) as f32, &mut self.seed) as i32)
+            .clamp(NB_PITCH_START, NB_PITCH_END) as usize;
+        for i in NB_EXC..NB_EXC + NB_FRAME_SIZE {
+            self.exc_buf[i] = pitch_gain * (self.exc_buf[i - pitch_val] + 1e-15)
+                + speex_rand(noise_gain, &mut self.seed);
+        }
+
+        let lpc = self.interp_qlpc;
+        bw_lpc(0.98, &lpc, &mut self.interp_qlpc);
+        out.copy_from_slice(&self.exc_buf[NB_EXC - NB_SUBFRAME_SIZE..][..NB_FRAME_SIZE]);
+        iir_mem16(out, &self.interp_qlpc, &mut self.mem_sp);
+        highpass(out, HIGHPASS_NARROWBAND | HIGHPASS_OUTPUT, &mut self.mem_hp);
+
+        self.first = false;
+        self.count_lost += 1;
+        self.push_pitch_gain(pitch_gain);
+    }
+
+    fn push_pitch_gain(&mut self, gain: f32) {
+        self.pitch_gain_buf[self.pitch_gain_buf_idx] = gain;
+        self.pitch_gain_buf_idx = (self.pitch_gain_buf_idx + 1) % 3;
+    }
+}
+
+impl Default for NbDecoder {
+    fn default() -> Self {
+        NbDecoder::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::vec::Vec;
+
+    use speex_safe::{DynamicEncoder, ModeId, NbMode, SpeexBits, SpeexDecoder};
+
+    use super::*;
+    use crate::support::{self, snr};
+
+    /// A voiced signal with a gliding pitch, a noise burst and some silence
+    fn test_signal(frames: usize) -> Vec<f32> {
+        let mut seed = 12345u32;
+        let mut phase = 0f32;
+        (0..frames * NB_FRAME_SIZE)
+            .map(|n| {
+                let t = n as f32 / 8000.0;
+                seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                let noise = (seed >> 16) as f32 / 32768.0 - 1.0;
+                let section = n / (NB_FRAME_SIZE * 25);
+                match section % 4 {
+                    0 | 2 => {
+                        phase +=
+                            2.0 * std::f32::consts::PI * (120.0 + 80.0 * (3.0 * t).sin()) / 8000.0;
+                        (1..10)
+                            .map(|k| (k as f32 * phase).sin() * 4000.0 / k as f32)
+                            .sum::<f32>()
+                            + 200.0 * noise
+                    }
+                    1 => 3000.0 * noise,
+                    _ => 5.0 * noise,
+                }
+            })
+            .collect()
+    }
+
+    /// Encodes `signal` with libspeex, returning the packets
+    fn encode(signal: &[f32], setup: impl Fn(&mut DynamicEncoder)) -> Vec<Vec<u8>> {
+        let mut encoder = DynamicEncoder::new(ModeId::NarrowBand);
+        setup(&mut encoder);
+        support::encode_packets(&mut encoder, signal)
+    }
+
+    /// Decodes `packets` with both decoders, treating every `lose_every`th
+    /// packet as lost, and checks the outputs agree
+    fn assert_matches_libspeex(packets: &[Vec<u8>], enhancement: bool, lose_every: usize) {
+        let mut reference = SpeexDecoder::<NbMode>::new();
+        reference.set_enhancement(enhancement);
+        let mut decoder = NbDecoder::new();
+        decoder.set_enhancement(enhancement);
+
+        let mut expected = vec![0f32; NB_FRAME_SIZE];
+        let mut actual = vec![0f32; NB_FRAME_SIZE];
+        for (i, packet) in packets.iter().enumerate() {
+            if lose_every != 0 && i % lose_every == lose_every - 1 {
+                reference.decode_lost(&mut expected).unwrap();
+                decoder.decode_lost(&mut actual).unwrap();
+            } else {
+                let mut sys_bits = SpeexBits::new();
+                sys_bits.read_from(&mut packet.clone());
+                reference.decode(&mut sys_bits, &mut expected).unwrap();
+                let mut bits = Bits::from_bytes(packet);
+                decoder.decode(&mut bits, &mut actual).unwrap();
+            }
+            let snr = snr(&expected, &actual);
+            assert!(
+                snr > 60.0,
+                "frame {i} differs from libspeex, SNR {snr:.1} dB"
+            );
+        }
+    }
+
+    #[test]
+    fn matches_libspeex_at_every_quality() {
+        let signal = test_signal(100);
+        for quality in 0..=10 {
+            let packets = encode(&signal, |encoder| encoder.set_quality(quality));
+            assert_matches_libspeex(&packets, true, 0);
+            assert_matches_libspeex(&packets, false, 0);
+        }
+    }
+
+    #[test]
+    fn matches_libspeex_with_lost_packets() {
+        let signal = test_signal(100);
+        for quality in [2, 5, 8] {
+            let packets = encode(&signal, |encoder| encoder.set_quality(quality));
+            assert_matches_libspeex(&packets, true, 4);
+        }
+    }
+
+    #[test]
+    fn matches_libspeex_with_vbr_and_dtx() {
+        let signal = test_signal(100);
+        let packets = encode(&signal, |encoder| {
+            encoder.set_vbr(true);
+            encoder.set_vbr_quality(4.0);
+            encoder.set_dtx(true);
+        });
+        assert_matches_libspeex(&packets, true, 0);
+        assert_matches_libspeex(&packets, true, 5);
+    }
+
+    #[test]
+    fn decode_int_rounds_like_libspeex() {
+        let mut out = [0i16; 4];
+        float_to_int(&[0.5, -0.5, 40000.0, -40000.0], &mut out);
+        assert_eq!(out, [1, 0, 32767, -32768]);
+    }
+
+    #[test]
+    fn rejects_small_buffer() {
+        let mut decoder = NbDecoder::new();
+        let mut out = [0f32; 10];
+        assert_eq!(
+            decoder.decode_lost(&mut out),
+            Err(DecoderError::TooSmallBuffer)
+        );
+    }
+}
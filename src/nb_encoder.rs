@@ -0,0 +1,1035 @@
+////////////////////////////////////////////////////////////////////////////////
+// Copyright (c) 2023.                                                         /
+// This Source Code Form is subject to the terms of the Mozilla Public License,/
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can    /
+// obtain one at http://mozilla.org/MPL/2.0/.                                  /
+////////////////////////////////////////////////////////////////////////////////
+
+use core::f32::consts::PI;
+use core::fmt::{Display, Formatter};
+
+#[cfg(not(any(feature = "std", test)))]
+use num_traits::Float;
+
+use crate::cb_search::innovation_quant;
+use crate::filters::{
+    bw_lpc,
+    compute_impulse_response,
+    compute_rms,
+    filter_mem16,
+    fir_mem16,
+    highpass,
+    iir_mem16,
+    WeightedSynthesis,
+    HIGHPASS_INPUT,
+    HIGHPASS_NARROWBAND,
+};
+use crate::lpc::{autocorr, lpc_from_autocorr};
+use crate::lsp::{lpc_to_lsp, lsp_interpolate, lsp_quant, lsp_to_lpc, LSP_MARGIN};
+use crate::ltp::{forced_pitch_quant, open_loop_nbest_pitch, pitch_search_3tap};
+use crate::modes::{Innovation, Ltp, NB_QUALITY_MAP, NB_SUBMODE_BITS, NB_SUBMODE_TABLE};
+use crate::nb_decoder::{
+    EXC_GAIN_QUANT_SCAL1,
+    EXC_GAIN_QUANT_SCAL3,
+    NB_FRAME_SIZE,
+    NB_NB_SUBFRAMES,
+    NB_ORDER,
+    NB_PITCH_END,
+    NB_PITCH_START,
+    NB_SUBFRAME_SIZE,
+};
+use crate::tables::{LAG_WINDOW, LPC_WINDOW};
+use crate::vbr::{VbrState, VBR_NB_THRESH};
+use crate::vq::scal_quant;
+use crate::Bits;
+
+/// Size of the LPC analysis window, a frame plus the lookahead
+const NB_WINDOW_SIZE: usize = NB_FRAME_SIZE + NB_SUBFRAME_SIZE;
+/// Position of the current frame in the excitation and weighted signal
+/// histories
+const NB_EXC: usize = NB_PITCH_END as usize + 2;
+/// Size of the excitation and weighted signal histories
+const NB_EXCBUF: usize = NB_FRAME_SIZE + NB_EXC;
+
+/// Bandwidth expansion of the numerator of the perceptual weighting filter
+const GAMMA1: f32 = 0.92;
+/// Bandwidth expansion of the denominator of the perceptual weighting filter
+const GAMMA2: f32 = 0.6;
+/// Noise floor added to the autocorrelation, relative to the energy
+const LPC_FLOOR: f32 = 0.0002;
+/// Step of the root search when converting LPCs to LSPs
+const LSP_DELTA1: f32 = 0.2;
+
+const EXC_GAIN_QUANT_SCAL3_BOUND: [f32; 7] = [
+    0.112338, 0.236980, 0.369316, 0.492054, 0.637471, 0.828874, 1.132784,
+];
+const EXC_GAIN_QUANT_SCAL1_BOUND: [f32; 1] = [0.87798];
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum EncoderError {
+    TooSmallBuffer,
+}
+
+impl Display for EncoderError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            EncoderError::TooSmallBuffer => write!(f, "Buffer is too small to encode from"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for EncoderError {}
+
+/// LSPs of a flat spectrum, used before the first frame is analysed
+fn initial_lsp() -> [f32; NB_ORDER] {
+    let mut lsp = [0f32; NB_ORDER];
+    for (i, l) in lsp.iter_mut().enumerate() {
+        *l = PI * (i + 1) as f32 / (NB_ORDER + 1) as f32;
+    }
+    lsp
+}
+
+/// A narrowband (8 kHz) encoder written in Rust
+///
+/// This is a port of libspeex's narrowband encoder, producing streams that
+/// any Speex decoder accepts, with the same settings as
+/// [`speex_safe::SpeexEncoder<NbMode>`](speex_safe::SpeexEncoder).
+#[derive(Clone, Debug)]
+pub struct NbEncoder {
+    first: bool,
+    submode_id: usize,
+    submode_select: usize,
+    complexity: i32,
+    plc_tuning: i32,
+    sampling_rate: i32,
+    highpass_enabled: bool,
+    bounded_pitch: bool,
+    cumul_gain: f32,
+
+    win_buf: [f32; NB_WINDOW_SIZE - NB_FRAME_SIZE],
+    exc_buf: [f32; NB_EXCBUF],
+    sw_buf: [f32; NB_EXCBUF],
+    old_lsp: [f32; NB_ORDER],
+    old_qlsp: [f32; NB_ORDER],
+    mem_sp: [f32; NB_ORDER],
+    mem_sw: [f32; NB_ORDER],
+    mem_sw_whole: [f32; NB_ORDER],
+    mem_exc: [f32; NB_ORDER],
+    mem_exc2: [f32; NB_ORDER],
+    mem_hp: [f32; 2],
+
+    vbr: VbrState,
+    vbr_enabled: bool,
+    vad_enabled: bool,
+    dtx_enabled: bool,
+    vbr_quality: f32,
+    relative_quality: f32,
+    vbr_max: i32,
+    dtx_count: i32,
+    abr_enabled: i32,
+    abr_drift: f32,
+    abr_drift2: f32,
+    abr_count: f32,
+}
+
+impl NbEncoder {
+    /// Creates a new narrowband encoder
+    pub fn new() -> Self {
+        Self {
+            first: true,
+            submode_id: 5,
+            submode_select: 5,
+            complexity: 2,
+            plc_tuning: 2,
+            sampling_rate: 8000,
+            highpass_enabled: true,
+            bounded_pitch: true,
+            cumul_gain: 1024.0,
+            win_buf: [0.0; NB_WINDOW_SIZE - NB_FRAME_SIZE],
+            exc_buf: [0.0; NB_EXCBUF],
+            sw_buf: [0.0; NB_EXCBUF],
+            old_lsp: initial_lsp(),
+            old_qlsp: [0.0; NB_ORDER],
+            mem_sp: [0.0; NB_ORDER],
+            mem_sw: [0.0; NB_ORDER],
+            mem_sw_whole: [0.0; NB_ORDER],
+            mem_exc: [0.0; NB_ORDER],
+            mem_exc2: [0.0; NB_ORDER],
+            mem_hp: [0.0; 2],
+            vbr: VbrState::default(),
+            vbr_enabled: false,
+            vad_enabled: false,
+            dtx_enabled: false,
+            vbr_quality: 8.0,
+            relative_quality: 0.0,
+            vbr_max: 0,
+            dtx_count: 0,
+            abr_enabled: 0,
+            abr_drift: 0.0,
+            abr_drift2: 0.0,
+            abr_count: 0.0,
+        }
+    }
+
+    /// Set the quality of the encoding, from 0 to 10
+    pub fn set_quality(&mut self, quality: i32) {
+        self.set_submode(NB_QUALITY_MAP[quality.clamp(0, 10) as usize]);
+    }
+
+    /// Set the amount of CPU spent searching the codebooks, from 1 to 10
+    pub fn set_complexity(&mut self, complexity: i32) {
+        self.complexity = complexity.max(0);
+    }
+
+    /// Get the amount of CPU spent searching the codebooks
+    pub fn get_complexity(&self) -> i32 {
+        self.complexity
+    }
+
+    /// Set whether to use variable bitrate
+    pub fn set_vbr(&mut self, state: bool) {
+        self.vbr_enabled = state;
+    }
+
+    /// Get whether variable bitrate is used
+    pub fn get_vbr(&self) -> bool {
+        self.vbr_enabled
+    }
+
+    /// Set the quality targeted by variable bitrate, from 0 to 10
+    pub fn set_vbr_quality(&mut self, quality: f32) {
+        self.vbr_quality = quality.clamp(0.0, 10.0);
+    }
+
+    /// Get the quality targeted by variable bitrate
+    pub fn get_vbr_quality(&self) -> f32 {
+        self.vbr_quality
+    }
+
+    /// Set whether to use voice activity detection, which encodes silence
+    /// with fewer bits
+    pub fn set_vad(&mut self, state: bool) {
+        self.vad_enabled = state;
+    }
+
+    /// Get whether voice activity detection is used
+    pub fn get_vad(&self) -> bool {
+        self.vad_enabled
+    }
+
+    /// Set whether to use discontinuous transmission, which skips most of
+    /// the frames of a silence when VBR or VAD is on
+    pub fn set_dtx(&mut self, state: bool) {
+        self.dtx_enabled = state;
+    }
+
+    /// Get whether discontinuous transmission is used
+    pub fn get_dtx(&self) -> bool {
+        self.dtx_enabled
+    }
+
+    /// Set the average bitrate to target, in bits per second, or 0 to turn
+    /// average bitrate off
+    pub fn set_abr(&mut self, bitrate: i32) {
+        self.abr_enabled = bitrate;
+        self.vbr_enabled = bitrate != 0;
+        if self.vbr_enabled {
+            let mut quality = 10;
+            while quality >= 0 {
+                self.set_quality(quality);
+                if self.get_bitrate() <= bitrate {
+                    break;
+                }
+                quality -= 1;
+            }
+            self.vbr_quality = quality.max(0) as f32;
+            self.abr_count = 0.0;
+            self.abr_drift = 0.0;
+            self.abr_drift2 = 0.0;
+        }
+    }
+
+    /// Get the average bitrate targeted, 0 if off
+    pub fn get_abr(&self) -> i32 {
+        self.abr_enabled
+    }
+
+    /// Set the highest quality whose bitrate doesn't exceed `bitrate`
+    pub fn set_bitrate(&mut self, bitrate: i32) {
+        for quality in (0..=10).rev() {
+            self.set_quality(quality);
+            if self.get_bitrate() <= bitrate {
+                break;
+            }
+        }
+    }
+
+    /// Bitrate of the current submode, in bits per second
+    pub fn get_bitrate(&self) -> i32 {
+        let bits = match NB_SUBMODE_TABLE[self.submode_id] {
+            Some(submode) => submode.bits_per_frame,
+            None => NB_SUBMODE_BITS as i32 + 1,
+        };
+        self.sampling_rate * bits / NB_FRAME_SIZE as i32
+    }
+
+    /// Set the highest bitrate variable bitrate may use, or 0 for no limit
+    pub fn set_vbr_max_bitrate(&mut self, bitrate: i32) {
+        self.vbr_max = bitrate;
+    }
+
+    /// Get the highest bitrate variable bitrate may use
+    pub fn get_vbr_max_bitrate(&self) -> i32 {
+        self.vbr_max
+    }
+
+    /// Set the sampling rate used to compute bitrates
+    pub fn set_sampling_rate(&mut self, rate: i32) {
+        self.sampling_rate = rate;
+    }
+
+    /// Get the sampling rate used to compute bitrates
+    pub fn get_sampling_rate(&self) -> i32 {
+        self.sampling_rate
+    }
+
+    /// Set whether to highpass filter the input
+    pub fn set_highpass(&mut self, state: bool) {
+        self.highpass_enabled = state;
+    }
+
+    /// Get whether the input is highpass filtered
+    pub fn get_highpass(&self) -> bool {
+        self.highpass_enabled
+    }
+
+    /// Set how much the encoder protects the stream against packet loss, as
+    /// the expected loss percentage
+    pub fn set_plc_tuning(&mut self, tuning: i32) {
+        self.plc_tuning = tuning.min(100);
+    }
+
+    /// Get how much the encoder protects the stream against packet loss
+    pub fn get_plc_tuning(&self) -> i32 {
+        self.plc_tuning
+    }
+
+    /// Quality the VBR analysis rated the last frame as needing, -1 when
+    /// neither VBR nor VAD is on
+    pub fn get_relative_quality(&self) -> f32 {
+        self.relative_quality
+    }
+
+    /// Number of samples in a frame
+    pub fn get_frame_size(&self) -> usize {
+        NB_FRAME_SIZE
+    }
+
+    /// Number of samples the encoder looks ahead of the frame
+    pub fn get_lookahead(&self) -> usize {
+        NB_WINDOW_SIZE - NB_FRAME_SIZE
+    }
+
+    /// Resets the filter memories and signal history
+    pub fn reset_state(&mut self) {
+        self.bounded_pitch = true;
+        self.first = true;
+        self.old_lsp = initial_lsp();
+        self.mem_sw = [0.0; NB_ORDER];
+        self.mem_sw_whole = [0.0; NB_ORDER];
+        self.mem_sp = [0.0; NB_ORDER];
+        self.mem_exc = [0.0; NB_ORDER];
+        self.exc_buf[..NB_FRAME_SIZE + NB_PITCH_END as usize + 1].fill(0.0);
+        self.sw_buf[..NB_FRAME_SIZE + NB_PITCH_END as usize + 1].fill(0.0);
+        self.win_buf = [0.0; NB_WINDOW_SIZE - NB_FRAME_SIZE];
+    }
+
+    /// Encode one frame of audio into the bitstream
+    pub fn encode(&mut self, input: &[f32], bits: &mut Bits) -> Result<(), EncoderError> {
+        if input.len() < NB_FRAME_SIZE {
+            return Err(EncoderError::TooSmallBuffer);
+        }
+        let mut frame = [0f32; NB_FRAME_SIZE];
+        frame.copy_from_slice(&input[..NB_FRAME_SIZE]);
+        self.encode_frame(&mut frame, bits);
+        Ok(())
+    }
+
+    /// Encode one frame of i16 audio into the bitstream
+    pub fn encode_int(&mut self, input: &[i16], bits: &mut Bits) -> Result<(), EncoderError> {
+        if input.len() < NB_FRAME_SIZE {
+            return Err(EncoderError::TooSmallBuffer);
+        }
+        let mut frame = [0f32; NB_FRAME_SIZE];
+        for (f, &i) in frame.iter_mut().zip(input) {
+            *f = i as f32;
+        }
+        self.encode_frame(&mut frame, bits);
+        Ok(())
+    }
+
+    fn set_submode(&mut self, submode: usize) {
+        self.submode_id = submode;
+        self.submode_select = submode;
+    }
+
+    /// Computes the LSPs of the frame, from a window over the end of the last
+    /// frame and the start of this one
+    fn analyse_lsp(&self, input: &[f32]) -> [f32; NB_ORDER] {
+        let lookahead = NB_WINDOW_SIZE - NB_FRAME_SIZE;
+        let mut w_sig = [0f32; NB_WINDOW_SIZE];
+        for i in 0..lookahead {
+            w_sig[i] = self.win_buf[i] * LPC_WINDOW[i];
+        }
+        for i in lookahead..NB_WINDOW_SIZE {
+            w_sig[i] = input[i - lookahead] * LPC_WINDOW[i];
+        }
+
+        let mut ac = [0f32; NB_ORDER + 1];
+        autocorr(&w_sig, &mut ac);
+        // Noise floor in the autocorrelation domain
+        ac[0] += ac[0] * LPC_FLOOR;
+        // Lag windowing, equivalent to filtering in the power-spectrum domain
+        for (a, &w) in ac.iter_mut().zip(&LAG_WINDOW) {
+            *a *= w;
+        }
+        ac[0] += 1.0;
+
+        let mut lpc = [0f32; NB_ORDER];
+        lpc_from_autocorr(&ac, &mut lpc);
+        let mut lsp = [0f32; NB_ORDER];
+        if lpc_to_lsp(&lpc, &mut lsp, 10, LSP_DELTA1) != NB_ORDER {
+            // Not all the roots were found, keep the last filter
+            lsp = self.old_lsp;
+        }
+        lsp
+    }
+
+    /// Estimates the pitch of the whole frame from the weighted signal,
+    /// returning it with its gain
+    fn open_loop_pitch(&mut self, input: &[f32], interp_lpc: &[f32]) -> (i32, f32) {
+        let lookahead = NB_WINDOW_SIZE - NB_FRAME_SIZE;
+        let mut bw_lpc1 = [0f32; NB_ORDER];
+        let mut bw_lpc2 = [0f32; NB_ORDER];
+        bw_lpc(0.9, interp_lpc, &mut bw_lpc1);
+        bw_lpc(0.55, interp_lpc, &mut bw_lpc2);
+
+        let sw = &mut self.sw_buf[NB_EXC..];
+        sw[..lookahead].copy_from_slice(&self.win_buf);
+        sw[lookahead..NB_FRAME_SIZE].copy_from_slice(&input[..NB_FRAME_SIZE - lookahead]);
+        filter_mem16(
+            &mut sw[..NB_FRAME_SIZE],
+            &bw_lpc1,
+            &bw_lpc2,
+            &mut self.mem_sw_whole,
+        );
+
+        let mut pitch = [0i32; 6];
+        let mut gain = [0f32; 6];
+        open_loop_nbest_pitch(
+            &self.sw_buf,
+            NB_EXC,
+            NB_PITCH_START,
+            NB_PITCH_END,
+            NB_FRAME_SIZE,
+            &mut pitch,
+            Some(&mut gain),
+        );
+
+        // Try to remove pitch multiples
+        let mut ol_pitch = pitch[0];
+        for i in 1..6 {
+            let is_multiple = (2..=5).any(|k| (k * pitch[i] - ol_pitch).abs() <= k);
+            if gain[i] as f64 > 0.85 * gain[0] as f64 && is_multiple {
+                ol_pitch = pitch[i];
+            }
+        }
+        (ol_pitch, gain[0])
+    }
+
+    /// Picks the submode of the frame from the VBR analysis
+    fn choose_submode(&mut self, input: &[f32], lsp: &[f32], ol_pitch_coef: f32) {
+        let mut lsp_dist = 0f32;
+        for (&old, &new) in self.old_lsp.iter().zip(lsp) {
+            lsp_dist += (old - new) * (old - new);
+        }
+
+        if self.abr_enabled != 0 {
+            let mut qual_change = 0f32;
+            // Only adapt if long-term and short-term drift are the same sign
+            if self.abr_drift2 * self.abr_drift > 0.0 {
+                qual_change =
+                    (-0.00001 * self.abr_drift as f64 / (1.0 + self.abr_count) as f64) as f32;
+                qual_change = qual_change.clamp(-0.05, 0.05);
+            }
+            self.vbr_quality = (self.vbr_quality + qual_change).clamp(0.0, 10.0);
+        }
+
+        self.relative_quality = self.vbr.analysis(input, ol_pitch_coef);
+        let dtx_enabled = self.dtx_enabled;
+        let keep_transmitting =
+            |count: i32| count == 0 || lsp_dist as f64 > 0.05 || !dtx_enabled || count > 20;
+
+        if self.vbr_enabled {
+            let mut choice = 0;
+            let mut min_diff = 100f32;
+            for mode in (1..=8).rev() {
+                let v1 = self.vbr_quality.floor() as usize;
+                let thresh = if v1 == 10 {
+                    VBR_NB_THRESH[mode][v1]
+                } else {
+                    (self.vbr_quality - v1 as f32) * VBR_NB_THRESH[mode][v1 + 1]
+                        + (1.0 + v1 as f32 - self.vbr_quality) * VBR_NB_THRESH[mode][v1]
+                };
+                if self.relative_quality > thresh && self.relative_quality - thresh < min_diff {
+                    choice = mode;
+                    min_diff = self.relative_quality - thresh;
+                }
+            }
+
+            let mut mode = choice;
+            if mode == 0 {
+                if keep_transmitting(self.dtx_count) {
+                    mode = 1;
+                    self.dtx_count = 1;
+                } else {
+                    self.dtx_count += 1;
+                }
+            } else {
+                self.dtx_count = 0;
+            }
+
+            self.set_submode(mode);
+            if self.vbr_max > 0 && self.get_bitrate() > self.vbr_max {
+                self.set_bitrate(self.vbr_max);
+            }
+
+            if self.abr_enabled != 0 {
+                let diff = self.get_bitrate() - self.abr_enabled;
+                self.abr_drift += diff as f32;
+                self.abr_drift2 = (0.95 * self.abr_drift2 as f64 + 0.05 * diff as f64) as f32;
+                self.abr_count = (self.abr_count as f64 + 1.0) as f32;
+            }
+        } else if self.relative_quality < 2.0 {
+            // VAD only
+            if keep_transmitting(self.dtx_count) {
+                self.dtx_count = 1;
+                self.submode_id = 1;
+            } else {
+                self.dtx_count += 1;
+                self.submode_id = 0;
+            }
+        } else {
+            self.dtx_count = 0;
+            self.submode_id = self.submode_select;
+        }
+    }
+
+    fn encode_frame(&mut self, input: &mut [f32; NB_FRAME_SIZE], bits: &mut Bits) {
+        let lookahead = NB_WINDOW_SIZE - NB_FRAME_SIZE;
+
+        // Move signals one frame towards the past
+        self.exc_buf.copy_within(NB_FRAME_SIZE.., 0);
+        self.sw_buf.copy_within(NB_FRAME_SIZE.., 0);
+
+        if self.highpass_enabled {
+            highpass(
+                input,
+                HIGHPASS_NARROWBAND | HIGHPASS_INPUT,
+                &mut self.mem_hp,
+            );
+        }
+
+        let lsp = self.analyse_lsp(input);
+
+        // Whole frame analysis (open-loop estimation of pitch and excitation
+        // gain)
+        let mut interp_lsp = lsp;
+        let mut interp_lpc = [0f32; NB_ORDER];
+        if !self.first {
+            lsp_interpolate(
+                &self.old_lsp,
+                &lsp,
+                &mut interp_lsp,
+                NB_NB_SUBFRAMES,
+                NB_NB_SUBFRAMES << 1,
+                LSP_MARGIN,
+            );
+        }
+        lsp_to_lpc(&interp_lsp, &mut interp_lpc);
+
+        let needs_pitch = match NB_SUBMODE_TABLE[self.submode_id] {
+            None => true,
+            Some(submode) => {
+                (self.complexity > 2 && submode.have_subframe_gain < 3)
+                    || submode.forced_pitch_gain
+                    || submode.lbr_pitch != -1
+            }
+        } || self.vbr_enabled
+            || self.vad_enabled;
+        let (mut ol_pitch, mut ol_pitch_coef) = if needs_pitch {
+            self.open_loop_pitch(input, &interp_lpc)
+        } else {
+            (0, 0.0)
+        };
+
+        // Compute the "real" excitation and its open-loop gain
+        let exc = &mut self.exc_buf[NB_EXC..NB_EXC + NB_FRAME_SIZE];
+        exc[..lookahead].copy_from_slice(&self.win_buf);
+        exc[lookahead..].copy_from_slice(&input[..NB_FRAME_SIZE - lookahead]);
+        fir_mem16(exc, &interp_lpc, &mut self.mem_exc);
+        let g = compute_rms(exc);
+        let mut ol_gain = if self.submode_id != 1 && ol_pitch > 0 {
+            let voicing = ol_pitch_coef * ol_pitch_coef;
+            g * (1.1 * (1.0 - 0.8 * voicing as f64).sqrt()) as f32
+        } else {
+            g
+        };
+
+        if self.vbr_enabled || self.vad_enabled {
+            self.choose_submode(input, &lsp, ol_pitch_coef);
+        } else {
+            self.relative_quality = -1.0;
+        }
+
+        // A zero for narrowband, then the submode of this frame
+        bits.pack(0, 1);
+        bits.pack(self.submode_id as u32, NB_SUBMODE_BITS);
+
+        let submode = match NB_SUBMODE_TABLE[self.submode_id] {
+            Some(submode) => submode,
+            None => {
+                // Nothing more is transmitted
+                self.exc_buf[NB_EXC..].fill(1e-15);
+                self.sw_buf[NB_EXC..].fill(1e-15);
+                self.mem_sw = [0.0; NB_ORDER];
+                self.first = true;
+                self.bounded_pitch = true;
+                self.win_buf
+                    .copy_from_slice(&input[NB_FRAME_SIZE - lookahead..]);
+                self.mem_sp = [0.0; NB_ORDER];
+                return;
+            }
+        };
+
+        if self.first {
+            self.old_lsp = lsp;
+        }
+        let mut qlsp = [0f32; NB_ORDER];
+        lsp_quant(submode.lsp, &lsp, &mut qlsp, bits);
+
+        // Low bit-rate pitch modes send the open-loop pitch
+        if submode.lbr_pitch != -1 {
+            bits.pack((ol_pitch - NB_PITCH_START) as u32, 7);
+        }
+
+        if submode.forced_pitch_gain {
+            // Damps the pitch a bit, as it tends to be too aggressive when
+            // forced
+            ol_pitch_coef = (0.9 * ol_pitch_coef as f64) as f32;
+            let quant = (0.5 + (15.0 * ol_pitch_coef) as f64).floor() as i32;
+            let quant = quant.clamp(0, 15);
+            bits.pack(quant as u32, 4);
+            ol_pitch_coef = (0.066667 * quant as f64) as f32;
+        }
+
+        let qe = ((0.5 + 3.5 * (ol_gain as f64).ln()).floor() as i32).clamp(0, 31);
+        ol_gain = (qe as f64 / 3.5).exp() as f32;
+        bits.pack(qe as u32, 5);
+
+        if self.first {
+            self.old_qlsp = qlsp;
+        }
+
+        let mut interp_qlsp = [0f32; NB_ORDER];
+        let mut interp_qlpc = [0f32; NB_ORDER];
+        let mut bw_lpc1 = [0f32; NB_ORDER];
+        let mut bw_lpc2 = [0f32; NB_ORDER];
+        for sub in 0..NB_NB_SUBFRAMES {
+            let offset = NB_SUBFRAME_SIZE * sub;
+            let pos = NB_EXC + offset;
+
+            lsp_interpolate(
+                &self.old_lsp,
+                &lsp,
+                &mut interp_lsp,
+                sub,
+                NB_NB_SUBFRAMES,
+                LSP_MARGIN,
+            );
+            lsp_interpolate(
+                &self.old_qlsp,
+                &qlsp,
+                &mut interp_qlsp,
+                sub,
+                NB_NB_SUBFRAMES,
+                LSP_MARGIN,
+            );
+            lsp_to_lpc(&interp_lsp, &mut interp_lpc);
+            lsp_to_lpc(&interp_qlsp, &mut interp_qlpc);
+
+            // Bandwidth-expanded (unquantized) LPCs for perceptual weighting
+            bw_lpc(GAMMA1, &interp_lpc, &mut bw_lpc1);
+            bw_lpc(GAMMA2, &interp_lpc, &mut bw_lpc2);
+
+            let mut in_buf = [0f32; NB_SUBFRAME_SIZE];
+            if sub == 0 {
+                in_buf.copy_from_slice(&self.win_buf);
+            } else {
+                in_buf.copy_from_slice(&input[(sub - 1) * NB_SUBFRAME_SIZE..][..NB_SUBFRAME_SIZE]);
+            }
+
+            let response_bound = if self.complexity == 0 {
+                NB_SUBFRAME_SIZE >> 1
+            } else {
+                NB_SUBFRAME_SIZE
+            };
+            let mut syn_resp = [1e-15f32; NB_SUBFRAME_SIZE];
+            compute_impulse_response(
+                &interp_qlpc,
+                &bw_lpc1,
+                &bw_lpc2,
+                &mut syn_resp[..response_bound],
+            );
+
+            // Zero input response of A(z/g1) / (A(z/g2) * A(z))
+            let exc = &mut self.exc_buf[pos..pos + NB_SUBFRAME_SIZE];
+            exc.fill(1e-15);
+            let mut mem = self.mem_sp;
+            iir_mem16(exc, &interp_qlpc, &mut mem);
+            let mut mem = self.mem_sw;
+            filter_mem16(exc, &bw_lpc1, &bw_lpc2, &mut mem);
+
+            // Weighted signal
+            let sw = &mut self.sw_buf[pos..pos + NB_SUBFRAME_SIZE];
+            sw.copy_from_slice(&in_buf);
+            let mut mem = self.mem_sw;
+            filter_mem16(sw, &bw_lpc1, &bw_lpc2, &mut mem);
+            if self.complexity == 0 {
+                self.mem_sw = mem;
+            }
+
+            let mut target = [0f32; NB_SUBFRAME_SIZE];
+            for i in 0..NB_SUBFRAME_SIZE {
+                target[i] = sw[i] - exc[i];
+            }
+
+            exc.copy_from_slice(&in_buf);
+            fir_mem16(exc, &interp_qlpc, &mut self.mem_exc2);
+
+            // Long-term prediction
+            let (pit_min, mut pit_max) = match submode.lbr_pitch {
+                -1 => (NB_PITCH_START, NB_PITCH_END),
+                0 => (ol_pitch, ol_pitch),
+                margin => {
+                    ol_pitch = ol_pitch
+                        .max(NB_PITCH_START + margin - 1)
+                        .min(NB_PITCH_END - margin);
+                    (ol_pitch - margin + 1, ol_pitch + margin)
+                }
+            };
+            // Only use the current frame if needed
+            if self.bounded_pitch && pit_max > offset as i32 {
+                pit_max = offset as i32;
+            }
+
+            let filters = WeightedSynthesis {
+                ak: &interp_qlpc,
+                awk1: &bw_lpc1,
+                awk2: &bw_lpc2,
+                resp: &syn_resp,
+            };
+            let mut exc32 = [0f32; NB_SUBFRAME_SIZE];
+            match submode.ltp {
+                Ltp::Forced => {
+                    forced_pitch_quant(
+                        &mut target,
+                        &filters,
+                        &self.exc_buf,
+                        pos,
+                        &mut exc32,
+                        pit_min,
+                        ol_pitch_coef,
+                    )
+                }
+                Ltp::ThreeTap(params) => {
+                    pitch_search_3tap(
+                        &mut target,
+                        &filters,
+                        &self.sw_buf,
+                        &self.exc_buf,
+                        pos,
+                        &mut exc32,
+                        params,
+                        pit_min,
+                        pit_max,
+                        self.complexity,
+                        self.plc_tuning,
+                        &mut self.cumul_gain,
+                        bits,
+                    )
+                }
+            }
+
+            let exc = &mut self.exc_buf[pos..pos + NB_SUBFRAME_SIZE];
+            for (e, &p) in exc.iter_mut().zip(&exc32) {
+                *e -= p;
+            }
+
+            // Gain correction for the subframe
+            let mut ener = compute_rms(exc);
+            let fine_gain = ener / ol_gain;
+            match submode.have_subframe_gain {
+                0 => ener = ol_gain,
+                3 => {
+                    let qe = scal_quant(fine_gain, &EXC_GAIN_QUANT_SCAL3_BOUND);
+                    bits.pack(qe as u32, 3);
+                    ener = EXC_GAIN_QUANT_SCAL3[qe] * ol_gain;
+                }
+                _ => {
+                    let qe = scal_quant(fine_gain, &EXC_GAIN_QUANT_SCAL1_BOUND);
+                    bits.pack(qe as u32, 1);
+                    ener = EXC_GAIN_QUANT_SCAL1[qe] * ol_gain;
+                }
+            }
+
+            // Normalize the target and quantize the innovation
+            let scale = 1.0 / ener;
+            for t in &mut target {
+                *t *= scale;
+            }
+            let mut innov = [0f32; NB_SUBFRAME_SIZE];
+            innovation_quant(
+                &submode.innovation,
+                &mut target,
+                &filters,
+                &mut innov,
+                bits,
+                self.complexity,
+                submode.double_codebook,
+            );
+            for v in &mut innov {
+                *v *= ener;
+            }
+
+            // Some modes do a second search to reduce the noise even more
+            if submode.double_codebook {
+                let mut innov2 = [0f32; NB_SUBFRAME_SIZE];
+                for t in &mut target {
+                    *t *= 2.2;
+                }
+                innovation_quant(
+                    &submode.innovation,
+                    &mut target,
+                    &filters,
+                    &mut innov2,
+                    bits,
+                    self.complexity,
+                    false,
+                );
+                let scale = 0.454545f32 * ener;
+                for (v, &v2) in innov.iter_mut().zip(&innov2) {
+                    *v += scale * v2;
+                }
+            }
+
+            for i in 0..NB_SUBFRAME_SIZE {
+                exc[i] = exc32[i] + innov[i];
+            }
+
+            // Synthesize the signal from the excitation, then weight it again
+            let sw = &mut self.sw_buf[pos..pos + NB_SUBFRAME_SIZE];
+            sw.copy_from_slice(exc);
+            iir_mem16(sw, &interp_qlpc, &mut self.mem_sp);
+            if self.complexity != 0 {
+                filter_mem16(sw, &bw_lpc1, &bw_lpc2, &mut self.mem_sw);
+            }
+        }
+
+        // Store the LSPs for interpolation in the next frame
+        self.old_lsp = lsp;
+        self.old_qlsp = qlsp;
+
+        if self.submode_id == 1 {
+            let dtx = if self.dtx_count != 0 { 15 } else { 0 };
+            bits.pack(dtx, 4);
+        }
+
+        self.first = false;
+        self.win_buf
+            .copy_from_slice(&input[NB_FRAME_SIZE - lookahead..]);
+        self.bounded_pitch = matches!(submode.innovation, Innovation::Noise);
+    }
+}
+
+impl Default for NbEncoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::vec::Vec;
+
+    use speex_safe::{DynamicEncoder, ModeId, NbMode, SpeexBits, SpeexDecoder};
+
+    use super::*;
+    use crate::support::{self, snr};
+
+    /// A voiced signal with a gliding pitch, a noise burst and some silence
+    fn test_signal(frames: usize) -> Vec<f32> {
+        let mut seed = 12345u32;
+        let mut phase = 0f32;
+        (0..frames * NB_FRAME_SIZE)
+            .map(|n| {
+                let t = n as f32 / 8000.0;
+                seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                let noise = (seed >> 16) as f32 / 32768.0 - 1.0;
+                let section = n / (NB_FRAME_SIZE * 25);
+                match section % 4 {
+                    0 | 2 => {
+                        phase +=
+                            2.0 * std::f32::consts::PI * (120.0 + 80.0 * (3.0 * t).sin()) / 8000.0;
+                        (1..10)
+                            .map(|k| (k as f32 * phase).sin() * 4000.0 / k as f32)
+                            .sum::<f32>()
+                            + 200.0 * noise
+                    }
+                    1 => 3000.0 * noise,
+                    _ => 5.0 * noise,
+                }
+            })
+            .collect()
+    }
+
+    /// Settings applied the same way to both encoders
+    #[derive(Copy, Clone, Default)]
+    struct Settings {
+        quality: i32,
+        complexity: i32,
+        vbr: bool,
+        vad: bool,
+        dtx: bool,
+        abr: i32,
+    }
+
+    fn encode_libspeex(signal: &[f32], settings: Settings) -> Vec<Vec<u8>> {
+        let mut encoder = DynamicEncoder::new(ModeId::NarrowBand);
+        encoder.set_quality(settings.quality);
+        encoder.set_complexity(settings.complexity);
+        encoder.set_vbr(settings.vbr);
+        encoder.set_vad(settings.vad);
+        encoder.set_dtx(settings.dtx);
+        if settings.abr != 0 {
+            encoder.set_abr(settings.abr);
+        }
+        support::encode_packets(&mut encoder, signal)
+    }
+
+    fn encode_rust(signal: &[f32], settings: Settings) -> Vec<Vec<u8>> {
+        let mut encoder = NbEncoder::new();
+        encoder.set_quality(settings.quality);
+        encoder.set_complexity(settings.complexity);
+        encoder.set_vbr(settings.vbr);
+        encoder.set_vad(settings.vad);
+        encoder.set_dtx(settings.dtx);
+        if settings.abr != 0 {
+            encoder.set_abr(settings.abr);
+        }
+        let mut bits = Bits::new();
+        signal
+            .chunks_exact(NB_FRAME_SIZE)
+            .map(|frame| {
+                bits.reset();
+                encoder.encode(frame, &mut bits).unwrap();
+                let mut packet = vec![0u8; 200];
+                let len = bits.write(&mut packet);
+                packet.truncate(len);
+                packet
+            })
+            .collect()
+    }
+
+    /// Decodes `packets` with libspeex, checking that every frame is accepted
+    fn decode_libspeex(packets: &[Vec<u8>]) -> Vec<f32> {
+        let mut decoder = SpeexDecoder::<NbMode>::new();
+        decoder.set_enhancement(false);
+        let mut decoded = Vec::with_capacity(packets.len() * NB_FRAME_SIZE);
+        let mut frame = vec![0f32; NB_FRAME_SIZE];
+        for packet in packets {
+            let mut bits = SpeexBits::new();
+            bits.read_from(&mut packet.clone());
+            decoder.decode(&mut bits, &mut frame).unwrap();
+            decoded.extend_from_slice(&frame);
+        }
+        decoded
+    }
+
+    /// Checks that libspeex decodes both encoders' output to the same audio
+    fn assert_close_to_libspeex(signal: &[f32], settings: Settings) {
+        let expected = decode_libspeex(&encode_libspeex(signal, settings));
+        let actual = decode_libspeex(&encode_rust(signal, settings));
+        let snr = snr(&expected, &actual);
+        assert!(
+            snr > 60.0,
+            "quality {} complexity {}: SNR against libspeex {snr:.2} dB",
+            settings.quality,
+            settings.complexity,
+        );
+    }
+
+    #[test]
+    fn matches_libspeex_quality() {
+        let signal = test_signal(100);
+        for quality in 0..=10 {
+            for complexity in [1, 2, 5, 10] {
+                let settings = Settings {
+                    quality,
+                    complexity,
+                    ..Default::default()
+                };
+                assert_close_to_libspeex(&signal, settings);
+            }
+        }
+    }
+
+    #[test]
+    fn matches_libspeex_quality_with_vbr() {
+        let signal = test_signal(100);
+        for settings in [
+            Settings {
+                quality: 8,
+                complexity: 3,
+                vbr: true,
+                ..Default::default()
+            },
+            Settings {
+                quality: 4,
+                complexity: 2,
+                vad: true,
+                dtx: true,
+                ..Default::default()
+            },
+            Settings {
+                quality: 8,
+                complexity: 3,
+                abr: 11000,
+                ..Default::default()
+            },
+        ] {
+            assert_close_to_libspeex(&signal, settings);
+        }
+    }
+
+    #[test]
+    fn rejects_small_buffer() {
+        let mut encoder = NbEncoder::new();
+        let mut bits = Bits::new();
+        assert_eq!(
+            encoder.encode(&[0.0; 10], &mut bits),
+            Err(EncoderError::TooSmallBuffer)
+        );
+    }
+}
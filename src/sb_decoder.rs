@@ -0,0 +1,688 @@
+////////////////////////////////////////////////////////////////////////////////
+// Copyright (c) 2023.                                                         /
+// This Source Code Form is subject to the terms of the Mozilla Public License,/
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can    /
+// obtain one at http://mozilla.org/MPL/2.0/.                                  /
+////////////////////////////////////////////////////////////////////////////////
+
+//! Sub-band CELP decoder, ported from libspeex's `sb_celp.c`
+
+use alloc::boxed::Box;
+use alloc::vec;
+
+#[cfg(not(any(feature = "std", test)))]
+use num_traits::Float;
+
+use crate::cb_search::split_cb_shape_sign_unquant;
+use crate::filters::{bw_lpc, compute_rms, iir_mem16, qmf_synth, speex_rand};
+use crate::lsp::{lsp_interpolate, lsp_to_lpc, lsp_unquant_high};
+use crate::modes::{SbSubmode, SB_SUBMODE_BITS, UWB_SUBMODE_TABLE, WB_SUBMODE_TABLE};
+use crate::nb_decoder::{float_to_int, NB_NB_SUBFRAMES};
+use crate::{Bits, DecoderError, Mode, NbDecoder};
+
+const SB_ORDER: usize = 8;
+const QMF_ORDER: usize = 64;
+/// Largest subframe, used by the ultra-wideband layer
+const SB_MAX_SUBFRAME_SIZE: usize = 80;
+
+/// Minimum distance kept between interpolated high-band LSPs
+const SB_LSP_MARGIN: f32 = 0.05;
+
+/// Gain correction for the longer subframes of the ultra-wideband layer
+// Kept as libspeex writes it rather than as `SQRT_2`
+#[allow(clippy::approx_constant)]
+const LONG_SUBFRAME_GAIN: f32 = 1.4142;
+
+/// Prototype filter of the QMF filter bank
+#[allow(clippy::excessive_precision)]
+const H0: [f32; QMF_ORDER] = [
+    3.596189e-05,
+    -0.0001123515,
+    -0.0001104587,
+    0.0002790277,
+    0.0002298438,
+    -0.0005953563,
+    -0.0003823631,
+    0.00113826,
+    0.0005308539,
+    -0.001986177,
+    -0.0006243724,
+    0.003235877,
+    0.0005743159,
+    -0.004989147,
+    -0.0002584767,
+    0.007367171,
+    -0.0004857935,
+    -0.01050689,
+    0.001894714,
+    0.01459396,
+    -0.004313674,
+    -0.01994365,
+    0.00828756,
+    0.02716055,
+    -0.01485397,
+    -0.03764973,
+    0.026447,
+    0.05543245,
+    -0.05095487,
+    -0.09779096,
+    0.1382363,
+    0.4600981,
+    0.4600981,
+    0.1382363,
+    -0.09779096,
+    -0.05095487,
+    0.05543245,
+    0.026447,
+    -0.03764973,
+    -0.01485397,
+    0.02716055,
+    0.00828756,
+    -0.01994365,
+    -0.004313674,
+    0.01459396,
+    0.001894714,
+    -0.01050689,
+    -0.0004857935,
+    0.007367171,
+    -0.0002584767,
+    -0.004989147,
+    0.0005743159,
+    0.003235877,
+    -0.0006243724,
+    -0.001986177,
+    0.0005308539,
+    0.00113826,
+    -0.0003823631,
+    -0.0005953563,
+    0.0002298438,
+    0.0002790277,
+    -0.0001104587,
+    -0.0001123515,
+    3.596189e-05,
+];
+
+/// Quantized high-band gains, relative to the low band's excitation
+#[allow(clippy::excessive_precision)]
+const GC_QUANT_BOUND: [f32; 16] = [
+    0.97979, 1.28384, 1.68223, 2.20426, 2.88829, 3.78458, 4.95900, 6.49787, 8.51428, 11.15642,
+    14.61846, 19.15484, 25.09895, 32.88761, 43.09325, 56.46588,
+];
+
+/// The decoder of the band below a sub-band layer
+#[derive(Clone, Debug)]
+enum LowBand {
+    Nb(Box<NbDecoder>),
+    Sb(Box<SbDecoder>),
+}
+
+impl LowBand {
+    fn decode(
+        &mut self,
+        bits: Option<&mut Bits>,
+        out: &mut [f32],
+        innov_save: &mut [f32],
+    ) -> Result<(), DecoderError> {
+        match self {
+            LowBand::Nb(decoder) => decoder.decode_low_band(bits, out, innov_save),
+            LowBand::Sb(decoder) => decoder.decode_frame(bits, out, Some(innov_save)),
+        }
+    }
+
+    fn get_dtx_status(&self) -> bool {
+        match self {
+            LowBand::Nb(decoder) => decoder.get_dtx_status(),
+            LowBand::Sb(decoder) => decoder.low.get_dtx_status(),
+        }
+    }
+
+    fn get_pi_gain(&self) -> [f32; NB_NB_SUBFRAMES] {
+        match self {
+            LowBand::Nb(decoder) => decoder.get_pi_gain(),
+            LowBand::Sb(decoder) => decoder.pi_gain,
+        }
+    }
+
+    fn get_exc_rms(&self) -> [f32; NB_NB_SUBFRAMES] {
+        match self {
+            LowBand::Nb(decoder) => decoder.get_exc_rms(),
+            LowBand::Sb(decoder) => decoder.exc_rms,
+        }
+    }
+
+    fn get_bitrate(&self) -> i32 {
+        match self {
+            LowBand::Nb(decoder) => decoder.get_bitrate(),
+            LowBand::Sb(decoder) => decoder.get_bitrate(),
+        }
+    }
+
+    fn set_enhancement(&mut self, state: bool) {
+        match self {
+            LowBand::Nb(decoder) => decoder.set_enhancement(state),
+            LowBand::Sb(decoder) => decoder.set_enhancement(state),
+        }
+    }
+
+    fn get_enhancement(&self) -> bool {
+        match self {
+            LowBand::Nb(decoder) => decoder.get_enhancement(),
+            LowBand::Sb(decoder) => decoder.get_enhancement(),
+        }
+    }
+
+    fn set_highpass(&mut self, state: bool) {
+        match self {
+            LowBand::Nb(decoder) => decoder.set_highpass(state),
+            LowBand::Sb(decoder) => decoder.set_highpass(state),
+        }
+    }
+
+    fn get_highpass(&self) -> bool {
+        match self {
+            LowBand::Nb(decoder) => decoder.get_highpass(),
+            LowBand::Sb(decoder) => decoder.get_highpass(),
+        }
+    }
+}
+
+/// A wideband (16 kHz) or ultra-wideband (32 kHz) decoder written in Rust
+///
+/// This is a port of libspeex's sub-band decoder, which decodes the low half
+/// of the spectrum with the decoder of the mode below and adds a high band on
+/// top. It produces the same output as
+/// [`speex_safe::SpeexDecoder<WbMode>`](speex_safe::SpeexDecoder) or
+/// `SpeexDecoder<UwbMode>` for the same stream.
+#[derive(Clone, Debug)]
+pub struct SbDecoder {
+    mode: Mode,
+    low: LowBand,
+    submode_id: usize,
+    /// Samples in each band
+    frame_size: usize,
+    subframe_size: usize,
+    folding_gain: f32,
+    first: bool,
+    seed: u32,
+
+    g0_mem: [f32; QMF_ORDER],
+    g1_mem: [f32; QMF_ORDER],
+    exc_buf: [f32; SB_MAX_SUBFRAME_SIZE],
+    old_qlsp: [f32; SB_ORDER],
+    interp_qlpc: [f32; SB_ORDER],
+    mem_sp: [f32; SB_ORDER],
+    pi_gain: [f32; NB_NB_SUBFRAMES],
+    exc_rms: [f32; NB_NB_SUBFRAMES],
+    last_ener: f32,
+}
+
+impl SbDecoder {
+    /// Creates a new wideband decoder
+    pub fn wideband() -> Self {
+        let mut low = NbDecoder::new();
+        low.set_wideband(true);
+        Self::with_low_band(Mode::WideBand, LowBand::Nb(Box::new(low)))
+    }
+
+    /// Creates a new ultra-wideband decoder
+    pub fn ultra_wideband() -> Self {
+        let low = Self::wideband();
+        Self::with_low_band(Mode::UltraWideBand, LowBand::Sb(Box::new(low)))
+    }
+
+    fn with_low_band(mode: Mode, low: LowBand) -> Self {
+        let (submode_id, frame_size, subframe_size, folding_gain) = match mode {
+            Mode::UltraWideBand => (1, 320, 80, 0.7),
+            _ => (3, 160, 40, 0.9),
+        };
+        Self {
+            mode,
+            low,
+            submode_id,
+            frame_size,
+            subframe_size,
+            folding_gain,
+            first: true,
+            seed: 1000,
+            g0_mem: [0.0; QMF_ORDER],
+            g1_mem: [0.0; QMF_ORDER],
+            exc_buf: [0.0; SB_MAX_SUBFRAME_SIZE],
+            old_qlsp: [0.0; SB_ORDER],
+            interp_qlpc: [0.0; SB_ORDER],
+            mem_sp: [0.0; SB_ORDER],
+            pi_gain: [0.0; NB_NB_SUBFRAMES],
+            exc_rms: [0.0; NB_NB_SUBFRAMES],
+            last_ener: 0.0,
+        }
+    }
+
+    /// The mode this decoder decodes
+    pub fn get_mode(&self) -> Mode {
+        self.mode
+    }
+
+    fn get_submode(&self) -> Option<&'static SbSubmode> {
+        match self.mode {
+            Mode::UltraWideBand => UWB_SUBMODE_TABLE[self.submode_id],
+            _ => WB_SUBMODE_TABLE[self.submode_id],
+        }
+    }
+
+    /// Set whether to use the perceptual enhancer
+    pub fn set_enhancement(&mut self, state: bool) {
+        self.low.set_enhancement(state);
+    }
+
+    /// Get whether the perceptual enhancer is turned on or not
+    pub fn get_enhancement(&self) -> bool {
+        self.low.get_enhancement()
+    }
+
+    /// Set whether to highpass filter the output
+    pub fn set_highpass(&mut self, state: bool) {
+        self.low.set_highpass(state);
+    }
+
+    /// Get whether the output is highpass filtered
+    pub fn get_highpass(&self) -> bool {
+        self.low.get_highpass()
+    }
+
+    /// Number of samples in a decoded frame
+    pub fn get_frame_size(&self) -> usize {
+        2 * self.frame_size
+    }
+
+    /// Bitrate of the last decoded frame, in bits per second
+    pub fn get_bitrate(&self) -> i32 {
+        let bits = match self.get_submode() {
+            Some(submode) => submode.bits_per_frame,
+            None => SB_SUBMODE_BITS as i32 + 1,
+        };
+        // Each layer doubles the sampling rate of the one below, so every
+        // layer spans the same duration as a narrowband frame
+        self.low.get_bitrate() + 50 * bits
+    }
+
+    /// Resets the filter memories of the high band
+    pub fn reset_state(&mut self) {
+        self.mem_sp = [0.0; SB_ORDER];
+        self.g0_mem = [0.0; QMF_ORDER];
+        self.g1_mem = [0.0; QMF_ORDER];
+        self.last_ener = 0.0;
+    }
+
+    /// Decode one frame of speex data from the bitstream
+    pub fn decode(&mut self, bits: &mut Bits, out: &mut [f32]) -> Result<(), DecoderError> {
+        let frame_size = self.get_frame_size();
+        if out.len() < frame_size {
+            return Err(DecoderError::TooSmallBuffer);
+        }
+        self.decode_frame(Some(bits), &mut out[..frame_size], None)
+    }
+
+    /// Decode one frame of speex data from the bitstream, as i16
+    pub fn decode_int(&mut self, bits: &mut Bits, out: &mut [i16]) -> Result<(), DecoderError> {
+        let frame_size = self.get_frame_size();
+        if out.len() < frame_size {
+            return Err(DecoderError::TooSmallBuffer);
+        }
+        let mut float_out = vec![0f32; frame_size];
+        self.decode_frame(Some(bits), &mut float_out, None)?;
+        float_to_int(&float_out, out);
+        Ok(())
+    }
+
+    /// Produces one frame of audio for a packet that was lost, using the
+    /// decoder's packet loss concealment
+    pub fn decode_lost(&mut self, out: &mut [f32]) -> Result<(), DecoderError> {
+        let frame_size = self.get_frame_size();
+        if out.len() < frame_size {
+            return Err(DecoderError::TooSmallBuffer);
+        }
+        self.decode_frame(None, &mut out[..frame_size], None)
+    }
+
+    /// Produces one frame of audio for a packet that was lost, as i16
+    pub fn decode_lost_int(&mut self, out: &mut [i16]) -> Result<(), DecoderError> {
+        let frame_size = self.get_frame_size();
+        if out.len() < frame_size {
+            return Err(DecoderError::TooSmallBuffer);
+        }
+        let mut float_out = vec![0f32; frame_size];
+        self.decode_frame(None, &mut float_out, None)?;
+        float_to_int(&float_out, out);
+        Ok(())
+    }
+
+    /// Decodes a frame into `out`, which holds both bands
+    ///
+    /// When this is the low band of another sub-band decoder, the excitation
+    /// of the high band is saved into `innov_save` for it to fold.
+    fn decode_frame(
+        &mut self,
+        mut bits: Option<&mut Bits>,
+        out: &mut [f32],
+        mut innov_save: Option<&mut [f32]>,
+    ) -> Result<(), DecoderError> {
+        let (low_out, high_out) = out.split_at_mut(self.frame_size);
+        // The low band leaves its innovation in the high band's half of the
+        // output, which is only overwritten once it has been folded
+        self.low.decode(bits.as_deref_mut(), low_out, high_out)?;
+        let dtx = self.low.get_dtx_status();
+
+        let bits = match bits {
+            Some(bits) => bits,
+            None => {
+                self.decode_lost_frame(out, dtx);
+                return Ok(());
+            }
+        };
+
+        let wideband = bits.remaining() > 0 && bits.peek() != 0;
+        if wideband {
+            bits.advance(1);
+            self.submode_id = bits.unpack_unsigned(SB_SUBMODE_BITS) as usize;
+        } else {
+            self.submode_id = 0;
+        }
+
+        let submode = match self.get_submode() {
+            Some(submode) => submode,
+            None if self.submode_id != 0 => return Err(DecoderError::CorruptStream),
+            None if dtx => {
+                self.decode_lost_frame(out, true);
+                return Ok(());
+            }
+            None => {
+                self.decode_null_frame(out);
+                return Ok(());
+            }
+        };
+
+        let low_pi_gain = self.low.get_pi_gain();
+        let low_exc_rms = self.low.get_exc_rms();
+
+        let mut qlsp = [0f32; SB_ORDER];
+        lsp_unquant_high(&mut qlsp, bits);
+        if self.first {
+            self.old_qlsp = qlsp;
+        }
+
+        let sub_size = self.subframe_size;
+        let (_, high_out) = out.split_at_mut(self.frame_size);
+        let mut interp_qlsp = [0f32; SB_ORDER];
+        let mut ak = [0f32; SB_ORDER];
+        let mut exc = [0f32; SB_MAX_SUBFRAME_SIZE];
+        let exc = &mut exc[..sub_size];
+        let mut exc_ener_sum = 0f32;
+
+        for sub in 0..NB_NB_SUBFRAMES {
+            let offset = sub_size * sub;
+
+            lsp_interpolate(
+                &self.old_qlsp,
+                &qlsp,
+                &mut interp_qlsp,
+                sub,
+                NB_NB_SUBFRAMES,
+                SB_LSP_MARGIN,
+            );
+            lsp_to_lpc(&interp_qlsp, &mut ak);
+
+            // Ratio between the responses of the low and high band filters
+            // where the bands meet
+            self.pi_gain[sub] = 1.0;
+            let mut rh = 1f32;
+            for pair in ak.chunks_exact(2) {
+                rh += pair[1] - pair[0];
+                self.pi_gain[sub] += pair[0] + pair[1];
+            }
+            let rl = low_pi_gain[sub];
+            let filter_ratio = ((rl as f64 + 0.01) / (rh as f64 + 0.01)) as f32;
+
+            exc.fill(0.0);
+            match submode.innovation {
+                None => {
+                    // Fold the spectrum of the low band's innovation
+                    let quant = bits.unpack_unsigned(5) as i32;
+                    let g = ((0.125f32 * (quant - 10) as f32) as f64).exp() as f32;
+                    let g = g / filter_ratio;
+
+                    let low_innov = &high_out[offset..offset + sub_size];
+                    for i in (0..sub_size).step_by(2) {
+                        exc[i] = (self.folding_gain * low_innov[i]) * g;
+                        exc[i + 1] = -((self.folding_gain * low_innov[i + 1]) * g);
+                    }
+                }
+                Some(params) => {
+                    let qgc = bits.unpack_unsigned(4) as usize;
+                    let el = low_exc_rms[sub];
+                    let mut gc = (0.87360 * GC_QUANT_BOUND[qgc] as f64) as f32;
+                    if sub_size == 80 {
+                        gc *= LONG_SUBFRAME_GAIN;
+                    }
+                    let scale = (gc * el) / filter_ratio;
+
+                    split_cb_shape_sign_unquant(exc, params, bits);
+                    for e in exc.iter_mut() {
+                        *e *= scale;
+                    }
+
+                    if submode.double_codebook {
+                        let mut innov2 = [0f32; SB_MAX_SUBFRAME_SIZE];
+                        let innov2 = &mut innov2[..sub_size];
+                        split_cb_shape_sign_unquant(innov2, params, bits);
+                        let scale2 = 0.4f32 * scale;
+                        for (e, i2) in exc.iter_mut().zip(innov2.iter()) {
+                            *e += scale2 * i2;
+                        }
+                    }
+                }
+            }
+
+            if let Some(innov_save) = innov_save.as_deref_mut() {
+                let save = &mut innov_save[2 * offset..2 * (offset + sub_size)];
+                save.fill(0.0);
+                for (s, &e) in save.iter_mut().step_by(2).zip(exc.iter()) {
+                    *s = e;
+                }
+            }
+
+            // The filter lags one subframe behind, like the low band
+            let sp = &mut high_out[offset..offset + sub_size];
+            sp.copy_from_slice(&self.exc_buf[..sub_size]);
+            iir_mem16(sp, &self.interp_qlpc, &mut self.mem_sp);
+
+            self.exc_buf[..sub_size].copy_from_slice(exc);
+            self.interp_qlpc = ak;
+            self.exc_rms[sub] = compute_rms(exc);
+            exc_ener_sum += (self.exc_rms[sub] * self.exc_rms[sub]) / NB_NB_SUBFRAMES as f32;
+        }
+        self.last_ener = (exc_ener_sum as f64).sqrt() as f32;
+
+        qmf_synth(out, &H0, &mut self.g0_mem, &mut self.g1_mem);
+        self.old_qlsp = qlsp;
+        self.first = false;
+
+        Ok(())
+    }
+
+    /// Fills the high band with a tiny signal, for frames where it was not
+    /// transmitted
+    fn decode_null_frame(&mut self, out: &mut [f32]) {
+        let high_out = &mut out[self.frame_size..];
+        high_out.fill(1e-15);
+        self.first = true;
+        iir_mem16(high_out, &self.interp_qlpc, &mut self.mem_sp);
+        qmf_synth(out, &H0, &mut self.g0_mem, &mut self.g1_mem);
+    }
+
+    /// Conceals the high band of a lost frame with noise, or continues the
+    /// comfort noise during discontinuous transmission
+    fn decode_lost_frame(&mut self, out: &mut [f32], dtx: bool) {
+        if !dtx {
+            let lpc = self.interp_qlpc;
+            bw_lpc(0.99, &lpc, &mut self.interp_qlpc);
+        }
+        self.first = true;
+
+        if !dtx {
+            self.last_ener *= 0.9f32;
+        }
+        let high_out = &mut out[self.frame_size..];
+        for v in high_out.iter_mut() {
+            *v = speex_rand(self.last_ener, &mut self.seed);
+        }
+        iir_mem16(high_out, &self.interp_qlpc, &mut self.mem_sp);
+        qmf_synth(out, &H0, &mut self.g0_mem, &mut self.g1_mem);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::vec::Vec;
+
+    use speex_safe::{DynamicDecoder, DynamicEncoder, ModeId, SpeexBits};
+
+    use super::*;
+    use crate::support::{self, snr};
+
+    /// A voiced signal with a gliding pitch and bright harmonics, a noise
+    /// burst and some silence
+    fn test_signal(rate: usize, frames: usize, frame_size: usize) -> Vec<f32> {
+        let mut seed = 12345u32;
+        let mut phase = 0f32;
+        (0..frames * frame_size)
+            .map(|n| {
+                let t = n as f32 / rate as f32;
+                seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                let noise = (seed >> 16) as f32 / 32768.0 - 1.0;
+                let section = n / (frame_size * 25);
+                match section % 4 {
+                    0 | 2 => {
+                        phase += 2.0 * std::f32::consts::PI * (120.0 + 80.0 * (3.0 * t).sin())
+                            / rate as f32;
+                        (1..40)
+                            .map(|k| (k as f32 * phase).sin() * 4000.0 / k as f32)
+                            .sum::<f32>()
+                            + 200.0 * noise
+                    }
+                    1 => 3000.0 * noise,
+                    _ => 5.0 * noise,
+                }
+            })
+            .collect()
+    }
+
+    /// Encodes `signal` with libspeex, returning the packets
+    fn encode(mode: ModeId, signal: &[f32], setup: impl Fn(&mut DynamicEncoder)) -> Vec<Vec<u8>> {
+        let mut encoder = DynamicEncoder::new(mode);
+        setup(&mut encoder);
+        support::encode_packets(&mut encoder, signal)
+    }
+
+    /// Decodes `packets` with both decoders, treating every `lose_every`th
+    /// packet as lost, and checks the outputs agree
+    fn assert_matches_libspeex(
+        mut decoder: SbDecoder,
+        packets: &[Vec<u8>],
+        enhancement: bool,
+        lose_every: usize,
+    ) {
+        let mode = match decoder.get_mode() {
+            Mode::UltraWideBand => ModeId::UltraWideBand,
+            _ => ModeId::WideBand,
+        };
+        let mut reference = DynamicDecoder::new(mode);
+        reference.set_enhancement(enhancement);
+        decoder.set_enhancement(enhancement);
+
+        let frame_size = decoder.get_frame_size();
+        let mut expected = vec![0f32; frame_size];
+        let mut actual = vec![0f32; frame_size];
+        for (i, packet) in packets.iter().enumerate() {
+            if lose_every != 0 && i % lose_every == lose_every - 1 {
+                reference.decode_lost(&mut expected).unwrap();
+                decoder.decode_lost(&mut actual).unwrap();
+            } else {
+                let mut sys_bits = SpeexBits::new();
+                sys_bits.read_from(&mut packet.clone());
+                reference.decode(&mut sys_bits, &mut expected).unwrap();
+                let mut bits = Bits::from_bytes(packet);
+                decoder.decode(&mut bits, &mut actual).unwrap();
+            }
+            let snr = snr(&expected, &actual);
+            assert!(
+                snr > 60.0,
+                "frame {i} differs from libspeex, SNR {snr:.1} dB"
+            );
+        }
+    }
+
+    #[test]
+    fn wideband_matches_libspeex_at_every_quality() {
+        let signal = test_signal(16000, 100, 320);
+        for quality in 0..=10 {
+            let packets = encode(ModeId::WideBand, &signal, |encoder| {
+                encoder.set_quality(quality)
+            });
+            assert_matches_libspeex(SbDecoder::wideband(), &packets, true, 0);
+            assert_matches_libspeex(SbDecoder::wideband(), &packets, false, 0);
+        }
+    }
+
+    #[test]
+    fn ultra_wideband_matches_libspeex_at_every_quality() {
+        let signal = test_signal(32000, 60, 640);
+        for quality in 0..=10 {
+            let packets = encode(ModeId::UltraWideBand, &signal, |encoder| {
+                encoder.set_quality(quality)
+            });
+            assert_matches_libspeex(SbDecoder::ultra_wideband(), &packets, true, 0);
+        }
+    }
+
+    #[test]
+    fn matches_libspeex_with_lost_packets() {
+        let signal = test_signal(16000, 100, 320);
+        for quality in [2, 5, 8] {
+            let packets = encode(ModeId::WideBand, &signal, |encoder| {
+                encoder.set_quality(quality)
+            });
+            assert_matches_libspeex(SbDecoder::wideband(), &packets, true, 4);
+        }
+        let signal = test_signal(32000, 60, 640);
+        let packets = encode(ModeId::UltraWideBand, &signal, |encoder| {
+            encoder.set_quality(8)
+        });
+        assert_matches_libspeex(SbDecoder::ultra_wideband(), &packets, true, 4);
+    }
+
+    #[test]
+    fn matches_libspeex_with_vbr_and_dtx() {
+        let signal = test_signal(16000, 100, 320);
+        let packets = encode(ModeId::WideBand, &signal, |encoder| {
+            encoder.set_vbr(true);
+            encoder.set_vbr_quality(4.0);
+            encoder.set_dtx(true);
+        });
+        assert_matches_libspeex(SbDecoder::wideband(), &packets, true, 0);
+        assert_matches_libspeex(SbDecoder::wideband(), &packets, true, 5);
+
+        let signal = test_signal(32000, 60, 640);
+        let packets = encode(ModeId::UltraWideBand, &signal, |encoder| {
+            encoder.set_vbr(true);
+            encoder.set_vbr_quality(4.0);
+            encoder.set_dtx(true);
+        });
+        assert_matches_libspeex(SbDecoder::ultra_wideband(), &packets, true, 5);
+    }
+
+    #[test]
+    fn frame_sizes_follow_mode() {
+        assert_eq!(SbDecoder::wideband().get_frame_size(), 320);
+        assert_eq!(SbDecoder::ultra_wideband().get_frame_size(), 640);
+        assert_eq!(SbDecoder::ultra_wideband().get_mode(), Mode::UltraWideBand);
+    }
+}
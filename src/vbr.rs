@@ -0,0 +1,194 @@
+////////////////////////////////////////////////////////////////////////////////
+// Copyright (c) 2023.                                                         /
+// This Source Code Form is subject to the terms of the Mozilla Public License,/
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can    /
+// obtain one at http://mozilla.org/MPL/2.0/.                                  /
+////////////////////////////////////////////////////////////////////////////////
+
+//! Variable bit-rate analysis, ported from libspeex's `vbr.c`
+
+#[cfg(not(any(feature = "std", test)))]
+use num_traits::Float;
+
+/// Number of past frames whose energy is used to measure stationarity
+const VBR_MEMORY_SIZE: usize = 5;
+/// Energy below which a frame is considered silent
+const MIN_ENERGY: f32 = 6000.0;
+/// Exponent applied to the energy when tracking the noise level
+const NOISE_POW: f64 = 0.3;
+
+/// Quality thresholds above which each narrowband submode is used, for each
+/// VBR quality
+pub(crate) static VBR_NB_THRESH: [[f32; 11]; 9] = [
+    // CNG
+    [
+        -1.0, -1.0, -1.0, -1.0, -1.0, -1.0, -1.0, -1.0, -1.0, -1.0, -1.0,
+    ],
+    // 2 kbps
+    [4.0, 2.5, 2.0, 1.2, 0.5, -0.25, -0.5, -0.7, -0.8, -0.9, -1.0],
+    // 6 kbps
+    [10.0, 6.5, 5.2, 4.5, 3.9, 3.7, 3.0, 2.5, 2.3, 1.8, 1.0],
+    // 8 kbps
+    [11.0, 8.8, 7.5, 6.5, 5.0, 4.2, 3.9, 3.9, 3.5, 3.0, 1.0],
+    // 11 kbps
+    [11.0, 11.0, 9.9, 8.5, 7.0, 5.25, 4.5, 4.0, 4.0, 4.0, 2.0],
+    // 15 kbps
+    [11.0, 11.0, 11.0, 11.0, 9.5, 9.25, 8.0, 7.0, 5.0, 4.0, 3.0],
+    // 18 kbps
+    [11.0, 11.0, 11.0, 11.0, 11.0, 11.0, 9.5, 8.5, 6.2, 5.2, 5.0],
+    // 24 kbps
+    [
+        11.0, 11.0, 11.0, 11.0, 11.0, 11.0, 11.0, 11.0, 10.0, 9.8, 7.5,
+    ],
+    // 4 kbps
+    [7.0, 4.5, 3.7, 3.0, 2.5, 1.0, 1.8, 1.5, 1.0, 0.0, 0.0],
+];
+
+/// State of the analysis deciding how many bits each frame deserves
+#[derive(Clone, Debug)]
+pub(crate) struct VbrState {
+    average_energy: f32,
+    last_energy: f32,
+    last_log_energy: [f32; VBR_MEMORY_SIZE],
+    soft_pitch: f32,
+    last_quality: f32,
+    noise_level: f32,
+    noise_accum: f32,
+    noise_accum_count: f32,
+    consec_noise: i32,
+}
+
+impl Default for VbrState {
+    fn default() -> Self {
+        let noise_accum = (0.05 * (MIN_ENERGY as f64).powf(NOISE_POW)) as f32;
+        let noise_accum_count = 0.05;
+        Self {
+            average_energy: 1600000.0,
+            last_energy: 1.0,
+            last_log_energy: [(MIN_ENERGY as f64).ln() as f32; VBR_MEMORY_SIZE],
+            soft_pitch: 0.0,
+            last_quality: 0.0,
+            noise_level: noise_accum / noise_accum_count,
+            noise_accum,
+            noise_accum_count,
+            consec_noise: 0,
+        }
+    }
+}
+
+impl VbrState {
+    /// Rates how perceptually important the coding error of `sig` is,
+    /// from -1 (silence) to 10, given its open-loop pitch gain
+    pub(crate) fn analysis(&mut self, sig: &[f32], pitch_coef: f32) -> f32 {
+        let (first, second) = sig.split_at(sig.len() >> 1);
+        let ener1: f32 = first.iter().fold(0.0, |acc, &x| acc + x * x);
+        let ener2: f32 = second.iter().fold(0.0, |acc, &x| acc + x * x);
+        let ener = ener1 + ener2;
+        let mut qual = 7f32;
+
+        let log_energy = ((ener + MIN_ENERGY) as f64).ln() as f32;
+        let mut non_st = 0f32;
+        for &last in &self.last_log_energy {
+            non_st += (log_energy - last) * (log_energy - last);
+        }
+        let non_st = (non_st / (30 * VBR_MEMORY_SIZE) as f32).min(1.0) as f64;
+
+        let deviation = pitch_coef as f64 - 0.4;
+        let voicing = (3.0 * deviation * deviation.abs()) as f32 as f64;
+        self.average_energy = (0.9 * self.average_energy as f64 + 0.1 * ener as f64) as f32;
+        self.noise_level = self.noise_accum / self.noise_accum_count;
+        let pow_ener = (ener as f64).powf(NOISE_POW) as f32;
+        if (self.noise_accum_count as f64) < 0.06 && ener > MIN_ENERGY {
+            self.noise_accum = (0.05 * pow_ener as f64) as f32;
+        }
+
+        let noise_level = self.noise_level as f64;
+        let pow = pow_ener as f64;
+        if (voicing < 0.3 && non_st < 0.2 && pow < 1.2 * noise_level)
+            || (voicing < 0.3 && non_st < 0.05 && pow < 1.5 * noise_level)
+            || (voicing < 0.4 && non_st < 0.05 && pow < 1.2 * noise_level)
+            || (voicing < 0.0 && non_st < 0.05)
+        {
+            self.consec_noise += 1;
+            let tmp = pow_ener.min(3.0 * self.noise_level);
+            if self.consec_noise >= 4 {
+                self.accumulate_noise(tmp);
+            }
+        } else {
+            self.consec_noise = 0;
+        }
+
+        if pow_ener < self.noise_level && ener > MIN_ENERGY {
+            self.accumulate_noise(pow_ener);
+        }
+
+        // Checking for very low absolute energy
+        if ener < 30000.0 {
+            qual = (qual as f64 - 0.7) as f32;
+            if ener < 10000.0 {
+                qual = (qual as f64 - 0.7) as f32;
+            }
+            if ener < 3000.0 {
+                qual = (qual as f64 - 0.7) as f32;
+            }
+        } else {
+            let short_diff = (((ener + 1.0) / (1.0 + self.last_energy)) as f64).ln() as f32;
+            let long_diff = (((ener + 1.0) / (1.0 + self.average_energy)) as f64).ln() as f32;
+            let long_diff = long_diff.clamp(-5.0, 2.0);
+            if long_diff > 0.0 {
+                qual = (qual as f64 + 0.6 * long_diff as f64) as f32;
+            }
+            if long_diff < 0.0 {
+                qual = (qual as f64 + 0.5 * long_diff as f64) as f32;
+            }
+            if short_diff > 0.0 {
+                qual += short_diff.min(5.0);
+            }
+            // Checking for energy increases
+            if ener2 as f64 > 1.6 * ener1 as f64 {
+                qual = (qual as f64 + 0.5) as f32;
+            }
+        }
+        self.last_energy = ener;
+        self.soft_pitch = (0.8 * self.soft_pitch as f64 + 0.2 * pitch_coef as f64) as f32;
+        qual = (qual as f64 + 2.2 * ((pitch_coef as f64 - 0.4) + (self.soft_pitch as f64 - 0.4)))
+            as f32;
+
+        if qual < self.last_quality {
+            qual = (0.5 * qual as f64 + 0.5 * self.last_quality as f64) as f32;
+        }
+        qual = qual.clamp(4.0, 10.0);
+
+        let noise_penalty = (3.0 + self.consec_noise as f64).ln() - 3f64.ln();
+        if self.consec_noise >= 3 {
+            qual = 4.0;
+        }
+        if self.consec_noise != 0 {
+            qual = (qual as f64 - noise_penalty) as f32;
+        }
+        qual = qual.max(0.0);
+
+        if ener < 1600000.0 {
+            if self.consec_noise > 2 {
+                qual = (qual as f64 - 0.5 * noise_penalty) as f32;
+            }
+            if ener < 10000.0 && self.consec_noise > 2 {
+                qual = (qual as f64 - 0.5 * noise_penalty) as f32;
+            }
+            qual = qual.max(0.0);
+            qual = (qual as f64 + 0.3 * (0.0001 + ener as f64 / 1600000.0).ln()) as f32;
+        }
+        qual = qual.max(-1.0);
+
+        self.last_quality = qual;
+        self.last_log_energy.copy_within(..VBR_MEMORY_SIZE - 1, 1);
+        self.last_log_energy[0] = log_energy;
+        qual
+    }
+
+    /// Moves the noise level estimate towards `level`
+    fn accumulate_noise(&mut self, level: f32) {
+        self.noise_accum = (0.95 * self.noise_accum as f64 + 0.05 * level as f64) as f32;
+        self.noise_accum_count = (0.95 * self.noise_accum_count as f64 + 0.05) as f32;
+    }
+}
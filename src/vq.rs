@@ -0,0 +1,102 @@
+////////////////////////////////////////////////////////////////////////////////
+// Copyright (c) 2023.                                                         /
+// This Source Code Form is subject to the terms of the Mozilla Public License,/
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can    /
+// obtain one at http://mozilla.org/MPL/2.0/.                                  /
+////////////////////////////////////////////////////////////////////////////////
+
+//! Vector quantization, ported from libspeex's `vq.c`
+
+/// Index of the quantization interval `input` falls in, given the
+/// boundaries between consecutive intervals
+pub(crate) fn scal_quant(input: f32, boundary: &[f32]) -> usize {
+    boundary.iter().take_while(|&&b| input > b).count()
+}
+
+/// Inserts `dist` into the sorted n-best list if it beats its last entry
+fn insert_nbest(
+    index: usize,
+    dist: f32,
+    used: &mut usize,
+    nbest: &mut [usize],
+    best_dist: &mut [f32],
+) -> Option<usize> {
+    let n = nbest.len();
+    if index < n || dist < best_dist[n - 1] {
+        let mut k = n - 1;
+        while k >= 1 && (k > *used || dist < best_dist[k - 1]) {
+            best_dist[k] = best_dist[k - 1];
+            nbest[k] = nbest[k - 1];
+            k -= 1;
+        }
+        best_dist[k] = dist;
+        nbest[k] = index;
+        *used += 1;
+        Some(k)
+    } else {
+        None
+    }
+}
+
+/// Finds the `nbest.len()` entries of `codebook` closest to `input`, given
+/// the energy `e` of each entry
+pub(crate) fn vq_nbest(
+    input: &[f32],
+    codebook: &[f32],
+    e: &[f32],
+    nbest: &mut [usize],
+    best_dist: &mut [f32],
+) {
+    let mut used = 0;
+    for (i, entry) in codebook.chunks_exact(input.len()).enumerate() {
+        let mut dist = 0f32;
+        for (&x, &c) in input.iter().zip(entry) {
+            dist += x * c;
+        }
+        let dist = 0.5 * e[i] - dist;
+        insert_nbest(i, dist, &mut used, nbest, best_dist);
+    }
+}
+
+/// Like [`vq_nbest`], but also considers each entry with its sign flipped,
+/// which is reported by adding the number of entries to its index
+pub(crate) fn vq_nbest_sign(
+    input: &[f32],
+    codebook: &[f32],
+    e: &[f32],
+    nbest: &mut [usize],
+    best_dist: &mut [f32],
+) {
+    let entries = codebook.len() / input.len();
+    let mut used = 0;
+    for (i, entry) in codebook.chunks_exact(input.len()).enumerate() {
+        let mut dist = 0f32;
+        for (&x, &c) in input.iter().zip(entry) {
+            dist += x * c;
+        }
+        let negative = dist <= 0.0;
+        if !negative {
+            dist = -dist;
+        }
+        let dist = dist + 0.5 * e[i];
+        if let Some(k) = insert_nbest(i, dist, &mut used, nbest, best_dist) {
+            if negative {
+                nbest[k] += entries;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn scal_quant_picks_interval() {
+        let bounds = [0.5, 1.0, 2.0];
+        assert_eq!(scal_quant(0.1, &bounds), 0);
+        assert_eq!(scal_quant(1.0, &bounds), 1);
+        assert_eq!(scal_quant(1.5, &bounds), 2);
+        assert_eq!(scal_quant(9.0, &bounds), 3);
+    }
+}
@@ -0,0 +1,61 @@
+////////////////////////////////////////////////////////////////////////////////
+// Copyright (c) 2023.                                                         /
+// This Source Code Form is subject to the terms of the Mozilla Public License,/
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can    /
+// obtain one at http://mozilla.org/MPL/2.0/.                                  /
+////////////////////////////////////////////////////////////////////////////////
+
+//! Checks that speex-rs builds without std, for the host and for a
+//! bare-metal target when its standard library is installed.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const BARE_METAL_TARGET: &str = "thumbv7em-none-eabihf";
+
+/// Runs `cargo check` on the library without default features
+fn check_no_std(target: Option<&str>) {
+    let mut command = Command::new(env!("CARGO"));
+    command
+        .args(["check", "--offline", "--lib", "--no-default-features"])
+        .args(["-p", "speex-rs", "--manifest-path"])
+        .arg(Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml"))
+        .env(
+            "CARGO_TARGET_DIR",
+            Path::new(env!("CARGO_TARGET_TMPDIR")).join("no_std"),
+        );
+    if let Some(target) = target {
+        command.args(["--target", target]);
+    }
+    let output = command.output().unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+/// Finds the standard library of `target` in the active toolchain
+fn installed_target(target: &str) -> Option<PathBuf> {
+    let output = Command::new("rustc")
+        .args(["--print", "sysroot"])
+        .output()
+        .ok()?;
+    let sysroot = String::from_utf8(output.stdout).ok()?;
+    let path = Path::new(sysroot.trim()).join("lib/rustlib").join(target);
+    path.exists().then_some(path)
+}
+
+#[test]
+fn builds_without_std_on_host() {
+    check_no_std(None);
+}
+
+#[test]
+fn builds_for_bare_metal() {
+    if installed_target(BARE_METAL_TARGET).is_none() {
+        eprintln!("{BARE_METAL_TARGET} is not installed, skipping");
+        return;
+    }
+    check_no_std(Some(BARE_METAL_TARGET));
+}